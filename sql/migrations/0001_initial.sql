@@ -1,4 +1,4 @@
--- Phloem SQLite Database Schema
+-- Phloem SQLite Database Schema, as of the first versioned release
 
 -- Command suggestions cache
 CREATE TABLE IF NOT EXISTS suggestions (
@@ -41,4 +41,4 @@ CREATE TABLE IF NOT EXISTS environment (
 CREATE INDEX IF NOT EXISTS idx_suggestions_prompt_hash ON suggestions(prompt_hash);
 CREATE INDEX IF NOT EXISTS idx_suggestions_created_at ON suggestions(created_at);
 CREATE INDEX IF NOT EXISTS idx_history_executed_at ON history(executed_at);
-CREATE INDEX IF NOT EXISTS idx_environment_updated_at ON environment(updated_at);
\ No newline at end of file
+CREATE INDEX IF NOT EXISTS idx_environment_updated_at ON environment(updated_at);
@@ -0,0 +1,2 @@
+-- Backend that generated each suggestion
+ALTER TABLE suggestions ADD COLUMN backend TEXT;
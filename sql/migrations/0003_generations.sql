@@ -0,0 +1,7 @@
+-- How each model reply was parsed, to track how often repairs are needed
+CREATE TABLE generations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    backend TEXT,
+    outcome TEXT NOT NULL, -- valid, repaired or fallback
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,7 @@
+-- Prompt embeddings for semantic cache lookups
+CREATE TABLE prompt_embeddings (
+    prompt_hash TEXT PRIMARY KEY,
+    model TEXT NOT NULL, -- embedding model; vectors of different models aren't comparable
+    embedding BLOB NOT NULL, -- little-endian f32 values
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
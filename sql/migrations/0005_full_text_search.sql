@@ -0,0 +1,42 @@
+-- Full-text indexes over prompts and commands, kept in sync by triggers
+CREATE VIRTUAL TABLE suggestions_fts USING fts5(
+    prompt, suggestion, content='suggestions', content_rowid='id'
+);
+
+CREATE TRIGGER suggestions_fts_insert AFTER INSERT ON suggestions BEGIN
+    INSERT INTO suggestions_fts(rowid, prompt, suggestion) VALUES (new.id, new.prompt, new.suggestion);
+END;
+
+CREATE TRIGGER suggestions_fts_delete AFTER DELETE ON suggestions BEGIN
+    INSERT INTO suggestions_fts(suggestions_fts, rowid, prompt, suggestion)
+    VALUES ('delete', old.id, old.prompt, old.suggestion);
+END;
+
+CREATE TRIGGER suggestions_fts_update AFTER UPDATE OF prompt, suggestion ON suggestions BEGIN
+    INSERT INTO suggestions_fts(suggestions_fts, rowid, prompt, suggestion)
+    VALUES ('delete', old.id, old.prompt, old.suggestion);
+    INSERT INTO suggestions_fts(rowid, prompt, suggestion) VALUES (new.id, new.prompt, new.suggestion);
+END;
+
+CREATE VIRTUAL TABLE history_fts USING fts5(
+    command, prompt, content='history', content_rowid='id'
+);
+
+CREATE TRIGGER history_fts_insert AFTER INSERT ON history BEGIN
+    INSERT INTO history_fts(rowid, command, prompt) VALUES (new.id, new.command, new.prompt);
+END;
+
+CREATE TRIGGER history_fts_delete AFTER DELETE ON history BEGIN
+    INSERT INTO history_fts(history_fts, rowid, command, prompt)
+    VALUES ('delete', old.id, old.command, old.prompt);
+END;
+
+CREATE TRIGGER history_fts_update AFTER UPDATE OF command, prompt ON history BEGIN
+    INSERT INTO history_fts(history_fts, rowid, command, prompt)
+    VALUES ('delete', old.id, old.command, old.prompt);
+    INSERT INTO history_fts(rowid, command, prompt) VALUES (new.id, new.command, new.prompt);
+END;
+
+-- Index rows written before the full-text tables existed
+INSERT INTO suggestions_fts(suggestions_fts) VALUES ('rebuild');
+INSERT INTO history_fts(history_fts) VALUES ('rebuild');
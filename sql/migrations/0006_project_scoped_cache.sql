@@ -0,0 +1,6 @@
+-- Project fingerprint of each suggestion, empty outside a project
+ALTER TABLE suggestions ADD COLUMN project TEXT NOT NULL DEFAULT '';
+
+-- The same prompt and command may be cached once per project
+DROP INDEX IF EXISTS idx_suggestions_unique;
+CREATE UNIQUE INDEX idx_suggestions_project_unique ON suggestions(prompt_hash, suggestion, project);
@@ -0,0 +1,13 @@
+-- Learned command patterns; PHLOEM.md renders a view of these
+CREATE TABLE IF NOT EXISTS learned_patterns (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    prompt TEXT NOT NULL,
+    command TEXT NOT NULL,
+    category TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    -- Project fingerprint when learned into a project context file, empty otherwise
+    project TEXT NOT NULL DEFAULT '',
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_learned_patterns_project ON learned_patterns(project, created_at);
@@ -0,0 +1,10 @@
+-- Suggestions the user passed over, by cancelling or picking another one
+CREATE TABLE IF NOT EXISTS rejected_suggestions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    prompt TEXT NOT NULL,
+    command TEXT NOT NULL,
+    project TEXT NOT NULL DEFAULT '',
+    rejected_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_rejected_suggestions_rejected_at ON rejected_suggestions(rejected_at);
@@ -0,0 +1,11 @@
+-- Suggestions the user changed before running them, with what they ran instead
+CREATE TABLE IF NOT EXISTS corrections (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    prompt TEXT NOT NULL,
+    original_command TEXT NOT NULL,
+    corrected_command TEXT NOT NULL,
+    project TEXT NOT NULL DEFAULT '',
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_corrections_created_at ON corrections(created_at);
@@ -0,0 +1,15 @@
+-- Environment snapshots per host, so a home directory shared between
+-- machines (or reached over SSH) keeps each machine's tools apart.
+-- The old snapshot belongs to no known host; the next prompt detects again.
+DROP TABLE environment;
+
+CREATE TABLE environment (
+    host TEXT NOT NULL DEFAULT '',
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    detected_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (host, key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_environment_updated_at ON environment(updated_at);
@@ -0,0 +1,4 @@
+-- What commands run from phloem printed, the tail of each stream, so a
+-- failure can be explained to the model
+ALTER TABLE history ADD COLUMN stdout TEXT;
+ALTER TABLE history ADD COLUMN stderr TEXT;
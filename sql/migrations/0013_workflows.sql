@@ -0,0 +1,12 @@
+-- Plans whose steps all ran, kept to offer again for the same request
+CREATE TABLE IF NOT EXISTS workflows (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    prompt TEXT NOT NULL,
+    prompt_hash TEXT NOT NULL,
+    -- JSON array of {"command", "explanation"} in the order they ran
+    steps TEXT NOT NULL,
+    project TEXT NOT NULL DEFAULT '',
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_workflows_prompt_hash ON workflows(prompt_hash);
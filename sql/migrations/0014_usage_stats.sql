@@ -0,0 +1,16 @@
+-- Prompts answered, for `phloem stats`: where the suggestions came from and
+-- whether one of them was run
+CREATE TABLE IF NOT EXISTS prompts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    prompt_hash TEXT NOT NULL,
+    source TEXT NOT NULL, -- cache, model or offline
+    category TEXT NOT NULL DEFAULT '',
+    accepted BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_prompts_prompt_hash ON prompts(prompt_hash);
+CREATE INDEX IF NOT EXISTS idx_prompts_created_at ON prompts(created_at);
+
+-- How long each model reply took
+ALTER TABLE generations ADD COLUMN latency_ms INTEGER;
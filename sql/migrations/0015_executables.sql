@@ -0,0 +1,9 @@
+-- Executables found in PATH per host, so they're listed once rather than
+-- PATH being searched for every command checked. `path` is what they were
+-- listed from; the list is read again when it changes or grows old.
+CREATE TABLE IF NOT EXISTS executables (
+    host TEXT NOT NULL DEFAULT '' PRIMARY KEY,
+    path TEXT NOT NULL,
+    names TEXT NOT NULL, -- one per line
+    scanned_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
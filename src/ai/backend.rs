@@ -0,0 +1,421 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use log::{debug, warn};
+use regex::Regex;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Certificate, Client, Proxy};
+use std::time::Instant;
+use url::{Host, Url};
+
+#[cfg(feature = "ollama")]
+use crate::ai::OllamaClient;
+#[cfg(feature = "openai")]
+use crate::ai::OpenAiClient;
+use crate::ai::{
+    BackendChain, Conversation, MissingTool, ParseOutcome, PromptBuilder, ResponseParser,
+    StreamingParser,
+};
+use crate::cli::Suggestion;
+use crate::config::settings::{BackendEndpoint, BackendKind, HttpOptions};
+use crate::config::Settings;
+use crate::utils::Timing;
+
+/// Invoked with each suggestion as soon as it is available during generation
+pub type SuggestionCallback<'a> = &'a (dyn Fn(&Suggestion) + Send + Sync);
+
+/// How long to wait for a connection before treating a backend as unreachable
+pub const CONNECT_TIMEOUT_SECS: u64 = 3;
+
+/// Invoked with each text fragment of a reply as it streams in
+pub type FragmentCallback<'a> = &'a mut (dyn FnMut(&str) + Send);
+
+/// Suggestions produced for one conversation turn, with how the reply was parsed
+#[derive(Debug, Clone)]
+pub struct Generation {
+    pub suggestions: Vec<Suggestion>,
+    pub outcome: ParseOutcome,
+    /// Label of the backend that answered, when known
+    pub backend: Option<String>,
+    /// Question the model asked instead of suggesting commands
+    pub clarify: Option<String>,
+    /// Suggestions left out because their executable isn't installed
+    pub missing: Vec<MissingTool>,
+}
+
+/// A single progress event reported while a model is being downloaded
+#[derive(Debug, Clone, Default)]
+pub struct PullProgress {
+    pub status: String,
+    pub digest: Option<String>,
+    pub total: Option<u64>,
+    pub completed: Option<u64>,
+}
+
+/// Digests of a model before and after it was pulled again
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelUpdate {
+    /// Digest before the pull; `None` when the model wasn't downloaded yet
+    pub previous: Option<String>,
+    pub current: String,
+}
+
+impl ModelUpdate {
+    pub fn changed(&self) -> bool {
+        self.previous.as_deref() != Some(self.current.as_str())
+    }
+}
+
+/// Invoked with each progress event during a model download
+pub type ProgressCallback<'a> = &'a (dyn Fn(&PullProgress) + Send + Sync);
+
+/// Common interface implemented by every model provider phloem can talk to
+#[async_trait]
+pub trait AiBackend: Send + Sync {
+    /// Short provider name used in logs and diagnostics
+    fn name(&self) -> &'static str;
+
+    /// Human-readable location of the service (URL, socket, path)
+    fn endpoint(&self) -> String;
+
+    /// Model used for generation
+    fn model(&self) -> &str;
+
+    /// Verifies the backend is reachable and ready to serve requests
+    async fn verify_connection(&self) -> Result<()>;
+
+    /// Lists the models the backend can serve
+    async fn list_models(&self) -> Result<Vec<String>>;
+
+    /// Makes sure the configured model is ready, downloading it if the backend supports that
+    async fn ensure_model_available(&self, _on_progress: ProgressCallback<'_>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Downloads the configured model again, picking up a newer build of its
+    /// tag; `None` when the backend doesn't manage its models
+    async fn update_model(
+        &self,
+        _on_progress: ProgressCallback<'_>,
+    ) -> Result<Option<ModelUpdate>> {
+        Ok(None)
+    }
+
+    /// Loads the model into memory so the next generation starts fast
+    async fn warmup(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Embeds `text` with the given embedding model, for semantic cache lookups
+    async fn embed(&self, _model: &str, _text: &str) -> Result<Vec<f32>> {
+        Err(anyhow::anyhow!(
+            "{} does not support embeddings",
+            self.name()
+        ))
+    }
+
+    /// Sends the conversation to the model and returns its raw reply.
+    ///
+    /// Streaming backends pass each fragment to `on_fragment` as it arrives;
+    /// others pass the whole reply once.
+    async fn complete(
+        &self,
+        conversation: &Conversation,
+        on_fragment: FragmentCallback<'_>,
+    ) -> Result<String>;
+
+    /// Generates command suggestions for the latest turn of the conversation,
+    /// appending the model's reply to it on success.
+    ///
+    /// Suggestions are reported through `on_suggestion` as soon as their JSON
+    /// object completes; the returned list is the authoritative final result.
+    /// A reply that isn't valid JSON gets one repair round-trip before falling
+    /// back to line-based extraction. The model may ask a clarifying question
+    /// instead of suggesting commands.
+    async fn generate_suggestions(
+        &self,
+        conversation: &mut Conversation,
+        max_suggestions: usize,
+        on_suggestion: SuggestionCallback<'_>,
+    ) -> Result<Generation> {
+        let parser = ResponseParser::new();
+        let reply = stream_reply(self, conversation, max_suggestions, on_suggestion).await?;
+
+        match Timing::measure("parse", || parser.parse_json(&reply, max_suggestions)) {
+            Some(parsed) if parsed.is_answer() => {
+                conversation.push_assistant(reply);
+                return Ok(Generation {
+                    suggestions: parsed.suggestions,
+                    outcome: ParseOutcome::Valid,
+                    backend: None,
+                    clarify: parsed.clarify,
+                    missing: parsed.missing,
+                });
+            }
+            Some(_) => {}
+            None => {
+                debug!("Reply was not valid JSON, asking the model to repair it");
+
+                // The repair exchange is dropped from the history either way
+                let checkpoint = conversation.len();
+                conversation.push_assistant(reply.clone());
+                conversation.push_user(PromptBuilder::new().build_repair_message());
+                let repaired =
+                    stream_reply(self, conversation, max_suggestions, on_suggestion).await;
+                conversation.truncate(checkpoint);
+
+                match repaired {
+                    Ok(repaired) => {
+                        if let Some(parsed) = Timing::measure("parse", || {
+                            parser.parse_json(&repaired, max_suggestions)
+                        })
+                        .filter(|parsed| parsed.is_answer())
+                        {
+                            conversation.push_assistant(repaired);
+                            return Ok(Generation {
+                                suggestions: parsed.suggestions,
+                                outcome: ParseOutcome::Repaired,
+                                backend: None,
+                                clarify: parsed.clarify,
+                                missing: parsed.missing,
+                            });
+                        }
+                    }
+                    Err(e) => warn!("Repair request failed: {e}"),
+                }
+            }
+        }
+
+        let suggestions = Timing::measure("parse", || {
+            parser.extract_commands_fallback(&reply, max_suggestions)
+        });
+        conversation.push_assistant(reply);
+        Ok(Generation {
+            suggestions,
+            outcome: ParseOutcome::Fallback,
+            backend: None,
+            clarify: None,
+            missing: Vec::new(),
+        })
+    }
+}
+
+/// Completes the conversation, reporting each suggestion object as soon as it streams in
+async fn stream_reply<B: AiBackend + ?Sized>(
+    backend: &B,
+    conversation: &Conversation,
+    max_suggestions: usize,
+    on_suggestion: SuggestionCallback<'_>,
+) -> Result<String> {
+    let parser = ResponseParser::new();
+    let mut streaming = StreamingParser::new();
+    let mut streamed = 0;
+
+    Timing::model_requested();
+    let started = Instant::now();
+    let reply = backend
+        .complete(conversation, &mut |fragment: &str| {
+            for raw in streaming.push(fragment) {
+                if streamed >= max_suggestions {
+                    break;
+                }
+                if let Some(suggestion) = parser.parse_suggestion_object(&raw) {
+                    on_suggestion(&suggestion);
+                    streamed += 1;
+                }
+            }
+        })
+        .await;
+    Timing::record("model", started.elapsed());
+    reply
+}
+
+/// Builds the HTTP client for a backend, applying proxy, header and TLS options
+pub fn build_http_client(timeout_secs: u64, http: &HttpOptions) -> Result<Client> {
+    let mut builder = Client::builder()
+        .timeout(std::time::Duration::from_secs(timeout_secs))
+        .connect_timeout(std::time::Duration::from_secs(CONNECT_TIMEOUT_SECS));
+
+    // reqwest already honors HTTP(S)_PROXY from the environment; an explicit proxy wins
+    if http.no_proxy {
+        builder = builder.no_proxy();
+    } else if let Some(proxy) = &http.proxy {
+        builder = builder
+            .proxy(Proxy::all(proxy).with_context(|| format!("Invalid proxy URL: {proxy}"))?);
+    }
+
+    if !http.headers.is_empty() {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &http.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("Invalid header name: {name}"))?;
+            let mut value = HeaderValue::from_str(&expand_env_vars(value))
+                .with_context(|| format!("Invalid value for header {name}"))?;
+            value.set_sensitive(true);
+            headers.insert(name, value);
+        }
+        builder = builder.default_headers(headers);
+    }
+
+    if let Some(ca_cert) = &http.ca_cert {
+        let path = match ca_cert.strip_prefix("~/") {
+            Some(rest) => dirs::home_dir()
+                .ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?
+                .join(rest),
+            None => ca_cert.into(),
+        };
+        let pem = std::fs::read(&path)
+            .with_context(|| format!("Failed to read CA certificate {}", path.display()))?;
+        builder = builder
+            .add_root_certificate(Certificate::from_pem(&pem).context("Invalid CA certificate")?);
+    }
+
+    if http.insecure {
+        warn!("TLS certificate verification is disabled");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    builder.build().context("Failed to create HTTP client")
+}
+
+/// Replaces `${VAR}` with the value of the environment variable, keeping secrets out of the config
+fn expand_env_vars(value: &str) -> String {
+    let pattern = Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}").expect("valid regex");
+    pattern
+        .replace_all(value, |caps: &regex::Captures| {
+            std::env::var(&caps[1]).unwrap_or_else(|_| {
+                warn!("Environment variable {} is not set", &caps[1]);
+                String::new()
+            })
+        })
+        .into_owned()
+}
+
+/// Builds the configured backend chain: `[backend] chain` when set, otherwise just `[backend] kind`
+pub fn create_backend(settings: &Settings) -> Result<BackendChain> {
+    let endpoints = if settings.backend.chain.is_empty() {
+        vec![BackendEndpoint {
+            kind: settings.backend.kind,
+            ..Default::default()
+        }]
+    } else {
+        settings.backend.chain.clone()
+    };
+
+    let backends = endpoints
+        .iter()
+        .map(|endpoint| build_backend(settings, endpoint))
+        .collect::<Result<Vec<_>>>()?;
+
+    BackendChain::new(backends)
+}
+
+/// Builds a single backend of the primary `[backend] kind` serving `model`
+pub fn create_backend_for_model(settings: &Settings, model: &str) -> Result<Box<dyn AiBackend>> {
+    let endpoint = match settings.backend.chain.first() {
+        Some(primary) => BackendEndpoint {
+            model: Some(model.to_string()),
+            ..primary.clone()
+        },
+        None => BackendEndpoint {
+            kind: settings.backend.kind,
+            model: Some(model.to_string()),
+            ..Default::default()
+        },
+    };
+    build_backend(settings, &endpoint)
+}
+
+fn build_backend(settings: &Settings, endpoint: &BackendEndpoint) -> Result<Box<dyn AiBackend>> {
+    let (base_url, mut http) = match endpoint.kind {
+        BackendKind::Ollama => (
+            endpoint
+                .base_url
+                .as_deref()
+                .unwrap_or(&settings.ollama.base_url),
+            settings.ollama.http.merged(&endpoint.http),
+        ),
+        BackendKind::OpenAi => (
+            endpoint
+                .base_url
+                .as_deref()
+                .unwrap_or(&settings.openai.base_url),
+            settings.openai.http.merged(&endpoint.http),
+        ),
+    };
+
+    if settings.privacy.local_only {
+        enforce_local_only(base_url, &http)?;
+        // Environment proxies could still route local traffic elsewhere
+        http.no_proxy = true;
+    }
+
+    match endpoint.kind {
+        #[cfg(feature = "ollama")]
+        BackendKind::Ollama => {
+            let ollama = &settings.ollama;
+            Ok(Box::new(
+                OllamaClient::with_endpoint(
+                    base_url,
+                    endpoint.model.as_deref().unwrap_or(&ollama.model),
+                    endpoint.timeout_secs.unwrap_or(ollama.timeout_secs),
+                    &http,
+                )?
+                .with_keep_alive(endpoint.keep_alive.as_deref().unwrap_or(&ollama.keep_alive))
+                .with_sampling(&settings.model)
+                .with_context_reuse(ollama.reuse_context),
+            ))
+        }
+        #[cfg(feature = "openai")]
+        BackendKind::OpenAi => {
+            let openai = &settings.openai;
+            Ok(Box::new(
+                OpenAiClient::with_endpoint(
+                    base_url,
+                    endpoint.model.as_deref().unwrap_or(&openai.model),
+                    endpoint
+                        .api_key_env
+                        .as_deref()
+                        .unwrap_or(&openai.api_key_env),
+                    endpoint.timeout_secs.unwrap_or(openai.timeout_secs),
+                    &http,
+                )?
+                .with_sampling(&settings.model),
+            ))
+        }
+        #[allow(unreachable_patterns)]
+        kind => Err(anyhow::anyhow!(
+            "phloem was built without the {kind} backend; rebuild it with `--features {kind}`",
+            kind = kind.as_str()
+        )),
+    }
+}
+
+/// Refuses backends that would send prompts off this machine when `privacy.local_only` is set
+fn enforce_local_only(base_url: &str, http: &HttpOptions) -> Result<()> {
+    let url = Url::parse(base_url).with_context(|| format!("Invalid backend URL: {base_url}"))?;
+
+    if !is_local_url(&url) {
+        return Err(anyhow::anyhow!(
+            "privacy.local_only is enabled: refusing to use backend at {base_url} because it is not on this machine. \
+             Point it at localhost/127.0.0.1 or disable local_only in ~/.phloem/config.toml"
+        ));
+    }
+
+    if let Some(proxy) = &http.proxy {
+        return Err(anyhow::anyhow!(
+            "privacy.local_only is enabled: refusing to send requests through proxy {proxy}"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Whether a URL points at this machine
+pub fn is_local_url(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Domain(domain)) => domain == "localhost",
+        Some(Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    }
+}
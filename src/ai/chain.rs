@@ -0,0 +1,191 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use log::{info, warn};
+
+use crate::ai::{
+    AiBackend, Conversation, FragmentCallback, Generation, ModelUpdate, ProgressCallback,
+    SuggestionCallback,
+};
+
+/// Ordered list of backends tried in turn when one is unreachable
+pub struct BackendChain {
+    backends: Vec<Box<dyn AiBackend>>,
+}
+
+impl BackendChain {
+    /// Creates a chain; the first backend is the primary one
+    pub fn new(backends: Vec<Box<dyn AiBackend>>) -> Result<Self> {
+        if backends.is_empty() {
+            return Err(anyhow::anyhow!("No AI backends configured"));
+        }
+        Ok(Self { backends })
+    }
+
+    /// All backends in fallback order
+    pub fn backends(&self) -> &[Box<dyn AiBackend>] {
+        &self.backends
+    }
+
+    fn primary(&self) -> &dyn AiBackend {
+        self.backends[0].as_ref()
+    }
+
+    /// First backend that answers a connection check
+    async fn first_reachable(&self) -> Result<&dyn AiBackend> {
+        for backend in &self.backends {
+            if backend.verify_connection().await.is_ok() {
+                return Ok(backend.as_ref());
+            }
+        }
+        Err(anyhow::anyhow!("No reachable AI backend"))
+    }
+
+    /// Label identifying a backend in logs and cache metadata
+    pub fn label(backend: &dyn AiBackend) -> String {
+        format!("{}@{}", backend.name(), backend.endpoint())
+    }
+}
+
+/// Whether an error means the backend could not be reached, so the next one should be tried
+pub fn is_unreachable(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|e| e.is_connect() || e.is_timeout())
+    })
+}
+
+#[async_trait]
+impl AiBackend for BackendChain {
+    fn name(&self) -> &'static str {
+        self.primary().name()
+    }
+
+    fn endpoint(&self) -> String {
+        self.backends
+            .iter()
+            .map(|b| b.endpoint())
+            .collect::<Vec<_>>()
+            .join(" → ")
+    }
+
+    fn model(&self) -> &str {
+        self.primary().model()
+    }
+
+    /// Succeeds if any backend in the chain is reachable
+    async fn verify_connection(&self) -> Result<()> {
+        let mut last_error = None;
+        for backend in &self.backends {
+            match backend.verify_connection().await {
+                Ok(()) => return Ok(()),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No AI backends configured")))
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let mut last_error = None;
+        for backend in &self.backends {
+            match backend.list_models().await {
+                Ok(models) => return Ok(models),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No AI backends configured")))
+    }
+
+    /// Prepares the model on the first reachable backend
+    async fn ensure_model_available(&self, on_progress: ProgressCallback<'_>) -> Result<()> {
+        self.first_reachable()
+            .await?
+            .ensure_model_available(on_progress)
+            .await
+    }
+
+    /// Pulls the model again on the first reachable backend
+    async fn update_model(&self, on_progress: ProgressCallback<'_>) -> Result<Option<ModelUpdate>> {
+        self.first_reachable()
+            .await?
+            .update_model(on_progress)
+            .await
+    }
+
+    /// Warms up the first reachable backend
+    async fn warmup(&self) -> Result<()> {
+        self.first_reachable().await?.warmup().await
+    }
+
+    /// Embeds on the first backend that supports embeddings
+    async fn embed(&self, model: &str, text: &str) -> Result<Vec<f32>> {
+        let mut last_error = None;
+        for backend in &self.backends {
+            match backend.embed(model, text).await {
+                Ok(embedding) => return Ok(embedding),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No AI backends configured")))
+    }
+
+    /// Completes on the first backend that can be reached
+    async fn complete(
+        &self,
+        conversation: &Conversation,
+        on_fragment: FragmentCallback<'_>,
+    ) -> Result<String> {
+        let mut last_error = None;
+
+        for backend in &self.backends {
+            match backend.complete(conversation, on_fragment).await {
+                Ok(reply) => return Ok(reply),
+                Err(e) if is_unreachable(&e) => {
+                    warn!(
+                        "Backend {} unreachable, trying next: {e}",
+                        Self::label(backend.as_ref())
+                    );
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No AI backends configured")))
+    }
+
+    /// Tries each backend in order, moving on only on connection failures or timeouts
+    async fn generate_suggestions(
+        &self,
+        conversation: &mut Conversation,
+        max_suggestions: usize,
+        on_suggestion: SuggestionCallback<'_>,
+    ) -> Result<Generation> {
+        let mut last_error = None;
+
+        for backend in &self.backends {
+            let label = Self::label(backend.as_ref());
+
+            match backend
+                .generate_suggestions(conversation, max_suggestions, on_suggestion)
+                .await
+            {
+                Ok(mut generation) => {
+                    info!("Suggestions served by {label}");
+                    for suggestion in &mut generation.suggestions {
+                        suggestion.backend = Some(label.clone());
+                    }
+                    generation.backend = Some(label);
+                    return Ok(generation);
+                }
+                Err(e) if is_unreachable(&e) => {
+                    warn!("Backend {label} unreachable, trying next: {e}");
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No AI backends configured")))
+    }
+}
@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+}
+
+/// Message history of one suggestion session, so follow-ups keep their context
+#[derive(Debug, Clone, Default)]
+pub struct Conversation {
+    messages: Vec<ChatMessage>,
+}
+
+impl Conversation {
+    /// Starts a conversation with the given system instructions
+    pub fn new(system_prompt: impl Into<String>) -> Self {
+        Self {
+            messages: vec![ChatMessage {
+                role: Role::System,
+                content: system_prompt.into(),
+            }],
+        }
+    }
+
+    pub fn push_user(&mut self, content: impl Into<String>) {
+        self.push(Role::User, content);
+    }
+
+    pub fn push_assistant(&mut self, content: impl Into<String>) {
+        self.push(Role::Assistant, content);
+    }
+
+    fn push(&mut self, role: Role, content: impl Into<String>) {
+        self.messages.push(ChatMessage {
+            role,
+            content: content.into(),
+        });
+    }
+
+    pub fn messages(&self) -> &[ChatMessage] {
+        &self.messages
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Drops messages added after `len`, e.g. to undo a failed turn
+    pub fn truncate(&mut self, len: usize) {
+        self.messages.truncate(len);
+    }
+}
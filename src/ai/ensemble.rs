@@ -0,0 +1,123 @@
+use anyhow::Result;
+use futures_util::future::join_all;
+use log::{info, warn};
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use crate::ai::{AiBackend, BackendChain, Conversation, Generation, SuggestionCallback};
+use crate::cli::Suggestion;
+
+/// Models asked the same question at once, whose suggestions are merged
+pub struct ModelEnsemble {
+    backends: Vec<Box<dyn AiBackend>>,
+}
+
+impl ModelEnsemble {
+    pub fn new(backends: Vec<Box<dyn AiBackend>>) -> Result<Self> {
+        if backends.is_empty() {
+            return Err(anyhow::anyhow!("No models to ask"));
+        }
+        Ok(Self { backends })
+    }
+
+    /// The models asked, in the order they're configured
+    pub fn models(&self) -> Vec<&str> {
+        self.backends
+            .iter()
+            .map(|backend| backend.model())
+            .collect()
+    }
+
+    /// Asks every model for suggestions on its own copy of the conversation
+    /// and merges what they suggest, each command once with the highest
+    /// confidence given to it; the list is for the caller to rank and cut
+    /// down. The conversation continues with the reply of the first model
+    /// that answered, and fails only when none did
+    pub async fn generate_suggestions(
+        &self,
+        conversation: &mut Conversation,
+        max_suggestions: usize,
+        on_suggestion: SuggestionCallback<'_>,
+    ) -> Result<Generation> {
+        // Commands streaming in from more than one model are listed once
+        let shown = Mutex::new(HashSet::new());
+        let show_new = |suggestion: &Suggestion| {
+            let new = shown
+                .lock()
+                .map(|mut shown| shown.insert(suggestion.command.trim().to_string()))
+                .unwrap_or(true);
+            if new {
+                on_suggestion(suggestion);
+            }
+        };
+
+        let mut conversations = vec![conversation.clone(); self.backends.len()];
+        let results = join_all(self.backends.iter().zip(conversations.iter_mut()).map(
+            |(backend, conversation)| {
+                backend.generate_suggestions(conversation, max_suggestions, &show_new)
+            },
+        ))
+        .await;
+
+        let mut merged: Option<(Generation, Conversation)> = None;
+        let mut last_error = None;
+        for ((backend, result), answered) in self.backends.iter().zip(results).zip(conversations) {
+            let label = BackendChain::label(backend.as_ref());
+            let mut generation = match result {
+                Ok(generation) => generation,
+                Err(e) => {
+                    warn!("Model {} failed to answer: {e}", backend.model());
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            info!(
+                "{} suggestions from {}",
+                generation.suggestions.len(),
+                backend.model()
+            );
+            for suggestion in &mut generation.suggestions {
+                suggestion.backend = Some(label.clone());
+            }
+
+            match &mut merged {
+                None => {
+                    generation.backend = Some(label);
+                    merged = Some((generation, answered));
+                }
+                Some((first, _)) => {
+                    for suggestion in generation.suggestions {
+                        let command = suggestion.command.trim();
+                        match first
+                            .suggestions
+                            .iter_mut()
+                            .find(|known| known.command.trim() == command)
+                        {
+                            Some(known) => {
+                                known.confidence = known.confidence.max(suggestion.confidence)
+                            }
+                            None => first.suggestions.push(suggestion),
+                        }
+                    }
+                    for tool in generation.missing {
+                        if !first
+                            .missing
+                            .iter()
+                            .any(|known| known.suggestion.command == tool.suggestion.command)
+                        {
+                            first.missing.push(tool);
+                        }
+                    }
+                    if first.clarify.is_none() {
+                        first.clarify = generation.clarify;
+                    }
+                }
+            }
+        }
+
+        let (generation, answered) = merged
+            .ok_or_else(|| last_error.unwrap_or_else(|| anyhow::anyhow!("No models to ask")))?;
+        *conversation = answered;
+        Ok(generation)
+    }
+}
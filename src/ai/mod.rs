@@ -1,7 +1,24 @@
+pub mod backend;
+pub mod chain;
+pub mod conversation;
+pub mod ensemble;
+#[cfg(feature = "ollama")]
 pub mod ollama_client;
+#[cfg(feature = "openai")]
+pub mod openai_client;
 pub mod prompt;
 pub mod response;
 
+pub use backend::{
+    create_backend, create_backend_for_model, AiBackend, FragmentCallback, Generation, ModelUpdate,
+    ProgressCallback, PullProgress, SuggestionCallback,
+};
+pub use chain::{is_unreachable, BackendChain};
+pub use conversation::{ChatMessage, Conversation, Role};
+pub use ensemble::ModelEnsemble;
+#[cfg(feature = "ollama")]
 pub use ollama_client::OllamaClient;
+#[cfg(feature = "openai")]
+pub use openai_client::OpenAiClient;
 pub use prompt::PromptBuilder;
-pub use response::ResponseParser;
+pub use response::{MissingTool, ParseOutcome, ParsedReply, ResponseParser, StreamingParser};
@@ -1,53 +1,103 @@
 // External dependencies
 use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures_util::StreamExt;
 use log::{debug, info, warn};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
 use url::Url;
 
 // Internal dependencies
-use crate::cli::Suggestion;
+use crate::ai::{
+    backend, AiBackend, ChatMessage, Conversation, FragmentCallback, ModelUpdate, ProgressCallback,
+    PullProgress, ResponseParser, Role,
+};
+use crate::config::settings::{HttpOptions, ModelConfig};
 use crate::config::Settings;
-use crate::context::ContextData;
+use crate::utils::{EnvironmentDetector, HardwareInfo};
 
 // ============================================================================
-// JSON Response Structures
+// Ollama API Structures
 // ============================================================================
 
-#[derive(Debug, Deserialize)]
-struct CommandSuggestion {
-    command: String,
-    explanation: String,
+#[derive(Debug, Serialize)]
+struct OllamaGenerateRequest<'a> {
+    model: String,
+    prompt: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<&'a str>,
+    /// Tokens of the exchange this continues, as returned with its reply
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<&'a [i64]>,
+    stream: bool,
+    format: Option<serde_json::Value>,
+    options: HashMap<String, serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Deserialize)]
-struct CommandsResponse {
-    commands: Vec<CommandSuggestion>,
+struct OllamaGenerateResponse {
+    #[serde(default)]
+    response: String,
+    done: bool,
+    /// Sent with the last event of a stream
+    context: Option<Vec<i64>>,
 }
 
-// ============================================================================
-// Ollama API Structures
-// ============================================================================
-
 #[derive(Debug, Serialize)]
-struct OllamaGenerateRequest {
+struct OllamaChatRequest<'a> {
     model: String,
-    prompt: String,
+    messages: &'a [ChatMessage],
     stream: bool,
-    format: Option<String>,
+    format: Option<serde_json::Value>,
     options: HashMap<String, serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Deserialize)]
-struct OllamaGenerateResponse {
-    response: String,
+struct OllamaChatMessage {
+    #[serde(default)]
+    content: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaChatResponse {
+    message: Option<OllamaChatMessage>,
     done: bool,
 }
 
+#[derive(Debug, Deserialize)]
+struct OllamaPullEvent {
+    #[serde(default)]
+    status: String,
+    digest: Option<String>,
+    total: Option<u64>,
+    completed: Option<u64>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct OllamaEmbeddingsRequest<'a> {
+    model: &'a str,
+    prompt: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaEmbeddingsResponse {
+    embedding: Vec<f32>,
+}
+
 #[derive(Debug, Deserialize)]
 struct OllamaModel {
     name: String,
+    #[serde(default)]
+    digest: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -59,6 +109,21 @@ pub struct OllamaClient {
     client: Client,
     base_url: Url,
     model_name: String,
+    keep_alive: Option<serde_json::Value>,
+    sampling: ModelConfig,
+    /// Hardware of this machine, known only when Ollama runs locally
+    hardware: Option<HardwareInfo>,
+    /// Continue follow-ups from `context` instead of sending every message
+    reuse_context: bool,
+    /// Tokens of the last exchange, kept for its follow-up
+    context: Mutex<Option<ContextTokens>>,
+}
+
+/// Tokens Ollama returned for an exchange, with the messages they hold,
+/// the reply included
+struct ContextTokens {
+    messages: Vec<ChatMessage>,
+    tokens: Vec<i64>,
 }
 
 // ============================================================================
@@ -66,56 +131,166 @@ pub struct OllamaClient {
 // ============================================================================
 
 impl OllamaClient {
-    /// Creates a new OllamaClient instance with default configuration
-    pub fn new(_settings: &Settings) -> Result<Self> {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(30))
-            .build()
-            .context("Failed to create HTTP client")?;
+    /// Creates a new OllamaClient instance from the `[ollama]` settings
+    pub fn new(settings: &Settings) -> Result<Self> {
+        let ollama = &settings.ollama;
+        Ok(Self::with_endpoint(
+            &ollama.base_url,
+            &ollama.model,
+            ollama.timeout_secs,
+            &ollama.http,
+        )?
+        .with_keep_alive(&ollama.keep_alive)
+        .with_sampling(&settings.model)
+        .with_context_reuse(ollama.reuse_context))
+    }
 
-        let base_url = Url::parse("http://localhost:11434").context("Invalid Ollama base URL")?;
-        let model_name = "gemma3n:e2b".to_string();
+    /// Sets how long Ollama keeps the model loaded between requests. Ollama
+    /// takes a plain number as seconds but rejects one in a string, so "-1"
+    /// is sent as the number -1
+    pub fn with_keep_alive(mut self, keep_alive: &str) -> Self {
+        let keep_alive = keep_alive.trim();
+        self.keep_alive = match keep_alive.parse::<i64>() {
+            Ok(seconds) => Some(seconds.into()),
+            Err(_) => Some(keep_alive.into()).filter(|_| !keep_alive.is_empty()),
+        };
+        self
+    }
+
+    /// Continues follow-ups from the tokens of the last exchange, so Ollama
+    /// doesn't process the conversation up to them again
+    pub fn with_context_reuse(mut self, reuse_context: bool) -> Self {
+        self.reuse_context = reuse_context;
+        self
+    }
+
+    /// Sets the generation options (temperature, token limit, ...) sent with each request
+    pub fn with_sampling(mut self, sampling: &ModelConfig) -> Self {
+        self.sampling = sampling.clone();
+        self
+    }
+
+    /// Creates a client for an explicit Ollama endpoint and model
+    pub fn with_endpoint(
+        base_url: &str,
+        model: &str,
+        timeout_secs: u64,
+        http: &HttpOptions,
+    ) -> Result<Self> {
+        let client = backend::build_http_client(timeout_secs, http)?;
+
+        let base_url =
+            Url::parse(base_url).with_context(|| format!("Invalid Ollama base URL: {base_url}"))?;
+        let model_name = model.to_string();
+
+        let hardware =
+            backend::is_local_url(&base_url).then(|| EnvironmentDetector::new().detect_hardware());
 
         Ok(Self {
             client,
             base_url,
             model_name,
+            keep_alive: None,
+            sampling: ModelConfig::default(),
+            hardware,
+            reuse_context: false,
+            context: Mutex::new(None),
         })
     }
 
+    /// Returns the base URL of the Ollama service
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    /// Returns the configured model name
+    pub fn model_name(&self) -> &str {
+        &self.model_name
+    }
+
     // ========================================================================
-    // Connection and Model Management
+    // Model Management
     // ========================================================================
 
-    /// Verifies connection to the Ollama service
-    pub async fn verify_connection(&self) -> Result<()> {
-        debug!("Verifying Ollama connection");
-
+    /// Pulls the configured model from Ollama, streaming download progress
+    async fn pull_model(&self, on_progress: ProgressCallback<'_>) -> Result<()> {
         let url = self
             .base_url
-            .join("/api/version")
-            .context("Failed to build version URL")?;
+            .join("/api/pull")
+            .context("Failed to build pull URL")?;
+
+        let request_body = serde_json::json!({
+            "name": self.model_name,
+            "stream": true
+        });
+
+        info!(
+            "Pulling model {}, this may take a while...",
+            self.model_name
+        );
 
+        // Large models can take far longer than the generation timeout
         let response = self
             .client
-            .get(url)
+            .post(url)
+            .timeout(std::time::Duration::from_secs(60 * 60 * 6))
+            .json(&request_body)
             .send()
             .await
-            .context("Failed to connect to Ollama service")?;
+            .context("Failed to start model pull")?;
 
         if !response.status().is_success() {
             return Err(anyhow::anyhow!(
-                "Ollama service returned error: {}",
+                "Failed to pull model: {}",
                 response.status()
             ));
         }
 
-        info!("Ollama connection verified");
+        // Ollama streams one JSON progress event per line
+        let mut stream = response.bytes_stream();
+        let mut pending = Vec::new();
+        let mut succeeded = false;
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.context("Failed to read pull progress")?;
+            pending.extend_from_slice(&chunk);
+
+            while let Some(newline) = pending.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = pending.drain(..=newline).collect();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+
+                let event: OllamaPullEvent =
+                    serde_json::from_slice(&line).context("Failed to parse pull progress event")?;
+
+                if let Some(error) = event.error {
+                    return Err(anyhow::anyhow!("Failed to pull model: {error}"));
+                }
+
+                succeeded |= event.status == "success";
+                on_progress(&PullProgress {
+                    status: event.status,
+                    digest: event.digest,
+                    total: event.total,
+                    completed: event.completed,
+                });
+            }
+        }
+
+        if !succeeded {
+            return Err(anyhow::anyhow!(
+                "Model pull for {} ended before completing",
+                self.model_name
+            ));
+        }
+
+        info!("Model {} pulled successfully", self.model_name);
         Ok(())
     }
 
-    /// Lists all available models from the Ollama service
-    pub async fn list_models(&self) -> Result<Vec<String>> {
+    /// Models Ollama has downloaded
+    async fn installed_models(&self) -> Result<Vec<OllamaModel>> {
         debug!("Listing available models");
 
         let url = self
@@ -142,105 +317,196 @@ impl OllamaClient {
             .await
             .context("Failed to parse models response")?;
 
-        let model_names: Vec<String> = models_response.models.into_iter().map(|m| m.name).collect();
+        debug!("Found {} models", models_response.models.len());
+        Ok(models_response.models)
+    }
 
-        debug!("Found {} models", model_names.len());
-        Ok(model_names)
+    /// Digest of the configured model; `None` when it isn't downloaded
+    async fn model_digest(&self) -> Result<Option<String>> {
+        Ok(self
+            .installed_models()
+            .await?
+            .into_iter()
+            .find(|m| m.name == self.model_name)
+            .and_then(|m| m.digest))
     }
 
-    /// Ensures the configured model is available, pulling it if necessary
-    pub async fn ensure_model_available(&self) -> Result<()> {
-        debug!("Ensuring model {} is available", self.model_name);
+    // ========================================================================
+    // Text Generation
+    // ========================================================================
 
-        let models = self.list_models().await?;
+    /// Sampling and hardware options sent with each generation
+    fn options(&self) -> HashMap<String, serde_json::Value> {
+        let sampling = &self.sampling;
+        let mut options = HashMap::new();
+        options.insert(
+            "temperature".to_string(),
+            serde_json::Value::from(sampling.temperature),
+        );
+        options.insert("top_k".to_string(), serde_json::Value::from(sampling.top_k));
+        options.insert("top_p".to_string(), serde_json::Value::from(sampling.top_p));
+        options.insert(
+            "num_predict".to_string(),
+            serde_json::Value::from(sampling.max_tokens),
+        );
+        if let Some(num_ctx) = sampling.num_ctx {
+            options.insert("num_ctx".to_string(), serde_json::Value::from(num_ctx));
+        }
 
-        if models.contains(&self.model_name) {
-            info!("Model {} already available", self.model_name);
-            return Ok(());
+        // Without a GPU, skip offload attempts and use every physical core
+        let cpu_only = self.hardware.as_ref().filter(|h| h.accelerator.is_none());
+        if let Some(num_gpu) = sampling.num_gpu.or(cpu_only.map(|_| 0)) {
+            options.insert("num_gpu".to_string(), serde_json::Value::from(num_gpu));
+        }
+        if let Some(num_thread) = sampling.num_thread.or(cpu_only.map(|h| h.cpu_cores as u32)) {
+            options.insert(
+                "num_thread".to_string(),
+                serde_json::Value::from(num_thread),
+            );
         }
 
-        info!("Model {} not found, pulling...", self.model_name);
-        self.pull_model().await
+        options
     }
 
-    /// Pulls the specified model from Ollama
-    async fn pull_model(&self) -> Result<()> {
+    /// Streams a chat request, passing each text fragment to `on_fragment`
+    /// and returning the complete reply text
+    async fn generate_text(
+        &self,
+        conversation: &Conversation,
+        on_fragment: FragmentCallback<'_>,
+    ) -> Result<String> {
         let url = self
             .base_url
-            .join("/api/pull")
-            .context("Failed to build pull URL")?;
+            .join("/api/chat")
+            .context("Failed to build chat URL")?;
 
-        let request_body = serde_json::json!({
-            "name": self.model_name
-        });
+        let request = OllamaChatRequest {
+            model: self.model_name.clone(),
+            messages: conversation.messages(),
+            stream: true,
+            format: Some(ResponseParser::response_schema()),
+            options: self.options(),
+            keep_alive: self.keep_alive.clone(),
+        };
 
-        info!(
-            "Pulling model {}, this may take a while...",
-            self.model_name
+        debug!(
+            "Sending chat request to Ollama with {} messages",
+            conversation.len()
         );
 
         let response = self
             .client
             .post(url)
-            .json(&request_body)
+            .json(&request)
             .send()
             .await
-            .context("Failed to start model pull")?;
+            .context("Failed to send chat request")?;
 
         if !response.status().is_success() {
             return Err(anyhow::anyhow!(
-                "Failed to pull model: {}",
+                "Chat request failed: {}",
                 response.status()
             ));
         }
 
-        // Note: In production, we'd stream the response and show progress
-        info!("Model {} pulled successfully", self.model_name);
-        Ok(())
+        // Ollama streams one JSON object per line
+        let mut stream = response.bytes_stream();
+        let mut pending = Vec::new();
+        let mut text = String::new();
+        let mut done = false;
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.context("Failed to read chat stream")?;
+            pending.extend_from_slice(&chunk);
+
+            while let Some(newline) = pending.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = pending.drain(..=newline).collect();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+
+                let event: OllamaChatResponse =
+                    serde_json::from_slice(&line).context("Failed to parse chat stream event")?;
+
+                if let Some(message) = event.message {
+                    on_fragment(&message.content);
+                    text.push_str(&message.content);
+                }
+                done = event.done;
+            }
+        }
+
+        if !done {
+            warn!("Generation was not completed");
+        }
+
+        debug!("Generated response length: {}", text.len());
+        Ok(text)
     }
 
-    // ========================================================================
-    // Suggestion Generation
-    // ========================================================================
+    /// The turn to send through `/api/generate` so its context tokens come
+    /// back: the system prompt and prompt of a new conversation, or the
+    /// prompt of a follow-up with the tokens of the exchange before it.
+    /// `None` sends the whole conversation as a chat instead
+    fn continuation<'a>(&self, conversation: &'a Conversation) -> Option<Continuation<'a>> {
+        if !self.reuse_context {
+            return None;
+        }
 
-    /// Generates command suggestions based on user prompt and context
-    pub async fn generate_suggestions(
-        &self,
-        prompt: &str,
-        context: &ContextData,
-        max_suggestions: usize,
-    ) -> Result<Vec<Suggestion>> {
-        debug!("Generating suggestions for prompt: {prompt}");
-
-        let enhanced_prompt = self.build_enhanced_prompt(prompt, context);
-        let response = self.generate_text(&enhanced_prompt).await?;
-        let suggestions = self.parse_response(&response, max_suggestions);
-
-        info!("Generated {} suggestions", suggestions.len());
-        Ok(suggestions)
+        match conversation.messages() {
+            [system, prompt] if system.role == Role::System && prompt.role == Role::User => {
+                Some(Continuation {
+                    system: Some(&system.content),
+                    prompt: &prompt.content,
+                    context: None,
+                })
+            }
+            [earlier @ .., prompt] if prompt.role == Role::User => {
+                let context = self.context.lock().unwrap_or_else(PoisonError::into_inner);
+                let context = context
+                    .as_ref()
+                    .filter(|context| context.messages == earlier)?;
+                Some(Continuation {
+                    system: None,
+                    prompt: &prompt.content,
+                    context: Some(context.tokens.clone()),
+                })
+            }
+            _ => None,
+        }
     }
 
-    async fn generate_text(&self, prompt: &str) -> Result<String> {
+    /// Streams `turn` through `/api/generate`, keeping the context tokens
+    /// that come back for the next follow-up of `conversation`
+    async fn generate_continued(
+        &self,
+        conversation: &Conversation,
+        turn: Continuation<'_>,
+        on_fragment: FragmentCallback<'_>,
+    ) -> Result<String> {
         let url = self
             .base_url
             .join("/api/generate")
             .context("Failed to build generate URL")?;
 
-        let mut options = HashMap::new();
-        options.insert("temperature".to_string(), serde_json::Value::from(0.0));
-        options.insert("top_k".to_string(), serde_json::Value::from(40));
-        options.insert("top_p".to_string(), serde_json::Value::from(0.9));
-        options.insert("num_predict".to_string(), serde_json::Value::from(200));
-
         let request = OllamaGenerateRequest {
             model: self.model_name.clone(),
-            prompt: prompt.to_string(),
-            stream: false,
-            format: Some("json".to_string()),
-            options,
+            prompt: turn.prompt,
+            system: turn.system,
+            context: turn.context.as_deref(),
+            stream: true,
+            format: Some(ResponseParser::response_schema()),
+            options: self.options(),
+            keep_alive: self.keep_alive.clone(),
         };
 
-        debug!("Sending request to Ollama, prompt length: {}", prompt.len());
+        debug!(
+            "Sending generate request to Ollama{}",
+            match &turn.context {
+                Some(tokens) => format!(" continuing from {} context tokens", tokens.len()),
+                None => String::new(),
+            }
+        );
 
         let response = self
             .client
@@ -257,241 +523,237 @@ impl OllamaClient {
             ));
         }
 
-        let generate_response: OllamaGenerateResponse = response
-            .json()
-            .await
-            .context("Failed to parse generate response")?;
+        let mut stream = response.bytes_stream();
+        let mut pending = Vec::new();
+        let mut text = String::new();
+        let mut tokens = None;
+        let mut done = false;
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.context("Failed to read generate stream")?;
+            pending.extend_from_slice(&chunk);
 
-        if !generate_response.done {
+            while let Some(newline) = pending.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = pending.drain(..=newline).collect();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+
+                let event: OllamaGenerateResponse = serde_json::from_slice(&line)
+                    .context("Failed to parse generate stream event")?;
+
+                on_fragment(&event.response);
+                text.push_str(&event.response);
+                if event.context.is_some() {
+                    tokens = event.context;
+                }
+                done = event.done;
+            }
+        }
+
+        if !done {
             warn!("Generation was not completed");
         }
 
-        debug!(
-            "Generated response length: {}",
-            generate_response.response.len()
-        );
-        Ok(generate_response.response)
+        let mut messages = conversation.messages().to_vec();
+        messages.push(ChatMessage {
+            role: Role::Assistant,
+            content: text.clone(),
+        });
+        *self.context.lock().unwrap_or_else(PoisonError::into_inner) =
+            tokens.map(|tokens| ContextTokens { messages, tokens });
+
+        debug!("Generated response length: {}", text.len());
+        Ok(text)
     }
+}
 
-    fn build_enhanced_prompt(&self, user_prompt: &str, context: &ContextData) -> String {
-        let environment = &context.environment;
-        let recent_commands = &context.recent_commands;
-        let context_content = &context.content;
-
-        let available_tools = &environment
-            .get("available_tools")
-            .map_or("basic".to_string(), |v| {
-                v.split(',').take(20).collect::<Vec<_>>().join(", ")
-            });
-
-        let mut prompt = format!(
-            r#"Generate ONLY valid shell commands for: {}
-
-OS: {} | Shell: {} 
-AVAILABLE EXECUTABLES: {}
-Recent Commands: {}
-
-CRITICAL - Commands MUST:
-1. Use ONLY executables listed above that exist in PATH
-2. Start with a real command name, not pseudo-commands
-3. Use proper shell syntax
-4. Be directly runnable
-
-IMPORTANT: If "lazygit" is in available executables, suggest "lazygit" not installation commands.
-
-"#,
-            user_prompt,
-            environment.get("os").map_or("unknown", |v| v.as_str()),
-            environment.get("shell").map_or("unknown", |v| v.as_str()),
-            available_tools,
-            recent_commands
-                .iter()
-                .take(2)
-                .map(|cmd| cmd.split_whitespace().next().unwrap_or(""))
-                .collect::<Vec<_>>()
-                .join(",")
-        );
+/// One turn sent through `/api/generate`
+struct Continuation<'a> {
+    system: Option<&'a str>,
+    prompt: &'a str,
+    /// Tokens of the exchange before the prompt
+    context: Option<Vec<i64>>,
+}
 
-        // Add learned context from PHLOEM.md if available
-        if !context_content.is_empty() {
-            prompt.push_str("\nLEARNED PATTERNS (use for reference):\n");
-            prompt.push_str(
-                &context_content
-                    .lines()
-                    .filter(|line| line.contains("→") || line.contains("✓"))
-                    .take(10) // Limit to prevent prompt explosion
-                    .collect::<Vec<_>>()
-                    .join("\n"),
-            );
-            prompt.push('\n');
-        }
+// ============================================================================
+// Backend Implementation
+// ============================================================================
 
-        prompt.push_str(
-            r#"
-RESPONSE FORMAT - Return JSON exactly like this:
-{
-  "commands": [
-    {"command": "actual_executable_command", "explanation": "brief description"},
-    {"command": "another_command", "explanation": "brief description"}
-  ]
-}
+#[async_trait]
+impl AiBackend for OllamaClient {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
 
-Generate maximum 3 commands in this JSON format:"#,
-        );
+    fn endpoint(&self) -> String {
+        self.base_url.to_string()
+    }
 
-        prompt
+    fn model(&self) -> &str {
+        &self.model_name
     }
 
-    fn parse_response(&self, response: &str, max_suggestions: usize) -> Vec<Suggestion> {
-        debug!("Parsing JSON response: {response}");
-
-        // Try to parse as JSON first
-        match serde_json::from_str::<CommandsResponse>(response) {
-            Ok(commands_response) => {
-                let mut suggestions = Vec::new();
-
-                for cmd_suggestion in commands_response.commands.into_iter().take(max_suggestions) {
-                    if self.is_valid_command(&cmd_suggestion.command) {
-                        suggestions.push(Suggestion {
-                            command: cmd_suggestion.command,
-                            explanation: Some(cmd_suggestion.explanation),
-                            confidence: 0.8,
-                        });
-                    } else {
-                        debug!("Invalid command rejected: {}", cmd_suggestion.command);
-                    }
-                }
+    /// Verifies connection to the Ollama service
+    async fn verify_connection(&self) -> Result<()> {
+        debug!("Verifying Ollama connection");
 
-                if !suggestions.is_empty() {
-                    return suggestions;
-                }
-            }
-            Err(e) => {
-                debug!("JSON parsing failed: {e}, trying fallback");
-            }
+        let url = self
+            .base_url
+            .join("/api/version")
+            .context("Failed to build version URL")?;
+
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .context("Failed to connect to Ollama service")?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Ollama service returned error: {}",
+                response.status()
+            ));
         }
 
-        // Fallback: try to extract commands from text response
-        self.extract_commands_fallback(response, max_suggestions)
+        info!("Ollama connection verified");
+        Ok(())
+    }
+
+    /// Lists all available models from the Ollama service
+    async fn list_models(&self) -> Result<Vec<String>> {
+        Ok(self
+            .installed_models()
+            .await?
+            .into_iter()
+            .map(|m| m.name)
+            .collect())
     }
 
-    fn extract_commands_fallback(&self, response: &str, max_suggestions: usize) -> Vec<Suggestion> {
-        let mut suggestions = Vec::new();
+    /// Re-pulls the model; Ollama only downloads layers that changed
+    async fn update_model(&self, on_progress: ProgressCallback<'_>) -> Result<Option<ModelUpdate>> {
+        let previous = self.model_digest().await?;
+        self.pull_model(on_progress).await?;
+        let current = self
+            .model_digest()
+            .await?
+            .with_context(|| format!("Model {} is missing after pulling it", self.model_name))?;
 
-        for line in response.lines() {
-            let line = line.trim();
+        Ok(Some(ModelUpdate { previous, current }))
+    }
 
-            // Skip empty lines and obvious non-commands
-            if line.is_empty() || line.starts_with('#') || line.len() > 200 {
-                continue;
-            }
+    /// Ensures the configured model is available, pulling it if necessary
+    async fn ensure_model_available(&self, on_progress: ProgressCallback<'_>) -> Result<()> {
+        debug!("Ensuring model {} is available", self.model_name);
 
-            // Look for lines that look like commands
-            if self.looks_like_command(line) && self.is_valid_command(line) {
-                suggestions.push(Suggestion {
-                    command: line.to_string(),
-                    explanation: None,
-                    confidence: 0.6,
-                });
+        let models = self.list_models().await?;
 
-                if suggestions.len() >= max_suggestions {
-                    break;
-                }
-            }
+        if models.contains(&self.model_name) {
+            info!("Model {} already available", self.model_name);
+            return Ok(());
         }
 
-        suggestions
+        info!("Model {} not found, pulling...", self.model_name);
+        self.pull_model(on_progress).await
     }
 
-    fn looks_like_command(&self, line: &str) -> bool {
-        // Simple heuristics to identify command-like lines
-        let starts_with_command = line
-            .split_whitespace()
-            .next()
-            .map(|first_word| {
-                // Common command prefixes
-                matches!(
-                    first_word,
-                    "ls" | "cd"
-                        | "grep"
-                        | "find"
-                        | "docker"
-                        | "kubectl"
-                        | "git"
-                        | "curl"
-                        | "wget"
-                        | "ssh"
-                        | "sudo"
-                        | "cp"
-                        | "mv"
-                        | "rm"
-                        | "cat"
-                        | "tail"
-                        | "head"
-                        | "ps"
-                        | "kill"
-                        | "top"
-                        | "df"
-                        | "du"
-                        | "tar"
-                        | "zip"
-                        | "unzip"
-                )
-            })
-            .unwrap_or(false);
-
-        starts_with_command || line.contains("--") || line.contains("|")
-    }
+    /// Loads the model by sending an empty prompt, keeping it resident per `keep_alive`
+    async fn warmup(&self) -> Result<()> {
+        debug!("Warming up model {}", self.model_name);
+
+        let url = self
+            .base_url
+            .join("/api/generate")
+            .context("Failed to build generate URL")?;
 
-    fn is_valid_command(&self, command: &str) -> bool {
-        // Basic safety checks
-        let dangerous_patterns = ["rm -rf /", "rm -rf *", "dd if=", "mkfs", "fdisk", "> /dev/"];
+        let request = OllamaGenerateRequest {
+            model: self.model_name.clone(),
+            prompt: "",
+            system: None,
+            context: None,
+            stream: false,
+            format: None,
+            options: HashMap::new(),
+            keep_alive: self.keep_alive.clone(),
+        };
 
-        for pattern in &dangerous_patterns {
-            if command.contains(pattern) {
-                return false;
-            }
-        }
+        // Loading a large model from disk can exceed the generation timeout
+        let response = self
+            .client
+            .post(url)
+            .timeout(std::time::Duration::from_secs(300))
+            .json(&request)
+            .send()
+            .await
+            .context("Failed to send warmup request")?;
 
-        // Must not be empty and not too long
-        if command.is_empty() || command.len() > 500 {
-            return false;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Warmup request failed: {}",
+                response.status()
+            ));
         }
 
-        // Extract the first word (the executable name)
-        let first_word = command.split_whitespace().next().unwrap_or("").trim();
+        info!("Model {} loaded", self.model_name);
+        Ok(())
+    }
 
-        // Skip shell operators and redirections
-        if first_word.is_empty() || first_word.starts_with('#') {
-            return false;
-        }
+    /// Embeds text through `/api/embeddings`
+    async fn embed(&self, model: &str, text: &str) -> Result<Vec<f32>> {
+        let url = self
+            .base_url
+            .join("/api/embeddings")
+            .context("Failed to build embeddings URL")?;
 
-        // Check if it's executable using 'which' command
-        if let Ok(output) = std::process::Command::new("which").arg(first_word).output() {
-            if output.status.success() {
-                return true;
-            }
+        let request = OllamaEmbeddingsRequest {
+            model,
+            prompt: text,
+            keep_alive: self.keep_alive.clone(),
+        };
+
+        let response = self
+            .client
+            .post(url)
+            .json(&request)
+            .send()
+            .await
+            .context("Failed to send embeddings request")?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Embeddings request failed: {}",
+                response.status()
+            ));
         }
 
-        // Allow shell built-ins and paths
-        if first_word.contains('/')
-            || first_word == "cd"
-            || first_word == "echo"
-            || first_word == "pwd"
-        {
-            return true;
+        let embeddings: OllamaEmbeddingsResponse = response
+            .json()
+            .await
+            .context("Failed to parse embeddings response")?;
+
+        if embeddings.embedding.is_empty() {
+            return Err(anyhow::anyhow!("Model {model} returned an empty embedding"));
         }
 
-        // Reject commands that look like pseudo-commands or APIs
-        let pseudo_patterns = [" query ", " api ", " endpoint ", " service "];
-        for pattern in &pseudo_patterns {
-            if command.to_lowercase().contains(pattern) {
-                return false;
+        debug!(
+            "Embedded prompt into {} dimensions",
+            embeddings.embedding.len()
+        );
+        Ok(embeddings.embedding)
+    }
+
+    async fn complete(
+        &self,
+        conversation: &Conversation,
+        on_fragment: FragmentCallback<'_>,
+    ) -> Result<String> {
+        match self.continuation(conversation) {
+            Some(turn) => {
+                self.generate_continued(conversation, turn, on_fragment)
+                    .await
             }
+            None => self.generate_text(conversation, on_fragment).await,
         }
-
-        // Log unknown commands for debugging
-        log::debug!("Command '{first_word}' not found in PATH");
-        false
     }
 }
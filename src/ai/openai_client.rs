@@ -0,0 +1,222 @@
+// External dependencies
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use log::debug;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+// Internal dependencies
+use crate::ai::{backend, AiBackend, ChatMessage, Conversation, FragmentCallback, ResponseParser};
+use crate::config::settings::{HttpOptions, ModelConfig};
+
+// ============================================================================
+// OpenAI-compatible API Structures
+// ============================================================================
+
+#[derive(Debug, Serialize)]
+struct ChatCompletionRequest<'a> {
+    model: String,
+    messages: &'a [ChatMessage],
+    temperature: f32,
+    top_p: f32,
+    max_tokens: u32,
+    response_format: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionMessage {
+    content: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionChoice {
+    message: ChatCompletionMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionResponse {
+    choices: Vec<ChatCompletionChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiModel {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiModelsResponse {
+    data: Vec<OpenAiModel>,
+}
+
+/// Client for OpenAI and OpenAI-compatible chat completion APIs
+pub struct OpenAiClient {
+    client: Client,
+    base_url: Url,
+    model_name: String,
+    api_key: Option<String>,
+    sampling: ModelConfig,
+}
+
+// ============================================================================
+// Client Implementation
+// ============================================================================
+
+impl OpenAiClient {
+    /// Creates a client for the given endpoint, reading the API key from `api_key_env`
+    pub fn with_endpoint(
+        base_url: &str,
+        model: &str,
+        api_key_env: &str,
+        timeout_secs: u64,
+        http: &HttpOptions,
+    ) -> Result<Self> {
+        let client = backend::build_http_client(timeout_secs, http)?;
+
+        // Relative joins need a trailing slash to keep the path prefix (e.g. /v1)
+        let normalized = format!("{}/", base_url.trim_end_matches('/'));
+        let base_url = Url::parse(&normalized)
+            .with_context(|| format!("Invalid OpenAI base URL: {base_url}"))?;
+
+        let api_key = std::env::var(api_key_env).ok().filter(|k| !k.is_empty());
+        if api_key.is_none() {
+            debug!("No API key found in ${api_key_env}");
+        }
+
+        Ok(Self {
+            client,
+            base_url,
+            model_name: model.to_string(),
+            api_key,
+            sampling: ModelConfig::default(),
+        })
+    }
+
+    /// Sets the generation options (temperature, token limit, ...) sent with each request
+    pub fn with_sampling(mut self, sampling: &ModelConfig) -> Self {
+        self.sampling = sampling.clone();
+        self
+    }
+
+    fn authorized(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.api_key {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        }
+    }
+}
+
+// ============================================================================
+// Backend Implementation
+// ============================================================================
+
+#[async_trait]
+impl AiBackend for OpenAiClient {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn endpoint(&self) -> String {
+        self.base_url.to_string()
+    }
+
+    fn model(&self) -> &str {
+        &self.model_name
+    }
+
+    /// Verifies the API is reachable and the credentials are accepted
+    async fn verify_connection(&self) -> Result<()> {
+        self.list_models().await.map(|_| ())
+    }
+
+    /// Lists all models available to the configured API key
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let url = self
+            .base_url
+            .join("models")
+            .context("Failed to build models URL")?;
+
+        let response = self
+            .authorized(self.client.get(url))
+            .send()
+            .await
+            .context("Failed to connect to OpenAI service")?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Failed to list models: {}",
+                response.status()
+            ));
+        }
+
+        let models_response: OpenAiModelsResponse = response
+            .json()
+            .await
+            .context("Failed to parse models response")?;
+
+        Ok(models_response.data.into_iter().map(|m| m.id).collect())
+    }
+
+    /// Completes the conversation; the full reply is reported at once
+    async fn complete(
+        &self,
+        conversation: &Conversation,
+        on_fragment: FragmentCallback<'_>,
+    ) -> Result<String> {
+        let url = self
+            .base_url
+            .join("chat/completions")
+            .context("Failed to build chat completions URL")?;
+
+        let request = ChatCompletionRequest {
+            model: self.model_name.clone(),
+            messages: conversation.messages(),
+            temperature: self.sampling.temperature,
+            top_p: self.sampling.top_p,
+            max_tokens: self.sampling.max_tokens,
+            response_format: serde_json::json!({
+                "type": "json_schema",
+                "json_schema": {
+                    "name": "commands",
+                    "strict": true,
+                    "schema": ResponseParser::response_schema()
+                }
+            }),
+        };
+
+        debug!(
+            "Sending request to OpenAI with {} messages",
+            conversation.len()
+        );
+
+        let response = self
+            .authorized(self.client.post(url))
+            .json(&request)
+            .send()
+            .await
+            .context("Failed to send chat completion request")?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Chat completion request failed: {}",
+                response.status()
+            ));
+        }
+
+        let completion: ChatCompletionResponse = response
+            .json()
+            .await
+            .context("Failed to parse chat completion response")?;
+
+        let text = completion
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .unwrap_or_default();
+
+        debug!("Generated response length: {}", text.len());
+        on_fragment(&text);
+        Ok(text)
+    }
+}
@@ -1,20 +1,321 @@
+use crate::ai::ResponseParser;
+use crate::context::learning::is_pattern_line;
 use crate::context::ContextData;
+use crate::utils::{SecretRedactor, ShellKind};
 
-pub struct PromptBuilder;
-
-impl Default for PromptBuilder {
-    fn default() -> Self {
-        Self::new()
-    }
+#[derive(Default)]
+pub struct PromptBuilder {
+    redactor: SecretRedactor,
 }
 
 impl PromptBuilder {
     pub fn new() -> Self {
-        Self
+        Self::default()
+    }
+
+    /// Masks secrets matching the built-in and configured patterns in every message
+    pub fn with_redactor(redactor: SecretRedactor) -> Self {
+        Self { redactor }
+    }
+
+    /// Builds the system instructions for a session from the gathered context,
+    /// asking for up to `max_commands` alternatives per answer
+    pub fn build_system_prompt(&self, context: &ContextData, max_commands: usize) -> String {
+        let environment = &context.environment;
+        let recent_commands = &context.recent_commands;
+        let context_content = &context.content;
+
+        let available_tools = &environment
+            .get("available_tools")
+            .map_or("basic".to_string(), |v| {
+                v.split(',').take(20).collect::<Vec<_>>().join(", ")
+            });
+
+        let mut prompt = format!(
+            r#"You translate natural language requests into shell commands. Generate ONLY valid shell commands.
+
+OS: {} | Shell: {}
+AVAILABLE EXECUTABLES: {}
+Recent Commands: {}
+
+CRITICAL - Commands MUST:
+1. Use ONLY executables listed above that exist in PATH
+2. Start with a real command name, not pseudo-commands
+3. Use proper shell syntax
+4. Be directly runnable
+
+IMPORTANT: If "lazygit" is in available executables, suggest "lazygit" not installation commands.
+
+"#,
+            environment.get("os").map_or("unknown", |v| v.as_str()),
+            context.target_shell.map_or_else(
+                || environment.get("shell").map_or("unknown", |v| v.as_str()),
+                |shell| shell.as_str()
+            ),
+            available_tools,
+            recent_commands
+                .iter()
+                .take(2)
+                .map(|cmd| cmd.split_whitespace().next().unwrap_or(""))
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+
+        // Commands for another shell than the one the user is in, e.g. for a script
+        if let Some(shell) = context.target_shell {
+            prompt.push_str(&format!(
+                "TARGET SHELL: {shell} - write every command in {shell} syntax, even where the user's own shell ({}) differs\n",
+                environment.get("shell").map_or("unknown", |v| v.as_str()),
+                shell = shell.as_str()
+            ));
+        }
+
+        // Syntax of the shell the commands are for, so PowerShell and cmd
+        // users get idiomatic commands rather than translated bash
+        let shell = context.target_shell.unwrap_or_else(|| {
+            environment
+                .get("shell")
+                .map_or(ShellKind::Bash, |shell| ShellKind::from_name(shell))
+        });
+        let guidance = shell.profile().guidance;
+        if !guidance.is_empty() {
+            prompt.push_str(&format!("{} SYNTAX:\n", shell.as_str().to_uppercase()));
+            for rule in guidance {
+                prompt.push_str(&format!("- {rule}\n"));
+            }
+        }
+
+        // Over SSH the machine at hand isn't the user's own; its tools and OS are what count
+        if environment.contains_key("ssh_session") {
+            prompt.push_str(&format!(
+                "REMOTE SESSION: the user is logged in over SSH to {}; suggest commands for this machine, not their local one\n",
+                environment.get("host").map_or("a remote host", |v| v.as_str())
+            ));
+        }
+
+        // Repository state, so "push this branch" or "continue the rebase" resolve correctly
+        if let Some(git) = &context.git {
+            prompt.push_str(&format!("GIT REPOSITORY: {}\n", git.summary()));
+        }
+
+        // Toolchain of the project, so "run the build" maps to the right one
+        if let Some(project) = &context.project {
+            prompt.push_str(&format!("PROJECT TYPE: {}\n", project.types.join(", ")));
+            if !project.tasks.is_empty() {
+                prompt.push_str(&format!("PROJECT TASKS: {}\n", project.tasks.join(", ")));
+            }
+        }
+
+        // Where kubectl commands would land, so they target the right cluster and namespace
+        if let Some(kubernetes) = &context.kubernetes {
+            prompt.push_str(&format!("KUBERNETES: {}\n", kubernetes.summary()));
+            if !kubernetes.recent_kinds.is_empty() {
+                prompt.push_str(&format!(
+                    "RECENT RESOURCE KINDS: {}\n",
+                    kubernetes.recent_kinds.join(", ")
+                ));
+            }
+            prompt.push_str(&format!(
+                "kubectl commands act on this context and namespace; add `-n {}` explicitly if the learned patterns ask for it.\n",
+                kubernetes.namespace
+            ));
+        }
+
+        // Actual container names, so "restart the api container" needs no placeholders
+        if let Some(containers) = &context.containers {
+            prompt.push_str(&format!(
+                "RUNNING CONTAINERS ({}): {}\n",
+                containers.runtime,
+                containers.summary()
+            ));
+            if !containers.compose_projects.is_empty() {
+                prompt.push_str(&format!(
+                    "COMPOSE PROJECTS: {}\n",
+                    containers.compose_projects.join(", ")
+                ));
+            }
+        }
+
+        // Real file names, so "compress the logs folder" needs no placeholders
+        if let Some(directory) = &context.directory {
+            prompt.push_str(&format!("CURRENT DIRECTORY: {}\n", directory.summary()));
+            if !directory.extensions.is_empty() {
+                prompt.push_str(&format!("FILE TYPES: {}\n", directory.extension_summary()));
+            }
+        }
+
+        // The user's own shortcuts; suggesting the long form when they have one feels foreign
+        if !context.aliases.is_empty() {
+            prompt.push_str(
+                "\nALIASES (the user's shortcuts; prefer them over the commands they stand for):\n",
+            );
+            for alias in &context.aliases {
+                prompt.push_str(&format!("{} = {}\n", alias.name, alias.expansion));
+            }
+        }
+
+        // Add learned context from PHLOEM.md if available
+        if !context_content.is_empty() {
+            prompt.push_str("\nLEARNED PATTERNS (use for reference):\n");
+            prompt.push_str(
+                &context_content
+                    .lines()
+                    .filter(|line| is_pattern_line(line))
+                    .take(10) // Already ranked by relevance; cap the count as well
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            );
+            prompt.push('\n');
+        }
+
+        // The strongest signal: commands the user fixed before running them
+        if !context.corrections.is_empty() {
+            prompt.push_str(
+                "\nCORRECTIONS (the user changed these suggestions before running them; follow the corrected form):\n",
+            );
+            for correction in &context.corrections {
+                prompt.push_str(&format!(
+                    "Request: {} → suggested `{}`, user ran `{}`\n",
+                    correction.prompt, correction.original, correction.corrected
+                ));
+            }
+        }
+
+        // Real examples of what worked for this user before
+        if !context.examples.is_empty() {
+            prompt.push_str("\nEXAMPLES (requests this user ran successfully):\n");
+            for example in &context.examples {
+                prompt.push_str(&format!(
+                    "Request: {} → {}\n",
+                    example.prompt,
+                    serde_json::json!({ "command": example.command })
+                ));
+            }
+        }
+
+        // Suggestions this user keeps passing over
+        if !context.avoid.is_empty() {
+            prompt.push_str(
+                "\nAVOID (the user passed over these for similar requests; suggest something different):\n",
+            );
+            for command in &context.avoid {
+                prompt.push_str(&format!("- {command}\n"));
+            }
+        }
+
+        prompt.push_str(
+            r#"
+RESPONSE FORMAT - Return JSON exactly like this:
+{
+  "commands": [
+    {"command": "actual_executable_command", "explanation": "brief description", "confidence": 0.9, "placeholders": []},
+    {"command": "docker restart {{container}}", "explanation": "brief description", "confidence": 0.6, "placeholders": [{"name": "container", "description": "container to restart"}]}
+  ],
+  "clarify": null
+}
+
+"confidence" is your certainty (0.0-1.0) that the command does exactly what was asked.
+When the command needs a value you can't tell from the request or the context (a container, file, branch or host name), write it as {{name}} and describe it in "placeholders"; otherwise leave "placeholders" empty.
+If the request is too ambiguous to answer safely (e.g. which files or how big), return "commands": [] and one short question in "clarify".
+"#,
+        );
+        prompt.push_str(&format!(
+            "Always answer with up to {max_commands} different commands in this JSON format."
+        ));
+
+        self.redactor.redact(&prompt)
+    }
+
+    /// Builds the first user turn of a session
+    pub fn build_user_message(&self, user_prompt: &str) -> String {
+        self.redactor
+            .redact(&format!("Generate commands for: {user_prompt}"))
+    }
+
+    /// Text embedded for semantic cache lookups
+    pub fn build_embedding_input(&self, user_prompt: &str) -> String {
+        self.redactor.redact(user_prompt.trim())
+    }
+
+    /// Builds the system instructions for writing a commit message
+    pub fn build_commit_system_prompt(&self) -> String {
+        r#"You write git commit messages following the Conventional Commits format:
+type(optional scope): short imperative summary
+
+Allowed types: feat, fix, docs, style, refactor, perf, test, build, ci, chore, revert.
+Keep the summary under 72 characters, lowercase after the colon, with no trailing period.
+
+Answer with up to 3 alternatives, each as a complete command that commits with the message:
+git commit -m 'type(scope): summary'
+Use single quotes around the message and never put a single quote inside it.
+
+RESPONSE FORMAT - Return JSON exactly like this:
+{
+  "commands": [
+    {"command": "git commit -m 'feat(cli): add commit command'", "explanation": "why this type and scope", "confidence": 0.9}
+  ]
+}"#
+        .to_string()
+    }
+
+    /// Builds the user turn carrying the staged changes
+    pub fn build_commit_message(&self, staged_diff: &str) -> String {
+        self.redactor.redact(&format!(
+            "Write a commit message for these staged changes:\n\n{staged_diff}"
+        ))
+    }
+
+    /// Builds the turn carrying the user's answer to a clarifying question
+    pub fn build_clarification_message(&self, answer: &str) -> String {
+        self.redactor.redact(&format!(
+            "{answer}\nNow answer with commands in the same JSON format."
+        ))
+    }
+
+    /// Builds the turn asking the model to fix a reply that wasn't valid JSON
+    pub fn build_repair_message(&self) -> String {
+        format!(
+            "Your previous reply was not valid JSON. Return ONLY valid JSON matching this schema, with no other text:\n{}",
+            ResponseParser::response_schema()
+        )
+    }
+
+    /// Builds the first user turn of a session asking for an ordered plan
+    pub fn build_plan_message(&self, user_prompt: &str, max_steps: usize) -> String {
+        self.redactor.redact(&format!(
+            "Plan the steps for: {user_prompt}\n\
+             Return the steps as \"commands\" in the order they must run, one command per step, \
+             each explaining what the step does. Every step runs after the previous one \
+             succeeded, so these are not alternatives and the command limit doesn't apply: \
+             use up to {max_steps} steps."
+        ))
+    }
+
+    /// Builds the turn asking for fixed versions of the commands ShellCheck
+    /// flagged, each with what it found
+    pub fn build_shellcheck_message(&self, flagged: &[(&str, &[String])]) -> String {
+        let commands: Vec<String> = flagged
+            .iter()
+            .map(|(command, warnings)| {
+                format!(
+                    "{command}
+  {}",
+                    warnings.join("\n  ")
+                )
+            })
+            .collect();
+        self.redactor.redact(&format!(
+            "ShellCheck found problems in these commands:\n{}\n\
+             Return each command fixed, in the same order and the same JSON format, \
+             doing the same thing.",
+            commands.join("\n")
+        ))
     }
 
-    pub fn build_enhanced_prompt(&self, user_prompt: &str, _context: &ContextData) -> String {
-        // This is handled by the Python layer, but we can do some preprocessing here
-        format!("User request: {user_prompt}")
+    /// Builds a follow-up turn refining the previous answer
+    pub fn build_followup_message(&self, modification: &str) -> String {
+        self.redactor.redact(&format!(
+            "Refine the commands you suggested: {modification}\nAnswer in the same JSON format."
+        ))
     }
 }
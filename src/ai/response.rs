@@ -1,4 +1,115 @@
-use crate::cli::Suggestion;
+use log::debug;
+use serde::Deserialize;
+
+use crate::cli::{Placeholder, Suggestion, SuggestionSource};
+use crate::utils::{CommandValidator, ExecutableIndex, ShellDetector, ShellKind};
+
+// ============================================================================
+// JSON Response Structures
+// ============================================================================
+
+/// Confidence assumed when the model doesn't report one
+const DEFAULT_CONFIDENCE: f32 = 0.8;
+
+#[derive(Debug, Deserialize)]
+struct CommandSuggestion {
+    command: String,
+    #[serde(default)]
+    explanation: String,
+    confidence: Option<f32>,
+    /// Descriptions of the `{{name}}` placeholders in the command
+    #[serde(default)]
+    placeholders: Vec<Placeholder>,
+}
+
+impl CommandSuggestion {
+    fn into_suggestion(self) -> Suggestion {
+        // The command decides which placeholders there are; the model only describes them
+        let mut placeholders = Placeholder::find(&self.command);
+        for placeholder in &mut placeholders {
+            placeholder.description = self
+                .placeholders
+                .iter()
+                .find(|described| described.name == placeholder.name)
+                .and_then(|described| described.description.clone())
+                .filter(|description| !description.trim().is_empty());
+        }
+
+        Suggestion {
+            command: self.command,
+            placeholders,
+            warnings: Vec::new(),
+            explanation: Some(self.explanation).filter(|e| !e.is_empty()),
+            confidence: self
+                .confidence
+                .filter(|c| c.is_finite())
+                .map_or(DEFAULT_CONFIDENCE, |c| c.clamp(0.0, 1.0)),
+            backend: None,
+            source: SuggestionSource::Model,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CommandsResponse {
+    #[serde(default)]
+    commands: Vec<CommandSuggestion>,
+    /// Question back to the user when the request is too ambiguous to answer
+    #[serde(default)]
+    clarify: Option<String>,
+}
+
+/// A model reply that parsed as JSON
+#[derive(Debug, Clone)]
+pub struct ParsedReply {
+    pub suggestions: Vec<Suggestion>,
+    pub clarify: Option<String>,
+    /// Commands left out only because their executable isn't installed
+    pub missing: Vec<MissingTool>,
+}
+
+/// A suggested command whose executable isn't installed
+#[derive(Debug, Clone)]
+pub struct MissingTool {
+    pub executable: String,
+    pub suggestion: Suggestion,
+}
+
+/// What validation made of a suggested command
+enum CommandCheck {
+    Valid,
+    /// Fine, except that this executable isn't installed
+    Missing(String),
+    Invalid,
+}
+
+impl ParsedReply {
+    /// Whether the reply gives the user anything: commands or a question
+    pub fn is_answer(&self) -> bool {
+        !self.suggestions.is_empty() || self.clarify.is_some()
+    }
+}
+
+/// How a model reply was turned into suggestions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseOutcome {
+    /// The reply was valid JSON on the first try
+    Valid,
+    /// The reply needed a repair round-trip to become valid JSON
+    Repaired,
+    /// Commands were scraped from free text by the line-based fallback
+    Fallback,
+}
+
+impl ParseOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ParseOutcome::Valid => "valid",
+            ParseOutcome::Repaired => "repaired",
+            ParseOutcome::Fallback => "fallback",
+        }
+    }
+}
 
 pub struct ResponseParser;
 
@@ -13,6 +124,61 @@ impl ResponseParser {
         Self
     }
 
+    /// JSON schema the model output must follow, passed to backends that support structured output
+    pub fn response_schema() -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "commands": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "command": { "type": "string" },
+                            "explanation": { "type": "string" },
+                            "confidence": { "type": "number", "minimum": 0, "maximum": 1 },
+                            "placeholders": {
+                                "type": "array",
+                                "items": {
+                                    "type": "object",
+                                    "properties": {
+                                        "name": { "type": "string" },
+                                        "description": { "type": "string" }
+                                    },
+                                    "required": ["name", "description"],
+                                    "additionalProperties": false
+                                }
+                            }
+                        },
+                        "required": ["command", "explanation", "confidence", "placeholders"],
+                        "additionalProperties": false
+                    }
+                },
+                "clarify": { "type": ["string", "null"] }
+            },
+            "required": ["commands", "clarify"],
+            "additionalProperties": false
+        })
+    }
+
+    /// Parses a single streamed `{"command", "explanation", "confidence", "placeholders"}` object into a suggestion
+    pub fn parse_suggestion_object(&self, raw: &str) -> Option<Suggestion> {
+        let cmd_suggestion = serde_json::from_str::<CommandSuggestion>(raw).ok()?;
+
+        if !matches!(
+            self.check_command(&cmd_suggestion.command),
+            CommandCheck::Valid
+        ) {
+            debug!(
+                "Invalid streamed command rejected: {}",
+                cmd_suggestion.command
+            );
+            return None;
+        }
+
+        Some(cmd_suggestion.into_suggestion())
+    }
+
     pub fn validate_suggestions(&self, suggestions: &[Suggestion]) -> Vec<Suggestion> {
         // Additional validation on the Rust side if needed
         suggestions
@@ -21,4 +187,283 @@ impl ResponseParser {
             .cloned()
             .collect()
     }
+
+    /// Parses a raw model response into validated suggestions
+    pub fn parse_response(&self, response: &str, max_suggestions: usize) -> Vec<Suggestion> {
+        match self.parse_json(response, max_suggestions) {
+            Some(parsed) if !parsed.suggestions.is_empty() => parsed.suggestions,
+            // Fallback: try to extract commands from text response
+            _ => self.extract_commands_fallback(response, max_suggestions),
+        }
+    }
+
+    /// Parses a JSON response, returning `None` when the reply isn't valid JSON
+    pub fn parse_json(&self, response: &str, max_suggestions: usize) -> Option<ParsedReply> {
+        debug!("Parsing JSON response: {response}");
+
+        let commands_response = match serde_json::from_str::<CommandsResponse>(response) {
+            Ok(commands_response) => commands_response,
+            Err(e) => {
+                debug!("JSON parsing failed: {e}");
+                return None;
+            }
+        };
+
+        let mut suggestions = Vec::new();
+        let mut missing = Vec::new();
+
+        for cmd_suggestion in commands_response.commands {
+            if suggestions.len() >= max_suggestions {
+                break;
+            }
+            if is_duplicate(&suggestions, &cmd_suggestion.command) {
+                debug!("Duplicate command dropped: {}", cmd_suggestion.command);
+                continue;
+            }
+            match self.check_command(&cmd_suggestion.command) {
+                CommandCheck::Valid => suggestions.push(cmd_suggestion.into_suggestion()),
+                CommandCheck::Missing(executable) => {
+                    debug!("Command needs {executable}, which isn't installed");
+                    missing.push(MissingTool {
+                        executable,
+                        suggestion: cmd_suggestion.into_suggestion(),
+                    });
+                }
+                CommandCheck::Invalid => {
+                    debug!("Invalid command rejected: {}", cmd_suggestion.command)
+                }
+            }
+        }
+
+        Some(ParsedReply {
+            suggestions,
+            missing,
+            clarify: commands_response
+                .clarify
+                .map(|question| question.trim().to_string())
+                .filter(|question| !question.is_empty()),
+        })
+    }
+
+    /// Extracts command-looking lines from a free-text response
+    pub fn extract_commands_fallback(
+        &self,
+        response: &str,
+        max_suggestions: usize,
+    ) -> Vec<Suggestion> {
+        let mut suggestions = Vec::new();
+
+        for line in response.lines() {
+            let line = line.trim();
+
+            // Skip empty lines and obvious non-commands
+            if line.is_empty() || line.starts_with('#') || line.len() > 200 {
+                continue;
+            }
+
+            // Look for lines that look like commands
+            if self.looks_like_command(line)
+                && !is_duplicate(&suggestions, line)
+                && matches!(self.check_command(line), CommandCheck::Valid)
+            {
+                suggestions.push(Suggestion {
+                    command: line.to_string(),
+                    explanation: None,
+                    confidence: 0.6,
+                    backend: None,
+                    source: SuggestionSource::Model,
+                    placeholders: Placeholder::find(line),
+                    warnings: Vec::new(),
+                });
+
+                if suggestions.len() >= max_suggestions {
+                    break;
+                }
+            }
+        }
+
+        suggestions
+    }
+
+    /// Whether a line of free text reads as a command: it parses as shell
+    /// and what it runs is named like an executable, unlike most prose
+    fn looks_like_command(&self, line: &str) -> bool {
+        let validator = CommandValidator::new();
+        validator.is_valid_syntax(line)
+            && validator.extract_command_name(line).is_some_and(|name| {
+                name.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./~+".contains(c))
+            })
+    }
+
+    fn check_command(&self, command: &str) -> CommandCheck {
+        // Must not be empty and not too long
+        if command.is_empty() || command.len() > 500 {
+            return CommandCheck::Invalid;
+        }
+
+        // Commands the policy denies are never suggested
+        let shell = ShellKind::detect();
+        let validator = CommandValidator::for_shell(shell);
+        if !validator.is_safe_command(command) {
+            return CommandCheck::Invalid;
+        }
+
+        // Lines that don't parse, like prose or an unclosed quote
+        if shell.profile().posix && !validator.is_valid_syntax(command) {
+            return CommandCheck::Invalid;
+        }
+
+        // Every executable of a parsed line is checked; elsewhere the later
+        // commands of a pipeline may be the shell's own, like nu's `where`
+        let mut executables = validator.extract_executables(command);
+        if !shell.profile().posix {
+            executables.truncate(1);
+        }
+        let Some(missing) = executables
+            .into_iter()
+            .find(|executable| !is_known_executable(executable))
+        else {
+            return CommandCheck::Valid;
+        };
+
+        // Reject commands that look like pseudo-commands or APIs
+        let pseudo_patterns = [" query ", " api ", " endpoint ", " service "];
+        for pattern in &pseudo_patterns {
+            if command.to_lowercase().contains(pattern) {
+                return CommandCheck::Invalid;
+            }
+        }
+
+        // Log unknown commands for debugging
+        log::debug!("Command '{missing}' not found in PATH");
+        CommandCheck::Missing(missing)
+    }
+}
+
+/// Whether `executable` runs: it's in PATH, a path, one of the user's
+/// aliases or a shell built-in
+fn is_known_executable(executable: &str) -> bool {
+    // Look the executable up among those in PATH; on Windows this also
+    // tries the PATHEXT extensions, as `where` does
+    if ExecutableIndex::contains(executable)
+        || ShellDetector::is_user_alias(executable)
+        || executable.contains('/')
+        || SHELL_BUILTINS.contains(&executable)
+    {
+        return true;
+    }
+
+    // PowerShell cmdlets (Get-ChildItem) and cmd built-ins (dir, copy)
+    // aren't files in PATH
+    (cfg!(windows) || ShellKind::detect().profile().windows)
+        && (executable.contains('\\')
+            || is_cmdlet(executable)
+            || CMD_BUILTINS.contains(&executable.to_lowercase().as_str()))
+}
+
+/// Built-ins of POSIX shells and bash that aren't programs in PATH
+const SHELL_BUILTINS: &[&str] = &[
+    "alias", "bg", "break", "cd", "continue", "declare", "dirs", "echo", "eval", "exit", "export",
+    "fg", "getopts", "hash", "history", "jobs", "let", "local", "popd", "printf", "pushd", "pwd",
+    "read", "readonly", "return", "set", "shift", "shopt", "source", "test", "trap", "type",
+    "typeset", "ulimit", "umask", "unalias", "unset", "wait",
+];
+
+/// Commands built into cmd.exe, which `where` can't find
+const CMD_BUILTINS: &[&str] = &[
+    "assoc", "cls", "copy", "date", "del", "dir", "erase", "md", "mkdir", "mklink", "move", "rd",
+    "ren", "rename", "rmdir", "set", "start", "time", "type", "ver", "vol",
+];
+
+/// Whether `word` looks like a PowerShell cmdlet, a Verb-Noun pair like
+/// Get-ChildItem
+fn is_cmdlet(word: &str) -> bool {
+    word.split_once('-').is_some_and(|(verb, noun)| {
+        verb.chars().next().is_some_and(|c| c.is_ascii_uppercase())
+            && !noun.is_empty()
+            && verb
+                .chars()
+                .chain(noun.chars())
+                .all(|c| c.is_ascii_alphanumeric())
+    })
+}
+
+/// Whether `command` is among `suggestions` already, ignoring how it's spaced
+fn is_duplicate(suggestions: &[Suggestion], command: &str) -> bool {
+    let words = |command: &str| command.split_whitespace().collect::<Vec<_>>().join(" ");
+    let command = words(command);
+    suggestions
+        .iter()
+        .any(|suggestion| words(&suggestion.command) == command)
+}
+
+// ============================================================================
+// Streaming Support
+// ============================================================================
+
+/// Incrementally extracts complete suggestion objects from a streamed
+/// `{"commands": [{...}, {...}]}` response as tokens arrive
+#[derive(Debug, Default)]
+pub struct StreamingParser {
+    buffer: String,
+    scanned: usize,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    object_start: Option<usize>,
+}
+
+impl StreamingParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a fragment and returns any suggestion objects completed by it
+    pub fn push(&mut self, fragment: &str) -> Vec<String> {
+        self.buffer.push_str(fragment);
+
+        let mut completed = Vec::new();
+        let bytes = self.buffer.as_bytes();
+
+        for (i, &byte) in bytes.iter().enumerate().skip(self.scanned) {
+            if self.in_string {
+                match byte {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+
+            match byte {
+                b'"' => self.in_string = true,
+                b'{' | b'[' => {
+                    // Objects nested inside the top-level "commands" array
+                    if byte == b'{' && self.depth == 2 {
+                        self.object_start = Some(i);
+                    }
+                    self.depth += 1;
+                }
+                b'}' | b']' => {
+                    self.depth = self.depth.saturating_sub(1);
+                    if byte == b'}' && self.depth == 2 {
+                        if let Some(start) = self.object_start.take() {
+                            completed.push(self.buffer[start..=i].to_string());
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.scanned = bytes.len();
+        completed
+    }
+
+    /// Returns everything received so far
+    pub fn text(&self) -> &str {
+        &self.buffer
+    }
 }
@@ -1,4 +1,10 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::config::settings::{BackendKind, LogLevel, OutputConfig};
+use crate::utils::ShellKind;
 
 #[derive(Parser)]
 #[command(name = "phloem")]
@@ -6,41 +12,119 @@ use clap::{Parser, Subcommand};
 #[command(version = env!("CARGO_PKG_VERSION"))]
 #[command(long_about = None)]
 pub struct Cli {
-    /// The prompt to generate a command for
+    /// The prompt to generate a command for; `-` reads it from stdin
     pub prompt: Option<String>,
 
     #[command(subcommand)]
     pub command: Option<Commands>,
 
-    /// Show detailed explanations
-    #[arg(short, long)]
+    /// Show detailed explanations [default: output.show_explanations]
+    #[arg(short, long, overrides_with = "no_explain")]
     pub explain: bool,
 
-    /// Number of suggestions to show
-    #[arg(short = 'n', long, default_value = "3")]
-    pub suggestions: usize,
+    /// Hide explanations, when the config shows them
+    #[arg(long, overrides_with = "explain")]
+    pub no_explain: bool,
+
+    /// Number of suggestions to show [default: output.max_suggestions]
+    #[arg(short = 'n', long)]
+    pub suggestions: Option<usize>,
 
     /// Skip cache and force fresh inference
     #[arg(long)]
     pub no_cache: bool,
 
-    /// Verbose output
-    #[arg(short, long)]
+    /// Print debug messages to the terminal
+    #[arg(short, long, global = true)]
     pub verbose: bool,
+
+    /// Least severe messages written to ~/.phloem/logs/phloem.log for this run
+    #[arg(long, value_name = "LEVEL", global = true)]
+    pub log_level: Option<LogLevel>,
+
+    /// Print how long each step took, from reading the settings to showing the suggestions
+    #[arg(long)]
+    pub timing: bool,
+
+    /// Serve matches from the cache and shell history without contacting the model
+    #[arg(long)]
+    pub offline: bool,
+
+    /// Don't cache, record or learn from this run
+    #[arg(long)]
+    pub incognito: bool,
+
+    /// Detect installed tools and services again instead of using the cached snapshot
+    #[arg(long)]
+    pub refresh_env: bool,
+
+    /// Print the suggestions in this format instead of showing the selector
+    #[arg(long, value_enum)]
+    pub output: Option<OutputMode>,
+
+    /// Print only the best command, without the selector, e.g. for `$(phloem --first ...)`
+    #[arg(long)]
+    pub first: bool,
+
+    /// Suggest fixes for the error output piped in, e.g. `make 2>&1 | phloem --diagnose`
+    #[arg(long)]
+    pub diagnose: bool,
+
+    /// Use this model instead of the configured one, e.g. `--model qwen2.5-coder:7b`
+    #[arg(long)]
+    pub model: Option<String>,
+
+    /// Use this backend instead of the configured one or chain
+    #[arg(long, value_enum)]
+    pub backend: Option<BackendKind>,
+
+    /// Write commands for this shell instead of yours, e.g. for a script on a server
+    #[arg(long, value_enum)]
+    pub shell: Option<ShellKind>,
+
+    /// Get an ordered plan of commands for a multi-step task and run it step by step
+    #[arg(long, conflicts_with_all = ["diagnose", "first"])]
+    pub plan: bool,
+
+    /// Show what the picked command would affect instead of running it
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Try the picked command in a read-only, offline sandbox before running it for real
+    #[arg(long)]
+    pub sandbox: bool,
+
+    /// Work as if started in this directory: its project, files and PHLOEM.md
+    #[arg(long, value_name = "PATH")]
+    pub cwd: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
     /// Initialize phloem setup
-    Init,
+    Init {
+        /// Create a shared .phloem/PHLOEM.md for the current project instead
+        #[arg(long)]
+        project: bool,
+        /// Load the Ctrl+G widget, history hook and completions from your shell's rc file instead
+        #[arg(long, conflicts_with = "project")]
+        shell_integration: bool,
+        /// Remove what --shell-integration added to the rc file
+        #[arg(long, requires = "shell_integration")]
+        uninstall: bool,
+    },
     /// Update model or binary
     Update {
         /// Update the ML model
         #[arg(long)]
         model: bool,
-        /// Update the binary
+        /// Update the binary; the download is only checked against the
+        /// SHA-256 published in the same release, not a signature
         #[arg(long)]
         binary: bool,
+        /// Only report whether an update is available
+        #[arg(long)]
+        check: bool,
     },
     /// Show configuration
     Config,
@@ -55,16 +139,234 @@ pub enum Commands {
     },
     /// Run diagnostics
     Doctor,
+    /// Load the model into memory ahead of the first prompt
+    Warmup,
+    /// Suggest a commit message for the staged changes
+    Commit,
+    /// Compare models on a built-in suite of prompts
+    Bench {
+        /// Models to benchmark, comma separated (defaults to the configured model)
+        #[arg(short, long, value_delimiter = ',')]
+        models: Vec<String>,
+    },
+    /// Inspect and manage the suggestion cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Manage learned patterns and notes in PHLOEM.md
+    Context {
+        #[command(subcommand)]
+        action: ContextAction,
+    },
+    /// Browse commands run from phloem
+    History {
+        #[command(subcommand)]
+        action: HistoryAction,
+        /// Print JSON instead of a table
+        #[arg(long, global = true)]
+        json: bool,
+    },
+    /// Save a command under a name, to run with `phloem run`
+    Save {
+        name: String,
+        /// The command to save (defaults to the last one run from phloem)
+        command: Option<String>,
+    },
+    /// Run a saved command
+    Run { name: String },
+    /// List, delete and rename saved commands
+    Favorites {
+        #[command(subcommand)]
+        action: FavoriteAction,
+        /// Print JSON instead of a list
+        #[arg(long, global = true)]
+        json: bool,
+    },
+    /// Summarize usage: prompts per day, cache hits, accepted suggestions and more
+    Stats {
+        /// Only count the last N days
+        #[arg(long, default_value_t = 30)]
+        days: u32,
+        /// Print JSON instead of a summary, e.g. for dashboards
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show or verify the audit log of suggestions that ran
+    Audit {
+        #[command(subcommand)]
+        action: AuditAction,
+        /// Print JSON instead of a list
+        #[arg(long, global = true)]
+        json: bool,
+    },
+    /// Detect installed tools and services again
+    RefreshEnv,
+    /// Back up or restore config, context and cache
+    Backup {
+        #[command(subcommand)]
+        action: BackupAction,
+    },
+    /// Print a completion script for the shell, e.g. `phloem completions zsh > _phloem`
+    Completions { shell: Shell },
+    /// Keep the cache, environment and model warm and answer prompts over a local socket
+    Daemon,
+    /// Print the Ctrl+G widget that puts the picked command on your prompt, e.g. `eval "$(phloem shell-init zsh)"`
+    ShellInit { shell: IntegrationShell },
     /// Show version information
     Version,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Subcommand)]
+pub enum HistoryAction {
+    /// List recently run commands
+    List {
+        /// Maximum number of entries to show
+        #[arg(short = 'n', long, default_value = "20")]
+        limit: usize,
+    },
+    /// Find commands whose command line or prompt contains the term
+    Search {
+        term: String,
+        /// Maximum number of entries to show
+        #[arg(short = 'n', long, default_value = "20")]
+        limit: usize,
+    },
+    /// Show the most used tools
+    Top {
+        /// Maximum number of tools to show
+        #[arg(short = 'n', long, default_value = "10")]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AuditAction {
+    /// List the latest entries, oldest first
+    Show {
+        /// Maximum number of entries to show
+        #[arg(short = 'n', long, default_value = "20")]
+        limit: usize,
+    },
+    /// Check that no entry was changed, removed or inserted
+    Verify,
+}
+
+#[derive(Subcommand)]
+pub enum FavoriteAction {
+    /// List saved commands, most used first
+    List,
+    /// Forget a saved command
+    Rm { name: String },
+    /// Save a command under another name
+    Rename { name: String, new_name: String },
+}
+
+#[derive(Subcommand)]
+pub enum ContextAction {
+    /// List learned patterns with their ids, newest first
+    Show {
+        /// Only show patterns of this category (e.g. docker, git)
+        category: Option<String>,
+    },
+    /// Add a note for the model to follow
+    Add { category: String, note: String },
+    /// Forget a learned pattern by id
+    Rm { id: i64 },
+    /// Open the context file in $EDITOR
+    Edit,
+}
+
+#[derive(Subcommand)]
+pub enum BackupAction {
+    /// Write config, context and cache to a .tar.gz (default: ~/.phloem/backups, keeping the latest 5)
+    Create { path: Option<PathBuf> },
+    /// Replace config, context and cache with a backup; the current state is backed up first
+    Restore { path: PathBuf },
+}
+
+#[derive(Subcommand)]
+pub enum CacheAction {
+    /// Show cache statistics
+    Stats,
+    /// List cached suggestions, most recently used first
+    List {
+        /// Only show prompts of this category (e.g. docker, git)
+        #[arg(short, long)]
+        category: Option<String>,
+        /// Maximum number of entries to show
+        #[arg(short = 'n', long, default_value = "20")]
+        limit: usize,
+    },
+    /// Remove suggestions and history older than the given number of days
+    Prune {
+        #[arg(long)]
+        days: u32,
+    },
+    /// Write cached suggestions, with categories and success stats, to a file
+    /// to share with a team or another machine; secrets are masked
+    Export {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// Only export prompts of this category (e.g. docker, git)
+        #[arg(short, long)]
+        category: Option<String>,
+        /// Only export suggestions that ran successfully
+        #[arg(long)]
+        successful: bool,
+    },
+    /// Add suggestions from a file written by `cache export`
+    Import { file: PathBuf },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Json,
+}
+
+/// Shells with an integration script
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum IntegrationShell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Non-interactive ways to print suggestions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputMode {
+    /// A JSON document with command, explanation, confidence and source of each
+    Json,
+    /// One command per line
+    Plain,
+    /// An aligned table
+    Table,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PromptOptions {
     pub no_cache: bool,
     pub explain: bool,
     pub max_suggestions: usize,
     pub verbose: bool,
+    pub offline: bool,
+    pub incognito: bool,
+    pub refresh_env: bool,
+    /// Shell to write commands for, when not the user's own
+    #[serde(default)]
+    pub shell: Option<ShellKind>,
+}
+
+impl Cli {
+    /// Takes `--explain` and `-n` from the `[output]` settings unless they
+    /// were given on the command line
+    pub fn apply_output_defaults(&mut self, output: &OutputConfig) {
+        if !self.no_explain {
+            self.explain |= output.show_explanations;
+        }
+        self.suggestions.get_or_insert(output.max_suggestions);
+    }
 }
 
 impl From<&Cli> for PromptOptions {
@@ -72,8 +374,14 @@ impl From<&Cli> for PromptOptions {
         Self {
             no_cache: cli.no_cache,
             explain: cli.explain,
-            max_suggestions: cli.suggestions,
+            max_suggestions: cli
+                .suggestions
+                .unwrap_or_else(|| OutputConfig::default().max_suggestions),
             verbose: cli.verbose,
+            offline: cli.offline,
+            incognito: cli.incognito,
+            refresh_env: cli.refresh_env,
+            shell: cli.shell,
         }
     }
 }
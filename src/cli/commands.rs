@@ -1,120 +1,1345 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+use clap::CommandFactory;
+use clap_complete::Shell;
 use log::{debug, info, warn};
-use std::io;
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
 
-use crate::ai::OllamaClient;
-use crate::cli::{Commands, FormatResult, OutputFormatter, PromptOptions, Spinner};
-use crate::config::Settings;
-use crate::context::ContextManager;
+use crate::ai::{
+    create_backend, create_backend_for_model, is_unreachable, AiBackend, BackendChain,
+    Conversation, Generation, MissingTool, ModelEnsemble, ParseOutcome, PromptBuilder,
+};
+use crate::cli::{
+    cancellable, daemon, integration, interrupt, AuditAction, BackupAction, CacheAction, Cancelled,
+    Cli, CommandFailure, Commands, ContextAction, DownloadProgress, ExportFormat, FavoriteAction,
+    FormatResult, HistoryAction, KeyMap, OutputFormatter, OutputMode, Placeholder, PromptOptions,
+};
+use crate::config::settings::ElevationMode;
+use crate::config::{InvalidConfig, Settings};
+use crate::context::learning::render_pattern;
+use crate::context::{
+    backup, CacheExport, ContextManager, Favorite, PromptEmbedding, StorageManager,
+};
+use crate::utils::{
+    CommandValidator, ContainerInspector, EnvironmentDetector, GitInspector, KubernetesInspector,
+    PackageManager, Policy, PolicyAction, Release, Sandbox, SecretRedactor, ShellCheck,
+    ShellCheckFinding, ShellDetector, ShellKind, Timing,
+};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Suggestion {
     pub command: String,
     pub explanation: Option<String>,
     pub confidence: f32,
+    /// Backend that generated this suggestion, when known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    pub source: SuggestionSource,
+    /// Values the user fills in before the command runs, written `{{name}}` in it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub placeholders: Vec<Placeholder>,
+    /// What ShellCheck found in the command, e.g. an unquoted variable
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// Where a suggestion came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SuggestionSource {
+    /// Generated by the model for this request
+    Model,
+    /// Served from the suggestion cache
+    Cache,
+    /// Matched from the user's shell history
+    History,
+    /// Saved by the user with `phloem save` for a similar prompt
+    Favorite,
+    /// A model suggestion preceded by the install of its missing executable
+    Install,
+}
+
+impl SuggestionSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Model => "model",
+            Self::Cache => "cache",
+            Self::History => "history",
+            Self::Favorite => "favorite",
+            Self::Install => "install",
+        }
+    }
+}
+
+/// Prompts used by `phloem bench`, covering common everyday tasks
+const BENCH_PROMPTS: &[&str] = &[
+    "list all files including hidden ones",
+    "find files larger than 100MB",
+    "show disk usage of the current directory",
+    "count lines in all rust files",
+    "follow the last 20 lines of a log file",
+    "list running processes sorted by memory usage",
+    "find which process is listening on port 8080",
+    "compress this directory into a tar.gz archive",
+    "show git commits from the last week",
+    "replace foo with bar in all txt files",
+];
+
+/// Questions the model may ask back before it has to suggest commands
+const MAX_CLARIFICATIONS: usize = 2;
+
+/// Diff budget sent to the model by `phloem commit`
+const MAX_COMMIT_DIFF_CHARS: usize = 12_000;
+
+/// Trailing lines of piped output kept by `--diagnose`; errors come last
+const MAX_DIAGNOSE_LINES: usize = 40;
+
+/// Budget for the piped output in a `--diagnose` prompt
+const MAX_DIAGNOSE_CHARS: usize = 4_000;
+
+/// Most steps a plan may have
+const MAX_PLAN_STEPS: usize = 10;
+
+/// Commands asked of the model beyond `-n`, so one rejected by validation or
+/// as a duplicate doesn't leave the list short
+const SPARE_CANDIDATES: usize = 1;
+
+/// Categories and executables listed by `phloem stats`
+const TOP_STATS: usize = 5;
+
+/// Width of the longest bar in the prompts-per-day chart of `phloem stats`
+const STATS_BAR_WIDTH: usize = 30;
+
+/// Prompt under which commit suggestions are recorded, so they land in the Git category
+const COMMIT_PROMPT: &str = "git commit message";
+
+/// Measurements for one model over the bench suite
+struct BenchResult {
+    model: String,
+    latencies: Vec<Duration>,
+    valid_json: usize,
+    repaired: usize,
+    usable: usize,
+    errors: usize,
 }
 
 pub struct CommandHandler {
-    context: ContextManager,
-    ai_client: OllamaClient,
+    /// Context and cache, opened on first use
+    context: OnceLock<ContextManager>,
+    /// Backends, built on first use; shared with the health check that runs
+    /// while a prompt's context is gathered
+    ai_client: OnceLock<Arc<BackendChain>>,
+    /// Backends of the models of `[backend] models`, built on first use
+    ensemble: OnceLock<ModelEnsemble>,
     settings: Settings,
     formatter: OutputFormatter,
+    prompt_builder: PromptBuilder,
+    /// Chat history of the current session, used for follow-up refinements
+    conversation: Option<Conversation>,
+    /// Whether the model's questions can be put to the user
+    interactive: bool,
+    /// Shell picked with `--shell`, kept for follow-ups
+    target_shell: Option<ShellKind>,
+    /// Suggestions asked for with `-n`, kept for follow-ups
+    max_suggestions: usize,
 }
 
 impl CommandHandler {
     pub fn new() -> Result<Self> {
-        let settings = Settings::load()?;
-        let context = ContextManager::new(&settings)?;
-        let ai_client = OllamaClient::new(&settings)?;
-        let formatter = OutputFormatter::new(settings.output.use_colors);
+        Self::with_settings(Settings::load()?)
+    }
+
+    /// Handler for `settings` as given, e.g. with `--model` or `--backend`
+    /// applied; the cache and the backends are set up when first needed
+    pub fn with_settings(settings: Settings) -> Result<Self> {
+        ShellDetector::set_windows_shell(settings.execution.windows_shell.as_str());
+        Policy::install(Policy::load()?);
+        let formatter = OutputFormatter::new(settings.output.use_colors)
+            .with_destructive_confirmation(settings.safety.confirm_destructive)
+            .with_elevation(settings.safety.elevation)
+            .with_shell_program(
+                Some(settings.execution.shell.clone()).filter(|shell| shell != "auto"),
+                settings.execution.interactive_shell,
+            )
+            .with_strict_execution(settings.execution.strict)
+            .with_syntax_highlighting(settings.output.syntax_highlighting)
+            .with_keys(KeyMap::new(&settings.keys))
+            .with_output_capture(
+                settings
+                    .execution
+                    .capture_output
+                    .then_some(settings.execution.max_captured_kb * 1024),
+            )
+            .with_sandbox(
+                settings
+                    .sandbox
+                    .enabled
+                    .then(|| Sandbox::new(&settings.sandbox)),
+            );
+        let prompt_builder =
+            PromptBuilder::with_redactor(SecretRedactor::new(&settings.privacy.redact_patterns)?);
 
         Ok(Self {
-            context,
-            ai_client,
+            context: OnceLock::new(),
+            ai_client: OnceLock::new(),
+            ensemble: OnceLock::new(),
+            max_suggestions: settings.output.max_suggestions,
             settings,
             formatter,
+            prompt_builder,
+            conversation: None,
+            interactive: true,
+            target_shell: None,
+        })
+    }
+
+    /// The context and cache, opened on first use, so commands that need
+    /// neither work without them and a broken cache fails only those that do
+    async fn context(&self) -> Result<&ContextManager> {
+        if let Some(context) = self.context.get() {
+            return Ok(context);
+        }
+        let context =
+            Timing::measure_async("cache open", ContextManager::new(&self.settings)).await?;
+        Ok(self.context.get_or_init(|| context))
+    }
+
+    async fn context_mut(&mut self) -> Result<&mut ContextManager> {
+        Self::open_context(&mut self.context, &self.settings).await
+    }
+
+    /// `context`, opened for `settings` unless it is already; apart from
+    /// `self` so the formatter can be borrowed alongside it
+    async fn open_context<'a>(
+        context: &'a mut OnceLock<ContextManager>,
+        settings: &Settings,
+    ) -> Result<&'a mut ContextManager> {
+        if context.get().is_none() {
+            let _ = context
+                .set(Timing::measure_async("cache open", ContextManager::new(settings)).await?);
+        }
+        Ok(context.get_mut().expect("opened above"))
+    }
+
+    /// The backends, built on first use
+    fn ai_client(&self) -> Result<&Arc<BackendChain>> {
+        if let Some(ai_client) = self.ai_client.get() {
+            return Ok(ai_client);
+        }
+        let ai_client = Arc::new(Timing::measure("backend setup", || {
+            create_backend(&self.settings)
+        })?);
+        Ok(self.ai_client.get_or_init(|| ai_client))
+    }
+
+    /// The models of `[backend] models` asked together, when it lists two
+    /// or more, built on first use
+    fn ensemble(&self) -> Result<Option<&ModelEnsemble>> {
+        let models = &self.settings.backend.models;
+        if models.len() < 2 {
+            return Ok(None);
+        }
+        if let Some(ensemble) = self.ensemble.get() {
+            return Ok(Some(ensemble));
+        }
+        let ensemble = Timing::measure("backend setup", || {
+            ModelEnsemble::new(
+                models
+                    .iter()
+                    .map(|model| create_backend_for_model(&self.settings, model))
+                    .collect::<Result<_>>()?,
+            )
+        })?;
+        Ok(Some(self.ensemble.get_or_init(|| ensemble)))
+    }
+
+    /// Sets up the backends, unless they are already, and checks that one
+    /// answers, on another task
+    fn check_backend(&self) -> JoinHandle<Result<(Arc<BackendChain>, Result<()>)>> {
+        let ai_client = self.ai_client.get().cloned();
+        let settings = self.settings.clone();
+        tokio::spawn(async move {
+            let ai_client = match ai_client {
+                Some(ai_client) => ai_client,
+                None => Arc::new(
+                    tokio::task::spawn_blocking(move || {
+                        Timing::measure("backend setup", || create_backend(&settings))
+                    })
+                    .await??,
+                ),
+            };
+            let started = Instant::now();
+            let health = ai_client.verify_connection().await;
+            Timing::record("health check", started.elapsed());
+            Ok((ai_client, health))
         })
     }
 
+    /// Waits for `check` of `check_backend` and keeps the backends it set up;
+    /// what the check found, or why they couldn't be set up
+    async fn finish_check(
+        &mut self,
+        check: JoinHandle<Result<(Arc<BackendChain>, Result<()>)>>,
+    ) -> Result<Result<()>> {
+        let (ai_client, health) = check.await??;
+        let _ = self.ai_client.set(ai_client);
+        Ok(health)
+    }
+
+    /// Writes and checks commands for `shell` instead of the user's own
+    pub fn set_target_shell(&mut self, shell: ShellKind) {
+        self.target_shell = Some(shell);
+        self.formatter.set_shell(shell);
+    }
+
+    /// Shows what picked commands would affect instead of running them
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.formatter.set_dry_run(dry_run);
+    }
+
+    /// Draws no spinners, e.g. when the suggestions are printed for a script
+    pub fn hide_progress(&mut self) {
+        self.formatter.set_progress(false);
+    }
+
+    /// Tries picked commands in the sandbox of `[sandbox]` before they run
+    /// for real, as when it's enabled there
+    pub fn enable_sandbox(&mut self) {
+        self.formatter
+            .set_sandbox(Some(Sandbox::new(&self.settings.sandbox)));
+    }
+
+    /// Answers a prompt sent from `cwd` by a daemon client
+    pub async fn handle_remote_prompt(
+        &mut self,
+        prompt: &str,
+        cwd: &Path,
+        options: PromptOptions,
+    ) -> Result<Vec<Suggestion>> {
+        self.context_mut().await?.enter_directory(cwd)?;
+        self.conversation = None;
+        self.handle_prompt(prompt, options).await
+    }
+
+    /// Fails when the backend can list its models and `model` isn't among
+    /// them; `llama3` also matches `llama3:latest`
+    pub async fn check_model_available(&self, model: &str) -> Result<()> {
+        let models = match self.ai_client()?.list_models().await {
+            Ok(models) => models,
+            Err(e) => {
+                warn!("Couldn't list models to check {model}: {e}");
+                return Ok(());
+            }
+        };
+
+        let latest = format!("{model}:latest");
+        if models.iter().any(|m| m == model || *m == latest) {
+            return Ok(());
+        }
+        anyhow::bail!(
+            "{} doesn't serve model {model}; available: {}",
+            self.ai_client()?.name(),
+            if models.is_empty() {
+                "none".to_string()
+            } else {
+                models.join(", ")
+            }
+        )
+    }
+
+    /// Loads the model into memory, e.g. before the daemon serves its first prompt
+    pub async fn warmup(&self) -> Result<()> {
+        self.ai_client()?.warmup().await
+    }
+
     pub async fn handle_prompt(
         &mut self,
         prompt: &str,
         options: PromptOptions,
     ) -> Result<Vec<Suggestion>> {
         debug!("Processing prompt: {prompt}");
+        self.context_mut().await?.set_incognito(options.incognito);
+        self.max_suggestions = options.max_suggestions;
+
+        // Cached answers are in the user's own shell; keep other shells' apart from them
+        let foreign_shell = options.shell.filter(|&shell| shell != ShellKind::detect());
+        let options = PromptOptions {
+            no_cache: options.no_cache || foreign_shell.is_some(),
+            ..options
+        };
+
+        // The backends are set up and checked while the cache and context are
+        // read, so an unreachable one is known before the model is asked
+        let mut check = (!options.offline).then(|| self.check_backend());
+        let mut health = None;
+
+        // Embed the prompt to also match cached prompts worded differently
+        let lookup_started = Instant::now();
+        let embedding_model = self.settings.cache.semantic.model.clone();
+        let embedding = if options.no_cache || options.offline {
+            None
+        } else {
+            if let Some(check) = check
+                .take()
+                .filter(|_| self.settings.cache.semantic.enabled)
+            {
+                health = Some(self.finish_check(check).await?);
+            }
+            self.embed_prompt(prompt).await
+        };
+        let prompt_embedding = embedding.as_deref().map(|vector| PromptEmbedding {
+            model: &embedding_model,
+            vector,
+        });
+
+        // Commands the user saved for this prompt come before anything else
+        let favorites = if foreign_shell.is_some() {
+            Vec::new()
+        } else {
+            self.context()
+                .await?
+                .cache
+                .get_matching_favorites(prompt, prompt_embedding.as_ref())
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Failed to look up saved commands: {e}");
+                    Vec::new()
+                })
+        };
+        let with_favorites =
+            |suggestions: Vec<Suggestion>| -> Vec<Suggestion> {
+                let mut merged: Vec<Suggestion> =
+                    favorites.iter().map(Favorite::to_suggestion).collect();
+                merged.extend(suggestions.into_iter().filter(|suggestion| {
+                    favorites.iter().all(|f| f.command != suggestion.command)
+                }));
+                merged
+            };
 
         // Check cache first unless explicitly disabled
-        if !options.no_cache {
-            if let Ok(Some(cached)) = self.context.get_cached_suggestion(prompt) {
-                info!("Found cached suggestion for prompt");
-                return Ok(vec![cached]);
+        let cached = if options.no_cache {
+            None
+        } else {
+            self.context()
+                .await?
+                .get_cached_suggestion(prompt, prompt_embedding.as_ref())
+                .await
+                .ok()
+                .flatten()
+        };
+        Timing::record("cache lookup", lookup_started.elapsed());
+        if let Some(cached) = cached {
+            if let Some(check) = &check {
+                check.abort();
             }
+            info!("Found cached suggestion for prompt");
+            self.record_prompt(prompt, "cache").await;
+            let mut suggestions = with_favorites(vec![cached]);
+            self.annotate_findings(&mut suggestions).await;
+            return Ok(suggestions);
+        }
+
+        if options.offline {
+            self.record_prompt(prompt, "offline").await;
+            return self
+                .serve_offline(prompt, options.max_suggestions, "--offline")
+                .await
+                .map(with_favorites);
         }
 
+        // Probe for tools and services again once the stored snapshot is stale
+        let refresh_env = options.refresh_env;
+        let context = self.context_mut().await?;
+        if let Err(e) =
+            Timing::measure_async("env detection", context.ensure_environment(refresh_env)).await
+        {
+            warn!("Failed to refresh the environment: {e}");
+        }
+        let context_started = Instant::now();
+
+        // Kubernetes and Docker requests also get the cluster or the running
+        // containers; the probes run alongside the other context gathering and
+        // are abandoned when slow
+        let context = self.context().await?;
+        let category = context.categorize_prompt(prompt);
+        let kubernetes = if self.settings.kubernetes.enabled && category == "Kubernetes" {
+            let timeout = Duration::from_millis(self.settings.kubernetes.probe_timeout_ms);
+            let history = context.cache.get_shell_history().await.unwrap_or_default();
+            Some(tokio::spawn(async move {
+                KubernetesInspector::inspect(timeout, &history).await
+            }))
+        } else {
+            None
+        };
+
+        let runtime = context
+            .cache
+            .get_environment()
+            .await
+            .ok()
+            .and_then(|environment| environment.get("container_runtime").cloned());
+        let containers = runtime
+            .filter(|_| self.settings.containers.enabled && category == "Docker")
+            .map(|runtime| {
+                let timeout = Duration::from_millis(self.settings.containers.probe_timeout_ms);
+                tokio::spawn(async move { ContainerInspector::inspect(&runtime, timeout).await })
+            });
+
         // Load context for prompt enhancement
-        let context_data = self.context.get_relevant_context(prompt)?;
+        let mut context_data = self.context().await?.get_relevant_context(prompt).await?;
+        if let Some(kubernetes) = kubernetes {
+            context_data.kubernetes = kubernetes.await.ok().flatten();
+        }
+        if let Some(containers) = containers {
+            context_data.containers = containers.await.ok().flatten();
+        }
+        if let Some(shell) = foreign_shell {
+            // The user's aliases don't exist in the other shell
+            context_data.aliases.clear();
+            context_data.target_shell = Some(shell);
+        }
         debug!(
             "Loaded context data with {} recent commands",
             context_data.recent_commands.len()
         );
+        Timing::record("context load", context_started.elapsed());
+
+        // Degrade to cache and history matches right away when no backend answers
+        if let Some(check) = check {
+            health = Some(self.finish_check(check).await?);
+        }
+        match health {
+            Some(Err(e)) if is_unreachable(&e) => {
+                warn!("Model unreachable, serving offline matches: {e}");
+                self.record_prompt(prompt, "offline").await;
+                return self
+                    .serve_offline(prompt, options.max_suggestions, "model unreachable")
+                    .await
+                    .map(with_favorites);
+            }
+            Some(Err(e)) => debug!("Backend check failed: {e}"),
+            _ => {}
+        }
+
+        // Start a new chat session for this prompt
+        let mut conversation = Conversation::new(
+            self.prompt_builder
+                .build_system_prompt(&context_data, options.max_suggestions + SPARE_CANDIDATES),
+        );
+        conversation.push_user(self.prompt_builder.build_user_message(prompt));
+
+        let suggestions = match self
+            .generate(&mut conversation, options.max_suggestions, true)
+            .await
+        {
+            // Fall back to whatever was suggested for this prompt before
+            Err(e) if e.is::<Cancelled>() => {
+                let previous = self
+                    .context()
+                    .await?
+                    .get_previous_suggestions(prompt, options.max_suggestions)
+                    .await
+                    .unwrap_or_default();
+                if previous.is_empty() {
+                    return Err(e);
+                }
+                eprintln!(
+                    "{}",
+                    self.formatter
+                        .format_warning("Generation cancelled, showing earlier suggestions")
+                );
+                return Ok(previous);
+            }
+            // Degrade to cache and history matches instead of failing
+            Err(e) if is_unreachable(&e) => {
+                warn!("Model unreachable, serving offline matches: {e}");
+                self.record_prompt(prompt, "offline").await;
+                return self
+                    .serve_offline(prompt, options.max_suggestions, "model unreachable")
+                    .await
+                    .map(with_favorites);
+            }
+            result => result?,
+        };
+        self.conversation = Some(conversation);
+        self.record_prompt(prompt, "model").await;
+
+        if foreign_shell.is_none() {
+            self.cache_suggestions(prompt, &suggestions).await;
+        }
+        let suggestions = if options.no_cache {
+            suggestions
+        } else {
+            let suggestions = self
+                .merge_cached_candidates(
+                    prompt,
+                    prompt_embedding.as_ref(),
+                    suggestions,
+                    options.max_suggestions,
+                )
+                .await;
+            self.top_up_from_cache(prompt, suggestions, options.max_suggestions)
+                .await
+        };
+        if let Some(embedding) = prompt_embedding.filter(|_| !suggestions.is_empty()) {
+            if let Err(e) = self
+                .context_mut()
+                .await?
+                .store_prompt_embedding(prompt, &embedding)
+                .await
+            {
+                warn!("Failed to store prompt embedding: {e}");
+            }
+        }
+
+        Ok(with_favorites(suggestions))
+    }
+
+    /// Fills the slots the model left empty with earlier suggestions for
+    /// `prompt`, skipping commands already listed
+    async fn top_up_from_cache(
+        &self,
+        prompt: &str,
+        mut suggestions: Vec<Suggestion>,
+        max_suggestions: usize,
+    ) -> Vec<Suggestion> {
+        if suggestions.len() >= max_suggestions {
+            return suggestions;
+        }
+        let previous = match async {
+            self.context()
+                .await?
+                .get_previous_suggestions(prompt, max_suggestions)
+                .await
+        }
+        .await
+        {
+            Ok(previous) => previous,
+            Err(e) => {
+                warn!("Failed to load earlier suggestions: {e}");
+                return suggestions;
+            }
+        };
+
+        for suggestion in previous {
+            if suggestions.len() >= max_suggestions {
+                break;
+            }
+            if suggestions.iter().all(|s| s.command != suggestion.command) {
+                suggestions.push(suggestion);
+            }
+        }
+        suggestions
+    }
+
+    /// Records where the suggestions for `prompt` came from, for `phloem stats`
+    async fn record_prompt(&mut self, prompt: &str, source: &str) {
+        if let Err(e) = async {
+            self.context_mut()
+                .await?
+                .record_prompt(prompt, source)
+                .await
+        }
+        .await
+        {
+            warn!("Failed to record the prompt: {e}");
+        }
+    }
+
+    /// Turns the output of a failed command into a request for commands that
+    /// fix it; `request` is what the user added, if anything
+    pub fn diagnosis_prompt(output: &str, request: Option<&str>) -> String {
+        let lines: Vec<&str> = output.trim_end().lines().collect();
+        let tail = lines[lines.len().saturating_sub(MAX_DIAGNOSE_LINES)..].join("\n");
+        // Keep the end, where the error is, when single lines are huge
+        let start = tail
+            .char_indices()
+            .rev()
+            .nth(MAX_DIAGNOSE_CHARS)
+            .map_or(0, |(index, _)| index);
+
+        format!(
+            "{}. The command failed with this output:\n{}",
+            request.unwrap_or("Fix the error in this command output"),
+            &tail[start..]
+        )
+    }
+
+    /// Lets suggestions that worked for this prompt before compete with the
+    /// fresh ones for the `max_suggestions` slots
+    async fn merge_cached_candidates(
+        &self,
+        prompt: &str,
+        embedding: Option<&PromptEmbedding<'_>>,
+        mut suggestions: Vec<Suggestion>,
+        max_suggestions: usize,
+    ) -> Vec<Suggestion> {
+        let mut candidates: Vec<Suggestion> = match async {
+            self.context()
+                .await?
+                .get_cached_candidates(prompt, embedding)
+                .await
+        }
+        .await
+        {
+            Ok(candidates) => candidates
+                .into_iter()
+                .filter(|candidate| suggestions.iter().all(|s| s.command != candidate.command))
+                .collect(),
+            Err(e) => {
+                warn!("Failed to load cached candidates: {e}");
+                return suggestions;
+            }
+        };
+        if candidates.is_empty() {
+            return suggestions;
+        }
+
+        debug!("Merging {} cached candidates", candidates.len());
+        if let Ok(context) = self.context().await {
+            context.rank_suggestions(&mut candidates).await;
+        }
+        suggestions.extend(candidates);
+        suggestions.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+        suggestions.truncate(max_suggestions);
+        suggestions
+    }
+
+    /// Embeds the prompt for semantic cache lookups, if enabled and the backend supports it
+    async fn embed_prompt(&self, prompt: &str) -> Option<Vec<f32>> {
+        let semantic = &self.settings.cache.semantic;
+        if !semantic.enabled {
+            return None;
+        }
+
+        let input = self.prompt_builder.build_embedding_input(prompt);
+        match self.ai_client().ok()?.embed(&semantic.model, &input).await {
+            Ok(embedding) => Some(embedding),
+            Err(e) => {
+                debug!("Semantic cache lookup unavailable: {e}");
+                None
+            }
+        }
+    }
+
+    /// Serves ranked matches from the cache and shell history under an offline banner
+    async fn serve_offline(
+        &self,
+        prompt: &str,
+        max_suggestions: usize,
+        reason: &str,
+    ) -> Result<Vec<Suggestion>> {
+        eprintln!(
+            "{}",
+            self.formatter.format_warning(&format!(
+                "Offline ({reason}): showing matches from cache and shell history"
+            ))
+        );
+        self.context()
+            .await?
+            .get_offline_suggestions(prompt, max_suggestions)
+            .await
+    }
 
-        // Show spinner while generating suggestions
-        let spinner = Spinner::new("Generating suggestions...");
+    /// Refines the current session's suggestions with a follow-up request,
+    /// keeping the earlier turns as conversational context
+    pub async fn handle_followup(
+        &mut self,
+        original_prompt: &str,
+        modification: &str,
+        options: PromptOptions,
+    ) -> Result<Vec<Suggestion>> {
+        let followup_prompt = format!("{original_prompt} ({modification})");
+
+        // Without a session (e.g. the answer came from the cache) start a fresh one
+        let Some(conversation) = self.conversation.take() else {
+            return self.handle_prompt(&followup_prompt, options).await;
+        };
+
+        debug!("Processing follow-up: {modification}");
 
-        // Generate suggestions via AI
+        let message = self.prompt_builder.build_followup_message(modification);
         let suggestions = self
-            .ai_client
-            .generate_suggestions(prompt, &context_data, options.max_suggestions)
+            .continue_conversation(conversation, message, options.max_suggestions)
+            .await?;
+
+        if self
+            .target_shell
+            .is_none_or(|shell| shell == ShellKind::detect())
+        {
+            self.cache_suggestions(&followup_prompt, &suggestions).await;
+        }
+
+        Ok(suggestions)
+    }
+
+    /// Ordered steps for `prompt`: the workflow that ran for it before, or a
+    /// plan from the model
+    pub async fn handle_plan(
+        &mut self,
+        prompt: &str,
+        options: PromptOptions,
+    ) -> Result<Vec<Suggestion>> {
+        debug!("Planning: {prompt}");
+        self.context_mut().await?.set_incognito(options.incognito);
+
+        let foreign_shell = options.shell.filter(|&shell| shell != ShellKind::detect());
+        if !options.no_cache && foreign_shell.is_none() {
+            match self
+                .context()
+                .await?
+                .cache
+                .get_workflow(&self.context().await?.redact(prompt))
+                .await
+            {
+                Ok(Some(steps)) => {
+                    info!("Found a recorded workflow for prompt");
+                    return Ok(steps);
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Failed to look up recorded workflows: {e}"),
+            }
+        }
+        if options.offline {
+            bail!("No workflow recorded for this request; plans need the model");
+        }
+
+        if let Err(e) = self
+            .context_mut()
+            .await?
+            .ensure_environment(options.refresh_env)
+            .await
+        {
+            warn!("Failed to refresh the environment: {e}");
+        }
+        let mut context_data = self.context().await?.get_relevant_context(prompt).await?;
+        if let Some(shell) = foreign_shell {
+            context_data.aliases.clear();
+            context_data.target_shell = Some(shell);
+        }
+
+        let mut conversation = Conversation::new(
+            self.prompt_builder
+                .build_system_prompt(&context_data, MAX_PLAN_STEPS),
+        );
+        conversation.push_user(
+            self.prompt_builder
+                .build_plan_message(prompt, MAX_PLAN_STEPS),
+        );
+        let steps = self
+            .generate(&mut conversation, MAX_PLAN_STEPS, false)
             .await?;
+        self.conversation = Some(conversation);
+
+        Ok(steps)
+    }
+
+    /// Shows the plan and runs its steps one by one, offering a fix when one fails
+    pub async fn run_plan(&mut self, steps: Vec<Suggestion>, prompt: &str) -> Result<String> {
+        let result = self
+            .formatter
+            .run_plan(
+                &steps,
+                prompt,
+                Self::open_context(&mut self.context, &self.settings).await?,
+            )
+            .await;
+        self.settle(result, prompt).await
+    }
+
+    /// Output of a command run outside the selector, asking for a fix and
+    /// showing it in the selector when the user wants one
+    async fn settle(&mut self, result: FormatResult, prompt: &str) -> Result<String> {
+        match result {
+            FormatResult::FixRequested(failure) => {
+                let fixes = self.handle_fix(&failure, self.followup_options()).await?;
+                self.format_suggestions(fixes, false, prompt).await
+            }
+            FormatResult::Executed(output)
+            | FormatResult::Output(output)
+            | FormatResult::Static(output) => Ok(output),
+            FormatResult::FollowupRequested(_) => Ok(String::new()),
+        }
+    }
+
+    /// Asks for commands that fix the command that just failed, within the
+    /// session when there is one. The answers aren't cached, as the request
+    /// carries this one failure's output
+    pub async fn handle_fix(
+        &mut self,
+        failure: &CommandFailure,
+        options: PromptOptions,
+    ) -> Result<Vec<Suggestion>> {
+        let request = Self::fix_prompt(failure);
+        let Some(conversation) = self.conversation.take() else {
+            return self.handle_prompt(&request, options).await;
+        };
+
+        debug!("Asking for a fix of: {}", failure.command);
+        let message = self.prompt_builder.build_followup_message(&request);
+        self.continue_conversation(conversation, message, options.max_suggestions)
+            .await
+    }
+
+    /// Request for a fix of `failure`, with the end of what it printed
+    fn fix_prompt(failure: &CommandFailure) -> String {
+        let exit = failure
+            .exit_code
+            .map_or("was killed by a signal".to_string(), |code| {
+                format!("exited with code {code}")
+            });
+        let request = format!("Fix `{}`, which {exit}", failure.command);
+
+        // Errors go to stderr; fall back to stdout for tools that print them there
+        let output = if failure.output.stderr.trim().is_empty() {
+            &failure.output.stdout
+        } else {
+            &failure.output.stderr
+        };
+        if output.trim().is_empty() {
+            return format!("{request} without printing anything");
+        }
+        Self::diagnosis_prompt(output, Some(&request))
+    }
 
+    /// Adds `message` to `conversation` and generates from it; the message is
+    /// dropped again when generation fails
+    async fn continue_conversation(
+        &mut self,
+        mut conversation: Conversation,
+        message: String,
+        max_suggestions: usize,
+    ) -> Result<Vec<Suggestion>> {
+        let checkpoint = conversation.len();
+        conversation.push_user(message);
+
+        let result = self
+            .generate(&mut conversation, max_suggestions, true)
+            .await;
+        if result.is_err() {
+            conversation.truncate(checkpoint);
+        }
+        self.conversation = Some(conversation);
+        result
+    }
+
+    /// Runs the backend on the conversation, asking the user whenever the model
+    /// needs a clarification before it can suggest commands. `ranked` orders
+    /// them by what worked before; plans keep the model's order
+    async fn generate(
+        &mut self,
+        conversation: &mut Conversation,
+        max_suggestions: usize,
+        ranked: bool,
+    ) -> Result<Vec<Suggestion>> {
+        for round in 0..=MAX_CLARIFICATIONS {
+            let generation = self
+                .generate_once(conversation, max_suggestions, ranked)
+                .await?;
+
+            let Some(question) = generation
+                .clarify
+                .filter(|_| generation.suggestions.is_empty())
+            else {
+                return Ok(self.shellcheck(conversation, generation.suggestions).await);
+            };
+
+            if round == MAX_CLARIFICATIONS {
+                break;
+            }
+
+            let Some(answer) = self.ask_clarification(&question)? else {
+                break;
+            };
+            conversation.push_user(self.prompt_builder.build_clarification_message(&answer));
+        }
+
+        Ok(Vec::new())
+    }
+
+    /// Notes what ShellCheck finds in each suggestion; with `fix` on, the
+    /// model is asked once for fixes of those it flagged, which replace
+    /// them when ShellCheck finds less in the fix
+    async fn shellcheck(
+        &mut self,
+        conversation: &mut Conversation,
+        mut suggestions: Vec<Suggestion>,
+    ) -> Vec<Suggestion> {
+        self.annotate_findings(&mut suggestions).await;
+
+        let flagged: Vec<usize> = (0..suggestions.len())
+            .filter(|&index| !suggestions[index].warnings.is_empty())
+            .collect();
+        if !self.settings.shellcheck.fix || flagged.is_empty() {
+            return suggestions;
+        }
+
+        let message = self.prompt_builder.build_shellcheck_message(
+            &flagged
+                .iter()
+                .map(|&index| {
+                    let suggestion = &suggestions[index];
+                    (suggestion.command.as_str(), suggestion.warnings.as_slice())
+                })
+                .collect::<Vec<_>>(),
+        );
+        conversation.push_user(message);
+        let mut fixes = match self.generate_once(conversation, flagged.len(), false).await {
+            Ok(generation) => generation.suggestions,
+            Err(e) => {
+                warn!("Failed to get fixes for ShellCheck findings: {e}");
+                return suggestions;
+            }
+        };
+        self.annotate_findings(&mut fixes).await;
+
+        for (index, fix) in flagged.into_iter().zip(fixes) {
+            let duplicate = suggestions.iter().any(|s| s.command == fix.command);
+            if !duplicate && fix.warnings.len() < suggestions[index].warnings.len() {
+                debug!(
+                    "Replaced \"{}\" with the ShellCheck fix \"{}\"",
+                    suggestions[index].command, fix.command
+                );
+                suggestions[index] = fix;
+            }
+        }
+        suggestions
+    }
+
+    /// Fills in `warnings` of each suggestion with what ShellCheck finds, all
+    /// checked at once; nothing when it's off, missing or can't read the shell
+    async fn annotate_findings(&self, suggestions: &mut [Suggestion]) {
+        let shell = self.target_shell.unwrap_or_else(ShellKind::detect);
+        if !self.settings.shellcheck.enabled || ShellCheck::dialect(shell).is_none() {
+            return;
+        }
+
+        let timeout = Duration::from_millis(self.settings.shellcheck.timeout_ms);
+        let findings = futures_util::future::join_all(
+            suggestions
+                .iter()
+                .map(|suggestion| ShellCheck::check(&suggestion.command, shell, timeout)),
+        )
+        .await;
+        for (suggestion, findings) in suggestions.iter_mut().zip(findings) {
+            suggestion.warnings = findings
+                .unwrap_or_default()
+                .iter()
+                .map(ShellCheckFinding::to_string)
+                .collect();
+        }
+    }
+
+    /// Shows the model's question and reads the answer; `None` if it can't be answered
+    fn ask_clarification(&self, question: &str) -> Result<Option<String>> {
+        if !self.interactive {
+            return Ok(None);
+        }
+        eprintln!("{}", self.formatter.format_info(question));
+
+        if !io::stdin().is_terminal() {
+            return Ok(None);
+        }
+
+        eprint!("> ");
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+
+        let answer = answer.trim();
+        Ok(Some(answer.to_string()).filter(|a| !a.is_empty()))
+    }
+
+    /// Runs the backend once, listing suggestions as they stream in. Ranked
+    /// suggestions come from all models of `[backend] models` when it lists
+    /// several, merged and ranked together
+    async fn generate_once(
+        &mut self,
+        conversation: &mut Conversation,
+        max_suggestions: usize,
+        ranked: bool,
+    ) -> Result<Generation> {
+        let spinner = self.formatter.spinner("Generating suggestions...");
+        let show_live =
+            |suggestion: &Suggestion| spinner.println(&format!("  {}", suggestion.command));
+
+        let started = Instant::now();
+        let ensemble = if ranked { self.ensemble()? } else { None };
+        let result = match ensemble {
+            Some(ensemble) => {
+                cancellable(ensemble.generate_suggestions(
+                    conversation,
+                    max_suggestions,
+                    &show_live,
+                ))
+                .await
+            }
+            None => {
+                cancellable(self.ai_client()?.generate_suggestions(
+                    conversation,
+                    max_suggestions,
+                    &show_live,
+                ))
+                .await
+            }
+        };
         spinner.stop();
-        info!("Generated {} suggestions", suggestions.len());
+        let latency = started.elapsed();
+        let mut generation = result??;
 
-        // Cache successful results
-        for suggestion in &suggestions {
-            if let Err(e) = self.context.cache_suggestion(prompt, suggestion) {
-                warn!("Failed to cache suggestion: {e}");
+        if ranked {
+            self.context()
+                .await?
+                .rank_suggestions(&mut generation.suggestions)
+                .await;
+            // Several models together suggest more than was asked for
+            generation.suggestions.truncate(max_suggestions);
+            // Commands for tools that aren't installed come last, as install steps
+            if self.settings.execution.suggest_installs {
+                let room = max_suggestions.saturating_sub(generation.suggestions.len());
+                generation
+                    .suggestions
+                    .extend(Timing::measure("install lookup", || {
+                        Self::install_suggestions(&generation.missing, room)
+                    }));
             }
         }
 
-        Ok(suggestions)
+        if let Err(e) = self
+            .context_mut()
+            .await?
+            .record_generation(
+                generation.backend.as_deref(),
+                generation.outcome,
+                started.elapsed(),
+            )
+            .await
+        {
+            warn!("Failed to record generation outcome: {e}");
+        }
+
+        info!(
+            "Generated {} suggestions ({}) in {} ms",
+            generation.suggestions.len(),
+            generation.outcome.as_str(),
+            latency.as_millis()
+        );
+        Ok(generation)
+    }
+
+    /// Up to `limit` of the commands whose executable is missing, each with
+    /// the install of its package put before it; none without a known
+    /// package manager, or for tools it has no package for
+    fn install_suggestions(missing: &[MissingTool], limit: usize) -> Vec<Suggestion> {
+        if missing.is_empty() || limit == 0 {
+            return Vec::new();
+        }
+        let Some(manager) = PackageManager::detect() else {
+            debug!("No package manager found to install missing tools");
+            return Vec::new();
+        };
+
+        missing
+            .iter()
+            .filter_map(|tool| {
+                let package = manager
+                    .package_for(&tool.executable)
+                    .filter(|package| manager.has_package(package))?;
+                let suggestion = &tool.suggestion;
+                let install = format!(
+                    "Install step: {} isn't installed, so {package} is installed with {} first.",
+                    tool.executable,
+                    manager.as_str()
+                );
+                Some(Suggestion {
+                    command: format!(
+                        "{} && {}",
+                        manager.install_command(&package),
+                        suggestion.command
+                    ),
+                    explanation: Some(match &suggestion.explanation {
+                        Some(explanation) => format!("{install} {explanation}"),
+                        None => install,
+                    }),
+                    source: SuggestionSource::Install,
+                    ..suggestion.clone()
+                })
+            })
+            .take(limit)
+            .collect()
+    }
+
+    async fn cache_suggestions(&mut self, prompt: &str, suggestions: &[Suggestion]) {
+        // An install only needs to happen once
+        let cached = suggestions
+            .iter()
+            .filter(|suggestion| suggestion.source != SuggestionSource::Install);
+        for suggestion in cached {
+            if let Err(e) = async {
+                self.context_mut()
+                    .await?
+                    .cache_suggestion(prompt, suggestion)
+                    .await
+            }
+            .await
+            {
+                warn!("Failed to cache suggestion: {e}");
+            }
+        }
     }
 
     pub async fn handle_command(&mut self, command: Commands) -> Result<String> {
         match command {
-            Commands::Init => self.handle_init().await,
-            Commands::Update { model, binary } => self.handle_update(model, binary),
-            Commands::Config => self.handle_config(),
-            Commands::Clear { cache, context } => self.handle_clear(cache, context),
+            Commands::Init {
+                project,
+                shell_integration,
+                uninstall,
+            } => {
+                if shell_integration {
+                    self.handle_shell_integration(uninstall)
+                } else {
+                    self.handle_init(project).await
+                }
+            }
+            Commands::Update {
+                model,
+                binary,
+                check,
+            } => self.handle_update(model, binary, check).await,
+            Commands::Config => self.handle_config().await,
+            Commands::Clear { cache, context } => self.handle_clear(cache, context).await,
             Commands::Doctor => self.handle_doctor().await,
+            Commands::Warmup => self.handle_warmup().await,
+            Commands::Commit => self.handle_commit().await,
+            Commands::Bench { models } => self.handle_bench(models).await,
+            Commands::Cache { action } => self.handle_cache(action).await,
+            Commands::Context { action } => self.handle_context(action).await,
+            Commands::History { action, json } => self.handle_history(action, json).await,
+            Commands::Save { name, command } => self.handle_save(&name, command).await,
+            Commands::Run { name } => self.handle_run(&name).await,
+            Commands::Favorites { action, json } => self.handle_favorites(action, json).await,
+            Commands::Stats { days, json } => self.handle_stats(days, json).await,
+            Commands::Audit { action, json } => self.handle_audit(action, json).await,
+            Commands::RefreshEnv => self.handle_refresh_env().await,
+            Commands::Backup { action } => Self::handle_backup(action),
+            Commands::Daemon => {
+                // Questions from the model go unanswered, so clients ask them themselves
+                self.interactive = false;
+                daemon::serve(self).await?;
+                Ok(String::new())
+            }
+            Commands::Completions { shell } => Ok(Self::completion_script(shell)),
+            Commands::ShellInit { shell } => Ok(integration::script(shell).to_string()),
             Commands::Version => self.handle_version(),
         }
     }
 
-    async fn handle_init(&mut self) -> Result<String> {
+    async fn handle_init(&mut self, project: bool) -> Result<String> {
+        if project {
+            let path = self.context().await?.create_project_context()?;
+            return Ok(self
+                .formatter
+                .format_success(&format!("Project context file ready at {}", path.display())));
+        }
+
         info!("Initializing Phloem");
 
-        let spinner = Spinner::new("Initializing phloem...");
+        let spinner = self.formatter.spinner("Initializing phloem...");
 
         // Initialize ~/.phloem directory
-        self.context.initialize_directory()?;
+        self.context_mut().await?.initialize_directory().await?;
 
-        // Check Ollama service
-        if let Err(e) = self.ai_client.verify_connection().await {
+        // Check AI backend service
+        if let Err(e) = self.ai_client()?.verify_connection().await {
             spinner.stop();
             return Ok(self.formatter.format_warning(&format!(
-                "Ollama service not available: {e}. Make sure Ollama is installed and running."
+                "{} backend not available: {e}. Make sure the service is installed and running.",
+                self.ai_client()?.name()
             )));
         }
 
         spinner.stop();
-        Ok(self
+
+        // Download the configured model if needed, showing progress
+        let progress = DownloadProgress::new(&self.settings.ollama.model);
+        let pulled = self
+            .ai_client()?
+            .ensure_model_available(&|event| progress.update(event))
+            .await;
+        progress.finish();
+
+        if let Err(e) = pulled {
+            return Ok(self
+                .formatter
+                .format_warning(&format!("Model not available: {e}")));
+        }
+
+        // Pre-load the model so the first prompt isn't slowed by loading it
+        let spinner = self.formatter.spinner("Loading model...");
+        if let Err(e) = self.ai_client()?.warmup().await {
+            warn!("Model warmup failed: {e}");
+        }
+        spinner.stop();
+
+        let mut output = self
             .formatter
-            .format_success("Phloem initialized successfully"))
+            .format_success("Phloem initialized successfully");
+        let integrated = ShellDetector::get_shell_config_file()
+            .is_some_and(|rc_file| integration::is_installed(Path::new(&rc_file)));
+        if !integrated && integration::detect_shell().is_some() {
+            output.push('\n');
+            output.push_str(&self.formatter.format_info(
+                "Run `phloem init --shell-integration` to ask phloem about your command line with Ctrl+G",
+            ));
+        }
+        Ok(output)
     }
 
-    fn handle_update(&mut self, model: bool, binary: bool) -> Result<String> {
+    /// Adds the shell integration to the rc file of the user's shell, or removes it
+    fn handle_shell_integration(&self, uninstall: bool) -> Result<String> {
+        let Some(shell) = integration::detect_shell() else {
+            return Ok(self.formatter.format_warning(&format!(
+                "No shell integration for {}; bash, zsh and fish are supported",
+                ShellDetector::detect_shell()
+            )));
+        };
+        let Some(rc_file) = ShellDetector::get_shell_config_file() else {
+            return Ok(self
+                .formatter
+                .format_warning("Couldn't find your shell's rc file; is $HOME set?"));
+        };
+        let rc_file = PathBuf::from(rc_file);
+
+        if uninstall {
+            return Ok(if integration::uninstall(&rc_file)? {
+                self.formatter.format_success(&format!(
+                    "Removed the shell integration from {}; open a new shell for it to take effect",
+                    rc_file.display()
+                ))
+            } else {
+                self.formatter
+                    .format_info(&format!("No shell integration in {}", rc_file.display()))
+            });
+        }
+
+        Ok(if integration::install(&rc_file, shell)? {
+            self.formatter.format_success(&format!(
+                "Shell integration added to {}; open a new shell, then press Ctrl+G on a line to ask phloem about it",
+                rc_file.display()
+            ))
+        } else {
+            self.formatter.format_info(&format!(
+                "Shell integration already in {}",
+                rc_file.display()
+            ))
+        })
+    }
+
+    async fn handle_update(&mut self, model: bool, binary: bool, check: bool) -> Result<String> {
         if !model && !binary {
             return Ok(self
                 .formatter
@@ -124,51 +1349,157 @@ impl CommandHandler {
         let mut messages = Vec::new();
 
         if model {
-            messages.push("Model update not yet implemented");
+            messages.push(self.update_model(check).await?);
         }
 
         if binary {
-            messages.push("Binary update not yet implemented");
+            messages.push(self.update_binary(check).await?);
         }
 
         Ok(messages.join("\n"))
     }
 
-    fn handle_config(&self) -> Result<String> {
+    /// Pulls the configured model again and tells whether its digest changed.
+    /// Hosted models have nothing to pull
+    async fn update_model(&self, check: bool) -> Result<String> {
+        let model = self.ai_client()?.model().to_string();
+        if check {
+            return Ok(self.formatter.format_info(&format!(
+                "Model updates can't be checked without pulling; run `phloem update --model` to pull {model} again"
+            )));
+        }
+
+        let progress = DownloadProgress::new(&model);
+        let updated = self
+            .ai_client()?
+            .update_model(&|event| progress.update(event))
+            .await;
+        progress.finish();
+
+        let short = |digest: &str| {
+            let digest = digest.trim_start_matches("sha256:");
+            digest[..digest.len().min(12)].to_string()
+        };
+        Ok(match updated? {
+            None => self.formatter.format_info(&format!(
+                "{model} is served by {}, which keeps it up to date",
+                self.ai_client()?.name()
+            )),
+            Some(update) => match update.previous {
+                None => self
+                    .formatter
+                    .format_success(&format!("Downloaded {model} ({})", short(&update.current))),
+                Some(previous) if update.changed() => self.formatter.format_success(&format!(
+                    "Updated {model}: {} → {}",
+                    short(&previous),
+                    short(&update.current)
+                )),
+                Some(_) => self.formatter.format_success(&format!(
+                    "{model} is up to date ({})",
+                    short(&update.current)
+                )),
+            },
+        })
+    }
+
+    /// Replaces the running binary with the latest GitHub release, or with
+    /// `check` only tells whether there is a newer one
+    async fn update_binary(&self, check: bool) -> Result<String> {
+        let current = env!("CARGO_PKG_VERSION");
+        let spinner = self.formatter.spinner("Checking for updates...");
+        let release = Release::latest().await;
+        spinner.stop();
+        let release = release?;
+
+        if !release.is_newer() {
+            return Ok(self
+                .formatter
+                .format_success(&format!("phloem {current} is the latest version")));
+        }
+        if check {
+            return Ok(self.formatter.format_info(&format!(
+                "phloem {} is available (installed: {current}), see {}\nRun `phloem update --binary` to install it",
+                release.version(),
+                release.url
+            )));
+        }
+
+        let progress = DownloadProgress::for_file(&format!("phloem {}", release.version()));
+        let installed = release
+            .install(&|completed, total| progress.update_bytes(completed, total))
+            .await;
+        progress.finish();
+        let path = installed?;
+
+        Ok(self.formatter.format_success(&format!(
+            "Updated phloem {current} to {} at {}",
+            release.version(),
+            path.display()
+        )))
+    }
+
+    /// The settings and paths in use, and the cache's statistics when it
+    /// opens; a broken cache doesn't stop the rest from showing
+    async fn handle_config(&self) -> Result<String> {
+        let storage = StorageManager::new()?;
         let mut config_info = format!(
             "Phloem Configuration:\n\
             - Config file: {:?}\n\
             - Context file: {:?}\n\
             - Cache database: {:?}\n\
-            - Model path: {:?}\n\
+            - Backends: {}\n\
+            - Ollama URL: {}\n\
+            - Ollama model: {}\n\
+            - Temperature: {} | Max tokens: {}\n\
+            - Local only: {}\n\
             - Max suggestions: {}\n\
             - Use colors: {}\n\n",
             self.settings.get_config_path(),
-            self.context.get_context_file_path(),
-            self.context.get_cache_path(),
-            self.settings.model.model_path,
+            storage.get_context_file_path(),
+            storage
+                .get_phloem_dir()
+                .join("cache")
+                .join("suggestions.db"),
+            self.ai_client()?.endpoint(),
+            self.settings.ollama.base_url,
+            self.settings.ollama.model,
+            self.settings.model.temperature,
+            self.settings.model.max_tokens,
+            self.settings.privacy.local_only,
             self.settings.output.max_suggestions,
             self.settings.output.use_colors
         );
 
+        if let Some(project_file) = storage.get_project_context_file_path() {
+            config_info.push_str(&format!("Project context file: {project_file:?}\n\n"));
+        }
+
+        if self.settings.backend.models.len() >= 2 {
+            config_info.push_str(&format!(
+                "Models asked together: {}\n\n",
+                self.settings.backend.models.join(", ")
+            ));
+        }
+
         // Add cache statistics
-        if let Ok(stats) = self.context.cache.get_cache_stats() {
-            config_info.push_str(&stats);
+        match async { self.context().await?.cache.get_cache_stats().await }.await {
+            Ok(stats) => config_info.push_str(&stats),
+            Err(e) => config_info.push_str(&format!("Cache unavailable: {e}\n")),
         }
 
         Ok(config_info)
     }
 
-    fn handle_clear(&mut self, cache: bool, context: bool) -> Result<String> {
+    async fn handle_clear(&mut self, cache: bool, context: bool) -> Result<String> {
         let mut messages = Vec::new();
 
         if cache {
-            self.context.clear_cache()?;
+            self.context_mut().await?.clear_cache().await?;
             messages.push(self.formatter.format_success("Cache cleared"));
         }
 
         if context {
-            self.context.clear_context()?;
+            self.context_mut().await?.clear_context().await?;
             messages.push(self.formatter.format_success("Context cleared"));
         }
 
@@ -182,8 +1513,443 @@ impl CommandHandler {
         Ok(messages.join("\n"))
     }
 
+    async fn handle_refresh_env(&mut self) -> Result<String> {
+        let environment = self.context_mut().await?.refresh_environment().await?;
+        let executables = self.context_mut().await?.refresh_executables().await?;
+        let tools = environment.get("available_tools").map_or(0, |tools| {
+            tools.split(',').filter(|tool| !tool.is_empty()).count()
+        });
+
+        let mut lines = vec![self.formatter.format_success(&format!(
+            "Environment refreshed: {tools} tools available, {executables} executables in PATH"
+        ))];
+        for key in ["container_runtime", "cloud_provider", "gpu"] {
+            if let Some(value) = environment.get(key) {
+                lines.push(format!("  {key}: {value}"));
+            }
+        }
+        Ok(lines.join("\n"))
+    }
+
+    async fn handle_cache(&mut self, action: CacheAction) -> Result<String> {
+        match action {
+            CacheAction::Stats => self.context().await?.cache.get_cache_stats().await,
+            CacheAction::List { category, limit } => {
+                let entries = self
+                    .context()
+                    .await?
+                    .list_cache_entries(category.as_deref(), limit)
+                    .await?;
+                if entries.is_empty() {
+                    return Ok(self.formatter.format_info("No cached suggestions"));
+                }
+
+                let lines: Vec<String> = entries
+                    .iter()
+                    .map(|(category, entry)| {
+                        format!(
+                            "{}\n   {} [{category}] · used {}× · {:.0}% success · last {}",
+                            entry.command,
+                            entry.prompt,
+                            entry.use_count,
+                            entry.success_rate * 100.0,
+                            entry.last_used.as_deref().unwrap_or("never")
+                        )
+                    })
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            CacheAction::Prune { days } => {
+                let pruned = self.context_mut().await?.cache.prune_old_data(days).await?;
+                Ok(self.formatter.format_success(&format!(
+                    "Removed {pruned} suggestions older than {days} days"
+                )))
+            }
+            CacheAction::Export {
+                file,
+                format,
+                category,
+                successful,
+            } => {
+                let export = self
+                    .context()
+                    .await?
+                    .export_cache(category.as_deref(), successful)
+                    .await?;
+                let content = match format {
+                    ExportFormat::Json => serde_json::to_string_pretty(&export)?,
+                };
+                std::fs::write(&file, content)?;
+                Ok(self.formatter.format_success(&format!(
+                    "Exported {} suggestions to {}",
+                    export.entries.len(),
+                    file.display()
+                )))
+            }
+            CacheAction::Import { file } => {
+                let content = std::fs::read_to_string(&file)?;
+                let entries = CacheExport::parse(&content)
+                    .map_err(|e| anyhow::anyhow!("Invalid cache file {}: {e}", file.display()))?;
+                let imported = self
+                    .context_mut()
+                    .await?
+                    .cache
+                    .import_entries(&entries)
+                    .await?;
+                Ok(self.formatter.format_success(&format!(
+                    "Imported {imported} of {} suggestions ({} already cached)",
+                    entries.len(),
+                    entries.len() - imported
+                )))
+            }
+        }
+    }
+
+    async fn handle_context(&mut self, action: ContextAction) -> Result<String> {
+        match action {
+            ContextAction::Show { category } => {
+                let patterns = self
+                    .context()
+                    .await?
+                    .list_learned_patterns(category.as_deref())
+                    .await?;
+                if patterns.is_empty() {
+                    return Ok(self.formatter.format_info("No learned patterns"));
+                }
+
+                let lines: Vec<String> = patterns
+                    .iter()
+                    .map(|pattern| {
+                        format!(
+                            "{:>5}  {}\n       [{}] · {}",
+                            pattern.id.unwrap_or_default(),
+                            render_pattern(pattern),
+                            pattern.category,
+                            pattern.created_at.as_deref().unwrap_or("unknown")
+                        )
+                    })
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            ContextAction::Add { category, note } => {
+                self.context_mut()
+                    .await?
+                    .add_context_note(&category, &note)
+                    .await?;
+                Ok(self.formatter.format_success("Note added"))
+            }
+            ContextAction::Rm { id } => {
+                if self.context_mut().await?.remove_learned_pattern(id).await? {
+                    Ok(self
+                        .formatter
+                        .format_success(&format!("Removed pattern {id}")))
+                } else {
+                    Ok(self
+                        .formatter
+                        .format_warning(&format!("No learned pattern with id {id}")))
+                }
+            }
+            ContextAction::Edit => {
+                let path = self.context().await?.get_active_context_file_path().clone();
+                let editor = std::env::var("VISUAL")
+                    .or_else(|_| std::env::var("EDITOR"))
+                    .unwrap_or_else(|_| {
+                        if cfg!(target_os = "windows") {
+                            "notepad".to_string()
+                        } else {
+                            "vi".to_string()
+                        }
+                    });
+
+                // The editor setting may carry its own arguments, e.g. `code --wait`
+                let editing = interrupt::child_running();
+                let status = if cfg!(target_os = "windows") {
+                    std::process::Command::new(&editor).arg(&path).status()
+                } else {
+                    std::process::Command::new("sh")
+                        .args(["-c", &format!("{editor} \"$1\""), "sh"])
+                        .arg(&path)
+                        .status()
+                }
+                .map_err(|e| anyhow::anyhow!("Failed to start editor {editor}: {e}"))?;
+                drop(editing);
+
+                if !status.success() {
+                    return Ok(self
+                        .formatter
+                        .format_warning(&format!("{editor} exited with {status}")));
+                }
+                Ok(self.formatter.format_info(
+                    "Saved. The Learned Patterns section is regenerated; use `phloem context add` and `phloem context rm` to change it",
+                ))
+            }
+        }
+    }
+
+    async fn handle_history(&self, action: HistoryAction, json: bool) -> Result<String> {
+        let entries = match action {
+            HistoryAction::List { limit } => {
+                self.context().await?.cache.get_history(Some(limit)).await?
+            }
+            HistoryAction::Search { term, limit } => {
+                self.context()
+                    .await?
+                    .cache
+                    .search_history(&term, limit)
+                    .await?
+            }
+            HistoryAction::Top { limit } => {
+                let tools = self.context().await?.get_top_tools(limit).await?;
+                if json {
+                    return Ok(serde_json::to_string_pretty(&tools)?);
+                }
+                if tools.is_empty() {
+                    return Ok(self.formatter.format_info("No commands run yet"));
+                }
+
+                let lines: Vec<String> = tools
+                    .iter()
+                    .map(|usage| {
+                        format!(
+                            "{:>5}× {:>4.0}% ok  {}",
+                            usage.uses,
+                            usage.successes as f64 / usage.uses as f64 * 100.0,
+                            usage.tool
+                        )
+                    })
+                    .collect();
+                return Ok(lines.join("\n"));
+            }
+        };
+
+        if json {
+            return Ok(serde_json::to_string_pretty(&entries)?);
+        }
+        if entries.is_empty() {
+            return Ok(self.formatter.format_info("No matching commands"));
+        }
+
+        let lines: Vec<String> = entries
+            .iter()
+            .map(|entry| {
+                format!(
+                    "{}  {}  {}\n   {}",
+                    entry.executed_at,
+                    if entry.success { "✓" } else { "✗" },
+                    entry.command,
+                    entry.prompt
+                )
+            })
+            .collect();
+        Ok(lines.join("\n"))
+    }
+
+    async fn handle_audit(&self, action: AuditAction, json: bool) -> Result<String> {
+        let Some(audit) = self.context().await?.audit_log() else {
+            return Ok(self
+                .formatter
+                .format_warning("The audit log is off; set enabled = true under [audit]"));
+        };
+
+        match action {
+            AuditAction::Show { limit } => {
+                let entries = audit.entries(limit)?;
+                if json {
+                    return Ok(serde_json::to_string_pretty(&entries)?);
+                }
+                if entries.is_empty() {
+                    return Ok(self.formatter.format_info("No commands run yet"));
+                }
+
+                let lines: Vec<String> = entries
+                    .iter()
+                    .map(|entry| {
+                        format!(
+                            "#{} {}  {}@{}  exit {}  {}\n   {}\n   in {}",
+                            entry.seq,
+                            entry.started_at.format("%Y-%m-%d %H:%M:%S"),
+                            entry.user,
+                            entry.host,
+                            entry
+                                .exit_code
+                                .map_or_else(|| "-".to_string(), |code| code.to_string()),
+                            entry.command,
+                            entry.prompt,
+                            entry.cwd
+                        )
+                    })
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            AuditAction::Verify => {
+                let verification = audit.verify()?;
+                if json {
+                    return Ok(serde_json::to_string_pretty(&match &verification {
+                        Ok(entries) => serde_json::json!({ "valid": true, "entries": entries }),
+                        Err(broken) => serde_json::json!({
+                            "valid": false,
+                            "line": broken.line,
+                            "reason": broken.reason,
+                        }),
+                    })?);
+                }
+                match verification {
+                    Ok(entries) => Ok(self.formatter.format_success(&format!(
+                        "{} is intact: {entries} entries",
+                        audit.path().display()
+                    ))),
+                    Err(broken) => bail!(
+                        "{} is broken at line {}: {}",
+                        audit.path().display(),
+                        broken.line,
+                        broken.reason
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Saves `command`, or the last command run from phloem, as `name`
+    async fn handle_save(&mut self, name: &str, command: Option<String>) -> Result<String> {
+        if !Favorite::is_valid_name(name) {
+            return Ok(self
+                .formatter
+                .format_error("Names are one word without spaces that doesn't start with -"));
+        }
+        let (command, prompt) = match command {
+            Some(command) => (command, String::new()),
+            None => match self
+                .context()
+                .await?
+                .cache
+                .get_history(Some(1))
+                .await?
+                .pop()
+            {
+                Some(entry) => (entry.command, entry.prompt),
+                None => {
+                    return Ok(self.formatter.format_error(
+                        "Nothing run from phloem yet; give the command to save after the name",
+                    ))
+                }
+            },
+        };
+
+        let replaced = self
+            .context_mut()
+            .await?
+            .cache
+            .save_favorite(name, &command, &prompt)
+            .await?;
+        let verb = if replaced { "Replaced" } else { "Saved" };
+        Ok(self.formatter.format_success(&format!(
+            "{verb} {name}: {command}\nRun it with: phloem run {name}"
+        )))
+    }
+
+    /// Runs the command saved as `name`, filling in its placeholders first
+    async fn handle_run(&mut self, name: &str) -> Result<String> {
+        let Some(favorite) = self.context().await?.cache.get_favorite(name).await? else {
+            return Ok(self.formatter.format_error(&format!(
+                "No command saved as {name}; see phloem favorites list"
+            )));
+        };
+        if let Err(e) = self
+            .context_mut()
+            .await?
+            .cache
+            .record_favorite_use(name)
+            .await
+        {
+            warn!("Failed to record the use of {name}: {e}");
+        }
+
+        let prompt = if favorite.prompt.is_empty() {
+            &favorite.command
+        } else {
+            &favorite.prompt
+        };
+        let result = self
+            .formatter
+            .run_picked(
+                &favorite.to_suggestion(),
+                prompt,
+                Self::open_context(&mut self.context, &self.settings).await?,
+            )
+            .await;
+        self.settle(result, prompt).await
+    }
+
+    async fn handle_favorites(&mut self, action: FavoriteAction, json: bool) -> Result<String> {
+        match action {
+            FavoriteAction::List => {
+                let favorites = self.context().await?.cache.list_favorites().await?;
+                if json {
+                    return Ok(serde_json::to_string_pretty(&favorites)?);
+                }
+                if favorites.is_empty() {
+                    return Ok(self
+                        .formatter
+                        .format_info("No saved commands; save one with phloem save NAME"));
+                }
+
+                let lines: Vec<String> = favorites
+                    .iter()
+                    .map(|favorite| {
+                        let mut line = format!(
+                            "{}  {}  ({} runs)",
+                            favorite.name, favorite.command, favorite.use_count
+                        );
+                        if !favorite.prompt.is_empty() {
+                            line.push_str(&format!("\n   {}", favorite.prompt));
+                        }
+                        line
+                    })
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            FavoriteAction::Rm { name } => {
+                if self
+                    .context_mut()
+                    .await?
+                    .cache
+                    .delete_favorite(&name)
+                    .await?
+                {
+                    Ok(self.formatter.format_success(&format!("Forgot {name}")))
+                } else {
+                    Ok(self
+                        .formatter
+                        .format_error(&format!("No command saved as {name}")))
+                }
+            }
+            FavoriteAction::Rename { name, new_name } => {
+                if !Favorite::is_valid_name(&new_name) {
+                    return Ok(self.formatter.format_error(
+                        "Names are one word without spaces that doesn't start with -",
+                    ));
+                }
+                if self
+                    .context_mut()
+                    .await?
+                    .cache
+                    .rename_favorite(&name, &new_name)
+                    .await?
+                {
+                    Ok(self
+                        .formatter
+                        .format_success(&format!("Renamed {name} to {new_name}")))
+                } else {
+                    Ok(self
+                        .formatter
+                        .format_error(&format!("No command saved as {name}")))
+                }
+            }
+        }
+    }
+
     async fn handle_doctor(&self) -> Result<String> {
-        let spinner = Spinner::new("Running diagnostics...");
+        let spinner = self.formatter.spinner("Running diagnostics...");
         let mut diagnostics = Vec::new();
 
         // Check directories
@@ -197,31 +1963,393 @@ impl CommandHandler {
             diagnostics.push("✗ ~/.phloem directory missing (run: phloem init)".to_string());
         }
 
-        // Check Ollama connection
-        match self.ai_client.verify_connection().await {
-            Ok(_) => diagnostics.push("✓ Ollama service running".to_string()),
-            Err(e) => diagnostics.push(format!("✗ Ollama service: {e}")),
+        // Check each AI backend in the fallback chain
+        let backends = match self.ai_client() {
+            Ok(ai_client) => ai_client.backends(),
+            Err(e) => {
+                diagnostics.push(format!("✗ Failed to set up the backends: {e}"));
+                &[]
+            }
+        };
+        for backend in backends {
+            match backend.verify_connection().await {
+                Ok(_) => diagnostics.push(format!(
+                    "✓ {} backend running at {} (model: {})",
+                    backend.name(),
+                    backend.endpoint(),
+                    backend.model()
+                )),
+                Err(e) => diagnostics.push(format!(
+                    "✗ {} backend at {}: {e}",
+                    backend.name(),
+                    backend.endpoint()
+                )),
+            }
         }
 
-        // Check database
-        if self.context.get_cache_path().exists() {
-            diagnostics.push("✓ Cache database exists".to_string());
-        } else {
-            diagnostics.push("✗ Cache database missing".to_string());
+        // Every model asked together has to be served
+        match self.ensemble() {
+            Ok(Some(ensemble)) => {
+                for model in ensemble.models() {
+                    match self.check_model_available(model).await {
+                        Ok(()) => diagnostics.push(format!("✓ Model {model} is served")),
+                        Err(e) => diagnostics.push(format!("✗ {e}")),
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(e) => diagnostics.push(format!("✗ Failed to set up backend.models: {e}")),
         }
 
-        // Check model
-        let model_path = PathBuf::from(&self.settings.model.model_path);
-        if model_path.exists() {
-            diagnostics.push("✓ Model files found".to_string());
+        // Check hardware acceleration
+        let detector = EnvironmentDetector::new();
+        let hardware = detector.detect_hardware();
+        match hardware.accelerator {
+            Some(accelerator) => diagnostics.push(format!(
+                "✓ GPU acceleration: {}{} ({} CPU cores)",
+                accelerator.as_str(),
+                detector
+                    .detect_gpu_name(accelerator)
+                    .map(|name| format!(" - {name}"))
+                    .unwrap_or_default(),
+                hardware.cpu_cores
+            )),
+            None => diagnostics.push(format!(
+                "✗ No GPU acceleration detected, models run on {} CPU cores",
+                hardware.cpu_cores
+            )),
+        }
+
+        // Check database, without creating it when it's missing
+        if !phloem_dir.join("cache").join("suggestions.db").exists() {
+            diagnostics.push("✗ Cache database missing".to_string());
+        } else if let Err(e) = self.context().await {
+            diagnostics.push(format!("✗ Cache database can't be opened: {e}"));
         } else {
-            diagnostics.push("✗ Model files missing (run installation script)".to_string());
+            diagnostics.push("✓ Cache database exists".to_string());
         }
 
+        diagnostics.extend(Self::config_diagnostics(&self.settings));
+
         spinner.stop();
         Ok(format!("Phloem Health Check:\n{}", diagnostics.join("\n")))
     }
 
+    /// Doctor lines for the config: every invalid and every deprecated key,
+    /// and whether the policy file loads
+    pub fn config_diagnostics(settings: &Settings) -> Vec<String> {
+        let mut problems = match settings.validate() {
+            Ok(()) => Vec::new(),
+            Err(InvalidConfig(errors)) => errors,
+        };
+        match Settings::deprecated_keys() {
+            Ok(deprecated) => problems.extend(deprecated),
+            Err(e) => return vec![format!("✗ Failed to read the config file: {e}")],
+        }
+
+        let policy = match Policy::load() {
+            Ok(_) => "✓ Policy is valid".to_string(),
+            Err(e) => format!("✗ {e:#}"),
+        };
+
+        if problems.is_empty() {
+            return vec!["✓ Configuration is valid".to_string(), policy];
+        }
+        problems
+            .iter()
+            .map(|problem| format!("✗ {problem}"))
+            .chain([policy])
+            .collect()
+    }
+
+    async fn handle_stats(&self, days: u32, json: bool) -> Result<String> {
+        let stats = self
+            .context()
+            .await?
+            .get_usage_stats(days, TOP_STATS)
+            .await?;
+        if json {
+            return Ok(serde_json::to_string_pretty(&stats)?);
+        }
+        if stats.prompts == 0 && stats.success_trend.is_empty() {
+            return Ok(self
+                .formatter
+                .format_info(&format!("No prompts in the last {days} days")));
+        }
+
+        let percent = |rate: Option<f64>| {
+            rate.map_or_else(|| "-".to_string(), |rate| format!("{:.1}%", rate * 100.0))
+        };
+        let mut lines = vec![
+            format!("Usage over the last {days} days:"),
+            format!(
+                "- Prompts: {} ({:.1} per day)",
+                stats.prompts,
+                stats.prompts as f64 / f64::from(days.max(1))
+            ),
+            format!("- Cache hit rate: {}", percent(stats.cache_hit_rate)),
+            format!("- Suggestions run: {}", percent(stats.acceptance_rate)),
+            format!(
+                "- Average generation time: {}",
+                stats
+                    .average_latency_ms
+                    .map_or_else(|| "-".to_string(), |ms| format!("{:.0} ms", ms))
+            ),
+        ];
+
+        if !stats.prompts_per_day.is_empty() {
+            lines.push("\nPrompts per day:".to_string());
+            let most = stats
+                .prompts_per_day
+                .iter()
+                .map(|day| day.prompts)
+                .max()
+                .unwrap_or(1);
+            lines.extend(stats.prompts_per_day.iter().map(|day| {
+                let bar = ((day.prompts * STATS_BAR_WIDTH as i64 + most - 1) / most) as usize;
+                format!("  {}  {:>4}  {}", day.date, day.prompts, "█".repeat(bar))
+            }));
+        }
+        if !stats.top_categories.is_empty() {
+            lines.push("\nTop categories:".to_string());
+            lines.extend(
+                stats
+                    .top_categories
+                    .iter()
+                    .map(|category| format!("  {:>5}  {}", category.prompts, category.category)),
+            );
+        }
+        if !stats.top_executables.is_empty() {
+            lines.push("\nTop executables:".to_string());
+            lines.extend(stats.top_executables.iter().map(|usage| {
+                format!(
+                    "  {:>5}× {:>4.0}% ok  {}",
+                    usage.uses,
+                    usage.successes as f64 / usage.uses as f64 * 100.0,
+                    usage.tool
+                )
+            }));
+        }
+        if !stats.success_trend.is_empty() {
+            lines.push("\nSuccess rate by week:".to_string());
+            lines.extend(stats.success_trend.iter().map(|week| {
+                format!(
+                    "  {}  {:>5.1}%  ({} runs)",
+                    week.week,
+                    week.success_rate * 100.0,
+                    week.runs
+                )
+            }));
+        }
+        Ok(lines.join("\n"))
+    }
+
+    async fn handle_warmup(&self) -> Result<String> {
+        let spinner = self.formatter.spinner("Loading model...");
+        let result = self.ai_client()?.warmup().await;
+        spinner.stop();
+
+        match result {
+            Ok(()) => Ok(self.formatter.format_success(&format!(
+                "Model {} loaded (keep_alive: {})",
+                self.ai_client()?.model(),
+                self.settings.ollama.keep_alive
+            ))),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn handle_commit(&mut self) -> Result<String> {
+        let Some(staged_diff) = GitInspector::staged_diff(MAX_COMMIT_DIFF_CHARS)? else {
+            return Ok(self
+                .formatter
+                .format_warning("No staged changes. Stage files with `git add` first."));
+        };
+
+        let mut conversation = Conversation::new(self.prompt_builder.build_commit_system_prompt());
+        conversation.push_user(self.prompt_builder.build_commit_message(&staged_diff));
+
+        let suggestions: Vec<Suggestion> = self
+            .generate(
+                &mut conversation,
+                self.settings.output.max_suggestions,
+                true,
+            )
+            .await?
+            .into_iter()
+            .filter(|s| s.command.starts_with("git commit"))
+            .collect();
+        self.conversation = Some(conversation);
+
+        if suggestions.is_empty() {
+            return Ok(self
+                .formatter
+                .format_error("No commit message suggested. Try again or use a different model."));
+        }
+
+        let explain = self.settings.output.show_explanations;
+        self.format_suggestions(suggestions, explain, COMMIT_PROMPT)
+            .await
+    }
+
+    async fn handle_bench(&self, models: Vec<String>) -> Result<String> {
+        let models = if models.is_empty() {
+            vec![self.ai_client()?.model().to_string()]
+        } else {
+            models
+        };
+        let max_suggestions = self.settings.output.max_suggestions;
+        let mut results = Vec::new();
+
+        for model in &models {
+            let backend = create_backend_for_model(&self.settings, model)?;
+
+            // Keep model load time out of the measurements
+            let spinner = self.formatter.spinner(&format!("Loading {model}..."));
+            let warmup = cancellable(backend.warmup()).await;
+            spinner.stop();
+            if let Err(e) = warmup? {
+                warn!("Failed to warm up {model}: {e}");
+            }
+
+            let mut result = BenchResult {
+                model: model.clone(),
+                latencies: Vec::new(),
+                valid_json: 0,
+                repaired: 0,
+                usable: 0,
+                errors: 0,
+            };
+
+            for (index, prompt) in BENCH_PROMPTS.iter().enumerate() {
+                let context_data = self.context().await?.get_relevant_context(prompt).await?;
+                let mut conversation = Conversation::new(
+                    self.prompt_builder
+                        .build_system_prompt(&context_data, max_suggestions),
+                );
+                conversation.push_user(self.prompt_builder.build_user_message(prompt));
+
+                let spinner = self.formatter.spinner(&format!(
+                    "Benchmarking {model} ({}/{})...",
+                    index + 1,
+                    BENCH_PROMPTS.len()
+                ));
+                let started = Instant::now();
+                let outcome = cancellable(backend.generate_suggestions(
+                    &mut conversation,
+                    max_suggestions,
+                    &|_| {},
+                ))
+                .await;
+                let elapsed = started.elapsed();
+                spinner.stop();
+
+                match outcome? {
+                    Ok(generation) => {
+                        result.latencies.push(elapsed);
+                        match generation.outcome {
+                            ParseOutcome::Valid => result.valid_json += 1,
+                            ParseOutcome::Repaired => result.repaired += 1,
+                            ParseOutcome::Fallback => {}
+                        }
+                        if !generation.suggestions.is_empty() {
+                            result.usable += 1;
+                        }
+                    }
+                    Err(e) => {
+                        debug!("Bench prompt failed on {model}: {e}");
+                        result.errors += 1;
+                    }
+                }
+            }
+
+            results.push(result);
+        }
+
+        Ok(Self::format_bench_results(&results))
+    }
+
+    fn format_bench_results(results: &[BenchResult]) -> String {
+        let total = BENCH_PROMPTS.len();
+        let model_width = results
+            .iter()
+            .map(|r| r.model.len())
+            .max()
+            .unwrap_or(0)
+            .max("Model".len());
+
+        let mut table = format!(
+            "Benchmark over {total} prompts:\n{:<model_width$}  {:>8}  {:>8}  {:>10}  {:>8}  {:>8}  {:>6}\n",
+            "Model", "Avg", "Max", "Valid JSON", "Repaired", "Usable", "Errors"
+        );
+
+        for result in results {
+            let (avg, max) = match result.latencies.iter().max() {
+                Some(max) => {
+                    let sum: Duration = result.latencies.iter().sum();
+                    (
+                        format!(
+                            "{:.2}s",
+                            (sum / result.latencies.len() as u32).as_secs_f64()
+                        ),
+                        format!("{:.2}s", max.as_secs_f64()),
+                    )
+                }
+                None => ("-".to_string(), "-".to_string()),
+            };
+            let percent = |count: usize| format!("{:.0}%", count as f64 / total as f64 * 100.0);
+
+            table.push_str(&format!(
+                "{:<model_width$}  {:>8}  {:>8}  {:>10}  {:>8}  {:>8}  {:>6}\n",
+                result.model,
+                avg,
+                max,
+                percent(result.valid_json),
+                percent(result.repaired),
+                percent(result.usable),
+                result.errors
+            ));
+        }
+
+        table.push_str(
+            "\nValid JSON: parsed on the first try. Usable: at least one command passed validation.",
+        );
+        table
+    }
+
+    /// Completion script for `shell`, generated from the argument definitions
+    /// so it always matches the real CLI
+    pub fn completion_script(shell: Shell) -> String {
+        let mut script = Vec::new();
+        clap_complete::generate(shell, &mut Cli::command(), "phloem", &mut script);
+        String::from_utf8_lossy(&script).into_owned()
+    }
+
+    /// Runs without a handler, so a setup whose config or cache no longer
+    /// loads can still be restored
+    pub fn handle_backup(action: BackupAction) -> Result<String> {
+        let formatter = OutputFormatter::new(true);
+        let storage = StorageManager::new()?;
+        let phloem_dir = storage.get_phloem_dir();
+
+        match action {
+            BackupAction::Create { path } => {
+                let path = backup::create_backup(phloem_dir, path)?;
+                Ok(formatter.format_success(&format!("Backup written to {}", path.display())))
+            }
+            BackupAction::Restore { path } => {
+                std::fs::create_dir_all(phloem_dir)?;
+                let metadata = backup::restore_backup(phloem_dir, &path)?;
+                Ok(formatter.format_success(&format!(
+                    "Restored {} from the backup made {} by phloem {}",
+                    metadata.files.join(", "),
+                    metadata.created_at,
+                    metadata.phloem_version
+                )))
+            }
+        }
+    }
+
     fn handle_version(&self) -> Result<String> {
         Ok(format!(
             "phloem {}\nRust version: {}\nPlatform: {}",
@@ -237,17 +2365,45 @@ impl CommandHandler {
         show_explanations: bool,
         original_prompt: &str,
     ) -> Result<String> {
+        // The suggestion the user first asked to modify, if any
+        let mut replacing: Option<String> = None;
+
         loop {
-            match self.formatter.format_suggestions(
-                &suggestions,
-                show_explanations,
-                original_prompt,
-                &mut self.context,
-            ) {
+            suggestions.retain(|suggestion| self.is_permitted(suggestion));
+            match self
+                .formatter
+                .format_suggestions(
+                    &suggestions,
+                    show_explanations,
+                    original_prompt,
+                    replacing.as_deref(),
+                    Self::open_context(&mut self.context, &self.settings).await?,
+                )
+                .await
+            {
                 FormatResult::Executed(output) => return Ok(output),
                 FormatResult::Output(output) => return Ok(output),
                 FormatResult::Static(output) => return Ok(output),
-                FormatResult::FollowupRequested => {
+                FormatResult::FixRequested(failure) => {
+                    let options = self.followup_options();
+                    match self.handle_fix(&failure, options).await {
+                        Ok(new_suggestions) => {
+                            // The fix replaces the command that failed
+                            replacing = Some(failure.command);
+                            suggestions = new_suggestions;
+                            continue;
+                        }
+                        Err(e) if e.is::<Cancelled>() => {
+                            return Ok(self.formatter.format_warning("Fix cancelled"));
+                        }
+                        Err(e) => {
+                            return Ok(self.format_error(&format!("Failed to get a fix: {e}")));
+                        }
+                    }
+                }
+                FormatResult::FollowupRequested(index) => {
+                    replacing.get_or_insert_with(|| suggestions[index].command.clone());
+
                     // Ask user for modification request
                     println!("What would you like to modify about the command?");
                     let mut input = String::new();
@@ -258,24 +2414,20 @@ impl CommandHandler {
                         continue;
                     }
 
-                    // Create follow-up prompt (much cleaner)
-                    let followup_prompt =
-                        format!("{original_prompt} ({})", modification_request.trim());
+                    // Get new suggestions within the same conversation
+                    let options = self.followup_options();
 
-                    // Get new suggestions
-                    let options = PromptOptions {
-                        max_suggestions: 3,
-                        no_cache: true,
-                        explain: false,
-                        verbose: false,
-                    };
-
-                    match self.handle_prompt(&followup_prompt, options).await {
+                    match self
+                        .handle_followup(original_prompt, modification_request, options)
+                        .await
+                    {
                         Ok(new_suggestions) => {
                             // Replace suggestions and continue the loop
                             suggestions = new_suggestions;
                             continue;
                         }
+                        // Back to the previous suggestions
+                        Err(e) if e.is::<Cancelled>() => continue,
                         Err(e) => {
                             return Ok(self.format_error(&format!(
                                 "Failed to get follow-up suggestions: {e}"
@@ -287,6 +2439,75 @@ impl CommandHandler {
         }
     }
 
+    /// Options for suggestions asked for from the selector
+    fn followup_options(&self) -> PromptOptions {
+        PromptOptions {
+            max_suggestions: self.max_suggestions,
+            no_cache: true,
+            explain: false,
+            verbose: false,
+            offline: false,
+            incognito: self.context.get().is_some_and(ContextManager::is_incognito),
+            refresh_env: false,
+            shell: self.target_shell,
+        }
+    }
+
+    /// Prints suggestions in `mode` without the interactive selector
+    pub fn format_suggestions_as(
+        &self,
+        suggestions: &[Suggestion],
+        mode: OutputMode,
+        show_explanations: bool,
+        original_prompt: &str,
+    ) -> Result<String> {
+        let suggestions: Vec<Suggestion> = suggestions
+            .iter()
+            .filter(|suggestion| self.is_permitted(suggestion))
+            .cloned()
+            .collect();
+        Ok(self.formatter.format_suggestions_as(
+            &suggestions,
+            mode,
+            show_explanations,
+            original_prompt,
+        )?)
+    }
+
+    /// Whether the policy lets `suggestion` be shown; those it denies, those
+    /// running as root when `safety.elevation` blocks them and those changing
+    /// things over the network when its categories deny that are dropped,
+    /// whether the model or the cache came up with them
+    fn is_permitted(&self, suggestion: &Suggestion) -> bool {
+        let validator =
+            CommandValidator::for_shell(self.target_shell.unwrap_or_else(ShellKind::detect));
+        if !validator.is_safe_command(&suggestion.command) {
+            warn!("Dropped \"{}\": denied by the policy", suggestion.command);
+            return false;
+        }
+        if self.settings.safety.elevation == ElevationMode::Block
+            && !validator.elevated_actions(&suggestion.command).is_empty()
+        {
+            warn!("Dropped \"{}\": elevation is blocked", suggestion.command);
+            return false;
+        }
+        let allowed = validator
+            .verdict(&suggestion.command)
+            .is_some_and(|verdict| verdict.action == PolicyAction::Allow);
+        let categories = Policy::current().categories();
+        if !allowed
+            && categories.network == PolicyAction::Deny
+            && !validator.network_changes(&suggestion.command).is_empty()
+        {
+            warn!(
+                "Dropped \"{}\": changes over the network are denied",
+                suggestion.command
+            );
+            return false;
+        }
+        true
+    }
+
     pub fn format_error(&self, message: &str) -> String {
         self.formatter.format_error(message)
     }
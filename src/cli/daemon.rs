@@ -0,0 +1,254 @@
+use anyhow::{bail, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+
+use crate::cli::{CommandHandler, PromptOptions, Spinner, Suggestion};
+use crate::utils::Timing;
+
+/// How long a client waits to reach the daemon before answering the prompt itself
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// How long the daemon waits for a connected client to send its prompt
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a client waits for the answer before answering the prompt
+/// itself; a generation with its repair round-trip fits at the default
+/// 30 s backend timeout
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(75);
+
+/// Longest request or answer line read from the other side
+const MAX_LINE_BYTES: u64 = 1024 * 1024;
+
+/// A prompt sent by a client, one JSON line per connection
+#[derive(Serialize, Deserialize)]
+struct DaemonRequest {
+    prompt: String,
+    /// Working directory of the client, for project context
+    cwd: PathBuf,
+    options: PromptOptions,
+}
+
+/// The daemon's answer, one JSON line
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DaemonResponse {
+    Suggestions(Vec<Suggestion>),
+    Error(String),
+}
+
+/// Answers prompts from clients one at a time with `handler`, whose cache
+/// connection, environment snapshot and backend stay warm between them
+pub async fn serve(handler: &mut CommandHandler) -> Result<()> {
+    if let Err(e) = handler.warmup().await {
+        warn!("Model warmup failed: {e}");
+    }
+    // Clients show their own spinner while waiting
+    handler.hide_progress();
+
+    let mut listener = transport::Listener::bind()?;
+    eprintln!("phloem daemon listening on {}", transport::address()?);
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Failed to accept a daemon client: {e}");
+                continue;
+            }
+        };
+        if let Err(e) = answer(handler, stream).await {
+            warn!("Failed to answer a daemon client: {e}");
+        }
+    }
+}
+
+async fn answer<S: AsyncRead + AsyncWrite + Unpin>(
+    handler: &mut CommandHandler,
+    stream: S,
+) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    let Ok(line) = tokio::time::timeout(REQUEST_TIMEOUT, read_line(&mut stream)).await else {
+        bail!("The client sent no prompt within {REQUEST_TIMEOUT:?}");
+    };
+    let request: DaemonRequest = serde_json::from_str(&line?)?;
+
+    info!("Daemon prompt from {}", request.cwd.display());
+    Timing::reset();
+    let started = Instant::now();
+    let response = match handler
+        .handle_remote_prompt(&request.prompt, &request.cwd, request.options)
+        .await
+    {
+        Ok(suggestions) => DaemonResponse::Suggestions(suggestions),
+        Err(e) => DaemonResponse::Error(e.to_string()),
+    };
+    info!(
+        "Answered daemon prompt in {} ms",
+        started.elapsed().as_millis()
+    );
+
+    let mut reply = serde_json::to_string(&response)?;
+    reply.push('\n');
+    stream.get_mut().write_all(reply.as_bytes()).await?;
+    stream.get_mut().flush().await?;
+    Ok(())
+}
+
+/// Suggestions for `prompt` from a running daemon; `None` when none is running
+/// or it couldn't answer, so the caller generates them itself. A spinner is
+/// shown while it answers, unless `progress` is off
+pub async fn request(
+    prompt: &str,
+    options: &PromptOptions,
+    progress: bool,
+) -> Option<Vec<Suggestion>> {
+    let stream = tokio::time::timeout(CONNECT_TIMEOUT, transport::connect())
+        .await
+        .ok()?
+        .ok()?;
+
+    let request = DaemonRequest {
+        prompt: prompt.to_string(),
+        cwd: std::env::current_dir().ok()?,
+        options: options.clone(),
+    };
+    let spinner = if progress {
+        Spinner::new("Generating suggestions...")
+    } else {
+        Spinner::hidden()
+    };
+    let response = tokio::time::timeout(ANSWER_TIMEOUT, exchange(stream, &request)).await;
+    spinner.stop();
+    let Ok(response) = response else {
+        warn!("The daemon did not answer within {ANSWER_TIMEOUT:?}");
+        return None;
+    };
+    match response {
+        Ok(DaemonResponse::Suggestions(suggestions)) => Some(suggestions),
+        Ok(DaemonResponse::Error(e)) => {
+            warn!("Daemon failed to answer: {e}");
+            None
+        }
+        Err(e) => {
+            warn!("Failed to talk to the daemon: {e}");
+            None
+        }
+    }
+}
+
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    request: &DaemonRequest,
+) -> Result<DaemonResponse> {
+    let mut stream = BufReader::new(stream);
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    stream.get_mut().write_all(line.as_bytes()).await?;
+    stream.get_mut().flush().await?;
+
+    Ok(serde_json::from_str(&read_line(&mut stream).await?)?)
+}
+
+/// One line from the other side, refused when it's longer than `MAX_LINE_BYTES`
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<String> {
+    let mut line = String::new();
+    if reader.take(MAX_LINE_BYTES).read_line(&mut line).await? == 0 {
+        bail!("The connection was closed");
+    }
+    if !line.ends_with('\n') {
+        bail!("Line longer than {MAX_LINE_BYTES} bytes");
+    }
+    Ok(line)
+}
+
+#[cfg(unix)]
+mod transport {
+    use anyhow::{bail, Result};
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    use tokio::net::{UnixListener, UnixStream};
+
+    use crate::context::StorageManager;
+
+    /// Socket in ~/.phloem, readable and writable by the user alone
+    fn socket_path() -> Result<PathBuf> {
+        Ok(StorageManager::new()?.get_phloem_dir().join("daemon.sock"))
+    }
+
+    pub fn address() -> Result<String> {
+        Ok(socket_path()?.display().to_string())
+    }
+
+    pub struct Listener(UnixListener);
+
+    impl Listener {
+        pub fn bind() -> Result<Self> {
+            let path = socket_path()?;
+            if path.exists() {
+                // Left behind by a daemon that was killed; refuse to run twice
+                if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+                    bail!("A phloem daemon is already listening on {}", path.display());
+                }
+                std::fs::remove_file(&path)?;
+            }
+            let listener = UnixListener::bind(&path)?;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+            Ok(Self(listener))
+        }
+
+        pub async fn accept(&mut self) -> std::io::Result<UnixStream> {
+            self.0.accept().await.map(|(stream, _)| stream)
+        }
+    }
+
+    pub async fn connect() -> Result<UnixStream> {
+        Ok(UnixStream::connect(socket_path()?).await?)
+    }
+}
+
+#[cfg(windows)]
+mod transport {
+    use anyhow::Result;
+    use tokio::net::windows::named_pipe::{
+        ClientOptions, NamedPipeClient, NamedPipeServer, ServerOptions,
+    };
+
+    /// Pipe named after the user, so users of one machine get their own daemon
+    fn pipe_name() -> String {
+        let user = std::env::var("USERNAME").unwrap_or_default();
+        format!(r"\\.\pipe\phloem-daemon-{user}")
+    }
+
+    pub fn address() -> Result<String> {
+        Ok(pipe_name())
+    }
+
+    /// The pipe instance waiting for the next client
+    pub struct Listener(NamedPipeServer);
+
+    impl Listener {
+        pub fn bind() -> Result<Self> {
+            Ok(Self(
+                ServerOptions::new()
+                    .first_pipe_instance(true)
+                    .create(pipe_name())?,
+            ))
+        }
+
+        pub async fn accept(&mut self) -> std::io::Result<NamedPipeServer> {
+            self.0.connect().await?;
+            // Each client gets the connected instance; the next one waits on a new one
+            let next = ServerOptions::new().create(pipe_name())?;
+            Ok(std::mem::replace(&mut self.0, next))
+        }
+    }
+
+    pub async fn connect() -> Result<NamedPipeClient> {
+        Ok(ClientOptions::new().open(pipe_name())?)
+    }
+}
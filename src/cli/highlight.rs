@@ -0,0 +1,95 @@
+/// Words after which the next word is still a command name
+const COMMAND_PREFIXES: &[&str] = &["sudo", "env", "time", "nohup", "exec", "xargs", "watch"];
+
+/// What a piece of a shell command is, for coloring
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// The executable of a simple command
+    Command,
+    /// `-x` or `--long`
+    Flag,
+    /// Quoted text
+    String,
+    /// Pipes, lists, redirections and subshells
+    Operator,
+    /// `$NAME` and `${...}`
+    Variable,
+    /// Arguments and whitespace
+    Text,
+}
+
+/// Splits a command into tokens that concatenate back to it. A lightweight
+/// tokenizer for display only: it doesn't expand or validate anything.
+pub fn tokenize(command: &str) -> Vec<(TokenKind, &str)> {
+    let mut tokens = Vec::new();
+    let mut expect_command = true;
+    let mut rest = command;
+
+    while let Some(c) = rest.chars().next() {
+        let (kind, len) = if c.is_whitespace() {
+            let len = rest
+                .find(|c: char| !c.is_whitespace())
+                .unwrap_or(rest.len());
+            (TokenKind::Text, len)
+        } else if c == '\'' || c == '"' {
+            (TokenKind::String, quoted_len(rest, c))
+        } else if c == '$' && rest[1..].starts_with('(') {
+            expect_command = true;
+            (TokenKind::Operator, 2)
+        } else if c == '$' {
+            (TokenKind::Variable, variable_len(rest))
+        } else if "|&;()<>".contains(c) {
+            let len = rest
+                .find(|c: char| !"|&;()<>".contains(c))
+                .unwrap_or(rest.len());
+            // A command follows pipes, lists and subshells, but not redirections
+            expect_command = !rest[..len].ends_with(['<', '>']);
+            (TokenKind::Operator, len)
+        } else {
+            let len = rest
+                .find(|c: char| c.is_whitespace() || "|&;()<>'\"".contains(c))
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            let kind = if word.starts_with('-') {
+                TokenKind::Flag
+            } else if expect_command && !word.contains('=') {
+                expect_command = COMMAND_PREFIXES.contains(&word);
+                TokenKind::Command
+            } else {
+                TokenKind::Text
+            };
+            (kind, len)
+        };
+
+        tokens.push((kind, &rest[..len]));
+        rest = &rest[len..];
+    }
+
+    tokens
+}
+
+/// Length of the quoted string at the start of `text`, up to and including
+/// the closing `quote`, or the rest when it isn't closed
+fn quoted_len(text: &str, quote: char) -> usize {
+    let mut escaped = false;
+    for (index, c) in text.char_indices().skip(1) {
+        match c {
+            '\\' if quote == '"' && !escaped => escaped = true,
+            c if c == quote && !escaped => return index + c.len_utf8(),
+            _ => escaped = false,
+        }
+    }
+    text.len()
+}
+
+/// Length of the `$NAME`, `${...}` or `$1` at the start of `text`
+fn variable_len(text: &str) -> usize {
+    if text[1..].starts_with('{') {
+        return text.find('}').map_or(text.len(), |end| end + 1);
+    }
+    1 + text[1..]
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(text.len() - 1)
+        .max(1)
+        .min(text.len() - 1)
+}
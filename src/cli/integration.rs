@@ -0,0 +1,179 @@
+use anyhow::{bail, Result};
+use std::fs;
+use std::path::Path;
+
+use crate::cli::IntegrationShell;
+use crate::utils::ShellDetector;
+
+/// Environment variable the shell widget sets to a file; the command picked
+/// with Tab is written there for the widget to put on the prompt
+pub const BUFFER_FILE_VAR: &str = "PHLOEM_BUFFER_FILE";
+
+/// Lines around the block `install` adds to the rc file, so it can be found again
+const BLOCK_START: &str = "# >>> phloem shell integration >>>";
+const BLOCK_END: &str = "# <<< phloem shell integration <<<";
+
+const ZSH: &str = r#"# phloem shell integration for zsh
+_phloem_widget() {
+  [[ -z $BUFFER ]] && return
+  local out
+  out=$(mktemp "${TMPDIR:-/tmp}/phloem.XXXXXX") || return
+  zle -I
+  PHLOEM_BUFFER_FILE=$out phloem -- "$BUFFER" </dev/tty
+  if [[ -s $out ]]; then
+    BUFFER=$(<"$out")
+    CURSOR=${#BUFFER}
+  fi
+  command rm -f -- "$out"
+  zle reset-prompt
+}
+zle -N _phloem_widget
+bindkey '^G' _phloem_widget
+
+# Write each command to the history file as it's entered, so phloem sees it
+setopt INC_APPEND_HISTORY
+
+(( $+functions[compdef] )) && eval "$(phloem completions zsh)"
+"#;
+
+const BASH: &str = r#"# phloem shell integration for bash
+_phloem_widget() {
+  [[ -z $READLINE_LINE ]] && return
+  local out
+  out=$(mktemp "${TMPDIR:-/tmp}/phloem.XXXXXX") || return
+  PHLOEM_BUFFER_FILE=$out phloem -- "$READLINE_LINE" </dev/tty
+  if [[ -s $out ]]; then
+    READLINE_LINE=$(<"$out")
+    READLINE_POINT=${#READLINE_LINE}
+  fi
+  command rm -f -- "$out"
+}
+bind -x '"\C-g": _phloem_widget'
+
+# bash only writes its history on exit; append it after every command so phloem sees it
+_phloem_history() { history -a; }
+if [[ ";${PROMPT_COMMAND[*]};" != *";_phloem_history;"* ]]; then
+  PROMPT_COMMAND="_phloem_history${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+fi
+
+eval "$(phloem completions bash)"
+"#;
+
+const FISH: &str = r#"# phloem shell integration for fish
+function _phloem_widget
+    set -l line (commandline)
+    test -z "$line"; and return
+    set -l out (mktemp); or return
+    env PHLOEM_BUFFER_FILE=$out phloem -- "$line" </dev/tty
+    if test -s $out
+        commandline -r -- (string collect < $out)
+        commandline -f end-of-line
+    end
+    command rm -f -- $out
+    commandline -f repaint
+end
+bind \cg _phloem_widget
+
+# fish writes each command to its history as it runs, so only completions are needed
+phloem completions fish | source
+"#;
+
+/// Script binding Ctrl+G to run phloem on the command line typed so far and
+/// put the command picked with Tab in its place. It also keeps the history
+/// file current and loads completions, e.g. `eval "$(phloem shell-init zsh)"`
+pub fn script(shell: IntegrationShell) -> &'static str {
+    match shell {
+        IntegrationShell::Zsh => ZSH,
+        IntegrationShell::Bash => BASH,
+        IntegrationShell::Fish => FISH,
+    }
+}
+
+/// The user's shell, when it has an integration script
+pub fn detect_shell() -> Option<IntegrationShell> {
+    match ShellDetector::detect_shell().as_str() {
+        "bash" => Some(IntegrationShell::Bash),
+        "zsh" => Some(IntegrationShell::Zsh),
+        "fish" => Some(IntegrationShell::Fish),
+        _ => None,
+    }
+}
+
+/// The line of the rc file that loads the integration script
+fn loader(shell: IntegrationShell) -> &'static str {
+    match shell {
+        IntegrationShell::Zsh => r#"eval "$(phloem shell-init zsh)""#,
+        IntegrationShell::Bash => r#"eval "$(phloem shell-init bash)""#,
+        IntegrationShell::Fish => "phloem shell-init fish | source",
+    }
+}
+
+/// Whether the rc file loads the integration script
+pub fn is_installed(rc_file: &Path) -> bool {
+    fs::read_to_string(rc_file).is_ok_and(|content| content.contains(BLOCK_START))
+}
+
+/// Adds the block loading the integration script to the end of `rc_file`, or
+/// replaces the one already there; returns whether the file changed
+pub fn install(rc_file: &Path, shell: IntegrationShell) -> Result<bool> {
+    let content = match fs::read_to_string(rc_file) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let block = format!("{BLOCK_START}\n{}\n{BLOCK_END}\n", loader(shell));
+
+    let updated = match remove_block(&content)? {
+        Some((before, after)) => format!("{before}{block}{after}"),
+        None if content.is_empty() => block,
+        None if content.ends_with('\n') => format!("{content}\n{block}"),
+        None => format!("{content}\n\n{block}"),
+    };
+    if updated == content {
+        return Ok(false);
+    }
+
+    if let Some(parent) = rc_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(rc_file, updated)?;
+    Ok(true)
+}
+
+/// Removes the block `install` added to `rc_file`; returns whether there was one
+pub fn uninstall(rc_file: &Path) -> Result<bool> {
+    let Ok(content) = fs::read_to_string(rc_file) else {
+        return Ok(false);
+    };
+    let Some((before, after)) = remove_block(&content)? else {
+        return Ok(false);
+    };
+
+    // Drop the blank line `install` put before the block
+    let before = before
+        .strip_suffix('\n')
+        .filter(|rest| rest.is_empty() || rest.ends_with('\n'))
+        .unwrap_or(before);
+    fs::write(rc_file, format!("{before}{after}"))?;
+    Ok(true)
+}
+
+/// The text before and after the integration block, without the block itself;
+/// `None` when there's no block
+fn remove_block(content: &str) -> Result<Option<(&str, &str)>> {
+    let Some(start) = content.find(BLOCK_START) else {
+        return Ok(None);
+    };
+    let Some(end) = content[start..]
+        .find(BLOCK_END)
+        .map(|offset| start + offset)
+    else {
+        bail!("Found \"{BLOCK_START}\" without a closing \"{BLOCK_END}\"; fix the file by hand");
+    };
+
+    let after = &content[end + BLOCK_END.len()..];
+    Ok(Some((
+        &content[..start],
+        after.strip_prefix('\n').unwrap_or(after),
+    )))
+}
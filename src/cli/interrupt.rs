@@ -0,0 +1,89 @@
+use crossterm::{cursor, execute, terminal};
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use tokio::sync::Notify;
+
+/// Set while a cancellable operation is running, so Ctrl-C cancels it instead of exiting
+static IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+/// Set while a command we started runs in the foreground; Ctrl-C is its to handle
+static CHILD_RUNNING: AtomicBool = AtomicBool::new(false);
+static CANCEL: Notify = Notify::const_new();
+static LISTENER: Once = Once::new();
+
+/// Error returned when the user pressed Ctrl-C during a cancellable operation
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Keeps Ctrl-C away from phloem while it lives, see `child_running`
+pub struct ChildGuard;
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        CHILD_RUNNING.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Marks a command of ours running in the foreground until the guard is
+/// dropped. The terminal sends Ctrl-C to it as well, so phloem ignores it
+/// then rather than exiting and leaving the command behind.
+pub fn child_running() -> ChildGuard {
+    install_listener();
+    CHILD_RUNNING.store(true, Ordering::SeqCst);
+    ChildGuard
+}
+
+/// Once a SIGINT handler is installed the default "exit on Ctrl-C" is gone for
+/// the whole process, so the single listener exits itself outside of
+/// cancellable sections and running commands.
+fn install_listener() {
+    LISTENER.call_once(|| {
+        tokio::spawn(async {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                if CHILD_RUNNING.load(Ordering::SeqCst) {
+                    continue;
+                }
+                if IN_PROGRESS.load(Ordering::SeqCst) {
+                    CANCEL.notify_waiters();
+                } else {
+                    restore_terminal();
+                    std::process::exit(130);
+                }
+            }
+        });
+    });
+}
+
+/// Runs `future` until it completes or the user presses Ctrl-C
+pub async fn cancellable<F: Future>(future: F) -> Result<F::Output, Cancelled> {
+    install_listener();
+
+    // Register for the notification before flagging, so no signal is missed
+    let cancelled = CANCEL.notified();
+    tokio::pin!(cancelled);
+    cancelled.as_mut().enable();
+
+    IN_PROGRESS.store(true, Ordering::SeqCst);
+    let result = tokio::select! {
+        output = future => Ok(output),
+        _ = cancelled => Err(Cancelled),
+    };
+    IN_PROGRESS.store(false, Ordering::SeqCst);
+
+    result
+}
+
+/// Leaves raw mode and shows the cursor again
+pub fn restore_terminal() {
+    let _ = terminal::disable_raw_mode();
+    let _ = execute!(io::stderr(), cursor::Show);
+}
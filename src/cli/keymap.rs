@@ -0,0 +1,245 @@
+use crossterm::event::KeyCode;
+use log::warn;
+use std::collections::HashMap;
+
+use crate::config::ConfigError;
+
+/// What a key does in the selector
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyAction {
+    Up,
+    Down,
+    Top,
+    Bottom,
+    Execute,
+    Output,
+    Edit,
+    Followup,
+    Save,
+    Mark,
+    Filter,
+    Docs,
+    DryRun,
+}
+
+/// Every action with its name under `[keys]`, its default keys and the word
+/// shown for it in the key help, if any
+const ACTIONS: &[(KeyAction, &str, &[&str], Option<&str>)] = &[
+    (KeyAction::Up, "up", &["up", "k"], None),
+    (KeyAction::Down, "down", &["down", "j"], None),
+    (KeyAction::Top, "top", &["home", "gg"], None),
+    (KeyAction::Bottom, "bottom", &["end", "G"], None),
+    (KeyAction::Execute, "execute", &["enter"], Some("run")),
+    (KeyAction::Mark, "mark", &["space"], Some("mark")),
+    (KeyAction::Edit, "edit", &["e", "E"], Some("edit")),
+    (KeyAction::Output, "output", &["tab"], Some("output")),
+    (KeyAction::Save, "save", &["s", "S"], Some("save")),
+    (KeyAction::Filter, "filter", &["/"], Some("filter")),
+    (KeyAction::Docs, "docs", &["?"], Some("docs")),
+    (KeyAction::DryRun, "dry_run", &["d", "D"], Some("dry run")),
+    (KeyAction::Followup, "followup", &["f", "F"], None),
+];
+
+/// A key of the selector: a single key, or two characters typed one after
+/// the other like vim's `gg`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Binding {
+    Key(KeyCode),
+    Sequence(char, char),
+}
+
+impl Binding {
+    /// Parses names like "enter", "pagedown" or "space", single characters
+    /// and two-character sequences
+    fn parse(spec: &str) -> Result<Self, String> {
+        let code = match spec.to_lowercase().as_str() {
+            "enter" => KeyCode::Enter,
+            "tab" => KeyCode::Tab,
+            "space" => KeyCode::Char(' '),
+            "backspace" => KeyCode::Backspace,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "esc" => return Err("Esc leaves the selector and can't be rebound".to_string()),
+            _ => {
+                let chars: Vec<char> = spec.chars().collect();
+                return match chars[..] {
+                    [c] if c.is_ascii_digit() && c != '0' => {
+                        Err(format!("{c} picks suggestion {c} and can't be rebound"))
+                    }
+                    [c] => Ok(Self::Key(KeyCode::Char(c))),
+                    [first, second] => Ok(Self::Sequence(first, second)),
+                    _ => Err("use a key name, a character or two characters".to_string()),
+                };
+            }
+        };
+        Ok(Self::Key(code))
+    }
+
+    /// How the key help shows the binding
+    fn label(&self) -> String {
+        match self {
+            Self::Key(KeyCode::Char(' ')) => "Space".to_string(),
+            Self::Key(KeyCode::Char(c)) => c.to_string(),
+            Self::Key(KeyCode::PageUp) => "PgUp".to_string(),
+            Self::Key(KeyCode::PageDown) => "PgDn".to_string(),
+            Self::Key(code) => format!("{code:?}"),
+            Self::Sequence(first, second) => format!("{first}{second}"),
+        }
+    }
+}
+
+/// Which key does what in the selector: the defaults, with the actions
+/// configured under `[keys]` bound to their keys instead
+#[derive(Debug, Clone)]
+pub struct KeyMap {
+    bindings: HashMap<Binding, KeyAction>,
+    /// Keys of each action in the order given, for the key help
+    keys: HashMap<KeyAction, Vec<Binding>>,
+}
+
+impl KeyMap {
+    /// The defaults with `overrides` applied, e.g. `{"edit": ["i"]}`;
+    /// invalid entries are skipped
+    pub fn new(overrides: &HashMap<String, Vec<String>>) -> Self {
+        let mut bindings = HashMap::new();
+        let mut configured = Vec::new();
+        let mut action_keys = HashMap::new();
+
+        for &(action, name, defaults, _) in ACTIONS {
+            let keys: Vec<Binding> = match overrides.get(name) {
+                Some(specs) => specs
+                    .iter()
+                    .filter_map(|spec| {
+                        Binding::parse(spec)
+                            .map_err(|e| warn!("Ignoring key \"{spec}\" of keys.{name}: {e}"))
+                            .ok()
+                    })
+                    .collect(),
+                None => defaults
+                    .iter()
+                    .filter_map(|spec| Binding::parse(spec).ok())
+                    .collect(),
+            };
+            if overrides.contains_key(name) {
+                configured.push((action, keys.clone()));
+            } else {
+                bindings.extend(keys.iter().map(|&key| (key, action)));
+            }
+            action_keys.insert(action, keys);
+        }
+        // Configured keys win over defaults they collide with
+        for (action, keys) in configured {
+            bindings.extend(keys.into_iter().map(|key| (key, action)));
+        }
+        // A default key taken by a configured action no longer shows for its own
+        for (action, keys) in action_keys.iter_mut() {
+            keys.retain(|key| bindings.get(key) == Some(action));
+        }
+
+        Self {
+            bindings,
+            keys: action_keys,
+        }
+    }
+
+    /// Problems with the `[keys]` section: unknown actions and keys that
+    /// can't be parsed or bound
+    pub fn check(overrides: &HashMap<String, Vec<String>>) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+        for (name, specs) in overrides {
+            let key = format!("keys.{name}");
+            if !ACTIONS.iter().any(|&(_, known, _, _)| known == name) {
+                errors.push(ConfigError::InvalidKey {
+                    key,
+                    value: name.clone(),
+                    reason: format!(
+                        "not an action; use {}",
+                        ACTIONS
+                            .iter()
+                            .map(|&(_, name, _, _)| name)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                });
+                continue;
+            }
+            for spec in specs {
+                if let Err(reason) = Binding::parse(spec) {
+                    errors.push(ConfigError::InvalidKey {
+                        key: key.clone(),
+                        value: spec.clone(),
+                        reason,
+                    });
+                }
+            }
+        }
+        errors
+    }
+
+    /// The action of `code`, typed after `pending` when that started a
+    /// two-key sequence
+    pub fn action(&self, pending: Option<char>, code: KeyCode) -> Option<KeyAction> {
+        match (pending, code) {
+            (Some(first), KeyCode::Char(second)) => self
+                .bindings
+                .get(&Binding::Sequence(first, second))
+                .copied(),
+            (Some(_), _) => None,
+            (None, code) => self.bindings.get(&Binding::Key(code)).copied(),
+        }
+    }
+
+    /// Whether `c` starts a two-key sequence, so the next key is needed
+    pub fn starts_sequence(&self, c: char) -> bool {
+        self.bindings
+            .keys()
+            .any(|binding| matches!(binding, Binding::Sequence(first, _) if *first == c))
+    }
+
+    /// The key help line, with one key of each action
+    pub fn help(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(run) = self.help_key(KeyAction::Execute) {
+            parts.push(format!("{run}/1-9=run"));
+        }
+        let moves: Vec<String> = [KeyAction::Down, KeyAction::Up]
+            .into_iter()
+            .filter_map(|action| self.help_key(action))
+            .collect();
+        if !moves.is_empty() {
+            parts.push(format!("{}=move", moves.join("/")));
+        }
+        for &(action, _, _, word) in ACTIONS {
+            if let (Some(word), Some(key)) = (word, self.help_key(action)) {
+                if action != KeyAction::Execute {
+                    parts.push(format!("{key}={word}"));
+                }
+            }
+        }
+        parts.push("Esc=follow-up".to_string());
+        parts.push("Esc Esc=exit".to_string());
+        parts.join("  ")
+    }
+
+    /// The key shown for `action`: its first character key, since arrows
+    /// and Enter go without saying, or else its first key
+    fn help_key(&self, action: KeyAction) -> Option<String> {
+        let keys = self.keys.get(&action)?;
+        keys.iter()
+            .find(|key| matches!(key, Binding::Key(KeyCode::Char(c)) if *c != ' '))
+            .or(keys.first())
+            .map(Binding::label)
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self::new(&HashMap::new())
+    }
+}
@@ -1,7 +1,23 @@
 pub mod args;
 pub mod commands;
+pub mod daemon;
+pub mod highlight;
+pub mod integration;
+pub mod interrupt;
+pub mod keymap;
 pub mod output;
+pub mod placeholders;
+#[cfg(feature = "tui")]
+pub mod tui;
 
-pub use args::{Cli, Commands, PromptOptions};
-pub use commands::{CommandHandler, Suggestion};
-pub use output::{FormatResult, OutputFormatter, Spinner};
+pub use args::{
+    AuditAction, BackupAction, CacheAction, Cli, Commands, ContextAction, ExportFormat,
+    FavoriteAction, HistoryAction, IntegrationShell, OutputMode, PromptOptions,
+};
+pub use commands::{CommandHandler, Suggestion, SuggestionSource};
+pub use interrupt::{cancellable, Cancelled};
+pub use keymap::{KeyAction, KeyMap};
+pub use output::{
+    CapturedOutput, CommandFailure, DownloadProgress, FormatResult, OutputFormatter, Spinner,
+};
+pub use placeholders::Placeholder;
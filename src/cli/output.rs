@@ -1,24 +1,48 @@
-use crate::cli::Suggestion;
-use crate::context::ContextManager;
-use arboard::Clipboard;
+use crate::ai::PullProgress;
+use crate::cli::highlight::{tokenize, TokenKind};
+#[cfg(feature = "tui")]
+use crate::cli::tui::Selector;
+use crate::cli::{integration, interrupt};
+use crate::cli::{KeyMap, OutputMode, Placeholder, Suggestion};
+use crate::config::settings::ElevationMode;
+use crate::context::{AuditRecord, ContextManager, Favorite};
+use crate::utils::{
+    CommandValidator, DryRun, EnvironmentDetector, ExecutableIndex, Policy, PolicyAction,
+    RiskLevel, Sandbox, ShellDetector, ShellKind, Timing, Verdict,
+};
+use base64::Engine;
+use chrono::Utc;
 use console::{style, Color};
 use crossterm::{
-    event::{self, Event, KeyCode},
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    terminal::{disable_raw_mode, enable_raw_mode},
 };
-use std::io::{self, Write};
-use std::process::Command;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex, PoisonError};
 use std::thread;
 use std::time::Duration;
 
 #[derive(Debug)]
 pub enum SelectAction {
     Execute(usize),
+    /// Edit the command before running it
+    Edit(usize),
     Output(usize),
+    /// Run the marked suggestions in the order they were marked
+    ExecuteAll(Vec<usize>),
+    /// Output the marked suggestions joined with `&&`
+    OutputAll(Vec<usize>),
     Followup(usize),
+    /// Save the command under a name for `phloem run`
+    Save(usize),
     Cancel,
 }
 
@@ -26,284 +50,1504 @@ pub enum SelectAction {
 pub enum FormatResult {
     Executed(String),
     Output(String),
-    FollowupRequested,
+    /// The user asked to modify the suggestion at this index
+    FollowupRequested(usize),
+    /// A command failed and the user asked for a fix
+    FixRequested(CommandFailure),
     Static(String),
 }
 
+/// The tail of what a command printed to each stream
+#[derive(Debug, Clone, Default)]
+pub struct CapturedOutput {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// A command that ran and didn't succeed
+#[derive(Debug)]
+pub struct CommandFailure {
+    pub command: String,
+    pub exit_code: Option<i32>,
+    /// Empty when the output wasn't captured
+    pub output: CapturedOutput,
+}
+
+/// Why a command didn't succeed; `failure` is set when it ran and failed
+struct RunError {
+    message: String,
+    failure: Option<CommandFailure>,
+}
+
+/// What a command changes that the policy's categories ask about
+struct Changes {
+    /// Files that exist and are replaced through a redirect
+    overwrites: Vec<String>,
+    /// Changes over the network, like "kubectl delete changes the cluster"
+    network: Vec<String>,
+}
+
+impl Changes {
+    fn is_empty(&self) -> bool {
+        self.overwrites.is_empty() && self.network.is_empty()
+    }
+}
+
+/// What `--output json` prints
+#[derive(Serialize)]
+struct SuggestionsDocument<'a> {
+    prompt: &'a str,
+    suggestions: &'a [Suggestion],
+}
+
 pub struct OutputFormatter {
+    /// Colors of the selector: `output.use_colors`, unless NO_COLOR is set
+    #[cfg_attr(not(feature = "tui"), allow(dead_code))]
     use_colors: bool,
+    /// Color printed text as well, which only happens when both stdout and
+    /// stderr are terminals; files, pipes and `--output` modes stay plain
+    styled: bool,
+    /// Ask before running destructive commands
+    confirm_destructive: bool,
+    /// Whether commands using sudo, doas or su run, need a typed "yes" or are refused
+    elevation: ElevationMode,
+    /// Color the parts of suggested commands
+    syntax_highlighting: bool,
+    /// Shell the suggested commands are written for
+    shell: ShellKind,
+    /// Bytes of each output stream kept from commands that run; `None` runs
+    /// them straight on the terminal
+    max_captured_bytes: Option<usize>,
+    /// Show what picked commands would affect instead of running them
+    dry_run: bool,
+    /// Where picked commands are tried before they run for real, if anywhere
+    sandbox: Option<Sandbox>,
+    /// Shell commands run with outside Windows; `None` picks it from SHELL
+    shell_program: Option<String>,
+    /// Start that shell with -i so it reads its startup files
+    interactive_shell: bool,
+    /// Run commands that are only a program with arguments without a shell
+    strict: bool,
+    /// Keys of the selector
+    keys: KeyMap,
+    /// Show spinners while waiting; off for `--output` modes
+    progress: bool,
+}
+
+/// Frames of the spinner, one drawn every `SPINNER_INTERVAL`
+const SPINNER_FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+const SPINNER_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What the spinner's task shares with the spinner
+struct SpinnerLine {
+    /// Whether frames may still be drawn; cleared when the spinner stops
+    active: bool,
+    /// Lines printed above the spinner, erased when it stops
+    printed_lines: usize,
 }
 
+/// Status line on stderr while something runs, drawn by a tokio task. It
+/// stays hidden when stderr isn't a terminal, and its line is cleared when
+/// it's stopped or dropped, e.g. when an error returns early
 pub struct Spinner {
-    running: Arc<AtomicBool>,
-    handle: Option<thread::JoinHandle<()>>,
+    /// `None` for a hidden spinner
+    line: Option<Arc<Mutex<SpinnerLine>>>,
+    task: Option<tokio::task::JoinHandle<()>>,
 }
 
 impl Spinner {
     pub fn new(message: &str) -> Self {
-        let running = Arc::new(AtomicBool::new(true));
-        let running_clone = running.clone();
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return Self::hidden();
+        };
+        if !io::stderr().is_terminal() {
+            return Self::hidden();
+        }
+
+        let line = Arc::new(Mutex::new(SpinnerLine {
+            active: true,
+            printed_lines: 0,
+        }));
+        let shared = line.clone();
         let message = message.to_string();
+        let task = runtime.spawn(async move {
+            let mut ticks = tokio::time::interval(SPINNER_INTERVAL);
+            for frame in SPINNER_FRAMES.iter().cycle() {
+                ticks.tick().await;
+                let Ok(line) = shared.lock() else {
+                    return;
+                };
+                if !line.active {
+                    return;
+                }
+                eprint!("\r{frame} {message}");
+                let _ = io::stderr().flush();
+            }
+        });
 
-        let handle = thread::spawn(move || {
-            let frames = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
-            let mut frame_index = 0;
+        Self {
+            line: Some(line),
+            task: Some(task),
+        }
+    }
 
-            while running_clone.load(Ordering::Relaxed) {
-                eprint!("\r{} {}", frames[frame_index], message);
-                io::stderr().flush().unwrap();
-                frame_index = (frame_index + 1) % frames.len();
-                thread::sleep(Duration::from_millis(100));
-            }
+    /// A spinner that draws nothing, e.g. for `--output` modes
+    pub fn hidden() -> Self {
+        Self {
+            line: None,
+            task: None,
+        }
+    }
 
-            // Clear the spinner line
-            eprint!("\r{}\r", " ".repeat(message.len() + 3));
-            io::stderr().flush().unwrap();
-        });
+    /// Prints a line above the spinner without interleaving with its
+    /// frames; nothing is printed while it's hidden
+    pub fn println(&self, text: &str) {
+        let Some(line) = &self.line else {
+            return;
+        };
+        let mut line = line.lock().unwrap_or_else(PoisonError::into_inner);
+        if !line.active {
+            return;
+        }
+        let _ = execute!(
+            io::stderr(),
+            crossterm::cursor::MoveToColumn(0),
+            crossterm::terminal::Clear(crossterm::terminal::ClearType::CurrentLine)
+        );
+        eprintln!("{text}");
+        let _ = io::stderr().flush();
+        line.printed_lines += 1;
+    }
+
+    /// Stops the spinner and erases its line and any lines printed through it
+    pub fn stop(self) {
+        drop(self);
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        let Some(line) = &self.line else {
+            return;
+        };
+
+        // Holding the lock keeps a frame being drawn from landing after this
+        let mut line = line.lock().unwrap_or_else(PoisonError::into_inner);
+        line.active = false;
+        let mut stderr = io::stderr();
+        let _ = execute!(
+            stderr,
+            crossterm::cursor::MoveToColumn(0),
+            crossterm::terminal::Clear(crossterm::terminal::ClearType::CurrentLine)
+        );
+        if line.printed_lines > 0 {
+            let _ = execute!(
+                stderr,
+                crossterm::cursor::MoveUp(line.printed_lines as u16),
+                crossterm::terminal::Clear(crossterm::terminal::ClearType::FromCursorDown)
+            );
+        }
+        let _ = stderr.flush();
+    }
+}
+
+/// Progress bar for model downloads, driven by backend pull events
+pub struct DownloadProgress {
+    bar: ProgressBar,
+    current_digest: Mutex<Option<String>>,
+}
+
+impl DownloadProgress {
+    pub fn new(model: &str) -> Self {
+        Self::with_message(format!("Preparing model {model}..."))
+    }
+
+    /// Progress of downloading one file, e.g. a release binary
+    pub fn for_file(name: &str) -> Self {
+        let progress = Self::with_message(format!("Downloading {name}..."));
+        progress.bar.set_style(Self::bytes_style());
+        progress.bar.set_message(name.to_string());
+        progress
+    }
+
+    fn with_message(message: String) -> Self {
+        let bar = ProgressBar::new_spinner();
+        bar.set_style(Self::status_style());
+        bar.set_message(message);
+        bar.enable_steady_tick(Duration::from_millis(100));
 
         Self {
-            running,
-            handle: Some(handle),
+            bar,
+            current_digest: Mutex::new(None),
         }
     }
 
-    pub fn stop(mut self) {
-        self.running.store(false, Ordering::Relaxed);
-        if let Some(handle) = self.handle.take() {
-            handle.join().unwrap();
+    /// Updates the bar from a pull event, switching to byte progress for layer downloads
+    pub fn update(&self, progress: &PullProgress) {
+        match (progress.total, &progress.digest) {
+            (Some(total), Some(digest)) if total > 0 => {
+                if let Ok(mut current) = self.current_digest.lock() {
+                    if current.as_deref() != Some(digest.as_str()) {
+                        *current = Some(digest.clone());
+                        self.bar.set_style(Self::bytes_style());
+                        self.bar.set_length(total);
+                        self.bar.set_position(0);
+                        let short = digest.trim_start_matches("sha256:");
+                        self.bar
+                            .set_message(format!("layer {}", &short[..short.len().min(12)]));
+                    }
+                }
+                self.bar.set_position(progress.completed.unwrap_or(0));
+            }
+            _ => {
+                if let Ok(mut current) = self.current_digest.lock() {
+                    if current.take().is_some() {
+                        self.bar.set_style(Self::status_style());
+                    }
+                }
+                self.bar.set_message(progress.status.clone());
+            }
         }
     }
+
+    /// Updates the bar of a file download with the bytes received so far
+    pub fn update_bytes(&self, completed: u64, total: Option<u64>) {
+        if let Some(total) = total {
+            self.bar.set_length(total);
+        }
+        self.bar.set_position(completed);
+    }
+
+    pub fn finish(self) {
+        self.bar.finish_and_clear();
+    }
+
+    fn status_style() -> ProgressStyle {
+        ProgressStyle::with_template("{spinner} {msg}")
+            .unwrap_or_else(|_| ProgressStyle::default_spinner())
+    }
+
+    fn bytes_style() -> ProgressStyle {
+        ProgressStyle::with_template(
+            "{spinner} {msg} [{bar:30}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})",
+        )
+        .unwrap_or_else(|_| ProgressStyle::default_bar())
+        .progress_chars("=> ")
+    }
 }
 
 impl OutputFormatter {
     pub fn new(use_colors: bool) -> Self {
-        Self { use_colors }
+        let use_colors = use_colors && !no_color();
+        Self {
+            use_colors,
+            styled: use_colors && io::stdout().is_terminal() && io::stderr().is_terminal(),
+            confirm_destructive: true,
+            elevation: ElevationMode::Confirm,
+            syntax_highlighting: true,
+            shell: ShellKind::detect(),
+            max_captured_bytes: None,
+            dry_run: false,
+            sandbox: None,
+            shell_program: None,
+            interactive_shell: true,
+            strict: false,
+            keys: KeyMap::default(),
+            progress: true,
+        }
+    }
+
+    /// Passes the output of commands through, keeping the last `max_bytes`
+    /// of each stream for the history and "fix it" follow-ups
+    pub fn with_output_capture(mut self, max_bytes: Option<usize>) -> Self {
+        self.max_captured_bytes = max_bytes;
+        self
+    }
+
+    /// Previews what picked commands would affect instead of running them
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// Runs commands with `program`, or the user's shell for `None`, started
+    /// as an interactive shell with `interactive`
+    pub fn with_shell_program(mut self, program: Option<String>, interactive: bool) -> Self {
+        self.shell_program = program;
+        self.interactive_shell = interactive;
+        self
+    }
+
+    /// Runs commands that are only a program with arguments without a shell
+    pub fn with_strict_execution(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Tries picked commands in `sandbox` and asks before running them for real
+    pub fn with_sandbox(mut self, sandbox: Option<Sandbox>) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    pub fn set_sandbox(&mut self, sandbox: Option<Sandbox>) {
+        self.sandbox = sandbox;
+    }
+
+    /// Judges the risks of suggested commands by the rules of `shell`
+    pub fn set_shell(&mut self, shell: ShellKind) {
+        self.shell = shell;
+    }
+
+    /// Whether suggested commands are colored by their parts or plain green
+    pub fn with_syntax_highlighting(mut self, syntax_highlighting: bool) -> Self {
+        self.syntax_highlighting = syntax_highlighting;
+        self
+    }
+
+    /// Binds the selector's keys as `keys` does
+    pub fn with_keys(mut self, keys: KeyMap) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    /// Whether destructive commands need a typed "yes" before they run
+    pub fn with_destructive_confirmation(mut self, confirm_destructive: bool) -> Self {
+        self.confirm_destructive = confirm_destructive;
+        self
+    }
+
+    /// How commands running as root or another user are handled
+    pub fn with_elevation(mut self, elevation: ElevationMode) -> Self {
+        self.elevation = elevation;
+        self
+    }
+
+    /// Shows spinners while waiting, or leaves stderr alone
+    pub fn set_progress(&mut self, progress: bool) {
+        self.progress = progress;
+    }
+
+    /// Spinner showing `message`, hidden when progress is turned off
+    pub fn spinner(&self, message: &str) -> Spinner {
+        if self.progress {
+            Spinner::new(message)
+        } else {
+            Spinner::hidden()
+        }
     }
 
-    pub fn format_suggestions(
+    /// Lets the user pick a suggestion. `replacing` is the earlier suggestion the
+    /// user asked to modify; running a different command records a correction.
+    pub async fn format_suggestions(
         &self,
         suggestions: &[Suggestion],
         show_explanations: bool,
         original_prompt: &str,
+        replacing: Option<&str>,
         context: &mut ContextManager,
     ) -> FormatResult {
         if suggestions.is_empty() {
             return FormatResult::Static(self.style_text("No suggestions found.", Color::Yellow));
         }
 
-        self.interactive_select(suggestions, show_explanations, original_prompt, context)
+        // Piped, in CI or in a subshell nobody can pick, and raw mode would wait forever
+        if !io::stdout().is_terminal() {
+            return FormatResult::Static(Timing::measure("render", || {
+                self.format_suggestions_static(suggestions, show_explanations)
+            }));
+        }
+
+        self.interactive_select(
+            suggestions,
+            show_explanations,
+            original_prompt,
+            replacing,
+            context,
+        )
+        .await
     }
 
-    fn interactive_select(
+    async fn interactive_select(
         &self,
         suggestions: &[Suggestion],
         show_explanations: bool,
         original_prompt: &str,
+        replacing: Option<&str>,
         context: &mut ContextManager,
     ) -> FormatResult {
+        let action = self.select(suggestions, show_explanations);
+
+        // Whatever wasn't picked was passed over; without a terminal nothing was shown to pick
+        let picked = match &action {
+            Ok(
+                SelectAction::Execute(index)
+                | SelectAction::Edit(index)
+                | SelectAction::Output(index)
+                | SelectAction::Followup(index)
+                | SelectAction::Save(index),
+            ) => vec![*index],
+            Ok(SelectAction::ExecuteAll(indexes) | SelectAction::OutputAll(indexes)) => {
+                indexes.clone()
+            }
+            _ => Vec::new(),
+        };
+        if action.is_ok() {
+            let rejected: Vec<&str> = suggestions
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| !picked.contains(index))
+                .map(|(_, suggestion)| suggestion.command.as_str())
+                .collect();
+            if let Err(e) = context
+                .record_rejected_suggestions(original_prompt, &rejected)
+                .await
+            {
+                log::warn!("Failed to record rejected suggestions: {e}");
+            }
+        }
+
+        // Placeholders are filled in before anything is run, edited or output
+        let mut filled: HashMap<usize, String> = HashMap::new();
+        if let Ok(
+            SelectAction::Execute(_)
+            | SelectAction::Edit(_)
+            | SelectAction::Output(_)
+            | SelectAction::ExecuteAll(_)
+            | SelectAction::OutputAll(_),
+        ) = &action
+        {
+            for &index in &picked {
+                match self.fill_placeholders(&suggestions[index], context).await {
+                    Ok(Some(command)) => {
+                        filled.insert(index, command);
+                    }
+                    _ => {
+                        return FormatResult::Static(
+                            self.format_suggestions_static(suggestions, show_explanations),
+                        )
+                    }
+                }
+            }
+        }
+        let command = |index: usize| {
+            filled
+                .get(&index)
+                .map_or(suggestions[index].command.as_str(), String::as_str)
+        };
+
+        match action {
+            Ok(SelectAction::Execute(index)) => {
+                self.execute(command(index), original_prompt, replacing, context)
+                    .await
+            }
+            Ok(SelectAction::Edit(index)) => {
+                let suggested = command(index);
+                match self.edit_line(suggested) {
+                    Ok(Some(edited)) => {
+                        // Running a changed command corrects the suggestion it started from
+                        let original = replacing.or(Some(suggested));
+                        self.execute(&edited, original_prompt, original, context)
+                            .await
+                    }
+                    _ => FormatResult::Static(
+                        self.format_suggestions_static(suggestions, show_explanations),
+                    ),
+                }
+            }
+            Ok(SelectAction::Output(index)) => self.output(command(index)),
+            Ok(SelectAction::ExecuteAll(indexes)) => {
+                let commands: Vec<&str> = indexes.iter().map(|&index| command(index)).collect();
+                self.execute_chain(&commands, original_prompt, context)
+                    .await
+            }
+            Ok(SelectAction::OutputAll(indexes)) => self.output(
+                &indexes
+                    .iter()
+                    .map(|&index| command(index))
+                    .collect::<Vec<_>>()
+                    .join(" && "),
+            ),
+            Ok(SelectAction::Followup(index)) => FormatResult::FollowupRequested(index),
+            Ok(SelectAction::Save(index)) => {
+                self.save(&suggestions[index].command, original_prompt, context)
+                    .await
+            }
+            // Picking by number leaves the list on screen already
+            Ok(SelectAction::Cancel) if !cfg!(feature = "tui") => {
+                FormatResult::Static(String::new())
+            }
+            Ok(SelectAction::Cancel) => {
+                FormatResult::Static(self.format_suggestions_static(suggestions, show_explanations))
+            }
+            Err(_) => {
+                FormatResult::Static(self.format_suggestions_static(suggestions, show_explanations))
+            }
+        }
+    }
+
+    /// Lets the user pick suggestions in the full-screen selector
+    #[cfg(feature = "tui")]
+    fn select(
+        &self,
+        suggestions: &[Suggestion],
+        show_explanations: bool,
+    ) -> io::Result<SelectAction> {
         let items: Vec<String> = suggestions
             .iter()
-            .map(|s| {
-                if show_explanations && s.explanation.is_some() {
-                    format!("{} - {}", s.command, s.explanation.as_ref().unwrap())
-                } else {
-                    s.command.clone()
+            .map(|s| match &s.explanation {
+                Some(explanation) if show_explanations => {
+                    format!("{} - {}", s.command, explanation)
                 }
+                _ => s.command.clone(),
             })
             .collect();
 
-        match self.custom_select(&items) {
-            Ok(SelectAction::Execute(index)) => {
-                let selected_command = &suggestions[index].command;
+        Selector::new(suggestions, &items, self.use_colors)
+            .with_syntax_highlighting(self.syntax_highlighting)
+            .with_shell(self.shell)
+            .with_keys(self.keys.clone())
+            .run()
+    }
+
+    /// Lets the user pick a suggestion by its number, for builds without the
+    /// full-screen selector; any other key cancels
+    #[cfg(not(feature = "tui"))]
+    fn select(
+        &self,
+        suggestions: &[Suggestion],
+        show_explanations: bool,
+    ) -> io::Result<SelectAction> {
+        eprintln!(
+            "{}",
+            self.format_suggestions_static(suggestions, show_explanations)
+        );
+
+        let count = suggestions.len().min(9);
+        let picked = match self.read_key(&format!("Run which? 1-{count}, any other key to cancel"))
+        {
+            Some(KeyCode::Char(key)) => key.to_digit(10).map(|number| number as usize),
+            _ => None,
+        };
+        Ok(match picked {
+            Some(number) if (1..=count).contains(&number) => SelectAction::Execute(number - 1),
+            _ => SelectAction::Cancel,
+        })
+    }
+
+    /// Runs a suggestion picked outside the selector, e.g. a saved command,
+    /// filling in its placeholders first
+    pub async fn run_picked(
+        &self,
+        suggestion: &Suggestion,
+        prompt: &str,
+        context: &mut ContextManager,
+    ) -> FormatResult {
+        if !suggestion.placeholders.is_empty() && !io::stdin().is_terminal() {
+            return FormatResult::Executed(
+                self.format_error("Filling in the placeholders of this command needs a terminal"),
+            );
+        }
+        let command = match self.fill_placeholders(suggestion, context).await {
+            Ok(Some(command)) => command,
+            _ => return FormatResult::Executed(self.format_warning("Not run")),
+        };
+        // Saved commands never run as root unasked, even with elevation allowed
+        let elevated = CommandValidator::for_shell(self.shell).elevated_actions(&command);
+        if self.elevation == ElevationMode::Allow && !elevated.is_empty() {
+            eprintln!("{}", self.highlight_command(&command));
+            let confirmed = io::stdin().is_terminal()
+                && matches!(
+                    self.read_key("It runs as root. Run it? y for yes, any other key to stop"),
+                    Some(KeyCode::Char('y' | 'Y'))
+                );
+            if !confirmed {
+                return FormatResult::Executed(self.format_warning("Not run"));
+            }
+        }
+        self.execute(&command, prompt, None, context).await
+    }
+
+    /// Asks for a name and saves `command` under it for `phloem run`
+    async fn save(
+        &self,
+        command: &str,
+        prompt: &str,
+        context: &mut ContextManager,
+    ) -> FormatResult {
+        eprintln!("{}", self.highlight_command(command));
+        let default_name = default_favorite_name(prompt);
+        let name = loop {
+            match self.read_line("Save as: ", &default_name, &[]) {
+                Ok(Some(name)) if Favorite::is_valid_name(name.trim()) => {
+                    break name.trim().to_string()
+                }
+                Ok(Some(_)) => eprintln!(
+                    "{}",
+                    self.format_warning("Use one word without spaces that doesn't start with -")
+                ),
+                _ => return FormatResult::Executed(self.format_warning("Not saved")),
+            }
+        };
+
+        match context.cache.save_favorite(&name, command, prompt).await {
+            Ok(replaced) => FormatResult::Executed(self.format_success(&format!(
+                "{} {name}; run it with: phloem run {name}",
+                if replaced { "Replaced" } else { "Saved as" }
+            ))),
+            Err(e) => FormatResult::Executed(self.format_error(&format!("Failed to save: {e}"))),
+        }
+    }
+
+    /// Runs `selected_command` and records how it went. `replacing` is the
+    /// suggestion it corrects, if the user changed one.
+    async fn execute(
+        &self,
+        selected_command: &str,
+        original_prompt: &str,
+        replacing: Option<&str>,
+        context: &mut ContextManager,
+    ) -> FormatResult {
+        if self.dry_run {
+            return self.preview(&[selected_command]);
+        }
+        match self
+            .run_and_record(selected_command, original_prompt, replacing, context)
+            .await
+        {
+            Ok(()) => FormatResult::Executed(String::new()),
+            Err(error) => self.failed(&error.message, error.failure),
+        }
+    }
+
+    /// Runs the marked commands one after another, stopping at the first
+    /// failure like `&&` would; each step is recorded on its own
+    async fn execute_chain(
+        &self,
+        commands: &[&str],
+        original_prompt: &str,
+        context: &mut ContextManager,
+    ) -> FormatResult {
+        if self.dry_run {
+            return self.preview(commands);
+        }
+        for (step, command) in commands.iter().enumerate() {
+            let progress = format!("[{}/{}]", step + 1, commands.len());
+            eprint!("{} ", self.style_text(&progress, Color::Cyan));
+
+            if let Err(error) = self
+                .run_and_record(command, original_prompt, None, context)
+                .await
+            {
+                let skipped = commands.len() - step - 1;
+                let message = format!(
+                    "Step {} failed, skipped the remaining {skipped}: {}",
+                    step + 1,
+                    error.message
+                );
+                return self.failed(&message, error.failure);
+            }
+        }
+
+        FormatResult::Executed(
+            self.format_success(&format!("All {} commands succeeded", commands.len())),
+        )
+    }
+
+    /// Prints what `commands` would affect, found with a listing or their
+    /// tools' dry-run modes, without running them
+    fn preview(&self, commands: &[&str]) -> FormatResult {
+        for command in commands {
+            eprintln!("{command}");
+            let dry_run = DryRun::plan(command, self.shell);
+            match (dry_run.command(), dry_run.run()) {
+                (Some(preview), Some(output)) => {
+                    eprintln!("{}", self.style_text(&format!("$ {preview}"), Color::Cyan));
+                    println!("{output}");
+                }
+                _ => eprintln!(
+                    "{}",
+                    self.format_warning(
+                        "No safe preview: this command can only show its effects by running"
+                    )
+                ),
+            }
+        }
+        FormatResult::Executed(self.format_info("Dry run: nothing was executed"))
+    }
 
-                // Ensure we're back to normal terminal mode before printing
-                io::stdout().flush().unwrap();
-                eprintln!("{selected_command}");
+    /// Reports a command that didn't succeed and, when it ran and failed,
+    /// offers to ask the model for a fix
+    fn failed(&self, message: &str, failure: Option<CommandFailure>) -> FormatResult {
+        let error = self.format_error(message);
+        match failure {
+            Some(failure) if io::stdin().is_terminal() => {
+                eprintln!("{error}");
+                if self.offer_fix() {
+                    FormatResult::FixRequested(failure)
+                } else {
+                    FormatResult::Executed(String::new())
+                }
+            }
+            _ => FormatResult::Executed(error),
+        }
+    }
+
+    /// Asks whether to get a fix for the failed command: f for yes, any other key for no
+    fn offer_fix(&self) -> bool {
+        matches!(
+            self.read_key("Press f to ask for a fix, any other key to exit"),
+            Some(KeyCode::Char('f' | 'F'))
+        )
+    }
+
+    /// Shows `prompt` and waits for a single key press
+    fn read_key(&self, prompt: &str) -> Option<KeyCode> {
+        eprint!("{} ", self.style_text(prompt, Color::Cyan));
+        let _ = io::stderr().flush();
+
+        enable_raw_mode().ok()?;
+        let key = loop {
+            match event::read() {
+                Ok(Event::Key(key_event)) if key_event.kind == KeyEventKind::Press => {
+                    break Some(key_event.code);
+                }
+                Ok(_) => continue,
+                Err(_) => break None,
+            }
+        };
+        let _ = disable_raw_mode();
+        eprintln!();
+        key
+    }
+
+    /// Runs the steps of a plan in order, asking before each one, and stops
+    /// at the first failure. A plan whose steps all ran is recorded, to be
+    /// offered again for the same request
+    pub async fn run_plan(
+        &self,
+        steps: &[Suggestion],
+        prompt: &str,
+        context: &mut ContextManager,
+    ) -> FormatResult {
+        if steps.is_empty() {
+            return FormatResult::Static(self.style_text("No plan found.", Color::Yellow));
+        }
+
+        let plan = self.format_plan(steps);
+        // Nobody can confirm the steps when piped
+        if !io::stdout().is_terminal() || !io::stdin().is_terminal() {
+            return FormatResult::Static(plan);
+        }
+        eprintln!("{plan}\n");
+        if self.dry_run {
+            let commands: Vec<&str> = steps.iter().map(|step| step.command.as_str()).collect();
+            return self.preview(&commands);
+        }
+
+        let validator = CommandValidator::for_shell(self.shell);
+        let mut ran = Vec::new();
+        let mut confirm = true;
+        for (index, step) in steps.iter().enumerate() {
+            let stopped = || {
+                FormatResult::Executed(
+                    self.format_warning(&format!("Stopped before step {}", index + 1)),
+                )
+            };
+            let progress = format!("[{}/{}]", index + 1, steps.len());
+            eprintln!(
+                "{} {}",
+                self.style_text(&progress, Color::Cyan),
+                self.highlight_command(&step.command)
+            );
+            // Steps running as root are asked about even after "yes to all",
+            // recorded workflows replayed from the cache included; with
+            // elevation confirmed they get the typed "yes" instead
+            let elevated = self.elevation == ElevationMode::Allow
+                && !validator.elevated_actions(&step.command).is_empty();
+            if confirm || elevated {
+                match self.read_key("Run it? y/Enter yes, s skip, a yes to all, q quit") {
+                    Some(KeyCode::Enter | KeyCode::Char('y' | 'Y')) => {}
+                    Some(KeyCode::Char('a' | 'A')) => confirm = false,
+                    Some(KeyCode::Char('s' | 'S')) => continue,
+                    _ => return stopped(),
+                }
+            }
 
-                let mut cmd = if cfg!(target_os = "windows") {
-                    let mut cmd = Command::new("cmd");
-                    cmd.args(["/C", selected_command]);
-                    cmd
+            let command = match self.fill_placeholders(step, context).await {
+                Ok(Some(command)) => command,
+                _ => return stopped(),
+            };
+            if let Err(error) = self.run_and_record(&command, prompt, None, context).await {
+                let message = format!(
+                    "Step {} failed, stopped the plan: {}",
+                    index + 1,
+                    error.message
+                );
+                return self.failed(&message, error.failure);
+            }
+            ran.push(Suggestion {
+                command,
+                placeholders: Vec::new(),
+                warnings: Vec::new(),
+                ..step.clone()
+            });
+        }
+
+        if ran.len() == steps.len() {
+            if let Err(e) = context.record_workflow(prompt, &ran).await {
+                log::warn!("Failed to record the workflow: {e}");
+            }
+        }
+        FormatResult::Executed(self.format_success(&format!(
+            "Ran {} of {} steps",
+            ran.len(),
+            steps.len()
+        )))
+    }
+
+    /// The steps of a plan, numbered, each with its explanation
+    fn format_plan(&self, steps: &[Suggestion]) -> String {
+        let mut output = self.style_text("Plan:", Color::Cyan);
+        for (index, step) in steps.iter().enumerate() {
+            output.push_str(&format!(
+                "\n{} {}",
+                self.style_text(&format!("{:>2}.", index + 1), Color::Cyan),
+                self.highlight_command(&step.command)
+            ));
+            if let Some(explanation) = &step.explanation {
+                output.push_str(&format!(
+                    "\n    {}",
+                    self.style_text(explanation, Color::White)
+                ));
+            }
+        }
+        output
+    }
+
+    /// The process that runs `command`: PowerShell (pwsh when installed),
+    /// cmd, or the user's shell, reading its startup files unless told not
+    /// to; Windows has no `sh`, so other shells get cmd there. In strict
+    /// mode a command that's only a program with arguments runs on its own
+    fn shell_command(&self, command: &str) -> Command {
+        match self.shell {
+            ShellKind::PowerShell => {
+                // Windows PowerShell ships with Windows; pwsh has to be installed
+                let program = if cfg!(target_os = "windows") && !ExecutableIndex::contains("pwsh") {
+                    "powershell"
                 } else {
-                    let mut cmd = Command::new("sh");
-                    cmd.args(["-c", selected_command]);
-                    cmd
+                    "pwsh"
                 };
+                let mut cmd = Command::new(program);
+                cmd.args(["-NoLogo", "-Command", command]);
+                cmd
+            }
+            shell if shell == ShellKind::Cmd || cfg!(target_os = "windows") => {
+                let mut cmd = Command::new("cmd");
+                cmd.args(["/C", command]);
+                cmd
+            }
+            _ => {
+                if let Some(argv) = self.strict.then(|| self.argv(command)).flatten() {
+                    let mut cmd = Command::new(&argv[0]);
+                    cmd.args(&argv[1..]);
+                    return cmd;
+                }
+                let program = self.shell_program();
+                let mut cmd = Command::new(&program);
+                // Interactive shells want a terminal to control
+                if self.interactive_shell && io::stdin().is_terminal() {
+                    cmd.args(["-i", "-c", command]);
+                } else {
+                    cmd.args(["-c", &with_aliases(&program, command)]);
+                }
+                cmd
+            }
+        }
+    }
 
-                match cmd.status() {
-                    Ok(status) => {
-                        let success = status.success();
-
-                        // Record feedback for learning
-                        if let Err(e) = context.record_suggestion_feedback(
-                            original_prompt,
-                            selected_command,
-                            success,
-                        ) {
-                            log::warn!("Failed to record suggestion feedback: {e}");
-                        }
-
-                        if success {
-                            FormatResult::Executed(String::new())
-                        } else {
-                            FormatResult::Executed(self.format_error(&format!(
-                                "Command exited with code: {:?}",
-                                status.code()
-                            )))
-                        }
-                    }
-                    Err(e) => {
-                        // Record execution failure
-                        if let Err(err) = context.record_suggestion_feedback(
-                            original_prompt,
-                            selected_command,
-                            false,
-                        ) {
-                            log::warn!("Failed to record suggestion feedback: {err}");
-                        }
-                        FormatResult::Executed(
-                            self.format_error(&format!("Failed to execute command: {e}")),
-                        )
+    /// `command` split into the program and its arguments, when it's only
+    /// that and the program is in PATH rather than an alias or built-in
+    fn argv(&self, command: &str) -> Option<Vec<String>> {
+        let argv = CommandValidator::for_shell(self.shell).argv(command)?;
+        let program = argv.first()?;
+        (program.contains('/')
+            || ExecutableIndex::contains(program) && !ShellDetector::is_user_alias(program))
+        .then_some(argv)
+    }
+
+    /// Shell running commands outside Windows: the configured one; SHELL
+    /// when the commands are written for it; else the shell they're written
+    /// for when it's installed, or `sh`
+    fn shell_program(&self) -> String {
+        if let Some(program) = &self.shell_program {
+            return program.clone();
+        }
+        if let Ok(shell) = env::var("SHELL") {
+            if ShellKind::from_name(&shell) == self.shell {
+                return shell;
+            }
+        }
+        let name = self.shell.as_str();
+        if ExecutableIndex::contains(name) {
+            name.to_string()
+        } else {
+            "sh".to_string()
+        }
+    }
+
+    /// Runs `selected_command`, records its outcome and output for learning,
+    /// and says why it failed if it did
+    async fn run_and_record(
+        &self,
+        selected_command: &str,
+        original_prompt: &str,
+        replacing: Option<&str>,
+        context: &mut ContextManager,
+    ) -> Result<(), RunError> {
+        // Ensure we're back to normal terminal mode before printing
+        io::stdout().flush().unwrap();
+        eprintln!("{selected_command}");
+
+        let validator = CommandValidator::for_shell(self.shell);
+        let verdict = validator.verdict(selected_command);
+        if let Some(verdict) = verdict
+            .as_ref()
+            .filter(|verdict| verdict.action == PolicyAction::Deny)
+        {
+            let reason = verdict
+                .reason
+                .as_ref()
+                .map_or_else(String::new, |reason| format!("{reason} "));
+            return Err(RunError {
+                message: format!("Not run: {reason}(denied by ~/.phloem/policy.toml)"),
+                failure: None,
+            });
+        }
+        let elevated = validator.elevated_actions(selected_command);
+        if self.elevation == ElevationMode::Block && !elevated.is_empty() {
+            return Err(RunError {
+                message: "Not run: it runs as root (blocked by safety.elevation)".to_string(),
+                failure: None,
+            });
+        }
+        // An allow rule lets the command through whatever it does
+        let (overwrites, network) = if verdict
+            .as_ref()
+            .is_some_and(|verdict| verdict.action == PolicyAction::Allow)
+        {
+            (Vec::new(), Vec::new())
+        } else {
+            (
+                validator.overwritten_files(selected_command),
+                validator.network_changes(selected_command),
+            )
+        };
+        let categories = Policy::current().categories();
+        if categories.overwrites == PolicyAction::Deny && !overwrites.is_empty() {
+            return Err(RunError {
+                message: format!(
+                    "Not run: it overwrites {} (denied by ~/.phloem/policy.toml)",
+                    overwrites.join(", ")
+                ),
+                failure: None,
+            });
+        }
+        if categories.network == PolicyAction::Deny && !network.is_empty() {
+            return Err(RunError {
+                message: format!(
+                    "Not run: {} (denied by ~/.phloem/policy.toml)",
+                    network.join(", ")
+                ),
+                failure: None,
+            });
+        }
+        if let Some(sandbox) = &self.sandbox {
+            if !self.trial_run(sandbox, selected_command) {
+                return Err(RunError {
+                    message: "Not run: stopped after the sandbox trial".to_string(),
+                    failure: None,
+                });
+            }
+        }
+        let verdict = verdict.filter(|_| self.confirm_destructive);
+        let elevated = match self.elevation {
+            ElevationMode::Confirm => elevated,
+            _ => Vec::new(),
+        };
+        let changes = Changes {
+            overwrites: match categories.overwrites {
+                PolicyAction::Confirm => overwrites,
+                _ => Vec::new(),
+            },
+            network: match categories.network {
+                PolicyAction::Confirm => network,
+                _ => Vec::new(),
+            },
+        };
+        if !self.confirm_if_risky(verdict.as_ref(), &elevated, &changes) {
+            return Err(RunError {
+                message: "Not run: not confirmed".to_string(),
+                failure: None,
+            });
+        }
+
+        let mut cmd = self.shell_command(selected_command);
+
+        let started_at = Utc::now();
+        // Held until the run is in the audit log: a Ctrl-C meant for the
+        // command may reach phloem's listener only after the command ended
+        let child = interrupt::child_running();
+        let result = match self.max_captured_bytes {
+            Some(max_bytes) if !needs_terminal(selected_command) => {
+                run_captured(&mut cmd, max_bytes).map(|(status, output)| (status, Some(output)))
+            }
+            _ => cmd.status().map(|status| (status, None)),
+        };
+        if let Err(e) = context.record_audit(AuditRecord {
+            prompt: original_prompt,
+            command: selected_command,
+            started_at,
+            exit_code: result.as_ref().ok().and_then(|(status, _)| status.code()),
+        }) {
+            log::warn!("Failed to record the command in the audit log: {e}");
+        }
+        drop(child);
+
+        match result {
+            Ok((status, output)) => {
+                let success = status.success();
+
+                // Record feedback for learning
+                if let Err(e) = context
+                    .record_suggestion_feedback(
+                        original_prompt,
+                        selected_command,
+                        success,
+                        status.code(),
+                        output.as_ref(),
+                    )
+                    .await
+                {
+                    log::warn!("Failed to record suggestion feedback: {e}");
+                }
+
+                if let Some(original) = replacing.filter(|_| success) {
+                    if let Err(e) = context
+                        .record_correction(original_prompt, original, selected_command)
+                        .await
+                    {
+                        log::warn!("Failed to record correction: {e}");
                     }
                 }
+
+                if success {
+                    Ok(())
+                } else {
+                    Err(RunError {
+                        message: format!("Command exited with code: {:?}", status.code()),
+                        failure: Some(CommandFailure {
+                            command: selected_command.to_string(),
+                            exit_code: status.code(),
+                            output: output.unwrap_or_default(),
+                        }),
+                    })
+                }
             }
-            Ok(SelectAction::Output(index)) => {
-                let selected_command = &suggestions[index].command;
-
-                // Copy to clipboard and show instructions
-                match Clipboard::new() {
-                    Ok(mut clipboard) => {
-                        if clipboard.set_text(selected_command).is_ok() {
-                            eprintln!("Command copied to clipboard: {selected_command}");
-                            eprintln!("Press Cmd+V (Mac) or Ctrl+V to paste at your prompt");
-                        } else {
-                            eprintln!("{selected_command}");
-                        }
-                    }
-                    Err(_) => {
-                        eprintln!("{selected_command}");
-                    }
+            Err(e) => {
+                // Record execution failure
+                if let Err(err) = context
+                    .record_suggestion_feedback(
+                        original_prompt,
+                        selected_command,
+                        false,
+                        None,
+                        None,
+                    )
+                    .await
+                {
+                    log::warn!("Failed to record suggestion feedback: {err}");
                 }
+                Err(RunError {
+                    message: format!("Failed to execute command: {e}"),
+                    failure: None,
+                })
+            }
+        }
+    }
 
-                FormatResult::Output(String::new())
+    /// Runs `command` in `sandbox`, read-only and offline, shows how it went
+    /// and asks whether to run it for real
+    fn trial_run(&self, sandbox: &Sandbox, command: &str) -> bool {
+        let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        let (mut cmd, name) = match sandbox.wrap(&self.shell_command(command), &cwd) {
+            Ok(wrapped) => wrapped,
+            Err(e) => {
+                eprintln!("{}", self.format_error(&format!("Can't try it first: {e}")));
+                return false;
             }
-            Ok(SelectAction::Followup(_index)) => FormatResult::FollowupRequested,
-            Ok(SelectAction::Cancel) => {
-                FormatResult::Static(self.format_suggestions_static(suggestions, show_explanations))
+        };
+
+        eprintln!(
+            "{}",
+            self.format_info(&format!("Trying it in {name}: read-only, without network"))
+        );
+        cmd.stdin(Stdio::null());
+        let child = interrupt::child_running();
+        let status = cmd.status();
+        drop(child);
+        let outcome = match status {
+            Ok(status) if status.success() => self.format_success("The trial succeeded"),
+            Ok(status) => self.format_warning(&format!(
+                "The trial exited with code {}; writes and network access fail in the sandbox",
+                status
+                    .code()
+                    .map_or_else(|| "none".to_string(), |code| code.to_string())
+            )),
+            Err(e) => self.format_error(&format!("The trial didn't start: {e}")),
+        };
+        eprintln!("{outcome}");
+
+        // Nobody can answer when the prompt itself was piped in
+        io::stdin().is_terminal()
+            && matches!(
+                self.read_key("Run it for real? y for yes, any other key to stop"),
+                Some(KeyCode::Char('y' | 'Y'))
+            )
+    }
+
+    /// Warns about a command the policy asks about and what it acts on, what
+    /// it does as root for each of `elevated` and the `changes` its categories
+    /// ask about. A rule or running as root asks once for a typed "yes", the
+    /// categories alone for a y keystroke; other commands pass straight through
+    fn confirm_if_risky(
+        &self,
+        verdict: Option<&Verdict>,
+        elevated: &[String],
+        changes: &Changes,
+    ) -> bool {
+        let verdict = verdict.filter(|verdict| verdict.action == PolicyAction::Confirm);
+        if verdict.is_none() && elevated.is_empty() && changes.is_empty() {
+            return true;
+        }
+
+        if let Some(verdict) = verdict {
+            let warning = match &verdict.reason {
+                Some(reason) => format!("⚠ This command {reason}"),
+                None => {
+                    "⚠ This command deletes data, rewrites history or stops the machine".to_string()
+                }
+            };
+            eprintln!("{}", self.style_text(&warning, Color::Red));
+            let targets = &verdict.targets;
+            if !targets.is_empty() {
+                eprintln!(
+                    "{}",
+                    self.style_text(&format!("  Targets: {}", targets.join(", ")), Color::Red)
+                );
             }
-            Err(_) => {
-                FormatResult::Static(self.format_suggestions_static(suggestions, show_explanations))
+        }
+        if !elevated.is_empty() {
+            eprintln!(
+                "{}",
+                self.style_text("⚠ This command runs as root or another user:", Color::Red)
+            );
+            for action in elevated {
+                eprintln!("{}", self.style_text(&format!("  {action}"), Color::Red));
+            }
+        }
+        if !changes.overwrites.is_empty() {
+            eprintln!(
+                "{}",
+                self.style_text(
+                    &format!(
+                        "⚠ This command overwrites {}",
+                        changes.overwrites.join(", ")
+                    ),
+                    Color::Yellow
+                )
+            );
+        }
+        if !changes.network.is_empty() {
+            eprintln!(
+                "{}",
+                self.style_text(
+                    "⚠ This command changes things over the network:",
+                    Color::Yellow
+                )
+            );
+            for change in &changes.network {
+                eprintln!("{}", self.style_text(&format!("  {change}"), Color::Yellow));
             }
         }
+
+        // Nobody can answer when the prompt itself was piped in
+        if !io::stdin().is_terminal() {
+            return false;
+        }
+        if verdict.is_none() && elevated.is_empty() {
+            return matches!(
+                self.read_key("Run it? y for yes, any other key to stop"),
+                Some(KeyCode::Char('y' | 'Y'))
+            );
+        }
+        eprint!("Type \"yes\" to run it: ");
+        let _ = io::stderr().flush();
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer).is_ok() && answer.trim() == "yes"
     }
 
-    // ========================================================================
-    // Interactive Selection
-    // ========================================================================
+    /// Colors command name, flags, strings and operators of `command`
+    fn highlight_command(&self, command: &str) -> String {
+        if !self.syntax_highlighting {
+            return self.style_text(command, Color::Green);
+        }
 
-    /// Custom selection interface with keyboard navigation
-    fn custom_select(&self, items: &[String]) -> Result<SelectAction, io::Error> {
-        enable_raw_mode()?;
-        let mut stdout = io::stdout();
-        execute!(stdout, EnterAlternateScreen)?;
+        tokenize(command)
+            .into_iter()
+            .map(|(kind, text)| match kind {
+                TokenKind::Command => self.style_text(text, Color::Green),
+                TokenKind::Flag => self.style_text(text, Color::Cyan),
+                TokenKind::String => self.style_text(text, Color::Yellow),
+                TokenKind::Operator => self.style_text(text, Color::Magenta),
+                TokenKind::Variable => self.style_text(text, Color::Blue),
+                TokenKind::Text => text.to_string(),
+            })
+            .collect()
+    }
 
-        let mut selected = 0;
+    /// Puts `command` on the prompt through the shell widget, or else copies it
+    /// to the clipboard; over SSH, or without a clipboard, through the terminal
+    /// with OSC 52
+    fn output(&self, command: &str) -> FormatResult {
+        if let Some(path) = env::var_os(integration::BUFFER_FILE_VAR).filter(|p| !p.is_empty()) {
+            match fs::write(&path, command) {
+                Ok(()) => return FormatResult::Output(String::new()),
+                Err(e) => log::warn!("Failed to hand the command to the shell: {e}"),
+            }
+        }
 
-        let result = self.selection_loop(&mut stdout, items, &mut selected);
+        let copied = !EnvironmentDetector::in_ssh_session() && copy_to_clipboard(command);
+        if copied {
+            eprintln!("Command copied to clipboard: {command}");
+            eprintln!("Press Cmd+V (Mac) or Ctrl+V to paste at your prompt");
+        } else if io::stderr().is_terminal() && copy_with_osc52(command).is_ok() {
+            eprintln!("Command copied to your terminal's clipboard: {command}");
+        } else {
+            eprintln!("{command}");
+        }
+
+        FormatResult::Output(String::new())
+    }
+
+    /// Lets the user change `command` on the current line; `None` when cancelled
+    /// with Esc or Ctrl+C
+    fn edit_line(&self, command: &str) -> Result<Option<String>, io::Error> {
+        self.read_line("Edit: ", command, &[])
+    }
 
+    /// Reads a line after `prompt`, starting from `initial`; `None` when the
+    /// user cancels or leaves it empty
+    fn read_line(
+        &self,
+        prompt: &str,
+        initial: &str,
+        candidates: &[String],
+    ) -> Result<Option<String>, io::Error> {
+        enable_raw_mode()?;
+        let result = self.edit_loop(prompt, initial, candidates);
         disable_raw_mode()?;
-        execute!(stdout, LeaveAlternateScreen)?;
-        result
+        eprintln!();
+
+        Ok(result?.filter(|edited| !edited.trim().is_empty()))
     }
 
-    /// Main selection loop handling user input
-    fn selection_loop(
+    /// Line editing loop: typing, Backspace/Delete, arrows, Home/End, and Tab
+    /// cycling through the `candidates` that start with what was typed
+    fn edit_loop(
         &self,
-        stdout: &mut io::Stdout,
-        items: &[String],
-        selected: &mut usize,
-    ) -> Result<SelectAction, io::Error> {
+        prompt: &str,
+        initial: &str,
+        candidates: &[String],
+    ) -> Result<Option<String>, io::Error> {
+        let mut line: Vec<char> = initial.chars().collect();
+        let mut cursor = line.len();
+        let mut stderr = io::stderr();
+        // What was typed before the first Tab, and how many Tabs followed
+        let mut completion: Option<(String, usize)> = None;
+
         loop {
-            self.render_menu(stdout, items, *selected)?;
+            execute!(
+                stderr,
+                crossterm::cursor::MoveToColumn(0),
+                crossterm::terminal::Clear(crossterm::terminal::ClearType::CurrentLine)
+            )?;
+            eprint!(
+                "{}{}",
+                self.style_text(prompt, Color::Cyan),
+                line.iter().collect::<String>()
+            );
+            execute!(
+                stderr,
+                crossterm::cursor::MoveToColumn((prompt.chars().count() + cursor) as u16)
+            )?;
 
-            if let Event::Key(key_event) = event::read()? {
-                match self.handle_key_input(key_event.code, selected, items.len()) {
-                    Some(action) => return Ok(action),
-                    None => continue,
+            let Event::Key(key_event) = event::read()? else {
+                continue;
+            };
+            if key_event.code == KeyCode::Tab {
+                let (stem, tabs) = completion.get_or_insert_with(|| (line.iter().collect(), 0));
+                let matches: Vec<&String> = candidates
+                    .iter()
+                    .filter(|candidate| candidate.starts_with(stem.as_str()))
+                    .collect();
+                if !matches.is_empty() {
+                    line = matches[*tabs % matches.len()].chars().collect();
+                    cursor = line.len();
+                    *tabs += 1;
                 }
+                continue;
+            }
+            completion = None;
+            match key_event.code {
+                KeyCode::Enter => return Ok(Some(line.iter().collect())),
+                KeyCode::Esc => return Ok(None),
+                KeyCode::Char('c') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(None)
+                }
+                KeyCode::Char(c) => {
+                    line.insert(cursor, c);
+                    cursor += 1;
+                }
+                KeyCode::Backspace if cursor > 0 => {
+                    cursor -= 1;
+                    line.remove(cursor);
+                }
+                KeyCode::Delete if cursor < line.len() => {
+                    line.remove(cursor);
+                }
+                KeyCode::Left => cursor = cursor.saturating_sub(1),
+                KeyCode::Right => cursor = (cursor + 1).min(line.len()),
+                KeyCode::Home => cursor = 0,
+                KeyCode::End => cursor = line.len(),
+                _ => {}
             }
         }
     }
 
-    /// Renders the selection menu
-    fn render_menu(
+    /// `suggestion`'s command with its placeholders filled in by the user,
+    /// offering values from the context on Tab; `None` when they cancel
+    async fn fill_placeholders(
         &self,
-        stdout: &mut io::Stdout,
-        items: &[String],
-        selected: usize,
-    ) -> Result<(), io::Error> {
-        execute!(
-            stdout,
-            crossterm::terminal::Clear(crossterm::terminal::ClearType::All)
-        )?;
-        execute!(stdout, crossterm::cursor::MoveTo(0, 0))?;
+        suggestion: &Suggestion,
+        context: &ContextManager,
+    ) -> Result<Option<String>, io::Error> {
+        if suggestion.placeholders.is_empty() {
+            return Ok(Some(suggestion.command.clone()));
+        }
 
-        println!("Select command (Enter=run, Tab=output, Esc=follow-up, Esc Esc=exit):\r");
-        println!("\r");
+        eprintln!("{}", self.highlight_command(&suggestion.command));
+        let runtime = context
+            .cache
+            .get_environment()
+            .await
+            .ok()
+            .and_then(|environment| environment.get("container_runtime").cloned());
 
-        for (i, item) in items.iter().enumerate() {
-            if i == selected {
-                println!("▶ {}\r", self.style_text(item, Color::Green));
-            } else {
-                println!("  {item}\r");
+        let mut values = Vec::new();
+        for placeholder in &suggestion.placeholders {
+            let candidates = placeholder.candidates(runtime.as_deref());
+            let mut hint = placeholder.description.clone().unwrap_or_default();
+            if !candidates.is_empty() {
+                let shown: Vec<&str> = candidates.iter().take(5).map(String::as_str).collect();
+                let more = if candidates.len() > shown.len() {
+                    ", ..."
+                } else {
+                    ""
+                };
+                hint = format!("{hint} (Tab: {}{more})", shown.join(", "))
+                    .trim_start()
+                    .to_string();
+            }
+            if !hint.is_empty() {
+                eprintln!("{}", self.style_text(&format!("  {hint}"), Color::White));
             }
+
+            let prompt = format!("{}: ", placeholder.name);
+            let Some(value) = self.read_line(&prompt, "", &candidates)? else {
+                return Ok(None);
+            };
+            values.push((placeholder.name.clone(), value));
         }
 
-        stdout.flush()
+        Ok(Some(Placeholder::fill(&suggestion.command, &values)))
     }
 
-    /// Handles keyboard input and returns action if any
-    fn handle_key_input(
+    /// Renders suggestions for scripts and other tools, without any interaction
+    pub fn format_suggestions_as(
         &self,
-        key_code: KeyCode,
-        selected: &mut usize,
-        items_len: usize,
-    ) -> Option<SelectAction> {
-        match key_code {
-            KeyCode::Up => {
-                *selected = selected.saturating_sub(1);
-                None
-            }
-            KeyCode::Down => {
-                if *selected < items_len - 1 {
-                    *selected += 1;
-                }
-                None
-            }
-            KeyCode::Enter => Some(SelectAction::Execute(*selected)),
-            KeyCode::Tab => Some(SelectAction::Output(*selected)),
-            KeyCode::Char('f') | KeyCode::Char('F') => Some(SelectAction::Followup(*selected)),
-            KeyCode::Esc => self.handle_escape_key(*selected),
-            _ => None,
+        suggestions: &[Suggestion],
+        mode: OutputMode,
+        show_explanations: bool,
+        original_prompt: &str,
+    ) -> serde_json::Result<String> {
+        match mode {
+            OutputMode::Json => serde_json::to_string_pretty(&SuggestionsDocument {
+                prompt: original_prompt,
+                suggestions,
+            }),
+            OutputMode::Plain => Ok(suggestions
+                .iter()
+                .map(|suggestion| suggestion.command.as_str())
+                .collect::<Vec<_>>()
+                .join("\n")),
+            OutputMode::Table => Ok(self.format_suggestions_table(suggestions, show_explanations)),
         }
     }
 
-    /// Handles escape key with double-escape detection
-    fn handle_escape_key(&self, selected: usize) -> Option<SelectAction> {
-        let timeout = Duration::from_millis(300);
+    fn format_suggestions_table(
+        &self,
+        suggestions: &[Suggestion],
+        show_explanations: bool,
+    ) -> String {
+        let width = suggestions
+            .iter()
+            .map(|suggestion| suggestion.command.chars().count())
+            .chain(std::iter::once("COMMAND".len()))
+            .max()
+            .unwrap_or_default();
+
+        let validator = CommandValidator::for_shell(self.shell);
+        let mut header = format!(
+            "{:<3} {:<width$}  {:>10}  {:<7}  {:<9}",
+            "#", "COMMAND", "CONFIDENCE", "SOURCE", "RISK"
+        );
+        if show_explanations {
+            header.push_str("  EXPLANATION");
+        }
+        let mut lines = vec![self.style_text(header.trim_end(), Color::Cyan)];
 
-        if let Ok(true) = event::poll(timeout) {
-            if let Ok(Event::Key(second_key)) = event::read() {
-                if matches!(second_key.code, KeyCode::Esc) {
-                    return Some(SelectAction::Cancel);
-                }
-                // Different key after escape - treat as single escape
-                return Some(SelectAction::Followup(selected));
+        for (i, suggestion) in suggestions.iter().enumerate() {
+            let mut line = format!(
+                "{:<3} {:<width$}  {:>9.0}%  {:<7}  {:<9}",
+                i + 1,
+                suggestion.command,
+                suggestion.confidence * 100.0,
+                suggestion.source.as_str(),
+                validator.assess_risk(&suggestion.command).level.as_str()
+            );
+            if show_explanations {
+                line.push_str(&format!(
+                    "  {}",
+                    suggestion.explanation.as_deref().unwrap_or("")
+                ));
             }
+            lines.push(line.trim_end().to_string());
         }
 
-        // Single escape (no second key within timeout)
-        Some(SelectAction::Followup(selected))
+        lines.join("\n")
     }
 
     fn format_suggestions_static(
@@ -311,13 +1555,14 @@ impl OutputFormatter {
         suggestions: &[Suggestion],
         show_explanations: bool,
     ) -> String {
+        let validator = CommandValidator::for_shell(self.shell);
         let mut output = String::new();
 
         for (i, suggestion) in suggestions.iter().enumerate() {
             // Command number and text
             let number = format!("{}. ", i + 1);
             output.push_str(&self.style_text(&number, Color::Cyan));
-            output.push_str(&self.style_text(&suggestion.command, Color::Green));
+            output.push_str(&self.highlight_command(&suggestion.command));
             output.push('\n');
 
             // Explanation if available and requested
@@ -329,6 +1574,19 @@ impl OutputFormatter {
                 }
             }
 
+            for warning in &suggestion.warnings {
+                output.push_str(&self.style_text(&format!("   ⚠ {warning}"), Color::Yellow));
+                output.push('\n');
+            }
+
+            let risk = validator.assess_risk(&suggestion.command);
+            let label = match risk.reasons.as_slice() {
+                [] => format!("   Risk: {}", risk.level.as_str()),
+                reasons => format!("   Risk: {} ({})", risk.level.as_str(), reasons.join("; ")),
+            };
+            output.push_str(&self.style_text(&label, risk_color(risk.level)));
+            output.push('\n');
+
             // Confidence (only in verbose mode)
             if suggestion.confidence > 0.0 {
                 let confidence = format!("   (confidence: {:.1}%)", suggestion.confidence * 100.0);
@@ -360,17 +1618,202 @@ impl OutputFormatter {
         format!("{} {}", self.style_text("ℹ", Color::Blue), message)
     }
 
+    /// The one place printed text gets colored
     fn style_text(&self, text: &str, color: Color) -> String {
-        if self.use_colors {
-            style(text).fg(color).to_string()
+        if self.styled {
+            // Whether to color is decided here, not by console's own detection
+            style(text).fg(color).force_styling(true).to_string()
         } else {
             text.to_string()
         }
     }
 }
 
+/// Color a risk level is printed in
+fn risk_color(level: RiskLevel) -> Color {
+    match level {
+        RiskLevel::Safe => Color::Green,
+        RiskLevel::Caution => Color::Yellow,
+        RiskLevel::Dangerous => Color::Red,
+    }
+}
+
+/// `command` with the definitions of the user's aliases it uses put first,
+/// for a `program` that doesn't read the startup files defining them
+fn with_aliases(program: &str, command: &str) -> String {
+    let name = program.rsplit('/').next().unwrap_or(program);
+    if name == "nu" {
+        return command.to_string();
+    }
+    let defined = ShellDetector::with_alias_definitions(command);
+    // bash only expands aliases outside interactive shells when told to
+    if name == "bash" && defined != command {
+        format!("shopt -s expand_aliases\n{defined}")
+    } else {
+        defined
+    }
+}
+
+/// Whether NO_COLOR asks for no colors at all (https://no-color.org)
+fn no_color() -> bool {
+    env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
 impl Default for OutputFormatter {
     fn default() -> Self {
         Self::new(true)
     }
 }
+
+/// Copies `text` with the system clipboard
+#[cfg(feature = "clipboard")]
+fn copy_to_clipboard(text: &str) -> bool {
+    arboard::Clipboard::new().is_ok_and(|mut clipboard| clipboard.set_text(text).is_ok())
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn copy_to_clipboard(_text: &str) -> bool {
+    false
+}
+
+/// Asks the terminal to set the clipboard of the machine it runs on, which
+/// works over SSH; inside tmux the sequence is passed through to the outer terminal
+fn copy_with_osc52(text: &str) -> io::Result<()> {
+    let payload = base64::engine::general_purpose::STANDARD.encode(text);
+    let sequence = if env::var_os("TMUX").is_some() {
+        format!("\x1bPtmux;\x1b\x1b]52;c;{payload}\x07\x1b\\")
+    } else {
+        format!("\x1b]52;c;{payload}\x07")
+    };
+    let mut stderr = io::stderr();
+    stderr.write_all(sequence.as_bytes())?;
+    stderr.flush()
+}
+
+/// Name offered when saving a command: the first words of its prompt joined
+/// with dashes, e.g. `restart-web-container`
+fn default_favorite_name(prompt: &str) -> String {
+    prompt
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .take(4)
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Programs that draw on or read from the terminal; they run uncaptured
+const TERMINAL_PROGRAMS: &[&str] = &[
+    "vi",
+    "vim",
+    "nvim",
+    "nano",
+    "emacs",
+    "micro",
+    "hx",
+    "less",
+    "more",
+    "most",
+    "man",
+    "top",
+    "htop",
+    "btop",
+    "watch",
+    "ssh",
+    "mosh",
+    "tmux",
+    "screen",
+    "fzf",
+    "lazygit",
+    "lazydocker",
+    "tig",
+    "k9s",
+    "ranger",
+    "nnn",
+    "mc",
+    "ncdu",
+    "psql",
+    "mysql",
+    "sqlite3",
+    "redis-cli",
+    "python",
+    "python3",
+    "node",
+    "irb",
+    "ghci",
+    "bash",
+    "zsh",
+    "fish",
+    "sh",
+];
+
+/// Whether `command` has to run on the terminal itself: a full-screen or
+/// interactive program, an `-it` container session, or a job left running in
+/// the background, which would hold the captured pipes open
+fn needs_terminal(command: &str) -> bool {
+    let interactive_flag = command
+        .split_whitespace()
+        .any(|word| matches!(word, "-it" | "-ti" | "--interactive" | "--tty"));
+    let background = command.trim_end().ends_with('&') && !command.trim_end().ends_with("&&")
+        || command.contains("nohup")
+        || command.contains("disown");
+
+    interactive_flag
+        || background
+        || CommandValidator::new()
+            .extract_executables(command)
+            .iter()
+            .any(|name| TERMINAL_PROGRAMS.contains(&name.rsplit('/').next().unwrap_or(name)))
+}
+
+/// Runs `cmd` with its output passed through to ours, keeping the last
+/// `max_bytes` of each stream
+fn run_captured(cmd: &mut Command, max_bytes: usize) -> io::Result<(ExitStatus, CapturedOutput)> {
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let stdout = child
+        .stdout
+        .take()
+        .map(|pipe| thread::spawn(move || tee(pipe, io::stdout(), max_bytes)));
+    let stderr = child
+        .stderr
+        .take()
+        .map(|pipe| thread::spawn(move || tee(pipe, io::stderr(), max_bytes)));
+
+    let status = child.wait()?;
+    let collect = |handle: Option<thread::JoinHandle<String>>| {
+        handle
+            .and_then(|handle| handle.join().ok())
+            .unwrap_or_default()
+    };
+    Ok((
+        status,
+        CapturedOutput {
+            stdout: collect(stdout),
+            stderr: collect(stderr),
+        },
+    ))
+}
+
+/// Copies `source` to `sink` as it arrives and returns its last `max_bytes`
+fn tee(mut source: impl Read, mut sink: impl Write, max_bytes: usize) -> String {
+    let mut tail: Vec<u8> = Vec::new();
+    let mut buffer = [0u8; 8192];
+    loop {
+        match source.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => {
+                let _ = sink.write_all(&buffer[..read]);
+                let _ = sink.flush();
+                tail.extend_from_slice(&buffer[..read]);
+                // Trim in batches rather than on every read
+                if tail.len() > 2 * max_bytes {
+                    tail.drain(..tail.len() - max_bytes);
+                }
+            }
+        }
+    }
+    if tail.len() > max_bytes {
+        tail.drain(..tail.len() - max_bytes);
+    }
+    String::from_utf8_lossy(&tail).into_owned()
+}
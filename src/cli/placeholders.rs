@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// How long a tool may take to list candidates before it's left out
+const CANDIDATE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Most candidates offered for one placeholder
+const MAX_CANDIDATES: usize = 50;
+
+/// A value the user fills in before a command runs, written `{{name}}` in it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Placeholder {
+    pub name: String,
+    /// What to fill in, as the model described it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl Placeholder {
+    /// The placeholders of `command` in the order they first appear
+    pub fn find(command: &str) -> Vec<Self> {
+        let mut placeholders: Vec<Self> = Vec::new();
+        let mut rest = command;
+        while let Some(start) = rest.find("{{") {
+            let Some(end) = rest[start + 2..].find("}}") else {
+                break;
+            };
+            let name = rest[start + 2..start + 2 + end].trim();
+            if is_name(name) && placeholders.iter().all(|p| p.name != name) {
+                placeholders.push(Self {
+                    name: name.to_string(),
+                    description: None,
+                });
+            }
+            rest = &rest[start + 2 + end + 2..];
+        }
+        placeholders
+    }
+
+    /// `command` with each placeholder replaced by its value; values are
+    /// quoted for the shell unless the placeholder already sits in quotes
+    pub fn fill(command: &str, values: &[(String, String)]) -> String {
+        let mut filled = String::with_capacity(command.len());
+        let mut rest = command;
+        while let Some(start) = rest.find("{{") {
+            let Some(end) = rest[start + 2..].find("}}") else {
+                break;
+            };
+            let name = rest[start + 2..start + 2 + end].trim();
+            filled.push_str(&rest[..start]);
+            match values.iter().find(|(placeholder, _)| placeholder == name) {
+                Some((_, value)) if filled.ends_with(['\'', '"']) => filled.push_str(value),
+                Some((_, value)) => filled.push_str(&quote(value)),
+                None => filled.push_str(&rest[start..start + 2 + end + 2]),
+            }
+            rest = &rest[start + 2 + end + 2..];
+        }
+        filled.push_str(rest);
+        filled
+    }
+
+    /// Values that fit this placeholder, judged by its name: running
+    /// containers for `{{container}}`, the files here for `{{file}}`, git
+    /// branches for `{{branch}}`, and so on. `runtime` is the container
+    /// runtime detected in the environment
+    pub fn candidates(&self, runtime: Option<&str>) -> Vec<String> {
+        let name = self.name.to_lowercase();
+        let has = |words: &[&str]| words.iter().any(|word| name.contains(word));
+
+        let mut candidates = if has(&["container"]) {
+            let program = match runtime {
+                Some("Podman") => "podman",
+                _ => "docker",
+            };
+            list(program, &["ps", "--format", "{{.Names}}"])
+        } else if has(&["image"]) {
+            let program = match runtime {
+                Some("Podman") => "podman",
+                _ => "docker",
+            };
+            list(program, &["images", "--format", "{{.Repository}}:{{.Tag}}"])
+        } else if let Some(kind) = ["pod", "deployment", "service", "namespace", "node"]
+            .into_iter()
+            .find(|kind| name.contains(kind))
+        {
+            list("kubectl", &["get", kind, "-o", "name"])
+                .into_iter()
+                .map(|resource| match resource.split_once('/') {
+                    Some((_, name)) => name.to_string(),
+                    None => resource,
+                })
+                .collect()
+        } else if has(&["branch"]) {
+            list("git", &["branch", "--format=%(refname:short)"])
+        } else if has(&["remote"]) {
+            list("git", &["remote"])
+        } else if has(&["dir", "folder"]) {
+            directory_entries(true)
+        } else if has(&["file", "path"]) {
+            directory_entries(false)
+        } else {
+            Vec::new()
+        };
+        candidates.truncate(MAX_CANDIDATES);
+        candidates
+    }
+}
+
+/// Placeholder names are identifiers, e.g. `container_name`; anything else in
+/// braces, like a Go template, is left alone
+fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// `value` in single quotes when the shell would split or expand it
+fn quote(value: &str) -> String {
+    let plain = value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "_-./:@%+=,~".contains(c));
+    if plain && !value.is_empty() {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+}
+
+/// Lines printed by `program`, empty when it fails or takes too long
+fn list(program: &str, args: &[&str]) -> Vec<String> {
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null());
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(command.output());
+    });
+    match receiver.recv_timeout(CANDIDATE_TIMEOUT) {
+        Ok(Ok(output)) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Names in the working directory, directories only when `directories`,
+/// leaving out hidden ones
+fn directory_entries(directories: bool) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(".") else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| !directories || entry.file_type().is_ok_and(|kind| kind.is_dir()))
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.starts_with('.'))
+        .collect();
+    names.sort();
+    names
+}
@@ -0,0 +1,684 @@
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    Frame, Terminal,
+};
+use std::collections::HashMap;
+use std::io::{self, Stdout};
+use std::time::{Duration, Instant};
+
+use crate::cli::highlight::{tokenize, TokenKind};
+use crate::cli::output::SelectAction;
+use crate::cli::{KeyAction, KeyMap, Suggestion};
+use crate::utils::{CommandDocs, CommandValidator, DryRun, Risk, RiskLevel, ShellKind, Timing};
+
+/// Terminal width from which the details pane sits beside the list instead of below it
+const SIDE_BY_SIDE_WIDTH: u16 = 100;
+
+/// Height of the details pane when it sits below the list
+const DETAILS_HEIGHT: u16 = 9;
+
+/// Lines the preview scrolls by on PageUp and PageDown
+const PREVIEW_SCROLL_LINES: u16 = 5;
+
+/// What the pane beside the list shows in place of the details
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Preview {
+    /// The docs of the highlighted command's executable
+    Docs,
+    /// What the highlighted command would affect
+    DryRun,
+}
+
+/// Full-screen picker: a scrollable list of suggestions next to the
+/// explanation and risks of the highlighted one
+pub struct Selector<'a> {
+    suggestions: &'a [Suggestion],
+    /// What the list shows for each suggestion
+    items: &'a [String],
+    use_colors: bool,
+    /// Color the parts of each command instead of the whole line
+    syntax_highlighting: bool,
+    /// Shell the commands are written for, whose rules judge their risks
+    shell: ShellKind,
+    /// Risk of each suggestion, assessed when the picker opens
+    risks: Vec<Risk>,
+    /// Fuzzy filter typed after `/`, while filtering
+    filter: Option<String>,
+    /// Suggestions marked with Space, in the order they were marked
+    marked: Vec<usize>,
+    /// Shown instead of the details of the highlighted command, if anything
+    preview: Option<Preview>,
+    preview_scroll: u16,
+    /// Docs looked up so far by executable, `None` when there are none
+    previews: HashMap<String, Option<String>>,
+    /// Dry runs so far by command, with what they printed
+    dry_runs: HashMap<String, (DryRun, Option<String>)>,
+    keys: KeyMap,
+    /// First key of a two-key sequence such as `gg`, waiting for the second
+    pending: Option<char>,
+    state: ListState,
+}
+
+impl<'a> Selector<'a> {
+    pub fn new(suggestions: &'a [Suggestion], items: &'a [String], use_colors: bool) -> Self {
+        Self {
+            suggestions,
+            items,
+            use_colors,
+            syntax_highlighting: false,
+            shell: ShellKind::detect(),
+            risks: Vec::new(),
+            filter: None,
+            marked: Vec::new(),
+            preview: None,
+            preview_scroll: 0,
+            previews: HashMap::new(),
+            dry_runs: HashMap::new(),
+            keys: KeyMap::default(),
+            pending: None,
+            state: ListState::default().with_selected(Some(0)),
+        }
+    }
+
+    /// Whether commands are colored by their parts
+    pub fn with_syntax_highlighting(mut self, syntax_highlighting: bool) -> Self {
+        self.syntax_highlighting = syntax_highlighting;
+        self
+    }
+
+    /// Judges the commands as written for `shell`
+    pub fn with_shell(mut self, shell: ShellKind) -> Self {
+        self.shell = shell;
+        self
+    }
+
+    /// Acts on keys as `keys` binds them
+    pub fn with_keys(mut self, keys: KeyMap) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    /// Shows the picker on the alternate screen until the user picks an action
+    pub fn run(mut self) -> io::Result<SelectAction> {
+        let started = Instant::now();
+        let validator = CommandValidator::for_shell(self.shell);
+        self.risks = self
+            .suggestions
+            .iter()
+            .map(|suggestion| validator.assess_risk(&suggestion.command))
+            .collect();
+
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+
+        let result = Terminal::new(CrosstermBackend::new(io::stdout())).and_then(|mut terminal| {
+            let action = self.event_loop(&mut terminal, started);
+            terminal.show_cursor()?;
+            action
+        });
+
+        disable_raw_mode()?;
+        execute!(io::stdout(), LeaveAlternateScreen)?;
+        result
+    }
+
+    /// Draws and answers keys until an action is picked; the time to the
+    /// first frame, from `started`, is timed as rendering
+    fn event_loop(
+        &mut self,
+        terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+        started: Instant,
+    ) -> io::Result<SelectAction> {
+        let mut started = Some(started);
+        loop {
+            let visible = filter_items(self.items, self.filter.as_deref().unwrap_or(""));
+            let selected = self
+                .state
+                .selected()
+                .unwrap_or(0)
+                .min(visible.len().saturating_sub(1));
+            self.state.select((!visible.is_empty()).then_some(selected));
+
+            // Every pass redraws to the current size, so resizes need no handling of their own
+            terminal.draw(|frame| self.draw(frame, &visible))?;
+            if let Some(started) = started.take() {
+                Timing::record("render", started.elapsed());
+            }
+
+            let Event::Key(key_event) = event::read()? else {
+                continue;
+            };
+            if key_event.kind != KeyEventKind::Press {
+                continue;
+            }
+            if let Some(action) = self.handle_key(key_event.code, &visible) {
+                return Ok(action);
+            }
+        }
+    }
+
+    /// Applies a key press; actions name the picked suggestion by its index
+    /// among all suggestions, not among the filtered ones
+    fn handle_key(&mut self, key_code: KeyCode, visible: &[usize]) -> Option<SelectAction> {
+        // Filters ignore whitespace, so Space marks even while filtering
+        if key_code == KeyCode::Char(' ')
+            && self.keys.action(None, key_code) == Some(KeyAction::Mark)
+        {
+            self.toggle_mark(visible);
+            return None;
+        }
+
+        if let Some(query) = self.filter.as_mut() {
+            match key_code {
+                KeyCode::Char(c) => {
+                    query.push(c);
+                    self.state.select(Some(0));
+                    return None;
+                }
+                KeyCode::Backspace => {
+                    if query.pop().is_none() {
+                        self.filter = None;
+                    }
+                    return None;
+                }
+                KeyCode::Esc => {
+                    self.filter = None;
+                    return None;
+                }
+                _ => {}
+            }
+        }
+
+        // The first key of a sequence waits for the second; a key that
+        // completes none is dropped along with it
+        let pending = self.pending.take();
+        if let (None, KeyCode::Char(c)) = (pending, key_code) {
+            if self.filter.is_none() && self.keys.starts_sequence(c) {
+                self.pending = Some(c);
+                return None;
+            }
+        }
+        let action = self.keys.action(pending, key_code);
+        if pending.is_some() && action.is_none() {
+            return None;
+        }
+
+        // 1-9 run that suggestion straight away
+        if let (None, KeyCode::Char(digit @ '1'..='9')) = (action, key_code) {
+            let position = digit.to_digit(10)? as usize - 1;
+            return visible
+                .get(position)
+                .map(|&index| SelectAction::Execute(index));
+        }
+
+        if self.filter.is_none() {
+            if let Some(preview) = match action {
+                Some(KeyAction::Filter) => {
+                    self.filter = Some(String::new());
+                    return None;
+                }
+                Some(KeyAction::Docs) => Some(Preview::Docs),
+                Some(KeyAction::DryRun) => Some(Preview::DryRun),
+                _ => None,
+            } {
+                // The same key closes the preview, the other switches to its own
+                self.preview = (self.preview != Some(preview)).then_some(preview);
+                self.preview_scroll = 0;
+                return None;
+            }
+        }
+
+        if self.preview.is_some() {
+            match (key_code, action) {
+                (KeyCode::Esc, _) => {
+                    self.preview = None;
+                    return None;
+                }
+                (KeyCode::PageDown, _) => {
+                    self.preview_scroll = self.preview_scroll.saturating_add(PREVIEW_SCROLL_LINES);
+                    return None;
+                }
+                (KeyCode::PageUp, _) => {
+                    self.preview_scroll = self.preview_scroll.saturating_sub(PREVIEW_SCROLL_LINES);
+                    return None;
+                }
+                // The preview follows the highlighted command from its top
+                (_, Some(KeyAction::Up | KeyAction::Down | KeyAction::Top | KeyAction::Bottom)) => {
+                    self.preview_scroll = 0
+                }
+                _ => {}
+            }
+        }
+
+        // Nothing to act on while no suggestion matches the filter
+        let selected = self.state.selected()?;
+        let index = visible[selected];
+        if key_code == KeyCode::Esc {
+            return Some(escape_action(index));
+        }
+        match action? {
+            KeyAction::Up => {
+                self.state.select(Some(selected.saturating_sub(1)));
+                None
+            }
+            KeyAction::Down => {
+                self.state
+                    .select(Some((selected + 1).min(visible.len() - 1)));
+                None
+            }
+            KeyAction::Top => {
+                self.state.select(Some(0));
+                None
+            }
+            KeyAction::Bottom => {
+                self.state.select(Some(visible.len() - 1));
+                None
+            }
+            KeyAction::Mark => {
+                self.toggle_mark(visible);
+                None
+            }
+            // With suggestions marked, Enter and Tab act on all of them
+            KeyAction::Execute if !self.marked.is_empty() => {
+                Some(SelectAction::ExecuteAll(self.marked.clone()))
+            }
+            KeyAction::Output if !self.marked.is_empty() => {
+                Some(SelectAction::OutputAll(self.marked.clone()))
+            }
+            KeyAction::Execute => Some(SelectAction::Execute(index)),
+            KeyAction::Output => Some(SelectAction::Output(index)),
+            KeyAction::Edit => Some(SelectAction::Edit(index)),
+            KeyAction::Followup => Some(SelectAction::Followup(index)),
+            KeyAction::Save => Some(SelectAction::Save(index)),
+            KeyAction::Filter | KeyAction::Docs | KeyAction::DryRun => None,
+        }
+    }
+
+    /// Marks the highlighted suggestion, or unmarks it when it was marked
+    fn toggle_mark(&mut self, visible: &[usize]) {
+        if let Some(selected) = self.state.selected() {
+            let index = visible[selected];
+            match self.marked.iter().position(|&marked| marked == index) {
+                Some(position) => {
+                    self.marked.remove(position);
+                }
+                None => self.marked.push(index),
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame, visible: &[usize]) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(2), Constraint::Min(0)])
+            .split(frame.size());
+
+        let filter_line = match &self.filter {
+            Some(query) => Line::from(vec![
+                Span::styled("/ ", self.color(Color::Cyan)),
+                Span::raw(query.as_str()),
+            ]),
+            None => Line::default(),
+        };
+        frame.render_widget(
+            Paragraph::new(vec![
+                Line::styled(
+                    self.keys.help(),
+                    Style::default().add_modifier(Modifier::DIM),
+                ),
+                filter_line,
+            ]),
+            rows[0],
+        );
+
+        let panes = if rows[1].width >= SIDE_BY_SIDE_WIDTH {
+            Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .split(rows[1])
+        } else if self.preview.is_some() {
+            // Docs and dry runs need more room than the details
+            Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(3), Constraint::Percentage(60)])
+                .split(rows[1])
+        } else {
+            Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(3), Constraint::Length(DETAILS_HEIGHT)])
+                .split(rows[1])
+        };
+
+        self.draw_list(frame, panes[0], visible);
+        if let Some(selected) = self.state.selected() {
+            let suggestion = &self.suggestions[visible[selected]];
+            match self.preview {
+                Some(Preview::Docs) => self.draw_preview(frame, panes[1], suggestion),
+                Some(Preview::DryRun) => self.draw_dry_run(frame, panes[1], suggestion),
+                None => self.draw_details(frame, panes[1], visible[selected]),
+            }
+        }
+    }
+
+    /// The tldr page or man synopsis of the command's executable
+    fn draw_preview(&mut self, frame: &mut Frame, area: Rect, suggestion: &Suggestion) {
+        let executable = CommandValidator::for_shell(self.shell)
+            .extract_executables(&suggestion.command)
+            .into_iter()
+            .next()
+            .unwrap_or_default();
+        let docs = self
+            .previews
+            .entry(executable.clone())
+            .or_insert_with(|| CommandDocs::lookup(&executable));
+
+        let text = match docs {
+            Some(docs) => docs.clone(),
+            None => format!("No tldr page or man page for {executable}"),
+        };
+        frame.render_widget(
+            Paragraph::new(text)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(format!(" {executable} (PgUp/PgDn scroll, ? closes) ")),
+                )
+                .wrap(Wrap { trim: false })
+                .scroll((self.preview_scroll, 0)),
+            area,
+        );
+    }
+
+    /// What the command would affect, found with a listing or its tool's
+    /// dry-run mode, or a warning that it has no safe preview
+    fn draw_dry_run(&mut self, frame: &mut Frame, area: Rect, suggestion: &Suggestion) {
+        // Placeholders are only filled in once the command is picked
+        if !suggestion.placeholders.is_empty() {
+            frame.render_widget(
+                Paragraph::new(Line::styled(
+                    "Fill in the placeholders first: run phloem with --dry-run and pick this command",
+                    self.color(Color::Yellow),
+                ))
+                .block(Block::default().borders(Borders::ALL).title(" Dry run "))
+                .wrap(Wrap { trim: false }),
+                area,
+            );
+            return;
+        }
+
+        let shell = self.shell;
+        let (dry_run, output) = self
+            .dry_runs
+            .entry(suggestion.command.clone())
+            .or_insert_with(|| {
+                let dry_run = DryRun::plan(&suggestion.command, shell);
+                let output = dry_run.run();
+                (dry_run, output)
+            })
+            .clone();
+
+        let lines = match (dry_run.command(), &output) {
+            (Some(command), Some(output)) => {
+                let mut lines = vec![
+                    Line::styled(format!("$ {command}"), self.color(Color::Cyan)),
+                    Line::default(),
+                ];
+                lines.extend(output.lines().map(|line| Line::raw(line.to_string())));
+                lines
+            }
+            _ => vec![Line::styled(
+                "No safe preview: this command can only show its effects by running",
+                self.color(Color::Yellow),
+            )],
+        };
+        frame.render_widget(
+            Paragraph::new(lines)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(" Dry run (PgUp/PgDn scroll, d closes) "),
+                )
+                .wrap(Wrap { trim: false })
+                .scroll((self.preview_scroll, 0)),
+            area,
+        );
+    }
+
+    fn draw_list(&mut self, frame: &mut Frame, area: Rect, visible: &[usize]) {
+        let block = Block::default().borders(Borders::ALL).title(format!(
+            " Suggestions {}/{} ",
+            visible.len(),
+            self.items.len()
+        ));
+
+        if visible.is_empty() {
+            frame.render_widget(
+                Paragraph::new(Span::styled("No matches", self.color(Color::Yellow))).block(block),
+                area,
+            );
+            return;
+        }
+
+        let list = List::new(visible.iter().map(|&index| {
+            let mut spans = Vec::new();
+            // Marked suggestions show the step they'll run as
+            if let Some(step) = self.marked.iter().position(|&marked| marked == index) {
+                spans.push(Span::styled(
+                    format!("[{}] ", step + 1),
+                    self.color(Color::Cyan),
+                ));
+            }
+            let item = self.items[index].as_str();
+            match item.strip_prefix(self.suggestions[index].command.as_str()) {
+                Some(rest) if self.highlights() => {
+                    spans.extend(self.command_spans(&self.suggestions[index].command));
+                    spans.push(Span::raw(rest));
+                }
+                _ => spans.push(Span::raw(item)),
+            }
+            if let Some(risk) = self.risks.get(index) {
+                if risk.level != RiskLevel::Safe {
+                    spans.push(Span::styled(
+                        format!("  [{}]", risk.level.as_str()),
+                        self.color(risk_color(risk.level)),
+                    ));
+                }
+            }
+            ListItem::new(Line::from(spans))
+        }))
+        .block(block)
+        .highlight_symbol("▶ ")
+        // Keep the token colors on the highlighted row
+        .highlight_style(if self.highlights() {
+            Style::default().add_modifier(Modifier::BOLD)
+        } else if self.use_colors {
+            Style::default()
+                .fg(Color::Green)
+                .add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().add_modifier(Modifier::REVERSED)
+        });
+        frame.render_stateful_widget(list, area, &mut self.state);
+    }
+
+    fn draw_details(&self, frame: &mut Frame, area: Rect, index: usize) {
+        let suggestion = &self.suggestions[index];
+        let command = if self.highlights() {
+            Line::from(self.command_spans(&suggestion.command))
+        } else {
+            Line::styled(
+                suggestion.command.as_str(),
+                self.color(Color::Green).add_modifier(Modifier::BOLD),
+            )
+        };
+        let mut lines = vec![
+            command,
+            Line::default(),
+            Line::raw(
+                suggestion
+                    .explanation
+                    .as_deref()
+                    .unwrap_or("No explanation given"),
+            ),
+            Line::default(),
+        ];
+        if !suggestion.placeholders.is_empty() {
+            lines.push(Line::styled(
+                "Filled in when picked:",
+                self.color(Color::Cyan),
+            ));
+            for placeholder in &suggestion.placeholders {
+                lines.push(Line::raw(match &placeholder.description {
+                    Some(description) => format!("  {{{{{}}}}} {description}", placeholder.name),
+                    None => format!("  {{{{{}}}}}", placeholder.name),
+                }));
+            }
+            lines.push(Line::default());
+        }
+        if !suggestion.warnings.is_empty() {
+            lines.push(Line::styled("ShellCheck:", self.color(Color::Yellow)));
+            lines.extend(
+                suggestion
+                    .warnings
+                    .iter()
+                    .map(|warning| Line::raw(format!("  {warning}"))),
+            );
+            lines.push(Line::default());
+        }
+        lines.extend([Line::raw(format!(
+            "Confidence {:.0}% · from {}",
+            suggestion.confidence * 100.0,
+            suggestion.source.as_str()
+        ))]);
+        if let Some(risk) = self.risks.get(index) {
+            let reasons = if risk.reasons.is_empty() {
+                "nothing known".to_string()
+            } else {
+                risk.reasons.join("; ")
+            };
+            lines.push(Line::styled(
+                format!("Risk: {}, {reasons}", risk.level.as_str()),
+                self.color(risk_color(risk.level)),
+            ));
+        }
+
+        // Wrapped, so long commands show in full however narrow the terminal
+        frame.render_widget(
+            Paragraph::new(lines)
+                .block(Block::default().borders(Borders::ALL).title(" Details "))
+                .wrap(Wrap { trim: false }),
+            area,
+        );
+    }
+
+    fn highlights(&self) -> bool {
+        self.use_colors && self.syntax_highlighting
+    }
+
+    /// `command` split into spans colored by token kind
+    fn command_spans<'c>(&self, command: &'c str) -> Vec<Span<'c>> {
+        tokenize(command)
+            .into_iter()
+            .map(|(kind, text)| {
+                let style = match kind {
+                    TokenKind::Command => Style::default().fg(Color::Green),
+                    TokenKind::Flag => Style::default().fg(Color::Cyan),
+                    TokenKind::String => Style::default().fg(Color::Yellow),
+                    TokenKind::Operator => Style::default().fg(Color::Magenta),
+                    TokenKind::Variable => Style::default().fg(Color::Blue),
+                    TokenKind::Text => Style::default(),
+                };
+                Span::styled(text, style)
+            })
+            .collect()
+    }
+
+    fn color(&self, color: Color) -> Style {
+        if self.use_colors {
+            Style::default().fg(color)
+        } else {
+            Style::default()
+        }
+    }
+}
+
+/// Color a risk level is shown in
+fn risk_color(level: RiskLevel) -> Color {
+    match level {
+        RiskLevel::Safe => Color::Green,
+        RiskLevel::Caution => Color::Yellow,
+        RiskLevel::Dangerous => Color::Red,
+    }
+}
+
+/// A second Esc right after the first leaves the picker; a single one asks
+/// for a follow-up
+fn escape_action(index: usize) -> SelectAction {
+    if let Ok(true) = event::poll(Duration::from_millis(300)) {
+        if let Ok(Event::Key(second_key)) = event::read() {
+            if matches!(second_key.code, KeyCode::Esc) {
+                return SelectAction::Cancel;
+            }
+        }
+    }
+
+    SelectAction::Followup(index)
+}
+
+/// Indexes of the items matching `query`, best match first; all of them, in
+/// order, for an empty query
+fn filter_items(items: &[String], query: &str) -> Vec<usize> {
+    let mut scored: Vec<(i64, usize)> = items
+        .iter()
+        .enumerate()
+        .filter_map(|(index, item)| fuzzy_score(query, item).map(|score| (score, index)))
+        .collect();
+    // Stable, so equally good matches keep the model's order
+    scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    scored.into_iter().map(|(_, index)| index).collect()
+}
+
+/// Scores `candidate` against `query` the way fzf does: the query's
+/// characters must appear in order, and consecutive matches and matches at
+/// the start of a word score higher. `None` when it doesn't match.
+fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let query: Vec<char> = query
+        .to_lowercase()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+
+    let mut matched = 0;
+    let mut score = 0;
+    let mut last_match = None;
+    for (index, &c) in candidate.iter().enumerate() {
+        if matched == query.len() {
+            break;
+        }
+        if c != query[matched] {
+            continue;
+        }
+
+        score += 1;
+        if index > 0 && last_match == Some(index - 1) {
+            score += 5;
+        }
+        if index == 0 || !candidate[index - 1].is_alphanumeric() {
+            score += 3;
+        }
+        last_match = Some(index);
+        matched += 1;
+    }
+
+    (matched == query.len()).then_some(score)
+}
@@ -6,26 +6,370 @@ impl DefaultConfig {
     pub fn create_default_config_file() -> String {
         r#"[general]
 max_context_size_kb = 50
-recent_commands_limit = 100
 learning_enabled = true
 
 [model]
-model_path = "~/.phloem/models/gemma-3n"
-max_tokens = 100
+max_tokens = 200
 temperature = 0.0
+top_k = 40
+top_p = 0.9
+# num_ctx = 4096  # context window; defaults to the model's own setting
+# num_gpu = 0     # layers offloaded to the GPU; detected automatically for a local Ollama
+# num_thread = 8  # CPU threads; detected automatically for a local Ollama
 
 [cache]
 max_cache_entries = 1000
 cache_ttl_hours = 24
+# Reuse detected tools and services for this long; `phloem refresh-env` re-detects now
+environment_ttl_minutes = 60
+
+# Reuse cached answers for prompts worded differently but meaning the same,
+# e.g. "show running containers" and "list running docker containers".
+# Needs an Ollama embedding model: ollama pull nomic-embed-text
+[cache.semantic]
+enabled = false
+model = "nomic-embed-text"
+threshold = 0.85
+
+# Cached suggestions are scored by frecency: how often and how recently they were
+# used, weighted by how often they succeeded. Above reuse_threshold the cache
+# answers alone; otherwise the best max_candidates are shown next to fresh ones.
+[cache.frecency]
+half_life_hours = 12
+frequency_weight = 0.4
+success_weight = 0.6
+reuse_threshold = 0.7
+max_candidates = 2
 
 [output]
-show_explanations = true
+# Defaults of --explain and -n; the flags override them for one run
+show_explanations = false
+# Colors are also off when NO_COLOR is set or output goes to a file or pipe
 use_colors = true
 max_suggestions = 3
+syntax_highlighting = true
 
 [privacy]
-collect_usage_stats = false
-share_anonymous_data = false
+# Secrets (API keys, tokens, passwords) are masked before reaching the model
+# and before prompts and commands are stored in the history, the cache and
+# PHLOEM.md; add regexes for anything else that must never be sent or kept
+redact_patterns = []
+# Refuse any backend that is not on this machine (localhost/127.0.0.1)
+local_only = false
+
+[backend]
+kind = "ollama"
+# Ask several models at once and rank their suggestions together; two small
+# local models racing each other often beat one:
+# models = ["qwen2.5-coder:1.5b", "llama3.2:1b"]
+# Optional ordered fallback chain, tried in order on connection failure or timeout:
+# [[backend.chain]]
+# kind = "ollama"
+#
+# [[backend.chain]]
+# kind = "ollama"
+# base_url = "http://gpu-box:11434"
+#
+# [[backend.chain]]
+# kind = "openai"
+
+[ollama]
+base_url = "http://localhost:11434"
+model = "gemma3n:e2b"
+timeout_secs = 30
+# How long the model stays loaded after a request; "-1m" keeps it loaded
+keep_alive = "30m"
+# Follow-ups continue from the tokens Ollama returned for the last answer, so
+# the system prompt isn't processed again; turn off for models whose
+# Modelfile sets a SYSTEM prompt, which Ollama would add to each follow-up
+reuse_context = true
+# Reaching a remote Ollama through a corporate network:
+# proxy = "http://proxy.corp:3128"   # HTTP_PROXY/HTTPS_PROXY are used when unset
+# ca_cert = "/etc/ssl/corp-ca.pem"   # extra CA certificate to trust
+# insecure = false                   # skip TLS certificate verification
+# no_proxy = false                   # ignore HTTP_PROXY/HTTPS_PROXY
+# headers = { Authorization = "Bearer ${OLLAMA_TOKEN}" }
+
+[openai]
+base_url = "https://api.openai.com/v1"
+model = "gpt-4o-mini"
+api_key_env = "OPENAI_API_KEY"
+timeout_secs = 30
+
+# For Kubernetes requests, tell the model the current context and namespace.
+# kubectl is skipped for the request when it takes longer than the timeout.
+[kubernetes]
+enabled = true
+probe_timeout_ms = 500
+
+# For Docker and Podman requests, tell the model which containers are running
+# so "restart the api container" uses the real name.
+[containers]
+enabled = true
+probe_timeout_ms = 500
+
+# Ask for a typed "yes" before running commands that delete data (rm, dd,
+# mkfs, kubectl delete), rewrite history (git push --force, git reset --hard)
+# or stop the machine.
+# Commands using sudo, doas or su are explained and need a typed "yes" too
+# with elevation = "confirm"; "block" never suggests or runs them, "allow"
+# runs them like the others.
+[safety]
+confirm_destructive = true
+elevation = "confirm"
+
+# Check bash suggestions with ShellCheck when it's installed, showing what it
+# finds under each one. With fix = true the model is asked once to fix them.
+[shellcheck]
+enabled = true
+fix = false
+timeout_ms = 1000
+
+# Try each picked command in a sandbox first, as with --sandbox: the file
+# system is read-only and there's no network. After the trial you're asked
+# whether to run it for real. backend is auto (bubblewrap or firejail on
+# Linux, sandbox-exec on macOS), bubblewrap, firejail, sandbox-exec or
+# container, a throwaway Podman or Docker container of image.
+[sandbox]
+enabled = false
+backend = "auto"
+# image = "debian:stable-slim"
+
+# Keep a tamper-evident trail of every suggestion that runs in
+# ~/.phloem/audit.jsonl: prompt, command, user, directory, exit code and
+# times, each entry chained to the one before by its hash. Incognito runs
+# are recorded too. `phloem audit verify` checks the chain.
+[audit]
+enabled = true
+
+# Pass the output of picked commands through phloem and keep its tail with
+# the history, so a failed command can be fixed with its error in the prompt.
+# Editors, pagers and other full-screen programs always get the terminal.
+# Captured commands see no terminal, so most print without colors.
+[execution]
+capture_output = true
+max_captured_kb = 8
+# Commands for tools that aren't installed are offered after the others, with
+# the install from brew, apt, dnf, pacman or winget put before them
+suggest_installs = true
+# Your shell on Windows, unless SHELL is set as in Git Bash: "powershell"
+# (run with pwsh when installed) or "cmd". Commands are written for it and run with it.
+windows_shell = "powershell"
+# The shell commands run with elsewhere: "auto" for your SHELL (or the one
+# picked with --shell), or a program like "bash" or "/bin/zsh". It starts as
+# an interactive shell, reading your startup files so aliases and functions
+# work, unless interactive_shell = false.
+shell = "auto"
+interactive_shell = true
+# Run commands that are just a program with arguments (no pipes, variables,
+# globs or redirections) directly, without a shell.
+strict = false
+
+# ~/.phloem/logs/phloem.log: one line per event with a timestamp, rotated
+# once it reaches max_file_kb. `--log-level` overrides the level for one run
+# and `-v` prints debug messages to the terminal as well.
+[logging]
+level = "info"   # off, error, warn, info, debug or trace
+max_file_kb = 1024
+max_files = 3
+
+# Keys of the selector, in place of the defaults of the actions listed:
+# up, down, top, bottom, execute, mark, edit, output, save, filter, docs,
+# dry_run and followup. Keys are names (enter, tab, space, up, down, home,
+# end, pageup, pagedown), characters, or two characters typed in a row.
+# 1-9 always run that suggestion and Esc always leaves.
+[keys]
+# edit = ["i"]
+# top = ["home", "gg"]
+"#
+        .to_string()
+    }
+
+    /// The policy written to ~/.phloem/policy.toml and used when it's missing
+    pub fn create_default_policy_file() -> String {
+        r#"# Which suggested commands phloem shows and runs. The first rule matching
+# a command decides what happens to it:
+#   allow   - shown and run as usual, whatever rules follow
+#   deny    - never shown, and refused if typed in
+#   confirm - runs only after typing "yes"
+# Commands no rule matches are shown and run as usual.
+#
+# A rule matches when all it gives match:
+#   executable - a name, or a list of names, of a command that runs
+#   args       - regexes that each match an argument of that command
+#   pattern    - a regex matching the whole command line
+#   shells     - shells it applies to: bash, zsh, fish, nu, powershell, cmd
+#   reason     - shown when the rule denies or asks
+#
+# For example, to run `git push --force` to your own fork without asking,
+# put this above the git rules:
+# [[rule]]
+# action = "allow"
+# executable = "git"
+# args = ["^push$", "^myfork$"]
+
+# Unless an allow rule matches them, commands are also handled by what they
+# do, each category being "allow", "deny" or "confirm", which asks for a y
+# keystroke (or the typed "yes" when a rule asks as well):
+#   overwrites - redirects replacing a file that exists, like `> config.yaml`
+#   network    - changes over the network, like `curl -X DELETE`,
+#                `kubectl delete` or `terraform apply`
+[categories]
+overwrites = "confirm"
+network = "confirm"
+
+[[rule]]
+action = "deny"
+pattern = 'rm\s+-rf\s+/'
+reason = "deletes everything from the root"
+
+[[rule]]
+action = "deny"
+pattern = 'rm\s+-rf\s+\*'
+reason = "deletes everything in the directory"
+
+[[rule]]
+action = "deny"
+pattern = '>\s*/dev/sd[a-z]'
+reason = "overwrites a raw disk"
+
+[[rule]]
+action = "deny"
+pattern = 'dd.*of=/dev/sd[a-z]'
+reason = "overwrites a raw disk"
+
+[[rule]]
+action = "deny"
+pattern = 'mkfs\.'
+reason = "formats a filesystem"
+
+[[rule]]
+action = "deny"
+pattern = 'fdisk\s+/dev/'
+reason = "repartitions a disk"
+
+[[rule]]
+action = "deny"
+pattern = 'parted\s+/dev/'
+reason = "repartitions a disk"
+
+[[rule]]
+action = "deny"
+pattern = ':\(\)\{.*\};'
+reason = "starts processes until the machine hangs"
+
+[[rule]]
+action = "deny"
+pattern = 'curl.*\|\s*bash'
+reason = "runs a downloaded script"
+
+[[rule]]
+action = "deny"
+pattern = 'wget.*\|\s*bash'
+reason = "runs a downloaded script"
+
+[[rule]]
+action = "deny"
+pattern = 'chmod\s+777\s+/'
+reason = "opens up permissions from the root"
+
+[[rule]]
+action = "deny"
+pattern = 'chown.*:.*\s+/'
+reason = "changes ownership from the root"
+
+# Windows itself, whatever shell runs it
+[[rule]]
+action = "deny"
+pattern = '(?i)\bformat(\.com)?\s+[a-z]:'
+reason = "formats a drive"
+
+[[rule]]
+action = "deny"
+pattern = '(?i)\b(del|erase|rd|rmdir)\s.*/s.*\s[a-z]:\\\s*$'
+reason = "deletes a whole drive"
+
+[[rule]]
+action = "deny"
+pattern = '(?i)\bdiskpart\b'
+reason = "repartitions a disk"
+
+[[rule]]
+action = "deny"
+pattern = '(?i)\bbcdedit\s.*/delete'
+reason = "breaks the boot configuration"
+
+[[rule]]
+action = "deny"
+pattern = '(?i)\breg\s+delete\s+(hklm|hkey_local_machine)'
+reason = "deletes machine registry keys"
+
+[[rule]]
+action = "deny"
+pattern = '(?i)\bvssadmin\s+delete\s+shadows'
+reason = "deletes restore points"
+
+[[rule]]
+action = "deny"
+pattern = '(?i)\bcipher\s+/w'
+reason = "wipes free space"
+
+[[rule]]
+action = "deny"
+shells = ["powershell"]
+pattern = '(?i)remove-item\s.*[a-z]:\\\s*(-|$)'
+reason = "deletes a whole drive"
+
+[[rule]]
+action = "deny"
+shells = ["powershell"]
+pattern = '(?i)(format-volume|clear-disk)\b'
+reason = "wipes a disk"
+
+[[rule]]
+action = "deny"
+shells = ["powershell"]
+pattern = '(?i)(iwr|irm|invoke-webrequest|invoke-restmethod).*\|\s*(iex|invoke-expression)'
+reason = "runs a downloaded script"
+
+[[rule]]
+action = "confirm"
+executable = [
+    "rm", "rmdir", "dd", "mkfs", "fdisk", "parted", "format", "del", "erase",
+    "shred", "wipe", "halt", "shutdown", "reboot", "poweroff",
+]
+
+[[rule]]
+action = "confirm"
+shells = ["powershell", "cmd"]
+executable = [
+    "remove-item", "ri", "rd", "clear-disk", "format-volume", "remove-partition",
+    "stop-computer", "restart-computer", "diskpart",
+]
+
+[[rule]]
+action = "confirm"
+executable = "git"
+args = ['^push$', '^(-f|--force|--force-with-lease|\+.+)$']
+reason = "rewrites history on the remote"
+
+[[rule]]
+action = "confirm"
+executable = "git"
+args = ['^reset$', '^--hard$']
+reason = "discards uncommitted changes"
+
+[[rule]]
+action = "confirm"
+executable = "git"
+args = ['^clean$', '^(--force|-[^-]*f.*)$']
+reason = "deletes untracked files"
+
+[[rule]]
+action = "confirm"
+executable = "kubectl"
+args = ['^delete$']
+reason = "deletes cluster resources"
 "#
         .to_string()
     }
@@ -1,5 +1,7 @@
 pub mod defaults;
 pub mod settings;
+pub mod validation;
 
 pub use defaults::DefaultConfig;
 pub use settings::Settings;
+pub use validation::{ConfigError, InvalidConfig};
@@ -1,5 +1,7 @@
 use anyhow::Result;
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 
@@ -10,39 +12,576 @@ pub struct Settings {
     pub cache: CacheConfig,
     pub output: OutputConfig,
     pub privacy: PrivacyConfig,
+    #[serde(default)]
+    pub backend: BackendConfig,
+    #[serde(default)]
+    pub ollama: OllamaConfig,
+    #[serde(default)]
+    pub openai: OpenAiConfig,
+    #[serde(default)]
+    pub kubernetes: KubernetesConfig,
+    #[serde(default)]
+    pub containers: ContainersConfig,
+    #[serde(default)]
+    pub safety: SafetyConfig,
+    #[serde(default)]
+    pub shellcheck: ShellCheckConfig,
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub execution: ExecutionConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    /// Keys of the selector by action, e.g. `edit = ["i"]`, in place of the
+    /// defaults of the actions given
+    #[serde(default)]
+    pub keys: HashMap<String, Vec<String>>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GeneralConfig {
     pub max_context_size_kb: usize,
+    /// Deprecated, has no effect
+    #[serde(default, skip_serializing)]
     pub recent_commands_limit: usize,
     pub learning_enabled: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct ModelConfig {
+    /// Deprecated, has no effect; the backend manages the model
+    #[serde(skip_serializing)]
     pub model_path: String,
+    /// Maximum number of tokens generated per reply
     pub max_tokens: u32,
     pub temperature: f32,
+    pub top_k: u32,
+    pub top_p: f32,
+    /// Context window size; the model's own default when unset
+    pub num_ctx: Option<u32>,
+    /// Layers offloaded to the GPU; derived from detected hardware when unset
+    pub num_gpu: Option<u32>,
+    /// CPU threads used for generation; derived from detected hardware when unset
+    pub num_thread: Option<u32>,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        let model_path = dirs::home_dir()
+            .map(|h| {
+                h.join(".phloem")
+                    .join("models")
+                    .join("gemma-3n")
+                    .display()
+                    .to_string()
+            })
+            .unwrap_or_else(|| "~/.phloem/models/gemma-3n".to_string());
+
+        Self {
+            model_path,
+            max_tokens: 200,
+            temperature: 0.0,
+            top_k: 40,
+            top_p: 0.9,
+            num_ctx: None,
+            num_gpu: None,
+            num_thread: None,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CacheConfig {
     pub max_cache_entries: usize,
     pub cache_ttl_hours: u32,
+    /// How long the detected tools and services are reused before probing
+    /// again; 0 probes on every prompt
+    #[serde(default = "default_environment_ttl_minutes")]
+    pub environment_ttl_minutes: u32,
+    #[serde(default)]
+    pub semantic: SemanticCacheConfig,
+    #[serde(default)]
+    pub frecency: FrecencyConfig,
+}
+
+fn default_environment_ttl_minutes() -> u32 {
+    60
+}
+
+/// Matching prompts by meaning rather than exact text, using prompt embeddings
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SemanticCacheConfig {
+    pub enabled: bool,
+    /// Ollama embedding model, e.g. "nomic-embed-text"
+    pub model: String,
+    /// Minimum cosine similarity for a cached prompt to count as the same request
+    pub threshold: f32,
+}
+
+impl Default for SemanticCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            model: "nomic-embed-text".to_string(),
+            threshold: 0.85,
+        }
+    }
+}
+
+/// Reading the kubeconfig for Kubernetes requests
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct KubernetesConfig {
+    /// Tell the model the current context, namespace and other contexts
+    pub enabled: bool,
+    /// How long kubectl may take before the request goes ahead without it
+    pub probe_timeout_ms: u64,
+}
+
+impl Default for KubernetesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            probe_timeout_ms: 500,
+        }
+    }
+}
+
+/// Listing running containers for Docker and Podman requests
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ContainersConfig {
+    /// Tell the model the running container names and compose projects
+    pub enabled: bool,
+    /// How long the runtime may take before the request goes ahead without it
+    pub probe_timeout_ms: u64,
+}
+
+impl Default for ContainersConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            probe_timeout_ms: 500,
+        }
+    }
+}
+
+/// Guards around running suggestions
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SafetyConfig {
+    /// Ask for a typed "yes" before running commands that delete data,
+    /// force-push or stop the machine
+    pub confirm_destructive: bool,
+    /// What to do with commands that run as root or another user through
+    /// sudo, doas or su
+    pub elevation: ElevationMode,
+}
+
+impl Default for SafetyConfig {
+    fn default() -> Self {
+        Self {
+            confirm_destructive: true,
+            elevation: ElevationMode::Confirm,
+        }
+    }
+}
+
+/// How commands using sudo, doas or su are handled
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ElevationMode {
+    /// Run them like any other, though never without being asked
+    Allow,
+    /// Explain what they do as root and ask for a typed "yes"
+    Confirm,
+    /// Never suggest or run them
+    Block,
+}
+
+/// Vetting suggestions with ShellCheck, when it's installed
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ShellCheckConfig {
+    /// Run each bash suggestion through shellcheck and show what it finds
+    pub enabled: bool,
+    /// Ask the model once to fix the suggestions shellcheck flagged
+    pub fix: bool,
+    /// How long shellcheck may take before a suggestion is shown unchecked
+    pub timeout_ms: u64,
+}
+
+impl Default for ShellCheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            fix: false,
+            timeout_ms: 1000,
+        }
+    }
+}
+
+/// The audit log of suggestions that ran
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// Append each suggestion that runs to ~/.phloem/audit.jsonl, even in
+    /// incognito runs
+    pub enabled: bool,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+/// Trying picked commands in a sandbox before they run for real
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SandboxConfig {
+    /// Run each picked command in the sandbox first, as with `--sandbox`
+    pub enabled: bool,
+    /// What the sandbox is
+    pub backend: SandboxBackend,
+    /// Image of the throwaway container with `backend = "container"`
+    pub image: String,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            backend: SandboxBackend::Auto,
+            image: "debian:stable-slim".to_string(),
+        }
+    }
+}
+
+/// Restricted environment commands are tried in
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SandboxBackend {
+    /// bubblewrap or firejail on Linux, sandbox-exec on macOS
+    Auto,
+    Bubblewrap,
+    Firejail,
+    SandboxExec,
+    /// A throwaway Podman or Docker container
+    Container,
+}
+
+/// How picked commands are run
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ExecutionConfig {
+    /// Pass the output of commands through phloem, keeping its tail with the
+    /// history record and for a "fix it" follow-up when the command fails.
+    /// Captured commands don't write to a terminal, so most print no colors.
+    pub capture_output: bool,
+    /// How much of each stream is kept, in KB
+    pub max_captured_kb: usize,
+    /// Offer commands for tools that aren't installed, with the install of
+    /// their package from the system package manager put before them
+    pub suggest_installs: bool,
+    /// The shell of Windows users, which picked commands are written for
+    /// and run with, as Windows has no `sh`; SHELL wins when it's set, as
+    /// in Git Bash
+    pub windows_shell: WindowsShell,
+    /// Shell picked commands run with elsewhere: "auto" for SHELL when the
+    /// commands are written for it, or a program like "bash" or "/bin/zsh"
+    pub shell: String,
+    /// Start that shell with -i, so it reads its startup files and the
+    /// aliases and functions defined there work
+    pub interactive_shell: bool,
+    /// Run commands that are just a program and its arguments without any
+    /// shell, so nothing in them is expanded
+    pub strict: bool,
+}
+
+impl Default for ExecutionConfig {
+    fn default() -> Self {
+        Self {
+            capture_output: true,
+            max_captured_kb: 8,
+            suggest_installs: true,
+            windows_shell: WindowsShell::PowerShell,
+            shell: "auto".to_string(),
+            interactive_shell: true,
+            strict: false,
+        }
+    }
+}
+
+/// Interpreter of picked commands on Windows
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowsShell {
+    /// pwsh when installed, else Windows PowerShell
+    PowerShell,
+    /// cmd.exe, with `cmd /C`
+    Cmd,
+}
+
+impl WindowsShell {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PowerShell => "powershell",
+            Self::Cmd => "cmd",
+        }
+    }
+}
+
+/// How much is logged to ~/.phloem/logs/phloem.log
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Least severe messages written to the log file
+    pub level: LogLevel,
+    /// Size from which the log file is rotated, in KB
+    pub max_file_kb: u64,
+    /// Rotated log files kept next to the current one
+    pub max_files: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: LogLevel::Info,
+            max_file_kb: 1024,
+            max_files: 3,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for log::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => Self::Off,
+            LogLevel::Error => Self::Error,
+            LogLevel::Warn => Self::Warn,
+            LogLevel::Info => Self::Info,
+            LogLevel::Debug => Self::Debug,
+            LogLevel::Trace => Self::Trace,
+        }
+    }
+}
+
+/// Scoring cached suggestions by how often, how recently and how reliably they were used
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct FrecencyConfig {
+    /// Hours since last use after which a suggestion's score is halved
+    pub half_life_hours: f32,
+    /// Weight of how often the suggestion was used
+    pub frequency_weight: f32,
+    /// Weight of how often it succeeded when run
+    pub success_weight: f32,
+    /// Score from which a cached suggestion is answered without asking the model
+    pub reuse_threshold: f32,
+    /// Cached suggestions offered alongside fresh model output
+    pub max_candidates: usize,
+}
+
+impl Default for FrecencyConfig {
+    fn default() -> Self {
+        Self {
+            half_life_hours: 12.0,
+            frequency_weight: 0.4,
+            success_weight: 0.6,
+            reuse_threshold: 0.7,
+            max_candidates: 2,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct OutputConfig {
+    /// Show explanations next to suggestions, as with `--explain`
     pub show_explanations: bool,
     pub use_colors: bool,
+    /// Suggestions to ask for, as with `-n`
     pub max_suggestions: usize,
+    /// Color command names, flags, strings and operators of suggestions
+    #[serde(default = "default_syntax_highlighting")]
+    pub syntax_highlighting: bool,
+}
+
+fn default_syntax_highlighting() -> bool {
+    true
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            show_explanations: false,
+            use_colors: true,
+            max_suggestions: 3,
+            syntax_highlighting: default_syntax_highlighting(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PrivacyConfig {
+    /// Deprecated, has no effect
+    #[serde(default, skip_serializing)]
     pub collect_usage_stats: bool,
+    /// Deprecated, has no effect
+    #[serde(default, skip_serializing)]
     pub share_anonymous_data: bool,
+    /// Extra regexes whose matches are masked before anything reaches the
+    /// model or is stored
+    #[serde(default)]
+    pub redact_patterns: Vec<String>,
+    /// Refuse any backend that is not on this machine
+    #[serde(default)]
+    pub local_only: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    #[default]
+    Ollama,
+    #[serde(rename = "openai")]
+    #[value(name = "openai")]
+    OpenAi,
+}
+
+impl BackendKind {
+    /// The name used in the config and for its cargo feature
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ollama => "ollama",
+            Self::OpenAi => "openai",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct BackendConfig {
+    pub kind: BackendKind,
+    /// Ordered fallback chain; when empty only `kind` is used
+    pub chain: Vec<BackendEndpoint>,
+    /// Models asked at once for each prompt, served like the first backend;
+    /// with fewer than two only the configured model is asked
+    pub models: Vec<String>,
+}
+
+/// One entry of the fallback chain. Unset fields fall back to the
+/// `[ollama]` / `[openai]` section matching `kind`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct BackendEndpoint {
+    pub kind: BackendKind,
+    pub base_url: Option<String>,
+    pub model: Option<String>,
+    pub api_key_env: Option<String>,
+    pub timeout_secs: Option<u64>,
+    pub keep_alive: Option<String>,
+    /// Merged over the transport options of the matching section
+    #[serde(flatten)]
+    pub http: HttpOptions,
+}
+
+/// Transport options for reaching a backend through corporate networks
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct HttpOptions {
+    /// Proxy URL; HTTP_PROXY/HTTPS_PROXY/NO_PROXY are honored when unset
+    pub proxy: Option<String>,
+    /// Extra headers sent with every request; `${VAR}` is replaced from the environment
+    pub headers: HashMap<String, String>,
+    /// PEM file with an additional CA certificate to trust
+    pub ca_cert: Option<String>,
+    /// Skip TLS certificate verification
+    pub insecure: bool,
+    /// Connect directly, ignoring any proxy including HTTP_PROXY/HTTPS_PROXY
+    pub no_proxy: bool,
+}
+
+impl HttpOptions {
+    /// Options of a chain entry layered over those of its section
+    pub fn merged(&self, overrides: &HttpOptions) -> HttpOptions {
+        let mut headers = self.headers.clone();
+        headers.extend(overrides.headers.clone());
+
+        HttpOptions {
+            proxy: overrides.proxy.clone().or_else(|| self.proxy.clone()),
+            headers,
+            ca_cert: overrides.ca_cert.clone().or_else(|| self.ca_cert.clone()),
+            insecure: self.insecure || overrides.insecure,
+            no_proxy: self.no_proxy || overrides.no_proxy,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct OllamaConfig {
+    pub base_url: String,
+    pub model: String,
+    pub timeout_secs: u64,
+    /// How long Ollama keeps the model loaded after a request, e.g. "30m", or
+    /// "-1m" to keep it loaded; a plain number counts seconds
+    pub keep_alive: String,
+    /// Send follow-ups with the context tokens Ollama returned for the last
+    /// exchange instead of the whole conversation
+    pub reuse_context: bool,
+    #[serde(flatten)]
+    pub http: HttpOptions,
+}
+
+impl Default for OllamaConfig {
+    fn default() -> Self {
+        Self {
+            base_url: "http://localhost:11434".to_string(),
+            model: "gemma3n:e2b".to_string(),
+            timeout_secs: 30,
+            keep_alive: "30m".to_string(),
+            reuse_context: true,
+            http: HttpOptions::default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct OpenAiConfig {
+    pub base_url: String,
+    pub model: String,
+    pub api_key_env: String,
+    pub timeout_secs: u64,
+    #[serde(flatten)]
+    pub http: HttpOptions,
+}
+
+impl Default for OpenAiConfig {
+    fn default() -> Self {
+        Self {
+            base_url: "https://api.openai.com/v1".to_string(),
+            model: "gpt-4o-mini".to_string(),
+            api_key_env: "OPENAI_API_KEY".to_string(),
+            timeout_secs: 30,
+            http: HttpOptions::default(),
+        }
+    }
 }
 
 impl Settings {
@@ -59,6 +598,43 @@ impl Settings {
         }
     }
 
+    /// Uses `kind` and `model` for this run instead of the configured backend.
+    /// The fallback chain shrinks to its first entry, or to a default endpoint
+    /// of `kind` when that entry is of another kind.
+    pub fn override_backend(&mut self, kind: Option<BackendKind>, model: Option<&str>) {
+        if kind.is_none() && model.is_none() {
+            return;
+        }
+
+        let primary = self
+            .backend
+            .chain
+            .first()
+            .cloned()
+            .unwrap_or_else(|| BackendEndpoint {
+                kind: self.backend.kind,
+                ..Default::default()
+            });
+        let mut endpoint = match kind {
+            Some(kind) if kind != primary.kind => BackendEndpoint {
+                kind,
+                ..Default::default()
+            },
+            _ => primary,
+        };
+
+        if let Some(model) = model {
+            endpoint.model = Some(model.to_string());
+            // Diagnostics and downloads read the model from the section
+            match endpoint.kind {
+                BackendKind::Ollama => self.ollama.model = model.to_string(),
+                BackendKind::OpenAi => self.openai.model = model.to_string(),
+            }
+        }
+        self.backend.kind = endpoint.kind;
+        self.backend.chain = vec![endpoint];
+    }
+
     pub fn save(&self) -> Result<()> {
         let config_path = Self::get_config_path_static()?;
 
@@ -77,7 +653,7 @@ impl Settings {
         Self::get_config_path_static()
     }
 
-    fn get_config_path_static() -> Result<PathBuf> {
+    pub(crate) fn get_config_path_static() -> Result<PathBuf> {
         let home_dir =
             dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
 
@@ -87,40 +663,39 @@ impl Settings {
 
 impl Default for Settings {
     fn default() -> Self {
-        let home_dir = dirs::home_dir()
-            .map(|h| {
-                h.join(".phloem")
-                    .join("models")
-                    .join("gemma-3n")
-                    .display()
-                    .to_string()
-            })
-            .unwrap_or_else(|| "~/.phloem/models/gemma-3n".to_string());
-
         Self {
             general: GeneralConfig {
                 max_context_size_kb: 50,
                 recent_commands_limit: 100,
                 learning_enabled: true,
             },
-            model: ModelConfig {
-                model_path: home_dir,
-                max_tokens: 100,
-                temperature: 0.0,
-            },
+            model: ModelConfig::default(),
             cache: CacheConfig {
                 max_cache_entries: 1000,
                 cache_ttl_hours: 24,
+                environment_ttl_minutes: default_environment_ttl_minutes(),
+                semantic: SemanticCacheConfig::default(),
+                frecency: FrecencyConfig::default(),
             },
-            output: OutputConfig {
-                show_explanations: true,
-                use_colors: true,
-                max_suggestions: 3,
-            },
+            output: OutputConfig::default(),
             privacy: PrivacyConfig {
                 collect_usage_stats: false,
                 share_anonymous_data: false,
+                redact_patterns: Vec::new(),
+                local_only: false,
             },
+            backend: BackendConfig::default(),
+            ollama: OllamaConfig::default(),
+            openai: OpenAiConfig::default(),
+            kubernetes: KubernetesConfig::default(),
+            containers: ContainersConfig::default(),
+            safety: SafetyConfig::default(),
+            shellcheck: ShellCheckConfig::default(),
+            sandbox: SandboxConfig::default(),
+            audit: AuditConfig::default(),
+            execution: ExecutionConfig::default(),
+            logging: LoggingConfig::default(),
+            keys: HashMap::new(),
         }
     }
 }
@@ -0,0 +1,356 @@
+use anyhow::Result;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::cli::KeyMap;
+use crate::config::settings::{BackendKind, HttpOptions, SandboxBackend, Settings};
+
+/// Keys of older config files that no longer do anything, with what to do instead
+const DEPRECATED_KEYS: &[(&str, &str)] = &[
+    ("general.recent_commands_limit", "has no effect; remove it"),
+    (
+        "model.model_path",
+        "has no effect; the backend manages the model, set ollama.model or openai.model",
+    ),
+    (
+        "privacy.collect_usage_stats",
+        "has no effect; phloem collects no usage data",
+    ),
+    (
+        "privacy.share_anonymous_data",
+        "has no effect; phloem shares no data",
+    ),
+];
+
+/// A setting phloem can't work with, or one that no longer does anything
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// A number outside the values the setting allows
+    OutOfRange {
+        key: String,
+        value: String,
+        expected: &'static str,
+    },
+    /// A setting that must not be empty
+    Empty { key: String },
+    /// A URL that can't be parsed or isn't http(s)
+    InvalidUrl {
+        key: String,
+        value: String,
+        reason: String,
+    },
+    /// A redaction pattern that isn't a valid regex
+    InvalidPattern {
+        key: String,
+        value: String,
+        reason: String,
+    },
+    /// A file that doesn't exist
+    MissingFile { key: String, path: String },
+    /// A selector key binding that names no action or no key
+    InvalidKey {
+        key: String,
+        value: String,
+        reason: String,
+    },
+    /// A key that is still read but no longer does anything
+    Deprecated { key: String, note: &'static str },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfRange {
+                key,
+                value,
+                expected,
+            } => write!(f, "{key} = {value} is out of range; use {expected}"),
+            Self::Empty { key } => write!(f, "{key} is empty; set it or remove it for the default"),
+            Self::InvalidUrl { key, value, reason } => {
+                write!(f, "{key} = \"{value}\" is not a valid URL ({reason})")
+            }
+            Self::InvalidPattern { key, value, reason } => {
+                let reason = reason.lines().last().unwrap_or(reason).trim();
+                write!(f, "{key} has an invalid regex \"{value}\" ({reason})")
+            }
+            Self::MissingFile { key, path } => write!(f, "{key} = \"{path}\" does not exist"),
+            Self::InvalidKey { key, value, reason } => {
+                write!(f, "{key} has an invalid binding \"{value}\" ({reason})")
+            }
+            Self::Deprecated { key, note } => write!(f, "{key} is deprecated: {note}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Every invalid setting of a config, returned by `Settings::validate`
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidConfig(pub Vec<ConfigError>);
+
+impl fmt::Display for InvalidConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for error in &self.0 {
+            write!(f, "\n  - {error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvalidConfig {}
+
+impl Settings {
+    /// Checks every setting, returning all that are out of range, empty,
+    /// unparsable or point at missing files
+    pub fn validate(&self) -> std::result::Result<(), InvalidConfig> {
+        let mut errors = Vec::new();
+
+        positive(
+            &mut errors,
+            "general.max_context_size_kb",
+            self.general.max_context_size_kb,
+        );
+
+        let model = &self.model;
+        positive(&mut errors, "model.max_tokens", model.max_tokens);
+        within(
+            &mut errors,
+            "model.temperature",
+            model.temperature,
+            0.0,
+            2.0,
+            "a number from 0 to 2",
+        );
+        within(
+            &mut errors,
+            "model.top_p",
+            model.top_p,
+            0.0,
+            1.0,
+            "a number from 0 to 1",
+        );
+        positive(&mut errors, "model.top_k", model.top_k);
+        if let Some(num_ctx) = model.num_ctx {
+            positive(&mut errors, "model.num_ctx", num_ctx);
+        }
+        if let Some(num_thread) = model.num_thread {
+            positive(&mut errors, "model.num_thread", num_thread);
+        }
+
+        let cache = &self.cache;
+        positive(
+            &mut errors,
+            "cache.max_cache_entries",
+            cache.max_cache_entries,
+        );
+        within(
+            &mut errors,
+            "cache.semantic.threshold",
+            cache.semantic.threshold,
+            0.0,
+            1.0,
+            "a number from 0 to 1",
+        );
+        if cache.semantic.enabled {
+            not_empty(&mut errors, "cache.semantic.model", &cache.semantic.model);
+        }
+        let frecency = &cache.frecency;
+        if frecency.half_life_hours.is_nan() || frecency.half_life_hours <= 0.0 {
+            errors.push(ConfigError::OutOfRange {
+                key: "cache.frecency.half_life_hours".to_string(),
+                value: frecency.half_life_hours.to_string(),
+                expected: "a number of hours above 0",
+            });
+        }
+        for (key, value) in [
+            ("cache.frecency.frequency_weight", frecency.frequency_weight),
+            ("cache.frecency.success_weight", frecency.success_weight),
+            ("cache.frecency.reuse_threshold", frecency.reuse_threshold),
+        ] {
+            within(&mut errors, key, value, 0.0, 1.0, "a number from 0 to 1");
+        }
+
+        if !(1..=10).contains(&self.output.max_suggestions) {
+            errors.push(ConfigError::OutOfRange {
+                key: "output.max_suggestions".to_string(),
+                value: self.output.max_suggestions.to_string(),
+                expected: "a number from 1 to 10",
+            });
+        }
+
+        for pattern in &self.privacy.redact_patterns {
+            if let Err(e) = regex::Regex::new(pattern) {
+                errors.push(ConfigError::InvalidPattern {
+                    key: "privacy.redact_patterns".to_string(),
+                    value: pattern.clone(),
+                    reason: e.to_string(),
+                });
+            }
+        }
+
+        let ollama = &self.ollama;
+        url(&mut errors, "ollama.base_url", &ollama.base_url);
+        not_empty(&mut errors, "ollama.model", &ollama.model);
+        positive(&mut errors, "ollama.timeout_secs", ollama.timeout_secs);
+        http(&mut errors, "ollama", &ollama.http);
+
+        let openai = &self.openai;
+        url(&mut errors, "openai.base_url", &openai.base_url);
+        not_empty(&mut errors, "openai.model", &openai.model);
+        not_empty(&mut errors, "openai.api_key_env", &openai.api_key_env);
+        positive(&mut errors, "openai.timeout_secs", openai.timeout_secs);
+        http(&mut errors, "openai", &openai.http);
+
+        for (index, endpoint) in self.backend.chain.iter().enumerate() {
+            let section = format!("backend.chain[{index}]");
+            if let Some(base_url) = &endpoint.base_url {
+                url(&mut errors, &format!("{section}.base_url"), base_url);
+            }
+            if let Some(model) = &endpoint.model {
+                not_empty(&mut errors, &format!("{section}.model"), model);
+            }
+            if let Some(api_key_env) = &endpoint.api_key_env {
+                if endpoint.kind == BackendKind::OpenAi {
+                    not_empty(&mut errors, &format!("{section}.api_key_env"), api_key_env);
+                }
+            }
+            if let Some(timeout_secs) = endpoint.timeout_secs {
+                positive(
+                    &mut errors,
+                    &format!("{section}.timeout_secs"),
+                    timeout_secs,
+                );
+            }
+            http(&mut errors, &section, &endpoint.http);
+        }
+
+        for (index, model) in self.backend.models.iter().enumerate() {
+            not_empty(&mut errors, &format!("backend.models[{index}]"), model);
+        }
+
+        positive(
+            &mut errors,
+            "shellcheck.timeout_ms",
+            self.shellcheck.timeout_ms,
+        );
+        if self.sandbox.backend == SandboxBackend::Container {
+            not_empty(&mut errors, "sandbox.image", &self.sandbox.image);
+        }
+        if self.execution.capture_output {
+            positive(
+                &mut errors,
+                "execution.max_captured_kb",
+                self.execution.max_captured_kb,
+            );
+        }
+        not_empty(&mut errors, "execution.shell", &self.execution.shell);
+        positive(&mut errors, "logging.max_file_kb", self.logging.max_file_kb);
+        errors.extend(KeyMap::check(&self.keys));
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidConfig(errors))
+        }
+    }
+
+    /// Keys of the config file that no longer do anything
+    pub fn deprecated_keys() -> Result<Vec<ConfigError>> {
+        let config_path = Self::get_config_path_static()?;
+        if !config_path.exists() {
+            return Ok(Vec::new());
+        }
+        let table: toml::Table = toml::from_str(&fs::read_to_string(config_path)?)?;
+
+        Ok(DEPRECATED_KEYS
+            .iter()
+            .filter(|(key, _)| {
+                let (section, name) = key.split_once('.').unwrap_or(("", key));
+                table
+                    .get(section)
+                    .and_then(|value| value.as_table())
+                    .is_some_and(|section| section.contains_key(name))
+            })
+            .map(|&(key, note)| ConfigError::Deprecated {
+                key: key.to_string(),
+                note,
+            })
+            .collect())
+    }
+}
+
+fn positive<T: PartialEq + Default + ToString>(errors: &mut Vec<ConfigError>, key: &str, value: T) {
+    if value == T::default() {
+        errors.push(ConfigError::OutOfRange {
+            key: key.to_string(),
+            value: value.to_string(),
+            expected: "a number of at least 1",
+        });
+    }
+}
+
+fn within(
+    errors: &mut Vec<ConfigError>,
+    key: &str,
+    value: f32,
+    min: f32,
+    max: f32,
+    expected: &'static str,
+) {
+    // NaN is in no range
+    if !(min..=max).contains(&value) {
+        errors.push(ConfigError::OutOfRange {
+            key: key.to_string(),
+            value: value.to_string(),
+            expected,
+        });
+    }
+}
+
+fn not_empty(errors: &mut Vec<ConfigError>, key: &str, value: &str) {
+    if value.trim().is_empty() {
+        errors.push(ConfigError::Empty {
+            key: key.to_string(),
+        });
+    }
+}
+
+fn url(errors: &mut Vec<ConfigError>, key: &str, value: &str) {
+    let reason = match url::Url::parse(value) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => return,
+        Ok(url) => format!("{} is not http or https", url.scheme()),
+        Err(e) => e.to_string(),
+    };
+    errors.push(ConfigError::InvalidUrl {
+        key: key.to_string(),
+        value: value.to_string(),
+        reason,
+    });
+}
+
+/// Transport options of `section`: the proxy must parse and the CA certificate exist
+fn http(errors: &mut Vec<ConfigError>, section: &str, http: &HttpOptions) {
+    if let Some(proxy) = &http.proxy {
+        if let Err(e) = url::Url::parse(proxy) {
+            errors.push(ConfigError::InvalidUrl {
+                key: format!("{section}.proxy"),
+                value: proxy.clone(),
+                reason: e.to_string(),
+            });
+        }
+    }
+    if let Some(ca_cert) = &http.ca_cert {
+        let path = match (ca_cert.strip_prefix("~/"), dirs::home_dir()) {
+            (Some(rest), Some(home)) => home.join(rest),
+            _ => PathBuf::from(ca_cert),
+        };
+        if !path.is_file() {
+            errors.push(ConfigError::MissingFile {
+                key: format!("{section}.ca_cert"),
+                path: ca_cert.clone(),
+            });
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::utils::EnvironmentDetector;
+
+/// `prev_hash` of the first entry
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// A suggestion that ran, as it's kept in the audit log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Position in the log, from 1
+    pub seq: u64,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub user: String,
+    pub host: String,
+    pub cwd: String,
+    pub prompt: String,
+    pub command: String,
+    /// `None` when it didn't start or was killed by a signal
+    pub exit_code: Option<i32>,
+    /// `hash` of the entry before, so no entry can be changed, removed or
+    /// put in between without breaking the chain
+    pub prev_hash: String,
+    /// SHA-256 of `prev_hash` and the entry with an empty `hash`
+    pub hash: String,
+}
+
+impl AuditEntry {
+    fn compute_hash(&self) -> String {
+        let unhashed = Self {
+            hash: String::new(),
+            ..self.clone()
+        };
+        let json = serde_json::to_string(&unhashed).unwrap_or_default();
+        format!(
+            "{:x}",
+            Sha256::digest(format!("{}{json}", self.prev_hash).as_bytes())
+        )
+    }
+}
+
+/// What ran and how it went, for `AuditLog::append`
+pub struct AuditRecord<'a> {
+    pub prompt: &'a str,
+    pub command: &'a str,
+    pub started_at: DateTime<Utc>,
+    pub exit_code: Option<i32>,
+}
+
+/// Where `AuditLog::verify` found the chain broken
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditBreak {
+    /// Line of the log, from 1
+    pub line: usize,
+    pub reason: String,
+}
+
+/// Append-only log of the suggestions that ran, one JSON entry per line,
+/// each carrying the hash of the one before it
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Adds the entry for `record` to the end of the log, chained to the
+    /// last one; the file is locked meanwhile so concurrent runs don't fork
+    /// the chain
+    pub fn append(&self, record: AuditRecord) -> Result<AuditEntry> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = open_private(&self.path)
+            .with_context(|| format!("Failed to open {}", self.path.display()))?;
+        file.lock()
+            .with_context(|| format!("Failed to lock {}", self.path.display()))?;
+
+        let last = last_entry(&mut file)?;
+        let mut entry = AuditEntry {
+            seq: last.as_ref().map_or(1, |last| last.seq + 1),
+            started_at: record.started_at,
+            finished_at: Utc::now(),
+            user: std::env::var("USER")
+                .or_else(|_| std::env::var("USERNAME"))
+                .unwrap_or_default(),
+            host: EnvironmentDetector::hostname().unwrap_or_default(),
+            cwd: std::env::current_dir()
+                .map(|dir| dir.display().to_string())
+                .unwrap_or_default(),
+            prompt: record.prompt.to_string(),
+            command: record.command.to_string(),
+            exit_code: record.exit_code,
+            prev_hash: last.map_or_else(|| GENESIS_HASH.to_string(), |last| last.hash),
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash();
+
+        file.seek(SeekFrom::End(0))?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        file.sync_data()?;
+        Ok(entry)
+    }
+
+    /// The last `limit` entries, oldest first; lines that can't be read are skipped
+    pub fn entries(&self, limit: usize) -> Result<Vec<AuditEntry>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let file = File::open(&self.path)?;
+        let entries: Vec<AuditEntry> = BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str(&line).ok())
+            .collect();
+        let skip = entries.len().saturating_sub(limit);
+        Ok(entries.into_iter().skip(skip).collect())
+    }
+
+    /// Checks every entry's hash and its link to the one before; the number
+    /// of entries when the chain is whole, or where it first breaks
+    pub fn verify(&self) -> Result<std::result::Result<usize, AuditBreak>> {
+        if !self.path.exists() {
+            return Ok(Ok(0));
+        }
+        let file = File::open(&self.path)?;
+        let mut prev_hash = GENESIS_HASH.to_string();
+        let mut count = 0;
+
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let broken = |reason: String| {
+                Ok(Err(AuditBreak {
+                    line: index + 1,
+                    reason,
+                }))
+            };
+            let entry: AuditEntry = match serde_json::from_str(&line) {
+                Ok(entry) => entry,
+                Err(e) => return broken(format!("it isn't an audit entry ({e})")),
+            };
+            if entry.seq != count as u64 + 1 {
+                return broken(format!(
+                    "it's entry {} where entry {} belongs",
+                    entry.seq,
+                    count + 1
+                ));
+            }
+            if entry.prev_hash != prev_hash {
+                return broken("it doesn't follow the entry before it".to_string());
+            }
+            if entry.compute_hash() != entry.hash {
+                return broken("it was changed after it was written".to_string());
+            }
+            prev_hash = entry.hash;
+            count += 1;
+        }
+        Ok(Ok(count))
+    }
+}
+
+/// Opens `path` for appending, readable by its owner only when it's created
+fn open_private(path: &Path) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true).read(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+/// The entry on the last line of `file`, if any
+fn last_entry(file: &mut File) -> Result<Option<AuditEntry>> {
+    file.seek(SeekFrom::Start(0))?;
+    let last = BufReader::new(&*file)
+        .lines()
+        .map_while(Result::ok)
+        .filter(|line| !line.trim().is_empty())
+        .last();
+    match last {
+        Some(line) => Ok(Some(
+            serde_json::from_str(&line).context("The last entry of the audit log is unreadable")?,
+        )),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,262 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{info, warn};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::utils::EnvironmentDetector;
+
+/// Version of the backup archive layout
+pub const BACKUP_FORMAT_VERSION: u32 = 1;
+
+/// Archives kept in ~/.phloem/backups when no path is given
+const KEPT_BACKUPS: usize = 5;
+
+/// Prefix of the archives written to ~/.phloem/backups
+const BACKUP_PREFIX: &str = "phloem_backup_";
+
+/// Files of ~/.phloem that are backed up, relative to it. Models and logs are
+/// left out: they're large and can be downloaded or regenerated. So is the
+/// audit log, which a restore would roll back, dropping the entries since.
+const BACKED_UP_FILES: &[&str] = &[
+    "config.toml",
+    "policy.toml",
+    "PHLOEM.md",
+    "cache/suggestions.db",
+];
+
+const METADATA_FILE: &str = "metadata.json";
+
+/// Describes what's in a backup archive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupMetadata {
+    pub format_version: u32,
+    pub phloem_version: String,
+    pub created_at: String,
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Backed up files, relative to ~/.phloem
+    pub files: Vec<String>,
+}
+
+/// Writes config, context and cache of `phloem_dir` to a gzipped tarball.
+/// Without `path` it goes to the backups folder, keeping the latest few there.
+pub fn create_backup(phloem_dir: &Path, path: Option<PathBuf>) -> Result<PathBuf> {
+    write_backup(phloem_dir, path, None)
+}
+
+/// `create_backup`, leaving `keep` alone when old archives are removed
+fn write_backup(phloem_dir: &Path, path: Option<PathBuf>, keep: Option<&Path>) -> Result<PathBuf> {
+    let backup_dir = phloem_dir.join("backups");
+    let rotate = path.is_none();
+    let (path, file) = match path {
+        Some(path) => {
+            let file = File::create(&path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            (path, file)
+        }
+        None => {
+            fs::create_dir_all(&backup_dir)?;
+            new_backup_file(&backup_dir)?
+        }
+    };
+
+    // A consistent copy of the database, even while another phloem writes to it
+    let staging = tempfile_path(phloem_dir, "backup.db");
+    let database = phloem_dir.join("cache").join("suggestions.db");
+    if database.exists() {
+        let _ = fs::remove_file(&staging);
+        Connection::open(&database)?
+            .execute("VACUUM INTO ?1", [staging.to_string_lossy()])
+            .context("Failed to snapshot the cache database")?;
+    }
+
+    let result = write_archive(phloem_dir, file, &staging);
+    let _ = fs::remove_file(&staging);
+    result?;
+
+    if rotate {
+        remove_old_backups(&backup_dir, keep);
+    }
+    info!("Backup written to {}", path.display());
+    Ok(path)
+}
+
+/// Replaces config, context and cache of `phloem_dir` with those in the
+/// archive at `path`, backing up the current state first. The metadata
+/// returned lists the files that were restored
+pub fn restore_backup(phloem_dir: &Path, path: &Path) -> Result<BackupMetadata> {
+    let mut metadata = read_metadata(path)?;
+    if metadata.format_version > BACKUP_FORMAT_VERSION {
+        return Err(anyhow::anyhow!(
+            "{} was written by phloem {} (backup format {}); upgrade phloem to restore it",
+            path.display(),
+            metadata.phloem_version,
+            metadata.format_version
+        ));
+    }
+
+    // Opened before the safety backup, whose rotation may remove old archives
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let safety = write_backup(phloem_dir, None, Some(path))
+        .context("Failed to back up the current state before restoring")?;
+    info!("Current state backed up to {}", safety.display());
+
+    let mut restored = Vec::new();
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        // Only the known files, so an archive can't write anywhere else
+        if !BACKED_UP_FILES.contains(&name.as_str()) {
+            continue;
+        }
+        // A link in their place would send later writes wherever it points
+        if entry.header().entry_type() != tar::EntryType::Regular {
+            warn!("Skipping {name} in the backup: it is not a regular file");
+            continue;
+        }
+
+        let target = phloem_dir.join(&name);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Write next to the target and swap it in, so a failure leaves the old file
+        let staging = tempfile_path(phloem_dir, "restore");
+        let _ = fs::remove_file(&staging);
+        let copied = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&staging)
+            .and_then(|mut file| io::copy(&mut entry, &mut file));
+        if let Err(e) = copied {
+            let _ = fs::remove_file(&staging);
+            return Err(e).with_context(|| format!("Failed to extract {name}"));
+        }
+        fs::rename(&staging, &target)
+            .with_context(|| format!("Failed to restore {}", target.display()))?;
+
+        // The database's write-ahead log belongs to the file just replaced
+        if name.ends_with(".db") {
+            for suffix in ["-wal", "-shm"] {
+                let _ = fs::remove_file(format!("{}{suffix}", target.display()));
+            }
+        }
+        restored.push(name);
+    }
+
+    metadata.files = restored;
+    Ok(metadata)
+}
+
+/// A new archive in the backups folder, named by the time; one made in the
+/// same second gets a numbered suffix instead of replacing it
+fn new_backup_file(backup_dir: &Path) -> Result<(PathBuf, File)> {
+    let stamp = Utc::now().format("%Y%m%d_%H%M%S");
+    for attempt in 0.. {
+        let suffix = if attempt == 0 {
+            String::new()
+        } else {
+            format!("_{attempt}")
+        };
+        let path = backup_dir.join(format!("{BACKUP_PREFIX}{stamp}{suffix}.tar.gz"));
+        match File::options().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to create {}", path.display()))
+            }
+        }
+    }
+    unreachable!("attempts don't run out")
+}
+
+fn write_archive(phloem_dir: &Path, file: File, database: &Path) -> Result<()> {
+    let mut files = Vec::new();
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+
+    for name in BACKED_UP_FILES {
+        let source = if name.ends_with(".db") {
+            database.to_path_buf()
+        } else {
+            phloem_dir.join(name)
+        };
+        if source.is_file() {
+            builder.append_path_with_name(&source, name)?;
+            files.push(name.to_string());
+        }
+    }
+
+    let metadata = BackupMetadata {
+        format_version: BACKUP_FORMAT_VERSION,
+        phloem_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: Utc::now().to_rfc3339(),
+        host: EnvironmentDetector::hostname(),
+        files,
+    };
+    let metadata = serde_json::to_vec_pretty(&metadata)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(metadata.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp().max(0) as u64);
+    builder.append_data(&mut header, METADATA_FILE, metadata.as_slice())?;
+
+    builder.into_inner()?.finish()?;
+    Ok(())
+}
+
+fn read_metadata(path: &Path) -> Result<BackupMetadata> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.as_os_str() == METADATA_FILE {
+            let mut content = String::new();
+            entry.read_to_string(&mut content)?;
+            return serde_json::from_str(&content)
+                .with_context(|| format!("Invalid backup metadata in {}", path.display()));
+        }
+    }
+
+    Err(anyhow::anyhow!(
+        "{} is not a phloem backup: it has no {METADATA_FILE}",
+        path.display()
+    ))
+}
+
+/// Keeps the most recent archives in the backups folder, and `keep` whatever its age
+fn remove_old_backups(backup_dir: &Path, keep: Option<&Path>) {
+    let Ok(entries) = fs::read_dir(backup_dir) else {
+        return;
+    };
+    // Names embed the timestamp, so they sort by age
+    let mut backups: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with(BACKUP_PREFIX))
+        })
+        .collect();
+    backups.sort();
+    backups.reverse();
+
+    let keep = keep.and_then(|keep| fs::canonicalize(keep).ok());
+    for backup in backups.iter().skip(KEPT_BACKUPS) {
+        if keep.is_some() && fs::canonicalize(backup).ok() == keep {
+            continue;
+        }
+        if let Err(e) = fs::remove_file(backup) {
+            warn!("Failed to remove old backup {}: {e}", backup.display());
+        }
+    }
+}
+
+fn tempfile_path(phloem_dir: &Path, purpose: &str) -> PathBuf {
+    phloem_dir.join(format!(".{purpose}.{}.tmp", std::process::id()))
+}
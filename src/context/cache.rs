@@ -1,159 +1,1591 @@
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
 // use chrono::Utc; // Will be used when we add timestamp functionality
-use anyhow::Result;
+use anyhow::{Context, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
 
-use crate::cli::Suggestion;
+use crate::cli::{Placeholder, Suggestion, SuggestionSource};
+use crate::config::settings::{CacheConfig, FrecencyConfig};
+use crate::context::learning::{LearnedPattern, LearningOutcome};
+use crate::context::manager::ToolUsage;
+use crate::context::migrations;
+use crate::utils::ShellDetector;
 
+/// How long to wait for another process holding the database lock
+const BUSY_TIMEOUT_MS: u64 = 5000;
+
+/// Uses after which more uses no longer raise a suggestion's frecency
+const FREQUENCY_SATURATION: f32 = 10.0;
+
+/// Prompt embedding used to find cached answers to differently worded prompts
+pub struct PromptEmbedding<'a> {
+    pub model: &'a str,
+    pub vector: &'a [f32],
+}
+
+/// A cached suggestion with its usage statistics, as listed and exported
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub prompt: String,
+    pub command: String,
+    #[serde(default)]
+    pub explanation: Option<String>,
+    #[serde(default)]
+    pub confidence: f32,
+    #[serde(default)]
+    pub backend: Option<String>,
+    #[serde(default)]
+    pub use_count: i64,
+    #[serde(default)]
+    pub success_count: i64,
+    #[serde(default = "default_success_rate")]
+    pub success_rate: f32,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub last_used: Option<String>,
+    /// Fingerprint of the project the suggestion was cached in; empty if none
+    #[serde(default)]
+    pub project: String,
+}
+
+/// Version of the document written by `cache export`
+pub const CACHE_EXPORT_VERSION: u32 = 1;
+
+/// Cached suggestions as written by `cache export`, for sharing between
+/// machines or with a team
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheExport {
+    pub version: u32,
+    pub exported_at: String,
+    pub entries: Vec<ExportedEntry>,
+}
+
+/// A cached suggestion with the category of its prompt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedEntry {
+    #[serde(default)]
+    pub category: String,
+    #[serde(flatten)]
+    pub entry: CacheEntry,
+}
+
+impl CacheExport {
+    /// Reads an export, or the bare list of entries older versions wrote
+    pub fn parse(content: &str) -> Result<Vec<CacheEntry>> {
+        if let Ok(entries) = serde_json::from_str::<Vec<CacheEntry>>(content) {
+            return Ok(entries);
+        }
+
+        let export: CacheExport = serde_json::from_str(content)?;
+        if export.version > CACHE_EXPORT_VERSION {
+            return Err(anyhow::anyhow!(
+                "Written by a newer phloem (format version {}); upgrade to import it",
+                export.version
+            ));
+        }
+        Ok(export
+            .entries
+            .into_iter()
+            .map(|entry| entry.entry)
+            .collect())
+    }
+}
+
+/// A command run from phloem, as recorded in the history table
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry {
+    pub command: String,
+    pub prompt: String,
+    pub success: bool,
+    pub exit_code: Option<i32>,
+    pub executed_at: String,
+    /// Tail of what the command printed, when it was captured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stdout: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<String>,
+}
+
+/// Usage of phloem over the last `days` days, as shown by `phloem stats`
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageStats {
+    pub days: u32,
+    pub prompts: i64,
+    pub prompts_per_day: Vec<DailyPrompts>,
+    /// Share of prompts answered from the cache without asking the model
+    pub cache_hit_rate: Option<f64>,
+    /// Share of prompts after which one of the suggestions was run
+    pub acceptance_rate: Option<f64>,
+    /// Average time the model took to reply, in milliseconds
+    pub average_latency_ms: Option<f64>,
+    pub top_categories: Vec<CategoryPrompts>,
+    pub top_executables: Vec<ToolUsage>,
+    /// Success rate of the commands run, week by week
+    pub success_trend: Vec<WeeklySuccess>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DailyPrompts {
+    pub date: String,
+    pub prompts: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CategoryPrompts {
+    pub category: String,
+    pub prompts: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WeeklySuccess {
+    /// Year and week number, e.g. "2026-W41"
+    pub week: String,
+    pub runs: i64,
+    pub success_rate: f64,
+}
+
+/// A command saved under a name with `phloem save`
+#[derive(Debug, Clone, Serialize)]
+pub struct Favorite {
+    pub name: String,
+    pub command: String,
+    /// Prompt the command was suggested for; empty when saved by hand
+    pub prompt: String,
+    pub use_count: i64,
+    pub created_at: String,
+    pub last_used: Option<String>,
+}
+
+impl Favorite {
+    /// Names are single words, so `phloem run NAME` needs no quoting
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty() && !name.starts_with('-') && !name.contains(char::is_whitespace)
+    }
+
+    /// The saved command as a suggestion to pick or run
+    pub fn to_suggestion(&self) -> Suggestion {
+        Suggestion {
+            command: self.command.clone(),
+            explanation: Some(format!("Saved as {}", self.name)),
+            confidence: 1.0,
+            backend: None,
+            source: SuggestionSource::Favorite,
+            placeholders: Placeholder::find(&self.command),
+            warnings: Vec::new(),
+        }
+    }
+}
+
+/// A step of a recorded workflow, as stored
+#[derive(Serialize, Deserialize)]
+struct WorkflowStep {
+    command: String,
+    #[serde(default)]
+    explanation: Option<String>,
+}
+
+fn default_success_rate() -> f32 {
+    0.5
+}
+
+/// Suggestion cache, history and learned context, kept in SQLite. Queries run
+/// on a blocking thread, so the other tasks of the runtime keep going
 pub struct CacheManager {
-    connection: Connection,
+    store: Arc<Mutex<CacheStore>>,
 }
 
 impl CacheManager {
-    pub fn new<P: AsRef<Path>>(db_path: P) -> Result<Self> {
-        let connection = Connection::open(db_path)?;
+    /// Opens the database at `db_path`, migrating it to the current schema
+    pub async fn new<P: AsRef<Path>>(db_path: P, config: &CacheConfig) -> Result<Self> {
+        let db_path = db_path.as_ref().to_path_buf();
+        let config = config.clone();
+        let store =
+            tokio::task::spawn_blocking(move || CacheStore::new(db_path, &config)).await??;
+        Ok(Self {
+            store: Arc::new(Mutex::new(store)),
+        })
+    }
+
+    /// Scopes new suggestions to a project and prefers them over those
+    /// cached outside any project
+    pub fn with_project(self, fingerprint: Option<String>) -> Self {
+        self.set_project(fingerprint);
+        self
+    }
+
+    /// Switches to another project, e.g. for a daemon request from another directory
+    pub fn set_project(&self, fingerprint: Option<String>) {
+        self.store().set_project(fingerprint);
+    }
+
+    /// Keeps the environment snapshot of this host apart from other machines
+    /// sharing the database
+    pub fn with_host(self, host: String) -> Self {
+        self.store().host = host;
+        self
+    }
+
+    /// The store, for settings that don't touch the database
+    fn store(&self) -> MutexGuard<'_, CacheStore> {
+        self.store.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Runs `work` on the store from a blocking thread
+    async fn run<T: Send + 'static>(
+        &self,
+        work: impl FnOnce(&mut CacheStore) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let store = Arc::clone(&self.store);
+        tokio::task::spawn_blocking(move || {
+            work(&mut store.lock().unwrap_or_else(PoisonError::into_inner))
+        })
+        .await?
+    }
+
+    /// Reusable suggestion for the prompt: the cached one with the best frecency,
+    /// if it clears the reuse threshold. Without one, the cached prompt most
+    /// similar to `embedding` is tried if it clears the similarity threshold.
+    pub async fn get_suggestion(
+        &self,
+        prompt: &str,
+        embedding: Option<&PromptEmbedding<'_>>,
+    ) -> Result<Option<Suggestion>> {
+        let prompt = prompt.to_string();
+        let embedding = embedding.map(OwnedEmbedding::new);
+        self.run(move |store| {
+            store.get_suggestion(
+                &prompt,
+                embedding.as_ref().map(OwnedEmbedding::borrow).as_ref(),
+            )
+        })
+        .await
+    }
+
+    /// Cached suggestions for the prompt that worked before, best frecency
+    /// first, to offer alongside fresh ones
+    pub async fn get_cached_candidates(
+        &self,
+        prompt: &str,
+        embedding: Option<&PromptEmbedding<'_>>,
+    ) -> Result<Vec<Suggestion>> {
+        let prompt = prompt.to_string();
+        let embedding = embedding.map(OwnedEmbedding::new);
+        self.run(move |store| {
+            store.get_cached_candidates(
+                &prompt,
+                embedding.as_ref().map(OwnedEmbedding::borrow).as_ref(),
+            )
+        })
+        .await
+    }
+
+    /// Stores the prompt's embedding so later prompts can match it semantically
+    pub async fn store_embedding(
+        &self,
+        prompt: &str,
+        embedding: &PromptEmbedding<'_>,
+    ) -> Result<()> {
+        let prompt = prompt.to_string();
+        let embedding = OwnedEmbedding::new(embedding);
+        self.run(move |store| store.store_embedding(&prompt, &embedding.borrow()))
+            .await
+    }
+
+    /// Suggestions generated for this prompt before, best first, without reuse thresholds
+    pub async fn get_previous_suggestions(
+        &self,
+        prompt: &str,
+        limit: usize,
+    ) -> Result<Vec<Suggestion>> {
+        let prompt = prompt.to_string();
+        self.run(move |store| store.get_previous_suggestions(&prompt, limit))
+            .await
+    }
+
+    /// All cached prompts with their suggestions, most successful first
+    pub async fn get_all_suggestions(&self, limit: usize) -> Result<Vec<(String, Suggestion)>> {
+        self.run(move |store| store.get_all_suggestions(limit))
+            .await
+    }
+
+    pub async fn cache_suggestion(&self, prompt: &str, suggestion: &Suggestion) -> Result<()> {
+        let prompt = prompt.to_string();
+        let suggestion = suggestion.clone();
+        self.run(move |store| store.cache_suggestion(&prompt, &suggestion))
+            .await
+    }
+
+    /// Cached suggestions with their usage statistics, most recently used first
+    pub async fn list_entries(&self, limit: Option<usize>) -> Result<Vec<CacheEntry>> {
+        self.run(move |store| store.list_entries(limit)).await
+    }
+
+    /// Adds entries exported from another cache, keeping existing ones.
+    /// Returns how many were new.
+    pub async fn import_entries(&self, entries: &[CacheEntry]) -> Result<usize> {
+        let entries = entries.to_vec();
+        self.run(move |store| store.import_entries(&entries)).await
+    }
+
+    /// Records how a model reply was parsed and how long it took
+    pub async fn record_generation(
+        &self,
+        backend: Option<&str>,
+        outcome: &str,
+        latency: Duration,
+    ) -> Result<()> {
+        let backend = backend.map(str::to_string);
+        let outcome = outcome.to_string();
+        self.run(move |store| store.record_generation(backend.as_deref(), &outcome, latency))
+            .await
+    }
+
+    /// Records a prompt answered from `source`: the cache, the model or offline matches
+    pub async fn record_prompt(&self, prompt: &str, source: &str, category: &str) -> Result<()> {
+        let (prompt, source, category) =
+            (prompt.to_string(), source.to_string(), category.to_string());
+        self.run(move |store| store.record_prompt(&prompt, &source, &category))
+            .await
+    }
+
+    /// Marks the latest time `prompt` was answered as one whose suggestion was run
+    pub async fn record_prompt_accepted(&self, prompt: &str) -> Result<()> {
+        let prompt = prompt.to_string();
+        self.run(move |store| store.record_prompt_accepted(&prompt))
+            .await
+    }
+
+    /// Usage over the last `days` days; `top_executables` is left for the
+    /// caller, which reads them from the history
+    pub async fn get_usage_stats(&self, days: u32, limit: usize) -> Result<UsageStats> {
+        self.run(move |store| store.get_usage_stats(days, limit))
+            .await
+    }
+
+    /// Stores a learned pattern, scoped to the current project when `in_project`
+    pub async fn record_learned_pattern(
+        &self,
+        pattern: &LearnedPattern,
+        in_project: bool,
+    ) -> Result<()> {
+        let pattern = pattern.clone();
+        self.run(move |store| store.record_learned_pattern(&pattern, in_project))
+            .await
+    }
+
+    /// Learned patterns of the current project, or the global ones, newest first
+    pub async fn get_learned_patterns(
+        &self,
+        in_project: bool,
+        limit: usize,
+    ) -> Result<Vec<LearnedPattern>> {
+        self.run(move |store| store.get_learned_patterns(in_project, limit))
+            .await
+    }
+
+    /// Records suggestions the user passed over for `prompt`
+    pub async fn record_rejected_suggestions(&self, prompt: &str, commands: &[&str]) -> Result<()> {
+        let prompt = prompt.to_string();
+        let commands: Vec<String> = commands.iter().map(|command| command.to_string()).collect();
+        self.run(move |store| {
+            let commands: Vec<&str> = commands.iter().map(String::as_str).collect();
+            store.record_rejected_suggestions(&prompt, &commands)
+        })
+        .await
+    }
+
+    /// Recently rejected prompt and command pairs of this project or no project,
+    /// most recent first, leaving out commands that later ran successfully
+    pub async fn get_rejected_suggestions(&self, limit: usize) -> Result<Vec<(String, String)>> {
+        self.run(move |store| store.get_rejected_suggestions(limit))
+            .await
+    }
+
+    /// Records that the user ran `corrected` in place of the suggested `original`
+    pub async fn record_correction(
+        &self,
+        prompt: &str,
+        original: &str,
+        corrected: &str,
+    ) -> Result<()> {
+        let (prompt, original, corrected) = (
+            prompt.to_string(),
+            original.to_string(),
+            corrected.to_string(),
+        );
+        self.run(move |store| store.record_correction(&prompt, &original, &corrected))
+            .await
+    }
+
+    /// Recent corrections of this project or no project, as prompt, original
+    /// and corrected command, most recent first
+    pub async fn get_corrections(&self, limit: usize) -> Result<Vec<(String, String, String)>> {
+        self.run(move |store| store.get_corrections(limit)).await
+    }
+
+    /// Saves `command` under `name`, replacing the command saved under it
+    /// before; returns whether there was one
+    pub async fn save_favorite(&self, name: &str, command: &str, prompt: &str) -> Result<bool> {
+        let (name, command, prompt) = (name.to_string(), command.to_string(), prompt.to_string());
+        self.run(move |store| store.save_favorite(&name, &command, &prompt))
+            .await
+    }
+
+    /// The command saved under `name`
+    pub async fn get_favorite(&self, name: &str) -> Result<Option<Favorite>> {
+        let name = name.to_string();
+        self.run(move |store| store.get_favorite(&name)).await
+    }
+
+    /// Saved commands, most used first
+    pub async fn list_favorites(&self) -> Result<Vec<Favorite>> {
+        self.run(|store| store.list_favorites()).await
+    }
+
+    /// Saved commands whose prompt is this one or, by `embedding`, closely
+    /// matches it
+    pub async fn get_matching_favorites(
+        &self,
+        prompt: &str,
+        embedding: Option<&PromptEmbedding<'_>>,
+    ) -> Result<Vec<Favorite>> {
+        let prompt = prompt.to_string();
+        let embedding = embedding.map(OwnedEmbedding::new);
+        self.run(move |store| {
+            store.get_matching_favorites(
+                &prompt,
+                embedding.as_ref().map(OwnedEmbedding::borrow).as_ref(),
+            )
+        })
+        .await
+    }
+
+    /// Counts a run of the command saved under `name`
+    pub async fn record_favorite_use(&self, name: &str) -> Result<()> {
+        let name = name.to_string();
+        self.run(move |store| store.record_favorite_use(&name))
+            .await
+    }
+
+    /// Forgets the command saved under `name`; false if there is none
+    pub async fn delete_favorite(&self, name: &str) -> Result<bool> {
+        let name = name.to_string();
+        self.run(move |store| store.delete_favorite(&name)).await
+    }
+
+    /// Saves the command saved under `name` under `new_name` instead; false
+    /// if there is none
+    pub async fn rename_favorite(&self, name: &str, new_name: &str) -> Result<bool> {
+        let (name, new_name) = (name.to_string(), new_name.to_string());
+        self.run(move |store| store.rename_favorite(&name, &new_name))
+            .await
+    }
+
+    /// Records a plan whose steps all ran, to offer again for `prompt`
+    pub async fn record_workflow(&self, prompt: &str, steps: &[Suggestion]) -> Result<()> {
+        let prompt = prompt.to_string();
+        let steps = steps.to_vec();
+        self.run(move |store| store.record_workflow(&prompt, &steps))
+            .await
+    }
+
+    /// Steps of the workflow last recorded for `prompt` in this project or
+    /// none, if any
+    pub async fn get_workflow(&self, prompt: &str) -> Result<Option<Vec<Suggestion>>> {
+        let prompt = prompt.to_string();
+        self.run(move |store| store.get_workflow(&prompt)).await
+    }
+
+    /// Removes one learned pattern; false if there is none with that id
+    pub async fn delete_learned_pattern(&self, id: i64) -> Result<bool> {
+        self.run(move |store| store.delete_learned_pattern(id))
+            .await
+    }
+
+    /// Forgets every learned pattern, in all projects
+    pub async fn clear_learned_patterns(&self) -> Result<()> {
+        self.run(|store| store.clear_learned_patterns()).await
+    }
+
+    /// Adds a run to the history; `output` is the tail of its stdout and
+    /// stderr, when they were captured
+    pub async fn record_command_execution(
+        &self,
+        command: &str,
+        prompt: &str,
+        success: bool,
+        exit_code: Option<i32>,
+        output: Option<(&str, &str)>,
+    ) -> Result<()> {
+        let (command, prompt) = (command.to_string(), prompt.to_string());
+        let output = output.map(|(stdout, stderr)| (stdout.to_string(), stderr.to_string()));
+        self.run(move |store| {
+            let output = output
+                .as_ref()
+                .map(|(stdout, stderr)| (stdout.as_str(), stderr.as_str()));
+            store.record_command_execution(&command, &prompt, success, exit_code, output)
+        })
+        .await
+    }
+
+    /// Executed commands, most recent first
+    pub async fn get_history(&self, limit: Option<usize>) -> Result<Vec<HistoryEntry>> {
+        self.run(move |store| store.get_history(limit)).await
+    }
+
+    /// Commands executed in the last `days` days, most recent first
+    pub async fn get_history_since(&self, days: u32) -> Result<Vec<HistoryEntry>> {
+        self.run(move |store| store.get_history_since(days)).await
+    }
+
+    /// Executed commands whose command line or prompt contains all words of
+    /// `term`, best matches first
+    pub async fn search_history(&self, term: &str, limit: usize) -> Result<Vec<HistoryEntry>> {
+        let term = term.to_string();
+        self.run(move |store| store.search_history(&term, limit))
+            .await
+    }
+
+    /// Successful commands from history sharing words with the prompt, best matches first
+    pub async fn get_relevant_commands(&self, prompt: &str, limit: usize) -> Result<Vec<String>> {
+        let prompt = prompt.to_string();
+        self.run(move |store| store.get_relevant_commands(&prompt, limit))
+            .await
+    }
+
+    /// Cached prompts with their suggestions sharing words with `text`, best matches first
+    pub async fn search_suggestions(
+        &self,
+        text: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, Suggestion)>> {
+        let text = text.to_string();
+        self.run(move |store| store.search_suggestions(&text, limit))
+            .await
+    }
+
+    pub async fn get_recent_commands(&self, limit: usize) -> Result<Vec<String>> {
+        self.run(move |store| store.get_recent_commands(limit))
+            .await
+    }
+
+    /// Returns recent successful prompt→command pairs from history and the suggestion cache
+    pub async fn get_successful_examples(&self, limit: usize) -> Result<Vec<(String, String)>> {
+        self.run(move |store| store.get_successful_examples(limit))
+            .await
+    }
+
+    /// Replaces this host's environment snapshot, so keys no longer detected don't linger
+    pub async fn replace_environment(
+        &self,
+        environment: &std::collections::HashMap<String, String>,
+    ) -> Result<()> {
+        let environment = environment.clone();
+        self.run(move |store| store.replace_environment(&environment))
+            .await
+    }
+
+    /// Minutes since this host's environment snapshot was detected; `None` without one
+    pub async fn environment_age_minutes(&self) -> Result<Option<f64>> {
+        self.run(|store| store.environment_age_minutes()).await
+    }
+
+    /// Executables this host listed from `path` less than `max_age_minutes`
+    /// ago; `None` when there's no such list
+    pub async fn get_executables(
+        &self,
+        path: &str,
+        max_age_minutes: u32,
+    ) -> Result<Option<Vec<String>>> {
+        let path = path.to_string();
+        self.run(move |store| store.get_executables(&path, max_age_minutes))
+            .await
+    }
+
+    /// Replaces this host's list of executables, listed from `path`
+    pub async fn replace_executables<'a>(
+        &self,
+        path: &str,
+        names: impl Iterator<Item = &'a str>,
+    ) -> Result<()> {
+        let path = path.to_string();
+        let names: Vec<String> = names.map(str::to_string).collect();
+        self.run(move |store| store.replace_executables(&path, names.iter().map(String::as_str)))
+            .await
+    }
+
+    pub async fn get_environment(&self) -> Result<std::collections::HashMap<String, String>> {
+        self.run(|store| store.get_environment()).await
+    }
+
+    pub async fn clear_cache(&self) -> Result<()> {
+        self.run(|store| store.clear_cache()).await
+    }
+
+    pub async fn get_cache_stats(&self) -> Result<String> {
+        self.run(|store| store.get_cache_stats()).await
+    }
+
+    /// Removes suggestions and history older than `days`, returning how many
+    /// suggestions were removed
+    pub async fn prune_old_data(&self, days: u32) -> Result<usize> {
+        self.run(move |store| store.prune_old_data(days)).await
+    }
+
+    /// Success rate of a command across all prompts, if it has ever been run
+    pub async fn get_command_success_rate(&self, command: &str) -> Result<Option<f32>> {
+        let command = command.to_string();
+        self.run(move |store| store.get_command_success_rate(&command))
+            .await
+    }
+
+    pub async fn record_suggestion_usage(
+        &self,
+        prompt: &str,
+        command: &str,
+        success: bool,
+    ) -> Result<()> {
+        let (prompt, command) = (prompt.to_string(), command.to_string());
+        self.run(move |store| store.record_suggestion_usage(&prompt, &command, success))
+            .await
+    }
+
+    pub async fn get_shell_history(&self) -> Result<Vec<String>> {
+        self.run(|store| store.get_shell_history()).await
+    }
+}
+
+/// A copy of a `PromptEmbedding` that can move to a blocking thread
+struct OwnedEmbedding {
+    model: String,
+    vector: Vec<f32>,
+}
+
+impl OwnedEmbedding {
+    fn new(embedding: &PromptEmbedding) -> Self {
+        Self {
+            model: embedding.model.to_string(),
+            vector: embedding.vector.to_vec(),
+        }
+    }
+
+    fn borrow(&self) -> PromptEmbedding<'_> {
+        PromptEmbedding {
+            model: &self.model,
+            vector: &self.vector,
+        }
+    }
+}
+
+/// The database behind `CacheManager`, used from one blocking thread at a
+/// time; its methods are documented on their `CacheManager` counterparts
+struct CacheStore {
+    connection: Connection,
+    /// Suggestions older than this are not reused
+    ttl_hours: u32,
+    /// Least recently used suggestions beyond this count are evicted
+    max_entries: usize,
+    /// Minimum cosine similarity for a semantic cache hit
+    similarity_threshold: f32,
+    /// How cached suggestions are scored for reuse
+    frecency: FrecencyConfig,
+    /// Fingerprint of the current project; empty outside of one
+    project: String,
+    /// Machine whose environment snapshot is read and written
+    host: String,
+}
+
+impl CacheStore {
+    fn new<P: AsRef<Path>>(db_path: P, config: &CacheConfig) -> Result<Self> {
+        let mut connection = Connection::open(db_path)?;
+
+        // Let concurrent phloem invocations read while one writes, and wait
+        // for a writer instead of failing with "database is locked"
+        connection.busy_timeout(Duration::from_millis(BUSY_TIMEOUT_MS))?;
+        connection.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))?;
+        connection.execute_batch("PRAGMA synchronous = NORMAL;")?;
+
+        migrations::migrate(&mut connection)?;
+
+        let cache = Self {
+            connection,
+            ttl_hours: config.cache_ttl_hours,
+            max_entries: config.max_cache_entries,
+            similarity_threshold: config.semantic.threshold,
+            frecency: config.frecency.clone(),
+            project: String::new(),
+            host: String::new(),
+        };
+
+        // Shrink caches grown past the limit, e.g. after lowering max_cache_entries
+        cache.evict_excess_entries()?;
+
+        Ok(cache)
+    }
+
+    fn set_project(&mut self, fingerprint: Option<String>) {
+        self.project = fingerprint.unwrap_or_default();
+    }
+
+    fn get_suggestion(
+        &self,
+        prompt: &str,
+        embedding: Option<&PromptEmbedding>,
+    ) -> Result<Option<Suggestion>> {
+        for prompt_hash in self.candidate_prompt_hashes(prompt, embedding)? {
+            let reusable = self
+                .get_frecent_suggestions(&prompt_hash)?
+                .into_iter()
+                .find(|(_, score)| *score >= self.frecency.reuse_threshold);
+
+            if let Some((suggestion, score)) = reusable {
+                debug!("Reusing cached suggestion with frecency {score:.2}");
+                self.update_suggestion_usage(&prompt_hash)?;
+                return Ok(Some(suggestion));
+            }
+        }
+        Ok(None)
+    }
+
+    fn get_cached_candidates(
+        &self,
+        prompt: &str,
+        embedding: Option<&PromptEmbedding>,
+    ) -> Result<Vec<Suggestion>> {
+        let mut candidates: Vec<Suggestion> = Vec::new();
+        for prompt_hash in self.candidate_prompt_hashes(prompt, embedding)? {
+            for (suggestion, _) in self.get_frecent_suggestions(&prompt_hash)? {
+                if !candidates.iter().any(|c| c.command == suggestion.command) {
+                    candidates.push(suggestion);
+                }
+            }
+        }
+        candidates.truncate(self.frecency.max_candidates);
+        Ok(candidates)
+    }
+
+    /// The prompt's hash, followed by that of the cached prompt most similar
+    /// to `embedding` if there is one
+    fn candidate_prompt_hashes(
+        &self,
+        prompt: &str,
+        embedding: Option<&PromptEmbedding>,
+    ) -> Result<Vec<String>> {
+        let prompt_hash = self.hash_prompt(prompt);
+        let similar = match embedding {
+            Some(embedding) => self.find_similar_prompt(embedding)?,
+            None => None,
+        };
+
+        let mut hashes = vec![prompt_hash];
+        if let Some((similar_hash, similarity)) = similar {
+            if !hashes.contains(&similar_hash) {
+                debug!("Semantic cache candidate with similarity {similarity:.3}");
+                hashes.push(similar_hash);
+            }
+        }
+        Ok(hashes)
+    }
+
+    /// Unexpired suggestions for a prompt that succeeded at least once, with
+    /// their frecency; this project's first, then the best scored
+    fn get_frecent_suggestions(&self, prompt_hash: &str) -> Result<Vec<(Suggestion, f32)>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT suggestion, explanation, confidence, backend, use_count, success_rate,
+                    (julianday('now') - julianday(COALESCE(last_used, created_at))) * 24,
+                    project = ?3
+             FROM suggestions
+             WHERE prompt_hash = ?1
+             AND project IN (?3, '')
+             AND created_at > datetime('now', '-' || ?2 || ' hours')
+             AND success_count > 0",
+        )?;
+
+        let mut scored = stmt
+            .query_map(params![prompt_hash, self.ttl_hours, self.project], |row| {
+                let command: String = row.get(0)?;
+                let suggestion = Suggestion {
+                    placeholders: Placeholder::find(&command),
+                    warnings: Vec::new(),
+                    command,
+                    explanation: row.get(1)?,
+                    confidence: row.get(2)?,
+                    backend: row.get(3)?,
+                    source: SuggestionSource::Cache,
+                };
+                let score = self.frecency_score(
+                    row.get(4)?,
+                    row.get(5)?,
+                    row.get::<_, Option<f64>>(6)?.unwrap_or(0.0),
+                );
+                Ok((suggestion, score, row.get::<_, bool>(7)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        scored.sort_by(|a, b| b.2.cmp(&a.2).then(b.1.total_cmp(&a.1)));
+
+        Ok(scored
+            .into_iter()
+            .map(|(suggestion, score, _)| (suggestion, score))
+            .collect())
+    }
+
+    /// Recency-decayed mix of how often a suggestion was used and how often it
+    /// succeeded, from 0 to 1
+    fn frecency_score(&self, use_count: i64, success_rate: f32, hours_since_use: f64) -> f32 {
+        let frecency = &self.frecency;
+        let frequency = ((use_count.max(0) as f32).ln_1p() / FREQUENCY_SATURATION.ln_1p()).min(1.0);
+        let recency =
+            0.5f32.powf(hours_since_use.max(0.0) as f32 / frecency.half_life_hours.max(0.1));
+
+        let total_weight = frecency.frequency_weight + frecency.success_weight;
+        if total_weight <= 0.0 {
+            return recency;
+        }
+        recency * (frequency * frecency.frequency_weight + success_rate * frecency.success_weight)
+            / total_weight
+    }
+
+    /// Hash of the cached prompt closest to `embedding`, with its similarity,
+    /// if any clears the threshold
+    fn find_similar_prompt(&self, embedding: &PromptEmbedding) -> Result<Option<(String, f32)>> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT prompt_hash, embedding FROM prompt_embeddings WHERE model = ?1")?;
+
+        let rows = stmt.query_map([embedding.model], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })?;
+
+        let mut best: Option<(String, f32)> = None;
+        for row in rows {
+            let (prompt_hash, blob) = row?;
+            let similarity = cosine_similarity(embedding.vector, &decode_embedding(&blob));
+
+            if similarity >= self.similarity_threshold
+                && best.as_ref().is_none_or(|(_, top)| similarity > *top)
+            {
+                best = Some((prompt_hash, similarity));
+            }
+        }
+
+        Ok(best)
+    }
+
+    fn store_embedding(&mut self, prompt: &str, embedding: &PromptEmbedding) -> Result<()> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO prompt_embeddings (prompt_hash, model, embedding)
+             VALUES (?1, ?2, ?3)",
+            params![
+                self.hash_prompt(prompt),
+                embedding.model,
+                encode_embedding(embedding.vector)
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get_previous_suggestions(&self, prompt: &str, limit: usize) -> Result<Vec<Suggestion>> {
+        let prompt_hash = self.hash_prompt(prompt);
+
+        let mut stmt = self.connection.prepare(
+            "SELECT suggestion, explanation, confidence, backend FROM suggestions
+             WHERE prompt_hash = ?1 AND project IN (?3, '')
+             ORDER BY project = ?3 DESC, (success_rate * 0.6 + confidence * 0.4) DESC, last_used DESC
+             LIMIT ?2",
+        )?;
+
+        let suggestions = stmt
+            .query_map(params![prompt_hash, limit as i64, self.project], |row| {
+                let command: String = row.get(0)?;
+                Ok(Suggestion {
+                    placeholders: Placeholder::find(&command),
+                    warnings: Vec::new(),
+                    command,
+                    explanation: row.get(1)?,
+                    confidence: row.get(2)?,
+                    backend: row.get(3)?,
+                    source: SuggestionSource::Cache,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(suggestions)
+    }
+
+    fn get_all_suggestions(&self, limit: usize) -> Result<Vec<(String, Suggestion)>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT prompt, suggestion, explanation, confidence, backend FROM suggestions
+             ORDER BY success_rate DESC, last_used DESC
+             LIMIT ?1",
+        )?;
+
+        let suggestions = stmt
+            .query_map([limit as i64], |row| {
+                let command: String = row.get(1)?;
+                Ok((
+                    row.get(0)?,
+                    Suggestion {
+                        placeholders: Placeholder::find(&command),
+                        warnings: Vec::new(),
+                        command,
+                        explanation: row.get(2)?,
+                        confidence: row.get(3)?,
+                        backend: row.get(4)?,
+                        source: SuggestionSource::Cache,
+                    },
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(suggestions)
+    }
+
+    fn cache_suggestion(&mut self, prompt: &str, suggestion: &Suggestion) -> Result<()> {
+        let prompt_hash = self.hash_prompt(prompt);
+
+        // Check if this suggestion already exists
+        let existing = self.connection.query_row(
+            "SELECT id, use_count, success_count FROM suggestions WHERE prompt_hash = ?1 AND suggestion = ?2 AND project = ?3",
+            params![prompt_hash, suggestion.command, self.project],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?))
+        );
 
-        // Initialize schema
-        connection.execute_batch(include_str!("../../sql/schema.sql"))?;
+        match existing {
+            Ok((id, use_count, success_count)) => {
+                // Update existing suggestion
+                let success_rate = if use_count > 0 {
+                    success_count as f32 / use_count as f32
+                } else {
+                    0.5
+                };
+
+                self.connection.execute(
+                    "UPDATE suggestions SET last_used = datetime('now'), confidence = ?1, success_rate = ?2, backend = COALESCE(?3, backend) WHERE id = ?4",
+                    params![suggestion.confidence, success_rate, suggestion.backend, id],
+                )?;
+            }
+            Err(_) => {
+                // Insert new suggestion with conservative defaults
+                self.connection.execute(
+                    "INSERT INTO suggestions 
+                     (prompt_hash, prompt, suggestion, explanation, confidence, created_at, last_used, use_count, success_count, success_rate, backend, project) 
+                     VALUES (?, ?, ?, ?, ?, datetime('now'), datetime('now'), 0, 0, 0.5, ?, ?)",
+                    params![
+                        prompt_hash,
+                        prompt,
+                        suggestion.command,
+                        suggestion.explanation,
+                        suggestion.confidence,
+                        suggestion.backend,
+                        self.project,
+                    ],
+                )?;
+                self.evict_excess_entries()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops the least recently used suggestions beyond `max_entries`
+    fn evict_excess_entries(&self) -> Result<()> {
+        let evicted = self.connection.execute(
+            "DELETE FROM suggestions WHERE id NOT IN (
+                 SELECT id FROM suggestions ORDER BY last_used DESC, id DESC LIMIT ?1
+             )",
+            [self.max_entries as i64],
+        )?;
+
+        if evicted > 0 {
+            debug!("Evicted {evicted} least recently used suggestions");
+            self.remove_orphan_embeddings()?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes embeddings of prompts no longer cached
+    fn remove_orphan_embeddings(&self) -> Result<()> {
+        self.connection.execute(
+            "DELETE FROM prompt_embeddings
+             WHERE prompt_hash NOT IN (SELECT prompt_hash FROM suggestions)",
+            [],
+        )?;
+        Ok(())
+    }
+
+    fn list_entries(&self, limit: Option<usize>) -> Result<Vec<CacheEntry>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT prompt, suggestion, explanation, confidence, backend,
+                    use_count, success_count, success_rate, created_at, last_used, project
+             FROM suggestions
+             ORDER BY last_used DESC, id DESC
+             LIMIT ?1",
+        )?;
+
+        let entries = stmt
+            .query_map([limit.map_or(-1, |limit| limit as i64)], |row| {
+                Ok(CacheEntry {
+                    prompt: row.get(0)?,
+                    command: row.get(1)?,
+                    explanation: row.get(2)?,
+                    confidence: row.get(3)?,
+                    backend: row.get(4)?,
+                    use_count: row.get(5)?,
+                    success_count: row.get(6)?,
+                    success_rate: row.get(7)?,
+                    created_at: row.get(8)?,
+                    last_used: row.get(9)?,
+                    project: row.get(10)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(entries)
+    }
+
+    fn import_entries(&mut self, entries: &[CacheEntry]) -> Result<usize> {
+        let hashes: Vec<String> = entries
+            .iter()
+            .map(|entry| self.hash_prompt(&entry.prompt))
+            .collect();
+
+        let transaction = self.connection.transaction()?;
+        let mut imported = 0;
+
+        {
+            let mut stmt = transaction.prepare(
+                "INSERT OR IGNORE INTO suggestions
+                 (prompt_hash, prompt, suggestion, explanation, confidence, backend,
+                  use_count, success_count, success_rate, created_at, last_used, project)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9,
+                         COALESCE(?10, datetime('now')), COALESCE(?11, datetime('now')), ?12)",
+            )?;
+
+            for (entry, prompt_hash) in entries.iter().zip(&hashes) {
+                imported += stmt.execute(params![
+                    prompt_hash,
+                    entry.prompt,
+                    entry.command,
+                    entry.explanation,
+                    entry.confidence,
+                    entry.backend,
+                    entry.use_count,
+                    entry.success_count,
+                    entry.success_rate,
+                    entry.created_at,
+                    entry.last_used,
+                    entry.project,
+                ])?;
+            }
+        }
+
+        transaction.commit()?;
+        self.evict_excess_entries()?;
+
+        Ok(imported)
+    }
+
+    fn record_generation(
+        &mut self,
+        backend: Option<&str>,
+        outcome: &str,
+        latency: Duration,
+    ) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO generations (backend, outcome, latency_ms) VALUES (?1, ?2, ?3)",
+            params![backend, outcome, latency.as_millis() as i64],
+        )?;
+        Ok(())
+    }
+
+    fn record_prompt(&mut self, prompt: &str, source: &str, category: &str) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO prompts (prompt_hash, source, category) VALUES (?1, ?2, ?3)",
+            params![self.hash_prompt(prompt), source, category],
+        )?;
+        Ok(())
+    }
+
+    fn record_prompt_accepted(&mut self, prompt: &str) -> Result<()> {
+        self.connection.execute(
+            "UPDATE prompts SET accepted = TRUE
+             WHERE id = (SELECT MAX(id) FROM prompts WHERE prompt_hash = ?1)",
+            [self.hash_prompt(prompt)],
+        )?;
+        Ok(())
+    }
+
+    fn get_usage_stats(&self, days: u32, limit: usize) -> Result<UsageStats> {
+        let since = format!("-{days} days");
+
+        let (prompts, cache_hits, accepted): (i64, i64, i64) = self.connection.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(source = 'cache'), 0), COALESCE(SUM(accepted), 0)
+             FROM prompts WHERE created_at > datetime('now', ?1)",
+            [&since],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        let rate = |count: i64| (prompts > 0).then(|| count as f64 / prompts as f64);
+
+        let mut stmt = self.connection.prepare(
+            "SELECT date(created_at), COUNT(*) FROM prompts
+             WHERE created_at > datetime('now', ?1)
+             GROUP BY date(created_at) ORDER BY date(created_at)",
+        )?;
+        let prompts_per_day = stmt
+            .query_map([&since], |row| {
+                Ok(DailyPrompts {
+                    date: row.get(0)?,
+                    prompts: row.get(1)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let average_latency_ms: Option<f64> = self.connection.query_row(
+            "SELECT AVG(latency_ms) FROM generations
+             WHERE latency_ms IS NOT NULL AND created_at > datetime('now', ?1)",
+            [&since],
+            |row| row.get(0),
+        )?;
+
+        let mut stmt = self.connection.prepare(
+            "SELECT category, COUNT(*) FROM prompts
+             WHERE created_at > datetime('now', ?1) AND category != ''
+             GROUP BY category ORDER BY COUNT(*) DESC, category LIMIT ?2",
+        )?;
+        let top_categories = stmt
+            .query_map(params![since, limit as i64], |row| {
+                Ok(CategoryPrompts {
+                    category: row.get(0)?,
+                    prompts: row.get(1)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut stmt = self.connection.prepare(
+            "SELECT strftime('%Y-W%W', executed_at), COUNT(*), AVG(success)
+             FROM history WHERE executed_at > datetime('now', ?1)
+             GROUP BY strftime('%Y-W%W', executed_at) ORDER BY MIN(executed_at)",
+        )?;
+        let success_trend = stmt
+            .query_map([&since], |row| {
+                Ok(WeeklySuccess {
+                    week: row.get(0)?,
+                    runs: row.get(1)?,
+                    success_rate: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(UsageStats {
+            days,
+            prompts,
+            prompts_per_day,
+            cache_hit_rate: rate(cache_hits),
+            acceptance_rate: rate(accepted),
+            average_latency_ms,
+            top_categories,
+            top_executables: Vec::new(),
+            success_trend,
+        })
+    }
+
+    fn record_learned_pattern(&mut self, pattern: &LearnedPattern, in_project: bool) -> Result<()> {
+        let project = if in_project {
+            self.project.as_str()
+        } else {
+            ""
+        };
+        self.connection.execute(
+            "INSERT INTO learned_patterns (prompt, command, category, outcome, project)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                pattern.prompt,
+                pattern.command,
+                pattern.category,
+                pattern.outcome.as_str(),
+                project
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get_learned_patterns(&self, in_project: bool, limit: usize) -> Result<Vec<LearnedPattern>> {
+        let project = if in_project {
+            self.project.as_str()
+        } else {
+            ""
+        };
+        let mut stmt = self.connection.prepare(
+            "SELECT id, prompt, command, category, outcome, created_at FROM learned_patterns
+             WHERE project = ?1
+             ORDER BY id DESC
+             LIMIT ?2",
+        )?;
+
+        let rows = stmt.query_map(params![project, limit as i64], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, Option<String>>(5)?,
+            ))
+        })?;
+
+        let mut patterns = Vec::new();
+        for row in rows {
+            let (id, prompt, command, category, outcome, created_at) = row?;
+            let Some(outcome) = LearningOutcome::parse(&outcome) else {
+                debug!("Skipping learned pattern with unknown outcome {outcome}");
+                continue;
+            };
+            patterns.push(LearnedPattern {
+                id: Some(id),
+                prompt,
+                command,
+                category,
+                outcome,
+                created_at,
+            });
+        }
+        Ok(patterns)
+    }
+
+    fn record_rejected_suggestions(&mut self, prompt: &str, commands: &[&str]) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        for command in commands {
+            transaction.execute(
+                "INSERT INTO rejected_suggestions (prompt, command, project) VALUES (?1, ?2, ?3)",
+                params![prompt, command, self.project],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn get_rejected_suggestions(&self, limit: usize) -> Result<Vec<(String, String)>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT prompt, command FROM rejected_suggestions
+             WHERE project IN (?1, '')
+               AND command NOT IN (SELECT command FROM history WHERE success = TRUE)
+             GROUP BY prompt, command
+             ORDER BY MAX(rejected_at) DESC, MAX(id) DESC
+             LIMIT ?2",
+        )?;
 
-        // Run migrations for existing databases
-        Self::migrate_database(&connection)?;
+        let rejected = stmt
+            .query_map(params![self.project, limit as i64], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
 
-        Ok(Self { connection })
+        Ok(rejected)
     }
 
-    fn migrate_database(connection: &Connection) -> Result<()> {
-        // Check if we need to add new columns to existing suggestions table
-        let mut stmt = connection.prepare("PRAGMA table_info(suggestions)")?;
-        let rows = stmt.query_map([], |row| {
-            row.get::<_, String>(1) // column name
-        })?;
-
-        let mut has_success_count = false;
-        let mut has_success_rate = false;
+    fn record_correction(&mut self, prompt: &str, original: &str, corrected: &str) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO corrections (prompt, original_command, corrected_command, project)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![prompt, original, corrected, self.project],
+        )?;
+        Ok(())
+    }
 
-        for row in rows {
-            match row? {
-                name if name == "success_count" => has_success_count = true,
-                name if name == "success_rate" => has_success_rate = true,
-                _ => {}
-            }
-        }
+    fn get_corrections(&self, limit: usize) -> Result<Vec<(String, String, String)>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT prompt, original_command, corrected_command FROM corrections
+             WHERE project IN (?1, '')
+             ORDER BY id DESC
+             LIMIT ?2",
+        )?;
 
-        // Add missing columns
-        if !has_success_count {
-            connection.execute(
-                "ALTER TABLE suggestions ADD COLUMN success_count INTEGER DEFAULT 0",
-                [],
-            )?;
-        }
-        if !has_success_rate {
-            connection.execute(
-                "ALTER TABLE suggestions ADD COLUMN success_rate REAL DEFAULT 0.5",
-                [],
-            )?;
-        }
+        let corrections = stmt
+            .query_map(params![self.project, limit as i64], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
 
-        Ok(())
+        Ok(corrections)
     }
 
-    pub fn get_suggestion(&self, prompt: &str) -> Result<Option<Suggestion>> {
-        let prompt_hash = self.hash_prompt(prompt);
+    fn save_favorite(&mut self, name: &str, command: &str, prompt: &str) -> Result<bool> {
+        let existed = self.favorite_exists(name)?;
+        self.connection.execute(
+            "INSERT INTO favorites (name, command, prompt, prompt_hash) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(name) DO UPDATE SET command = ?2, prompt = ?3, prompt_hash = ?4,
+                 created_at = CURRENT_TIMESTAMP",
+            params![name, command, prompt, self.hash_prompt(prompt)],
+        )?;
+        Ok(existed)
+    }
 
+    fn get_favorite(&self, name: &str) -> Result<Option<Favorite>> {
         let mut stmt = self.connection.prepare(
-            "SELECT suggestion, explanation, confidence, use_count, success_rate FROM suggestions 
-             WHERE prompt_hash = ?1 
-             AND created_at > datetime('now', '-7 days')
-             AND use_count >= 5
-             AND success_rate > 0.7
-             ORDER BY (success_rate * 0.6 + confidence * 0.4) DESC 
-             LIMIT 1",
+            "SELECT name, command, prompt, use_count, created_at, last_used FROM favorites
+             WHERE name = ?1",
         )?;
+        let mut favorites = stmt
+            .query_map([name], favorite)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(favorites.pop())
+    }
 
-        let result = stmt.query_row([prompt_hash.clone()], |row| {
-            Ok(Suggestion {
-                command: row.get(0)?,
-                explanation: row.get(1)?,
-                confidence: row.get(2)?,
-            })
-        });
+    fn list_favorites(&self) -> Result<Vec<Favorite>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT name, command, prompt, use_count, created_at, last_used FROM favorites
+             ORDER BY use_count DESC, name",
+        )?;
+        let favorites = stmt
+            .query_map([], favorite)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(favorites)
+    }
 
-        match result {
-            Ok(suggestion) => {
-                // Update last_used timestamp and use_count
-                self.update_suggestion_usage(&prompt_hash)?;
-                Ok(Some(suggestion))
+    fn get_matching_favorites(
+        &self,
+        prompt: &str,
+        embedding: Option<&PromptEmbedding>,
+    ) -> Result<Vec<Favorite>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT name, command, prompt, use_count, created_at, last_used FROM favorites
+             WHERE prompt_hash = ?1
+             ORDER BY use_count DESC",
+        )?;
+        let mut favorites = Vec::new();
+        for prompt_hash in self.candidate_prompt_hashes(prompt, embedding)? {
+            for favorite in stmt.query_map([prompt_hash], favorite)? {
+                favorites.push(favorite?);
             }
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
         }
+        Ok(favorites)
     }
 
-    pub fn cache_suggestion(&mut self, prompt: &str, suggestion: &Suggestion) -> Result<()> {
-        let prompt_hash = self.hash_prompt(prompt);
+    fn record_favorite_use(&mut self, name: &str) -> Result<()> {
+        self.connection.execute(
+            "UPDATE favorites SET use_count = use_count + 1, last_used = datetime('now')
+             WHERE name = ?1",
+            [name],
+        )?;
+        Ok(())
+    }
 
-        // Check if this suggestion already exists
-        let existing = self.connection.query_row(
-            "SELECT id, use_count, success_count FROM suggestions WHERE prompt_hash = ?1 AND suggestion = ?2",
-            params![prompt_hash, suggestion.command],
-            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?))
-        );
+    fn favorite_exists(&self, name: &str) -> Result<bool> {
+        Ok(self.connection.query_row(
+            "SELECT EXISTS(SELECT 1 FROM favorites WHERE name = ?1)",
+            [name],
+            |row| row.get(0),
+        )?)
+    }
 
-        match existing {
-            Ok((id, use_count, success_count)) => {
-                // Update existing suggestion
-                let success_rate = if use_count > 0 {
-                    success_count as f32 / use_count as f32
-                } else {
-                    0.5
-                };
+    fn delete_favorite(&mut self, name: &str) -> Result<bool> {
+        let deleted = self
+            .connection
+            .execute("DELETE FROM favorites WHERE name = ?1", [name])?;
+        Ok(deleted > 0)
+    }
 
-                self.connection.execute(
-                    "UPDATE suggestions SET last_used = datetime('now'), confidence = ?1, success_rate = ?2 WHERE id = ?3",
-                    params![suggestion.confidence, success_rate, id],
-                )?;
-            }
-            Err(_) => {
-                // Insert new suggestion with conservative defaults
-                self.connection.execute(
-                    "INSERT INTO suggestions 
-                     (prompt_hash, prompt, suggestion, explanation, confidence, created_at, last_used, use_count, success_count, success_rate) 
-                     VALUES (?, ?, ?, ?, ?, datetime('now'), datetime('now'), 0, 0, 0.5)",
-                    params![
-                        prompt_hash,
-                        prompt,
-                        suggestion.command,
-                        suggestion.explanation,
-                        suggestion.confidence,
-                    ],
-                )?;
-            }
+    fn rename_favorite(&mut self, name: &str, new_name: &str) -> Result<bool> {
+        if self.favorite_exists(new_name)? {
+            return Err(anyhow::anyhow!("A command is already saved as {new_name}"));
         }
+        let renamed = self.connection.execute(
+            "UPDATE favorites SET name = ?2 WHERE name = ?1",
+            params![name, new_name],
+        )?;
+        Ok(renamed > 0)
+    }
+
+    fn record_workflow(&mut self, prompt: &str, steps: &[Suggestion]) -> Result<()> {
+        let steps: Vec<WorkflowStep> = steps
+            .iter()
+            .map(|step| WorkflowStep {
+                command: step.command.clone(),
+                explanation: step.explanation.clone(),
+            })
+            .collect();
+        let steps = serde_json::to_string(&steps)?;
+        self.connection.execute(
+            "INSERT INTO workflows (prompt, prompt_hash, steps, project) VALUES (?1, ?2, ?3, ?4)",
+            params![prompt, self.hash_prompt(prompt), steps, self.project],
+        )?;
+        Ok(())
+    }
+
+    fn get_workflow(&self, prompt: &str) -> Result<Option<Vec<Suggestion>>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT steps FROM workflows
+             WHERE prompt_hash = ?1 AND project IN (?2, '')
+             ORDER BY project = ?2 DESC, id DESC
+             LIMIT 1",
+        )?;
+        let mut rows = stmt.query_map(params![self.hash_prompt(prompt), self.project], |row| {
+            row.get::<_, String>(0)
+        })?;
+        let Some(steps) = rows.next().transpose()? else {
+            return Ok(None);
+        };
+
+        let steps: Vec<WorkflowStep> = serde_json::from_str(&steps)?;
+        Ok(Some(
+            steps
+                .into_iter()
+                .map(|step| Suggestion {
+                    placeholders: Placeholder::find(&step.command),
+                    warnings: Vec::new(),
+                    command: step.command,
+                    explanation: step.explanation,
+                    confidence: 1.0,
+                    backend: None,
+                    source: SuggestionSource::Cache,
+                })
+                .collect(),
+        ))
+    }
+
+    fn delete_learned_pattern(&mut self, id: i64) -> Result<bool> {
+        let deleted = self
+            .connection
+            .execute("DELETE FROM learned_patterns WHERE id = ?1", [id])?;
+        Ok(deleted > 0)
+    }
 
+    fn clear_learned_patterns(&mut self) -> Result<()> {
+        self.connection
+            .execute("DELETE FROM learned_patterns", [])?;
         Ok(())
     }
 
-    pub fn record_command_execution(
+    fn record_command_execution(
         &mut self,
         command: &str,
         prompt: &str,
         success: bool,
         exit_code: Option<i32>,
+        output: Option<(&str, &str)>,
     ) -> Result<()> {
         let context_snapshot = self.get_current_environment_snapshot()?;
+        let (stdout, stderr) = output.unzip();
 
         self.connection.execute(
-            "INSERT INTO history (command, prompt, success, exit_code, context_snapshot) 
-             VALUES (?, ?, ?, ?, ?)",
-            params![command, prompt, success, exit_code, context_snapshot,],
+            "INSERT INTO history (command, prompt, success, exit_code, context_snapshot, stdout, stderr)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            params![command, prompt, success, exit_code, context_snapshot, stdout, stderr],
         )?;
 
         Ok(())
     }
 
-    pub fn get_recent_commands(&self, limit: usize) -> Result<Vec<String>> {
+    fn get_history(&self, limit: Option<usize>) -> Result<Vec<HistoryEntry>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT command, prompt, success, exit_code, executed_at, stdout, stderr FROM history
+             ORDER BY executed_at DESC, id DESC
+             LIMIT ?1",
+        )?;
+
+        let entries = stmt
+            .query_map([limit.map_or(-1, |limit| limit as i64)], history_entry)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(entries)
+    }
+
+    fn get_history_since(&self, days: u32) -> Result<Vec<HistoryEntry>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT command, prompt, success, exit_code, executed_at, stdout, stderr FROM history
+             WHERE executed_at > datetime('now', '-' || ?1 || ' days')
+             ORDER BY executed_at DESC, id DESC",
+        )?;
+
+        let entries = stmt
+            .query_map([days], history_entry)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(entries)
+    }
+
+    fn search_history(&self, term: &str, limit: usize) -> Result<Vec<HistoryEntry>> {
+        let Some(query) = fts_query(term, " AND ") else {
+            return Ok(Vec::new());
+        };
+
+        let mut stmt = self.connection.prepare(
+            "SELECT h.command, h.prompt, h.success, h.exit_code, h.executed_at, h.stdout, h.stderr
+             FROM history_fts JOIN history h ON h.id = history_fts.rowid
+             WHERE history_fts MATCH ?1
+             ORDER BY history_fts.rank, h.executed_at DESC
+             LIMIT ?2",
+        )?;
+
+        let entries = stmt
+            .query_map(params![query, limit as i64], history_entry)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(entries)
+    }
+
+    fn get_relevant_commands(&self, prompt: &str, limit: usize) -> Result<Vec<String>> {
+        let Some(query) = fts_query(prompt, " OR ") else {
+            return Ok(Vec::new());
+        };
+
+        let mut stmt = self.connection.prepare(
+            "SELECT h.command FROM history_fts JOIN history h ON h.id = history_fts.rowid
+             WHERE history_fts MATCH ?1 AND h.success = TRUE
+             GROUP BY h.command
+             ORDER BY MIN(history_fts.rank)
+             LIMIT ?2",
+        )?;
+
+        let commands = stmt
+            .query_map(params![query, limit as i64], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+
+        Ok(commands)
+    }
+
+    fn search_suggestions(&self, text: &str, limit: usize) -> Result<Vec<(String, Suggestion)>> {
+        let Some(query) = fts_query(text, " OR ") else {
+            return Ok(Vec::new());
+        };
+
+        let mut stmt = self.connection.prepare(
+            "SELECT s.prompt, s.suggestion, s.explanation, s.confidence, s.backend
+             FROM suggestions_fts JOIN suggestions s ON s.id = suggestions_fts.rowid
+             WHERE suggestions_fts MATCH ?1
+             ORDER BY suggestions_fts.rank, s.success_rate DESC
+             LIMIT ?2",
+        )?;
+
+        let suggestions = stmt
+            .query_map(params![query, limit as i64], |row| {
+                let command: String = row.get(1)?;
+                Ok((
+                    row.get(0)?,
+                    Suggestion {
+                        placeholders: Placeholder::find(&command),
+                        warnings: Vec::new(),
+                        command,
+                        explanation: row.get(2)?,
+                        confidence: row.get(3)?,
+                        backend: row.get(4)?,
+                        source: SuggestionSource::Cache,
+                    },
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(suggestions)
+    }
+
+    fn get_recent_commands(&self, limit: usize) -> Result<Vec<String>> {
         let mut stmt = self.connection.prepare(
             "SELECT command FROM history 
              WHERE success = TRUE 
@@ -171,22 +1603,91 @@ impl CacheManager {
         Ok(commands)
     }
 
-    pub fn update_environment(&mut self, key: &str, value: &str) -> Result<()> {
-        self.connection.execute(
-            "INSERT OR REPLACE INTO environment (key, value, updated_at) 
-             VALUES (?, ?, datetime('now'))",
-            params![key, value],
+    fn get_successful_examples(&self, limit: usize) -> Result<Vec<(String, String)>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT prompt, command FROM (
+                 SELECT prompt, command, executed_at AS used_at FROM history
+                 WHERE success = TRUE AND prompt != ''
+                 UNION ALL
+                 SELECT prompt, suggestion AS command, last_used AS used_at FROM suggestions
+                 WHERE success_count > 0 AND success_rate >= 0.5
+             )
+             ORDER BY used_at DESC
+             LIMIT ?1",
         )?;
 
+        let rows = stmt.query_map([limit], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut examples = Vec::new();
+        for row in rows {
+            examples.push(row?);
+        }
+
+        Ok(examples)
+    }
+
+    fn replace_environment(
+        &mut self,
+        environment: &std::collections::HashMap<String, String>,
+    ) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute("DELETE FROM environment WHERE host = ?1", [&self.host])?;
+        for (key, value) in environment {
+            transaction.execute(
+                "INSERT INTO environment (host, key, value, updated_at)
+                 VALUES (?1, ?2, ?3, datetime('now'))",
+                params![self.host, key, value],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn environment_age_minutes(&self) -> Result<Option<f64>> {
+        Ok(self.connection.query_row(
+            "SELECT (julianday('now') - julianday(MIN(updated_at))) * 1440 FROM environment
+             WHERE host = ?1",
+            [&self.host],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn get_executables(&self, path: &str, max_age_minutes: u32) -> Result<Option<Vec<String>>> {
+        let names: Option<String> = self
+            .connection
+            .query_row(
+                "SELECT names FROM executables
+                 WHERE host = ?1 AND path = ?2
+                   AND (julianday('now') - julianday(scanned_at)) * 1440 < ?3",
+                params![self.host, path, max_age_minutes],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(names.map(|names| names.lines().map(str::to_string).collect()))
+    }
+
+    fn replace_executables<'a>(
+        &mut self,
+        path: &str,
+        names: impl Iterator<Item = &'a str>,
+    ) -> Result<()> {
+        let names: Vec<&str> = names.collect();
+        self.connection.execute(
+            "INSERT OR REPLACE INTO executables (host, path, names, scanned_at)
+             VALUES (?1, ?2, ?3, datetime('now'))",
+            params![self.host, path, names.join("\n")],
+        )?;
         Ok(())
     }
 
-    pub fn get_environment(&self) -> Result<std::collections::HashMap<String, String>> {
+    fn get_environment(&self) -> Result<std::collections::HashMap<String, String>> {
         let mut stmt = self
             .connection
-            .prepare("SELECT key, value FROM environment")?;
+            .prepare("SELECT key, value FROM environment WHERE host = ?1")?;
 
-        let rows = stmt.query_map([], |row| {
+        let rows = stmt.query_map([&self.host], |row| {
             Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
         })?;
 
@@ -199,13 +1700,19 @@ impl CacheManager {
         Ok(env)
     }
 
-    pub fn clear_cache(&mut self) -> Result<()> {
+    fn clear_cache(&mut self) -> Result<()> {
         self.connection.execute("DELETE FROM suggestions", [])?;
         self.connection.execute("DELETE FROM history", [])?;
+        self.connection
+            .execute("DELETE FROM prompt_embeddings", [])?;
+        self.connection
+            .execute("DELETE FROM rejected_suggestions", [])?;
+        self.connection.execute("DELETE FROM corrections", [])?;
+        self.connection.execute("DELETE FROM prompts", [])?;
         Ok(())
     }
 
-    pub fn get_cache_stats(&self) -> Result<String> {
+    fn get_cache_stats(&self) -> Result<String> {
         let mut stats = String::new();
 
         // Total suggestions
@@ -214,15 +1721,29 @@ impl CacheManager {
                 .query_row("SELECT COUNT(*) FROM suggestions", [], |row| row.get(0))?;
 
         // Cached suggestions (ready for reuse)
-        let cached: i64 = self.connection.query_row(
-            "SELECT COUNT(*) FROM suggestions WHERE use_count >= 5 AND success_rate > 0.7",
-            [],
-            |row| row.get(0),
+        let mut stmt = self.connection.prepare(
+            "SELECT use_count, success_rate,
+                    (julianday('now') - julianday(COALESCE(last_used, created_at))) * 24
+             FROM suggestions
+             WHERE success_count > 0
+             AND created_at > datetime('now', '-' || ?1 || ' hours')",
         )?;
+        let mut cached: i64 = 0;
+        let mut rows = stmt.query([self.ttl_hours])?;
+        while let Some(row) = rows.next()? {
+            let score = self.frecency_score(
+                row.get(0)?,
+                row.get(1)?,
+                row.get::<_, Option<f64>>(2)?.unwrap_or(0.0),
+            );
+            if score >= self.frecency.reuse_threshold {
+                cached += 1;
+            }
+        }
 
         // Success rate stats
         let (avg_success_rate, high_success): (f64, i64) = self.connection.query_row(
-            "SELECT AVG(success_rate), COUNT(*) FROM suggestions WHERE success_rate > 0.8",
+            "SELECT COALESCE(AVG(success_rate), 0), COUNT(*) FROM suggestions WHERE success_rate > 0.8",
             [],
             |row| Ok((row.get(0)?, row.get(1)?)),
         )?;
@@ -244,23 +1765,59 @@ impl CacheManager {
         ));
         stats.push_str(&format!("- High success (>80%): {high_success}\n"));
 
+        // Malformed model replies
+        let (replies, repaired, fallback): (i64, i64, i64) = self.connection.query_row(
+            "SELECT COUNT(*),
+                    COALESCE(SUM(outcome = 'repaired'), 0),
+                    COALESCE(SUM(outcome = 'fallback'), 0)
+             FROM generations",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+
+        stats.push_str(&format!(
+            "- Malformed replies: {} of {} ({:.1}%), {} repaired, {} fell back to text parsing\n",
+            repaired + fallback,
+            replies,
+            if replies > 0 {
+                (repaired + fallback) as f64 / replies as f64 * 100.0
+            } else {
+                0.0
+            },
+            repaired,
+            fallback
+        ));
+
         Ok(stats)
     }
 
-    pub fn prune_old_data(&mut self, days: i32) -> Result<()> {
+    fn prune_old_data(&mut self, days: u32) -> Result<usize> {
         // Remove old suggestions
-        self.connection.execute(
+        let pruned = self.connection.execute(
             "DELETE FROM suggestions WHERE created_at < datetime('now', '-' || ?1 || ' days')",
             [days],
         )?;
+        self.remove_orphan_embeddings()?;
 
         // Remove old history
         self.connection.execute(
             "DELETE FROM history WHERE executed_at < datetime('now', '-' || ?1 || ' days')",
             [days],
         )?;
+        self.connection.execute(
+            "DELETE FROM rejected_suggestions WHERE rejected_at < datetime('now', '-' || ?1 || ' days')",
+            [days],
+        )?;
+        self.connection.execute(
+            "DELETE FROM corrections WHERE created_at < datetime('now', '-' || ?1 || ' days')",
+            [days],
+        )?;
+        self.connection.execute(
+            "DELETE FROM prompts WHERE created_at < datetime('now', '-' || ?1 || ' days')",
+            [days],
+        )?;
 
-        Ok(())
+        Ok(pruned)
     }
 
     fn hash_prompt(&self, prompt: &str) -> String {
@@ -273,14 +1830,25 @@ impl CacheManager {
         self.connection.execute(
             "UPDATE suggestions 
              SET last_used = datetime('now'), use_count = use_count + 1 
-             WHERE prompt_hash = ?1",
-            [prompt_hash],
+             WHERE prompt_hash = ?1 AND project IN (?2, '')",
+            params![prompt_hash, self.project],
         )?;
 
         Ok(())
     }
 
-    pub fn record_suggestion_usage(
+    fn get_command_success_rate(&self, command: &str) -> Result<Option<f32>> {
+        let (use_count, success_count): (i64, i64) = self.connection.query_row(
+            "SELECT COALESCE(SUM(use_count), 0), COALESCE(SUM(success_count), 0)
+             FROM suggestions WHERE suggestion = ?1",
+            [command],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        Ok((use_count > 0).then(|| success_count as f32 / use_count as f32))
+    }
+
+    fn record_suggestion_usage(
         &mut self,
         prompt: &str,
         command: &str,
@@ -295,51 +1863,38 @@ impl CacheManager {
                  success_count = success_count + CASE WHEN ?3 THEN 1 ELSE 0 END,
                  success_rate = CAST(success_count + CASE WHEN ?3 THEN 1 ELSE 0 END AS FLOAT) / (use_count + 1),
                  last_used = datetime('now')
-             WHERE prompt_hash = ?1 AND suggestion = ?2"
+             WHERE prompt_hash = ?1 AND suggestion = ?2
+             AND project = (
+                 SELECT project FROM suggestions
+                 WHERE prompt_hash = ?1 AND suggestion = ?2 AND project IN (?4, '')
+                 ORDER BY project = ?4 DESC
+                 LIMIT 1
+             )"
         )?;
 
-        stmt.execute(params![prompt_hash, command, success])?;
+        stmt.execute(params![prompt_hash, command, success, self.project])?;
         Ok(())
     }
 
-    pub fn get_shell_history(&self) -> Result<Vec<String>> {
-        let home = std::env::var("HOME")?;
-        let shell = std::env::var("SHELL").unwrap_or_default();
+    fn get_shell_history(&self) -> Result<Vec<String>> {
+        let home = dirs::home_dir().context("Could not find home directory")?;
+        let shell_name = ShellDetector::detect_shell();
+        let shell_name = shell_name.trim_end_matches(".exe");
 
-        let history_file = if shell.contains("zsh") {
-            format!("{home}/.zsh_history")
-        } else if shell.contains("bash") {
-            format!("{home}/.bash_history")
+        let mut commands = if shell_name.contains("zsh") {
+            read_line_history(&home.join(".zsh_history"))?
+        } else if shell_name.contains("bash") {
+            read_line_history(&home.join(".bash_history"))?
+        } else if shell_name.contains("fish") {
+            read_fish_history(&fish_history_path(&home))?
+        } else if shell_name == "nu" {
+            read_nushell_history(&nushell_config_dir(&home))?
+        } else if matches!(shell_name, "pwsh" | "powershell") {
+            read_powershell_history(&powershell_history_path(&home))?
         } else {
             return Ok(Vec::new());
         };
 
-        let history_path = std::path::Path::new(&history_file);
-        if !history_path.exists() {
-            return Ok(Vec::new());
-        }
-
-        let content = std::fs::read_to_string(history_path)?;
-        let mut commands: Vec<String> = content
-            .lines()
-            .filter_map(|line| {
-                let line = line.trim();
-                // Skip empty lines and comments
-                if line.is_empty() || line.starts_with('#') {
-                    return None;
-                }
-
-                // Handle zsh history format (: timestamp:duration;command)
-                if line.starts_with(':') {
-                    if let Some(semicolon_pos) = line.find(';') {
-                        return Some(line[semicolon_pos + 1..].to_string());
-                    }
-                }
-
-                Some(line.to_string())
-            })
-            .collect();
-
         // Get last 100 commands and reverse to get most recent first
         commands.reverse();
         commands.truncate(100);
@@ -352,3 +1907,230 @@ impl CacheManager {
         Ok(serde_json::to_string(&env)?)
     }
 }
+
+fn encode_embedding(vector: &[f32]) -> Vec<u8> {
+    vector
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn decode_embedding(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect()
+}
+
+/// Cosine similarity of two vectors; 0 when their dimensions differ
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+/// Reads zsh and bash history, oldest first
+fn read_line_history(path: &Path) -> Result<Vec<String>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = std::fs::read_to_string(path)?;
+    Ok(content
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            // Skip empty lines and comments
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+
+            // Handle zsh history format (: timestamp:duration;command)
+            if line.starts_with(':') {
+                if let Some(semicolon_pos) = line.find(';') {
+                    return Some(line[semicolon_pos + 1..].to_string());
+                }
+            }
+
+            Some(line.to_string())
+        })
+        .collect())
+}
+
+/// Where PSReadLine keeps the history of the PowerShell console
+fn powershell_history_path(home: &Path) -> PathBuf {
+    let data_dir = if cfg!(windows) {
+        dirs::data_dir()
+            .unwrap_or_else(|| home.join("AppData/Roaming"))
+            .join("Microsoft/Windows/PowerShell")
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| home.join(".local/share"))
+            .join("powershell")
+    };
+    data_dir.join("PSReadLine/ConsoleHost_history.txt")
+}
+
+/// Reads PSReadLine's history, oldest first; a line ending in a backtick
+/// continues on the next one
+fn read_powershell_history(path: &Path) -> Result<Vec<String>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = std::fs::read_to_string(path)?;
+    let mut commands = Vec::new();
+    let mut command = String::new();
+    for line in content.lines() {
+        match line.strip_suffix('`') {
+            Some(continued) => {
+                command.push_str(continued);
+                command.push('\n');
+            }
+            None => {
+                command.push_str(line);
+                if !command.trim().is_empty() {
+                    commands.push(command.trim().to_string());
+                }
+                command.clear();
+            }
+        }
+    }
+    Ok(commands)
+}
+
+fn fish_history_path(home: &Path) -> PathBuf {
+    std::env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.join(".local/share"))
+        .join("fish/fish_history")
+}
+
+/// Reads fish's YAML-like history, oldest first:
+///   - cmd: git status
+///     when: 1700000000
+fn read_fish_history(path: &Path) -> Result<Vec<String>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = std::fs::read_to_string(path)?;
+    Ok(content
+        .lines()
+        .filter_map(|line| line.strip_prefix("- cmd: "))
+        .map(unescape_fish_command)
+        .filter(|command| !command.trim().is_empty())
+        .collect())
+}
+
+/// Fish stores newlines as `\n` and backslashes as `\\`
+fn unescape_fish_command(escaped: &str) -> String {
+    let mut command = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            command.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => command.push('\n'),
+            Some('\\') => command.push('\\'),
+            Some(other) => {
+                command.push('\\');
+                command.push(other);
+            }
+            None => command.push('\\'),
+        }
+    }
+    command
+}
+
+fn nushell_config_dir(home: &Path) -> PathBuf {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(dirs::config_dir)
+        .unwrap_or_else(|| home.join(".config"))
+        .join("nushell")
+}
+
+/// Reads nushell history, oldest first, from whichever of its SQLite and
+/// plain text formats is configured
+fn read_nushell_history(config_dir: &Path) -> Result<Vec<String>> {
+    let sqlite_path = config_dir.join("history.sqlite3");
+    if sqlite_path.exists() {
+        let connection = Connection::open_with_flags(
+            &sqlite_path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        connection.busy_timeout(Duration::from_millis(BUSY_TIMEOUT_MS))?;
+
+        let mut stmt =
+            connection.prepare("SELECT command_line FROM history ORDER BY id DESC LIMIT 100")?;
+        let mut commands = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        commands.reverse();
+        return Ok(commands);
+    }
+
+    let text_path = config_dir.join("history.txt");
+    if !text_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    // Multi-line commands are stored on one line with `<\n>` separators
+    let content = std::fs::read_to_string(text_path)?;
+    Ok(content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.replace("<\\n>", "\n"))
+        .collect())
+}
+
+fn history_entry(row: &rusqlite::Row) -> rusqlite::Result<HistoryEntry> {
+    Ok(HistoryEntry {
+        command: row.get(0)?,
+        prompt: row.get(1)?,
+        success: row.get(2)?,
+        exit_code: row.get(3)?,
+        executed_at: row.get(4)?,
+        stdout: row.get(5)?,
+        stderr: row.get(6)?,
+    })
+}
+
+fn favorite(row: &rusqlite::Row) -> rusqlite::Result<Favorite> {
+    Ok(Favorite {
+        name: row.get(0)?,
+        command: row.get(1)?,
+        prompt: row.get(2)?,
+        use_count: row.get(3)?,
+        created_at: row.get(4)?,
+        last_used: row.get(5)?,
+    })
+}
+
+/// FTS5 query matching the words of `text` as prefixes, joined by `operator`;
+/// `None` when there is nothing to search for
+fn fts_query(text: &str, operator: &str) -> Option<String> {
+    let terms: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.len() >= 2)
+        .map(|word| format!("\"{}\"*", word.to_lowercase()))
+        .collect();
+
+    (!terms.is_empty()).then(|| terms.join(operator))
+}
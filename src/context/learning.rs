@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Most recent patterns rendered per category
+const PATTERNS_PER_CATEGORY: usize = 20;
+
+/// What happened to a learned command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LearningOutcome {
+    /// The model suggested it
+    Suggested,
+    /// The user ran it and it exited successfully
+    Succeeded,
+    /// The user ran it and it failed
+    Failed,
+    /// A note the user added by hand; the prompt holds its text
+    Note,
+}
+
+impl LearningOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Suggested => "suggested",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+            Self::Note => "note",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "suggested" => Some(Self::Suggested),
+            "succeeded" => Some(Self::Succeeded),
+            "failed" => Some(Self::Failed),
+            "note" => Some(Self::Note),
+            _ => None,
+        }
+    }
+}
+
+/// A learned command pattern, as stored in the learned_patterns table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LearnedPattern {
+    /// Row id; set by the database
+    #[serde(default)]
+    pub id: Option<i64>,
+    pub prompt: String,
+    pub command: String,
+    pub category: String,
+    pub outcome: LearningOutcome,
+    /// When it was learned; set by the database
+    #[serde(default)]
+    pub created_at: Option<String>,
+}
+
+/// Renders patterns, newest first, as the markdown body of PHLOEM.md's learned
+/// section in at most `max_bytes`. Only the latest outcome of each prompt and
+/// command is shown; when over budget the least valuable patterns are left out.
+pub fn render_learned_patterns(patterns: &[LearnedPattern], max_bytes: usize) -> String {
+    // Latest record of each prompt and command, with how often it was recorded
+    let mut latest: Vec<(&LearnedPattern, usize)> = Vec::new();
+    let mut positions: HashMap<(&str, &str), usize> = HashMap::new();
+    for pattern in patterns {
+        let key = (pattern.prompt.as_str(), pattern.command.as_str());
+        match positions.get(&key) {
+            Some(&position) => latest[position].1 += 1,
+            None => {
+                positions.insert(key, latest.len());
+                latest.push((pattern, 1));
+            }
+        }
+    }
+
+    // Most valuable first; the sort is stable, so ties stay newest first
+    let mut ranked: Vec<(usize, &LearnedPattern, usize)> = latest
+        .iter()
+        .enumerate()
+        .map(|(recency, &(pattern, count))| (recency, pattern, count))
+        .collect();
+    ranked.sort_by(|a, b| pattern_value(b.1, b.2).total_cmp(&pattern_value(a.1, a.2)));
+
+    let mut rendered = String::from("## Learned Patterns\n");
+    let mut used = rendered.len();
+    let mut categories: BTreeMap<&str, Vec<(usize, String)>> = BTreeMap::new();
+    for (recency, pattern, _) in ranked {
+        let line = render_pattern(pattern);
+        let entries = categories.get(pattern.category.as_str());
+        if entries.is_some_and(|entries| entries.len() >= PATTERNS_PER_CATEGORY) {
+            continue;
+        }
+
+        let header = if entries.is_none() {
+            pattern.category.len() + 6
+        } else {
+            0
+        };
+        if used + header + line.len() + 1 > max_bytes {
+            continue;
+        }
+        used += header + line.len() + 1;
+        categories
+            .entry(pattern.category.as_str())
+            .or_default()
+            .push((recency, line));
+    }
+
+    for (category, mut entries) in categories {
+        entries.sort_by_key(|(recency, _)| *recency);
+        rendered.push_str(&format!("\n### {category}\n"));
+        for (_, line) in entries {
+            rendered.push_str(&line);
+            rendered.push('\n');
+        }
+    }
+    rendered
+}
+
+/// Picks the learned pattern lines of `content` most relevant to `prompt`, best
+/// first, in at most `max_bytes`
+pub fn select_relevant_patterns(content: &str, prompt: &str, max_bytes: usize) -> String {
+    let prompt_lower = prompt.to_lowercase();
+    let terms: Vec<&str> = prompt_lower
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| term.len() > 2)
+        .collect();
+
+    let mut scored: Vec<(usize, &str)> = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| is_pattern_line(line))
+        .map(|line| {
+            let line_lower = line.to_lowercase();
+            let matches = terms
+                .iter()
+                .filter(|term| line_lower.contains(*term))
+                .count();
+            let endorsed = usize::from(line.starts_with('✓') || line.starts_with('✎'));
+            (matches * 2 + endorsed, line)
+        })
+        .collect();
+    scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+
+    let mut selected = Vec::new();
+    let mut used = 0;
+    for (_, line) in scored {
+        if used + line.len() + 1 > max_bytes {
+            continue;
+        }
+        used += line.len() + 1;
+        selected.push(line);
+    }
+    selected.join("\n")
+}
+
+/// How much a pattern is worth keeping: outcomes that were run count most, and
+/// patterns recorded again and again were reinforced
+fn pattern_value(pattern: &LearnedPattern, count: usize) -> f32 {
+    let outcome = match pattern.outcome {
+        LearningOutcome::Note => 4.0,
+        LearningOutcome::Succeeded => 3.0,
+        LearningOutcome::Suggested => 1.0,
+        LearningOutcome::Failed => 0.5,
+    };
+    outcome * (1.0 + (count as f32).ln())
+}
+
+/// Whether a line of PHLOEM.md is a pattern worth showing the model
+pub fn is_pattern_line(line: &str) -> bool {
+    line.contains('→') || line.contains('✓') || line.trim_start().starts_with('✎')
+}
+
+/// One pattern as a line of markdown
+pub fn render_pattern(pattern: &LearnedPattern) -> String {
+    let prompt = pattern.prompt.replace('\n', " ");
+    let command = pattern.command.replace('\n', " ");
+    match pattern.outcome {
+        LearningOutcome::Succeeded => format!("✓ \"{prompt}\" → `{command}`"),
+        LearningOutcome::Suggested => format!("\"{prompt}\" → `{command}` (suggested)"),
+        // No arrow: the prompt builder treats arrow lines as patterns to follow
+        LearningOutcome::Failed => format!("✗ \"{prompt}\": `{command}` failed"),
+        LearningOutcome::Note => format!("✎ {prompt}"),
+    }
+}
@@ -1,13 +1,26 @@
 use anyhow::Result;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
 use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use crate::cli::Suggestion;
+use crate::ai::ParseOutcome;
+use crate::cli::{CapturedOutput, Suggestion, SuggestionSource};
 use crate::config::Settings;
-use crate::context::{CacheManager, StorageManager};
+use crate::context::learning::select_relevant_patterns;
+use crate::context::{
+    AuditLog, AuditRecord, CacheEntry, CacheExport, CacheManager, ExportedEntry, HistoryEntry,
+    LearnedPattern, LearningOutcome, PromptEmbedding, StorageManager, SuggestionRanker, UsageStats,
+    CACHE_EXPORT_VERSION,
+};
 use crate::utils::environment::EnvironmentDetector;
+use crate::utils::{
+    CommandValidator, ContainerState, DirectoryListing, ExecutableIndex, GitInspector, GitState,
+    KubernetesState, Project, ProjectInfo, SecretRedactor, ShellAlias, ShellDetector, ShellKind,
+};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ContextData {
@@ -15,71 +28,497 @@ pub struct ContextData {
     pub environment: HashMap<String, String>,
     pub recent_commands: Vec<String>,
     pub prompt_category: String,
+    /// Successful prompt→command pairs from the same category, used as few-shot examples
+    #[serde(default)]
+    pub examples: Vec<FewShotExample>,
+    /// Commands the user passed over for similar requests
+    #[serde(default)]
+    pub avoid: Vec<String>,
+    /// Suggestions for similar requests the user corrected before running
+    #[serde(default)]
+    pub corrections: Vec<Correction>,
+    /// The git repository the user is in, if any
+    #[serde(default)]
+    pub git: Option<GitState>,
+    /// Toolchains and tasks of the project the user is in, if any
+    #[serde(default)]
+    pub project: Option<ProjectInfo>,
+    /// What's in the current directory, so requests can name real files
+    #[serde(default)]
+    pub directory: Option<DirectoryListing>,
+    /// Kubernetes context and namespace for Kubernetes requests; filled in by
+    /// the caller, as probing kubectl is async
+    #[serde(default)]
+    pub kubernetes: Option<KubernetesState>,
+    /// Running containers for Docker requests; filled in by the caller, as
+    /// probing the runtime is async
+    #[serde(default)]
+    pub containers: Option<ContainerState>,
+    /// The user's shell aliases, those relevant to the request first
+    #[serde(default)]
+    pub aliases: Vec<ShellAlias>,
+    /// Shell the commands are for when it isn't the user's own; filled in by
+    /// the caller from `--shell`
+    #[serde(default)]
+    pub target_shell: Option<ShellKind>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct FewShotExample {
+    pub prompt: String,
+    pub command: String,
+}
+
+/// A suggested command and the one the user ran in its place
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Correction {
+    pub prompt: String,
+    pub original: String,
+    pub corrected: String,
+}
+
+/// How often an executable was run from phloem
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolUsage {
+    pub tool: String,
+    pub uses: usize,
+    pub successes: usize,
+}
+
+/// Maximum number of few-shot examples included in a prompt
+const MAX_FEW_SHOT_EXAMPLES: usize = 5;
+
+/// Categories prompts are sorted into by `categorize_prompt`
+const PROMPT_CATEGORIES: &[&str] = &[
+    "Docker",
+    "Kubernetes",
+    "Git",
+    "File Management",
+    "Process Management",
+    "General",
+];
+
+/// Learned context included in a prompt, in bytes
+const PROMPT_CONTEXT_BYTES: usize = 2 * 1024;
+
+/// Most recent learned patterns considered for PHLOEM.md
+const RENDERED_LEARNED_PATTERNS: usize = 500;
+
+/// Maximum number of rejected commands the model is told to avoid
+const MAX_AVOIDED_COMMANDS: usize = 5;
+
+/// Maximum number of corrections included in a prompt
+const MAX_CORRECTIONS: usize = 3;
+
+/// Most shell aliases offered to the model per request
+const MAX_PROMPT_ALIASES: usize = 20;
+
+/// Cached suggestions considered when ranking offline matches
+const OFFLINE_CANDIDATES: usize = 500;
+
 pub struct ContextManager {
     pub cache: CacheManager,
     storage: StorageManager,
     env_detector: EnvironmentDetector,
+    redactor: SecretRedactor,
+    /// Size PHLOEM.md is kept within
+    max_context_bytes: usize,
+    /// Whether outcomes are recorded and learned from
+    learning_enabled: bool,
+    /// Leave no trace of this run: nothing is cached, recorded or learned
+    incognito: bool,
+    /// How long the stored environment snapshot is reused
+    environment_ttl_minutes: u32,
+    /// Where suggestions that run are recorded, when enabled
+    audit: Option<AuditLog>,
 }
 
 impl ContextManager {
-    pub fn new(_settings: &Settings) -> Result<Self> {
+    pub async fn new(settings: &Settings) -> Result<Self> {
         let storage = StorageManager::new()?;
         let cache_path = storage
             .get_phloem_dir()
             .join("cache")
             .join("suggestions.db");
-        let cache = CacheManager::new(&cache_path)?;
+        let cache = CacheManager::new(&cache_path, &settings.cache)
+            .await?
+            .with_project(Project::current().map(|project| project.fingerprint()))
+            .with_host(EnvironmentDetector::hostname().unwrap_or_default());
         let env_detector = EnvironmentDetector::new();
+        let redactor = SecretRedactor::new(&settings.privacy.redact_patterns)?;
+        let audit = settings
+            .audit
+            .enabled
+            .then(|| AuditLog::new(&storage.get_phloem_dir().join("audit.jsonl")));
 
-        Ok(Self {
+        let mut manager = Self {
             cache,
             storage,
             env_detector,
-        })
+            redactor,
+            max_context_bytes: settings.general.max_context_size_kb * 1024,
+            learning_enabled: settings.general.learning_enabled,
+            incognito: false,
+            environment_ttl_minutes: settings.cache.environment_ttl_minutes,
+            audit,
+        };
+        manager.install_executable_index().await;
+        Ok(manager)
+    }
+
+    /// Makes the executables in PATH known to every lookup, reusing the list
+    /// this host stored while PATH is unchanged and the list is younger than
+    /// the environment TTL
+    async fn install_executable_index(&mut self) {
+        let path = ExecutableIndex::fingerprint();
+        match self
+            .cache
+            .get_executables(&path, self.environment_ttl_minutes)
+            .await
+        {
+            Ok(Some(names)) => {
+                debug!("Using the stored list of {} executables", names.len());
+                ExecutableIndex::install(ExecutableIndex::from_names(names));
+                return;
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to read the stored list of executables: {e}"),
+        }
+
+        let index = ExecutableIndex::scan();
+        debug!("Found {} executables in PATH", index.len());
+        if let Err(e) = self.cache.replace_executables(&path, index.names()).await {
+            warn!("Failed to store the list of executables: {e}");
+        }
+        ExecutableIndex::install(index);
+    }
+
+    /// Makes `dir` the working directory and scopes the cache and project
+    /// context to the project it belongs to
+    pub fn enter_directory(&mut self, dir: &Path) -> Result<()> {
+        std::env::set_current_dir(dir)?;
+        self.storage = StorageManager::new()?;
+        self.cache
+            .set_project(Project::current().map(|project| project.fingerprint()));
+        Ok(())
+    }
+
+    /// `text` with the secrets in it masked, as it's stored: prompts and
+    /// commands are kept this way in the cache, history and context files
+    pub fn redact(&self, text: &str) -> String {
+        self.redactor.redact(text)
+    }
+
+    /// Stops this run from writing to the cache, history or learned context
+    pub fn set_incognito(&mut self, incognito: bool) {
+        self.incognito = incognito;
+    }
+
+    pub fn is_incognito(&self) -> bool {
+        self.incognito
     }
 
-    pub fn initialize_directory(&mut self) -> Result<()> {
+    pub async fn initialize_directory(&mut self) -> Result<()> {
         info!("Initializing Commandy directory structure");
         self.storage.initialize_directory()?;
 
         // Detect and store initial environment
-        let env_info = self.env_detector.detect_environment()?;
-        self.update_environment_info(&env_info)?;
+        self.refresh_environment().await?;
 
         Ok(())
     }
 
-    pub fn get_cached_suggestion(&self, prompt: &str) -> Result<Option<Suggestion>> {
+    /// Probes the environment again and stores the snapshot
+    pub async fn refresh_environment(&mut self) -> Result<HashMap<String, String>> {
+        let environment: HashMap<String, String> = self
+            .env_detector
+            .detect_environment()?
+            .into_iter()
+            .map(|(key, value)| (key, self.redact(&value)))
+            .collect();
+        self.cache.replace_environment(&environment).await?;
+        Ok(environment)
+    }
+
+    /// Lists the executables in PATH again and stores the list for the next
+    /// runs; this one finds tools installed since in PATH when its list
+    /// misses them. Returns how many there are
+    pub async fn refresh_executables(&mut self) -> Result<usize> {
+        let index = ExecutableIndex::scan();
+        self.cache
+            .replace_executables(&ExecutableIndex::fingerprint(), index.names())
+            .await?;
+        Ok(index.len())
+    }
+
+    /// Refreshes the environment snapshot once it's older than the configured
+    /// TTL, or right away with `force`. Incognito runs use the snapshot as is.
+    pub async fn ensure_environment(&mut self, force: bool) -> Result<()> {
+        if self.incognito {
+            return Ok(());
+        }
+
+        let stale = force
+            || self
+                .cache
+                .environment_age_minutes()
+                .await?
+                .is_none_or(|age| age >= f64::from(self.environment_ttl_minutes));
+        if stale {
+            debug!("Environment snapshot is stale, detecting again");
+            self.refresh_environment().await?;
+        }
+        if force {
+            self.refresh_executables().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn get_cached_suggestion(
+        &self,
+        prompt: &str,
+        embedding: Option<&PromptEmbedding<'_>>,
+    ) -> Result<Option<Suggestion>> {
         debug!("Checking cache for prompt: {prompt}");
-        self.cache.get_suggestion(prompt)
+        self.cache
+            .get_suggestion(&self.redact(prompt), embedding)
+            .await
+    }
+
+    /// Cached entries with their prompt category, optionally only those of `category`
+    pub async fn list_cache_entries(
+        &self,
+        category: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(String, CacheEntry)>> {
+        let entries = self
+            .cache
+            .list_entries(None)
+            .await?
+            .into_iter()
+            .map(|entry| (self.categorize_prompt(&entry.prompt), entry))
+            .filter(|(entry_category, _)| {
+                category.is_none_or(|category| entry_category.eq_ignore_ascii_case(category))
+            })
+            .take(limit)
+            .collect();
+
+        Ok(entries)
+    }
+
+    /// Cached suggestions with their categories, secrets masked, optionally
+    /// only those of `category` or only those that ran successfully
+    pub async fn export_cache(
+        &self,
+        category: Option<&str>,
+        successful_only: bool,
+    ) -> Result<CacheExport> {
+        let redact = |text: &str| self.redactor.redact(text);
+        let entries = self
+            .list_cache_entries(category, usize::MAX)
+            .await?
+            .into_iter()
+            .filter(|(_, entry)| !successful_only || entry.success_count > 0)
+            .map(|(category, entry)| ExportedEntry {
+                category,
+                entry: CacheEntry {
+                    prompt: redact(&entry.prompt),
+                    command: redact(&entry.command),
+                    explanation: entry.explanation.as_deref().map(redact),
+                    ..entry
+                },
+            })
+            .collect();
+
+        Ok(CacheExport {
+            version: CACHE_EXPORT_VERSION,
+            exported_at: chrono::Utc::now().to_rfc3339(),
+            entries,
+        })
     }
 
-    pub fn cache_suggestion(&mut self, prompt: &str, suggestion: &Suggestion) -> Result<()> {
+    /// Executables run from phloem, most used first
+    pub async fn get_top_tools(&self, limit: usize) -> Result<Vec<ToolUsage>> {
+        Ok(top_tools(&self.cache.get_history(None).await?, limit))
+    }
+
+    /// Usage over the last `days` days, with the `limit` most frequent
+    /// categories and executables
+    pub async fn get_usage_stats(&self, days: u32, limit: usize) -> Result<UsageStats> {
+        let mut stats = self.cache.get_usage_stats(days, limit).await?;
+        stats.top_executables = top_tools(&self.cache.get_history_since(days).await?, limit);
+        Ok(stats)
+    }
+
+    /// Remembers the prompt's embedding for semantic cache lookups
+    pub async fn store_prompt_embedding(
+        &mut self,
+        prompt: &str,
+        embedding: &PromptEmbedding<'_>,
+    ) -> Result<()> {
+        if self.incognito {
+            return Ok(());
+        }
+        self.cache
+            .store_embedding(&self.redact(prompt), embedding)
+            .await
+    }
+
+    /// Earlier suggestions for this prompt regardless of their success record
+    /// Cached suggestions for the prompt that worked before, best frecency first
+    pub async fn get_cached_candidates(
+        &self,
+        prompt: &str,
+        embedding: Option<&PromptEmbedding<'_>>,
+    ) -> Result<Vec<Suggestion>> {
+        self.cache
+            .get_cached_candidates(&self.redact(prompt), embedding)
+            .await
+    }
+
+    pub async fn get_previous_suggestions(
+        &self,
+        prompt: &str,
+        limit: usize,
+    ) -> Result<Vec<Suggestion>> {
+        self.cache
+            .get_previous_suggestions(&self.redact(prompt), limit)
+            .await
+    }
+
+    /// Ranks cached suggestions and shell history against the prompt without the model.
+    ///
+    /// Each prompt word is fuzzy matched on its own, since a natural language
+    /// request rarely matches a command as a whole.
+    pub async fn get_offline_suggestions(
+        &self,
+        prompt: &str,
+        limit: usize,
+    ) -> Result<Vec<Suggestion>> {
+        let matcher = SkimMatcherV2::default();
+        let words: Vec<&str> = prompt
+            .split_whitespace()
+            .filter(|word| word.len() >= 3)
+            .collect();
+
+        let score = |candidate: &str| -> i64 {
+            words
+                .iter()
+                .filter_map(|word| matcher.fuzzy_match(candidate, word))
+                .sum()
+        };
+
+        let mut ranked: Vec<(i64, Suggestion)> = Vec::new();
+
+        // Full-text matches first; misspelled prompts still get fuzzy matched over the cache
+        let mut candidates = self
+            .cache
+            .search_suggestions(prompt, OFFLINE_CANDIDATES)
+            .await?;
+        if candidates.is_empty() {
+            candidates = self.cache.get_all_suggestions(OFFLINE_CANDIDATES).await?;
+        }
+
+        for (cached_prompt, suggestion) in candidates {
+            let relevance = score(&format!("{cached_prompt} {}", suggestion.command));
+            if relevance > 0 {
+                // Cached answers were generated for a request, so they outrank raw history
+                ranked.push((relevance * 2, suggestion));
+            }
+        }
+
+        if let Ok(history) = self.cache.get_shell_history().await {
+            for command in history {
+                let relevance = score(&command);
+                if relevance > 0 {
+                    ranked.push((
+                        relevance,
+                        Suggestion {
+                            command,
+                            explanation: Some("From shell history".to_string()),
+                            confidence: 0.3,
+                            backend: None,
+                            source: SuggestionSource::History,
+                            placeholders: Vec::new(),
+                            warnings: Vec::new(),
+                        },
+                    ));
+                }
+            }
+        }
+
+        ranked.sort_by_key(|(relevance, _)| std::cmp::Reverse(*relevance));
+
+        let mut suggestions: Vec<Suggestion> = Vec::new();
+        for (_, suggestion) in ranked {
+            if !suggestions.iter().any(|s| s.command == suggestion.command) {
+                suggestions.push(suggestion);
+            }
+            if suggestions.len() >= limit {
+                break;
+            }
+        }
+
+        debug!("Found {} offline matches", suggestions.len());
+        Ok(suggestions)
+    }
+
+    /// Re-scores generated suggestions against the environment and past outcomes, best first
+    pub async fn rank_suggestions(&self, suggestions: &mut [Suggestion]) {
+        SuggestionRanker::new(&self.cache).rank(suggestions).await;
+    }
+
+    pub async fn cache_suggestion(&mut self, prompt: &str, suggestion: &Suggestion) -> Result<()> {
+        if self.incognito {
+            return Ok(());
+        }
+
+        // Suggestions carrying secrets aren't kept, as they'd come back masked
+        if self.redact(&suggestion.command) != suggestion.command {
+            debug!("Not caching a suggestion that holds a secret");
+            return Ok(());
+        }
+        let prompt = &self.redact(prompt);
+        let suggestion = &Suggestion {
+            explanation: suggestion.explanation.as_deref().map(|e| self.redact(e)),
+            ..suggestion.clone()
+        };
+
         debug!("Caching suggestion for prompt: {prompt}");
-        self.cache.cache_suggestion(prompt, suggestion)?;
+        self.cache.cache_suggestion(prompt, suggestion).await?;
 
         // Also update context learning
-        self.update_context_learning(prompt, suggestion)?;
+        self.learn(prompt, &suggestion.command, LearningOutcome::Suggested)
+            .await?;
 
         Ok(())
     }
 
-    pub fn get_relevant_context(&self, prompt: &str) -> Result<ContextData> {
+    pub async fn get_relevant_context(&self, prompt: &str) -> Result<ContextData> {
         debug!("Loading relevant context for prompt: {prompt}");
 
-        // Read context file
-        let context_content = self.storage.read_context_file()?;
+        // Learned patterns most relevant to this request, within the prompt's budget
+        let context_content = select_relevant_patterns(
+            &self.storage.read_context_file()?,
+            prompt,
+            PROMPT_CONTEXT_BYTES,
+        );
 
-        // Get environment information
-        let environment = self.cache.get_environment()?;
+        // The stored snapshot, with the values that change between runs read now
+        let mut environment = self.cache.get_environment().await?;
+        environment.extend(self.env_detector.detect_session());
 
         // Get recent successful commands from commandy history
-        let mut recent_commands = self.cache.get_recent_commands(10)?;
+        let mut recent_commands = self.cache.get_recent_commands(10).await?;
+
+        // Past commands matching the request, wherever they are in the history
+        match self.cache.get_relevant_commands(prompt, 5).await {
+            Ok(relevant) => recent_commands.extend(relevant),
+            Err(e) => warn!("Failed to search command history: {e}"),
+        }
 
         // Integrate shell history for richer context
-        if let Ok(shell_history) = self.cache.get_shell_history() {
+        if let Ok(shell_history) = self.cache.get_shell_history().await {
             // Add relevant shell commands to context
             let relevant_shell_commands: Vec<String> = shell_history
                 .into_iter()
@@ -96,56 +535,402 @@ impl ContextManager {
         // Categorize the prompt
         let prompt_category = self.categorize_prompt(prompt);
 
+        // Pull real successful examples from the same category
+        let examples = match self.get_few_shot_examples(prompt, &prompt_category).await {
+            Ok(examples) => examples,
+            Err(e) => {
+                warn!("Failed to load few-shot examples: {e}");
+                Vec::new()
+            }
+        };
+
+        // Commands the user keeps passing over for requests like this one
+        let avoid = match self.get_avoided_commands(prompt, &prompt_category).await {
+            Ok(avoid) => avoid,
+            Err(e) => {
+                warn!("Failed to load rejected suggestions: {e}");
+                Vec::new()
+            }
+        };
+
+        // Suggestions the user fixed for requests like this one
+        let corrections = match self
+            .get_relevant_corrections(prompt, &prompt_category)
+            .await
+        {
+            Ok(corrections) => corrections,
+            Err(e) => {
+                warn!("Failed to load corrections: {e}");
+                Vec::new()
+            }
+        };
+
+        // Mask secrets before any of this can reach the model
+        let redact = |text: &str| self.redactor.redact(text);
         Ok(ContextData {
-            content: context_content,
-            environment,
-            recent_commands,
+            content: redact(&context_content),
+            environment: environment
+                .into_iter()
+                .map(|(key, value)| (key, redact(&value)))
+                .collect(),
+            recent_commands: recent_commands.iter().map(|cmd| redact(cmd)).collect(),
             prompt_category,
+            examples: examples
+                .into_iter()
+                .map(|example| FewShotExample {
+                    prompt: redact(&example.prompt),
+                    command: redact(&example.command),
+                })
+                .collect(),
+            avoid: avoid.iter().map(|cmd| redact(cmd)).collect(),
+            corrections: corrections
+                .into_iter()
+                .map(|correction| Correction {
+                    prompt: redact(&correction.prompt),
+                    original: redact(&correction.original),
+                    corrected: redact(&correction.corrected),
+                })
+                .collect(),
+            git: GitInspector::repository_state(),
+            project: ProjectInfo::current(),
+            directory: DirectoryListing::current().map(|listing| DirectoryListing {
+                entries: listing.entries.iter().map(|entry| redact(entry)).collect(),
+                ..listing
+            }),
+            kubernetes: None,
+            containers: None,
+            aliases: self
+                .select_aliases(prompt)
+                .into_iter()
+                .map(|alias| ShellAlias {
+                    name: alias.name.clone(),
+                    expansion: redact(&alias.expansion),
+                })
+                .collect(),
+            target_shell: None,
         })
     }
 
-    pub fn record_command_execution(
+    /// The user's aliases worth offering for `prompt`: those expanding to
+    /// something the request mentions first, then the rest in definition order
+    fn select_aliases(&self, prompt: &str) -> Vec<&'static ShellAlias> {
+        let prompt_lower = prompt.to_lowercase();
+        let words: Vec<&str> = prompt_lower
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| word.len() > 2)
+            .collect();
+
+        let mut aliases: Vec<&ShellAlias> = ShellDetector::user_aliases().iter().collect();
+        aliases.sort_by_key(|alias| {
+            let expansion = alias.expansion.to_lowercase();
+            !words.iter().any(|word| expansion.contains(word))
+        });
+        aliases.truncate(MAX_PROMPT_ALIASES);
+        aliases
+    }
+
+    /// Corrections made for the same prompt, or for prompts in the same category sharing a word
+    async fn get_relevant_corrections(
+        &self,
+        prompt: &str,
+        category: &str,
+    ) -> Result<Vec<Correction>> {
+        let prompt_lower = prompt.trim().to_lowercase();
+        let words: Vec<&str> = prompt_lower
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| word.len() > 2)
+            .collect();
+
+        let mut corrections: Vec<Correction> = Vec::new();
+        for (corrected_prompt, original, corrected) in self.cache.get_corrections(200).await? {
+            let corrected_lower = corrected_prompt.trim().to_lowercase();
+            let similar = corrected_lower == prompt_lower
+                || (self.categorize_prompt(&corrected_prompt) == category
+                    && words.iter().any(|word| corrected_lower.contains(word)));
+
+            let correction = Correction {
+                prompt: corrected_prompt,
+                original,
+                corrected,
+            };
+            if similar && !corrections.contains(&correction) {
+                corrections.push(correction);
+            }
+            if corrections.len() >= MAX_CORRECTIONS {
+                break;
+            }
+        }
+
+        Ok(corrections)
+    }
+
+    /// Remembers that the user ran `corrected` in place of the suggested `original`
+    pub async fn record_correction(
+        &mut self,
+        prompt: &str,
+        original: &str,
+        corrected: &str,
+    ) -> Result<()> {
+        if !self.records_feedback() || original.trim() == corrected.trim() {
+            return Ok(());
+        }
+
+        let (prompt, original, corrected) = (
+            self.redact(prompt),
+            self.redact(original),
+            self.redact(corrected),
+        );
+        debug!("Recording correction for {prompt}: {original} -> {corrected}");
+        self.cache
+            .record_correction(&prompt, &original, &corrected)
+            .await
+    }
+
+    /// Remembers a plan whose steps all ran, to offer it again for `prompt`
+    pub async fn record_workflow(&mut self, prompt: &str, steps: &[Suggestion]) -> Result<()> {
+        if !self.records_feedback() || steps.is_empty() {
+            return Ok(());
+        }
+
+        // Workflows are replayed as recorded, so none with a secret is kept
+        if steps
+            .iter()
+            .any(|step| self.redact(&step.command) != step.command)
+        {
+            debug!("Not recording a workflow that holds a secret");
+            return Ok(());
+        }
+
+        let prompt = self.redact(prompt);
+        debug!("Recording a workflow of {} steps for {prompt}", steps.len());
+        self.cache.record_workflow(&prompt, steps).await
+    }
+
+    /// Rejected commands of the same prompt, or of prompts in the same category sharing a word
+    async fn get_avoided_commands(&self, prompt: &str, category: &str) -> Result<Vec<String>> {
+        let prompt_lower = prompt.trim().to_lowercase();
+        let words: Vec<&str> = prompt_lower
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| word.len() > 2)
+            .collect();
+
+        let mut avoid: Vec<String> = Vec::new();
+        for (rejected_prompt, command) in self.cache.get_rejected_suggestions(200).await? {
+            let rejected_lower = rejected_prompt.trim().to_lowercase();
+            let similar = rejected_lower == prompt_lower
+                || (self.categorize_prompt(&rejected_prompt) == category
+                    && words.iter().any(|word| rejected_lower.contains(word)));
+
+            if similar && !avoid.contains(&command) {
+                avoid.push(command);
+            }
+            if avoid.len() >= MAX_AVOIDED_COMMANDS {
+                break;
+            }
+        }
+
+        Ok(avoid)
+    }
+
+    /// Remembers the suggestions the user passed over, so similar requests avoid them
+    pub async fn record_rejected_suggestions(
+        &mut self,
+        prompt: &str,
+        commands: &[&str],
+    ) -> Result<()> {
+        if !self.records_feedback() || commands.is_empty() {
+            return Ok(());
+        }
+
+        let prompt = self.redact(prompt);
+        let commands: Vec<String> = commands
+            .iter()
+            .map(|command| self.redact(command))
+            .collect();
+        let commands: Vec<&str> = commands.iter().map(String::as_str).collect();
+        debug!(
+            "Recording {} rejected suggestions for: {prompt}",
+            commands.len()
+        );
+        self.cache
+            .record_rejected_suggestions(&prompt, &commands)
+            .await
+    }
+
+    async fn get_few_shot_examples(
+        &self,
+        prompt: &str,
+        category: &str,
+    ) -> Result<Vec<FewShotExample>> {
+        let prompt_lower = prompt.trim().to_lowercase();
+        let mut examples: Vec<FewShotExample> = Vec::new();
+
+        for (example_prompt, command) in self.cache.get_successful_examples(200).await? {
+            // The current prompt itself is not an example
+            if example_prompt.trim().to_lowercase() == prompt_lower
+                || self.categorize_prompt(&example_prompt) != category
+            {
+                continue;
+            }
+
+            let example = FewShotExample {
+                prompt: example_prompt,
+                command,
+            };
+            if !examples.contains(&example) {
+                examples.push(example);
+            }
+
+            if examples.len() >= MAX_FEW_SHOT_EXAMPLES {
+                break;
+            }
+        }
+
+        debug!(
+            "Selected {} few-shot examples for {category}",
+            examples.len()
+        );
+        Ok(examples)
+    }
+
+    /// Records how a model reply was parsed and how long it took, for
+    /// repair-rate and latency stats
+    pub async fn record_generation(
+        &mut self,
+        backend: Option<&str>,
+        outcome: ParseOutcome,
+        latency: Duration,
+    ) -> Result<()> {
+        if self.incognito {
+            return Ok(());
+        }
+        self.cache
+            .record_generation(backend, outcome.as_str(), latency)
+            .await
+    }
+
+    /// Records where the suggestions for a prompt came from, for `phloem stats`
+    pub async fn record_prompt(&mut self, prompt: &str, source: &str) -> Result<()> {
+        if self.incognito {
+            return Ok(());
+        }
+        let category = self.categorize_prompt(prompt);
+        self.cache
+            .record_prompt(&self.redact(prompt), source, &category)
+            .await
+    }
+
+    pub async fn record_command_execution(
         &mut self,
         command: &str,
         prompt: &str,
         success: bool,
         exit_code: Option<i32>,
     ) -> Result<()> {
+        if !self.records_feedback() {
+            return Ok(());
+        }
+
+        let (command, prompt) = (&self.redact(command), &self.redact(prompt));
         debug!("Recording command execution: {command} (success: {success})");
 
         // Record in history table
         self.cache
-            .record_command_execution(command, prompt, success, exit_code)?;
+            .record_command_execution(command, prompt, success, exit_code, None)
+            .await?;
 
         // Update suggestion success metrics
-        if let Err(e) = self.cache.record_suggestion_usage(prompt, command, success) {
+        if let Err(e) = self
+            .cache
+            .record_suggestion_usage(prompt, command, success)
+            .await
+        {
             warn!("Failed to update suggestion usage metrics: {e}");
         }
 
         if success {
-            self.update_successful_command_pattern(prompt, command)?;
+            self.learn(prompt, command, LearningOutcome::Succeeded)
+                .await?;
         }
 
         Ok(())
     }
 
-    pub fn record_suggestion_feedback(
+    /// Appends a suggestion that ran to the audit log, whatever the
+    /// learning and incognito settings; secrets are masked there too
+    pub fn record_audit(&self, record: AuditRecord) -> Result<()> {
+        if let Some(audit) = &self.audit {
+            let (prompt, command) = (self.redact(record.prompt), self.redact(record.command));
+            audit.append(AuditRecord {
+                prompt: &prompt,
+                command: &command,
+                ..record
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The audit log, when it's enabled
+    pub fn audit_log(&self) -> Option<&AuditLog> {
+        self.audit.as_ref()
+    }
+
+    pub async fn record_suggestion_feedback(
         &mut self,
         prompt: &str,
         command: &str,
         success: bool,
+        exit_code: Option<i32>,
+        output: Option<&CapturedOutput>,
     ) -> Result<()> {
+        let (command, prompt) = (&self.redact(command), &self.redact(prompt));
+        if !self.incognito {
+            if let Err(e) = self.cache.record_prompt_accepted(prompt).await {
+                warn!("Failed to record the prompt as accepted: {e}");
+            }
+        }
+        if !self.records_feedback() {
+            return Ok(());
+        }
+
         debug!("Recording suggestion feedback: {prompt} -> {command} (success: {success})");
 
-        // If successful, learn about the command pattern
-        if success {
-            self.learn_successful_command(prompt, command)?;
+        // Output can carry tokens and passwords just like the commands do
+        let output = output.map(|output| {
+            (
+                self.redactor.redact(&output.stdout),
+                self.redactor.redact(&output.stderr),
+            )
+        });
+        if let Err(e) = self
+            .cache
+            .record_command_execution(
+                command,
+                prompt,
+                success,
+                exit_code,
+                output
+                    .as_ref()
+                    .map(|(stdout, stderr)| (stdout.as_str(), stderr.as_str())),
+            )
+            .await
+        {
+            warn!("Failed to record command history: {e}");
         }
 
-        self.cache.record_suggestion_usage(prompt, command, success)
+        self.learn_from_feedback(prompt, command, success).await?;
+
+        self.cache
+            .record_suggestion_usage(prompt, command, success)
+            .await
     }
 
-    fn learn_successful_command(&self, prompt: &str, command: &str) -> Result<()> {
+    async fn learn_from_feedback(
+        &mut self,
+        prompt: &str,
+        command: &str,
+        success: bool,
+    ) -> Result<()> {
         // Extract the executable name
         let executable = command.split_whitespace().next().unwrap_or("").trim();
 
@@ -155,28 +940,129 @@ impl ContextManager {
             return Ok(());
         }
 
-        let category = self.categorize_prompt(prompt);
+        let outcome = if success {
+            LearningOutcome::Succeeded
+        } else {
+            LearningOutcome::Failed
+        };
+        self.learn(prompt, command, outcome).await
+    }
 
-        // Update COMMANDY.md with learned command pattern
-        let learning_content = format!(
-            "✓ Validated executable: `{executable}`\n\
-            Context: \"{prompt}\"\n\
-            Full command: `{command}`"
-        );
+    /// Stores a learned pattern and re-renders the learned section of PHLOEM.md from the store
+    async fn learn(&mut self, prompt: &str, command: &str, outcome: LearningOutcome) -> Result<()> {
+        if !self.records_feedback() {
+            return Ok(());
+        }
+
+        let pattern = LearnedPattern {
+            id: None,
+            prompt: self.redact(prompt),
+            command: self.redact(command),
+            category: self.categorize_prompt(prompt),
+            outcome,
+            created_at: None,
+        };
 
+        self.cache
+            .record_learned_pattern(&pattern, self.storage.has_project_context())
+            .await?;
+        self.render_learned_context().await
+    }
+
+    /// Whether outcomes are learned from: `learning_enabled` is set and this
+    /// isn't an incognito run
+    fn records_feedback(&self) -> bool {
+        self.learning_enabled && !self.incognito
+    }
+
+    async fn render_learned_context(&self) -> Result<()> {
+        let patterns = self
+            .cache
+            .get_learned_patterns(
+                self.storage.has_project_context(),
+                RENDERED_LEARNED_PATTERNS,
+            )
+            .await?;
         self.storage
-            .append_to_context(&category, &learning_content)?;
+            .write_learned_patterns(&patterns, self.max_context_bytes)
+    }
 
-        Ok(())
+    /// Learned patterns of the current project or global context, newest first,
+    /// optionally only those of one category
+    pub async fn list_learned_patterns(
+        &self,
+        category: Option<&str>,
+    ) -> Result<Vec<LearnedPattern>> {
+        let patterns = self
+            .cache
+            .get_learned_patterns(
+                self.storage.has_project_context(),
+                RENDERED_LEARNED_PATTERNS,
+            )
+            .await?;
+
+        Ok(patterns
+            .into_iter()
+            .filter(|pattern| category.is_none_or(|c| pattern.category.eq_ignore_ascii_case(c)))
+            .collect())
+    }
+
+    /// Adds a hand-written note to the learned context, under a built-in or
+    /// existing category when one matches regardless of case
+    pub async fn add_context_note(&mut self, category: &str, note: &str) -> Result<()> {
+        let category = match PROMPT_CATEGORIES
+            .iter()
+            .find(|known| known.eq_ignore_ascii_case(category))
+        {
+            Some(known) => known.to_string(),
+            None => self
+                .list_learned_patterns(Some(category))
+                .await?
+                .into_iter()
+                .next()
+                .map_or_else(|| category.to_string(), |pattern| pattern.category),
+        };
+
+        let pattern = LearnedPattern {
+            id: None,
+            prompt: self.redact(note),
+            command: String::new(),
+            category,
+            outcome: LearningOutcome::Note,
+            created_at: None,
+        };
+
+        self.cache
+            .record_learned_pattern(&pattern, self.storage.has_project_context())
+            .await?;
+        self.render_learned_context().await
+    }
+
+    /// Forgets a learned pattern; false if there is none with that id
+    pub async fn remove_learned_pattern(&mut self, id: i64) -> Result<bool> {
+        if !self.cache.delete_learned_pattern(id).await? {
+            return Ok(false);
+        }
+        self.render_learned_context().await?;
+        Ok(true)
+    }
+
+    /// The context file learning currently goes to
+    pub fn get_active_context_file_path(&self) -> &PathBuf {
+        self.storage.get_active_context_file_path()
     }
 
-    pub fn clear_cache(&mut self) -> Result<()> {
+    pub async fn clear_cache(&mut self) -> Result<()> {
         info!("Clearing command cache");
-        self.cache.clear_cache()
+        self.cache.clear_cache().await
     }
 
-    pub fn clear_context(&self) -> Result<()> {
+    pub async fn clear_context(&mut self) -> Result<()> {
         info!("Clearing learning context");
+        self.cache.clear_learned_patterns().await?;
+        if self.storage.has_project_context() {
+            self.render_learned_context().await?;
+        }
         self.storage.clear_context()
     }
 
@@ -184,6 +1070,20 @@ impl ContextManager {
         self.storage.get_context_file_path()
     }
 
+    pub fn get_project_context_file_path(&self) -> Option<&PathBuf> {
+        self.storage.get_project_context_file_path()
+    }
+
+    /// Starts a shared context file for the current project (or directory)
+    pub fn create_project_context(&self) -> Result<PathBuf> {
+        let root = match Project::current() {
+            Some(project) => project.root,
+            None => std::env::current_dir()?,
+        };
+        info!("Creating project context file in {}", root.display());
+        self.storage.create_project_context_file(&root)
+    }
+
     pub fn get_cache_path(&self) -> PathBuf {
         self.storage
             .get_phloem_dir()
@@ -191,16 +1091,8 @@ impl ContextManager {
             .join("suggestions.db")
     }
 
-    fn update_environment_info(&mut self, env_info: &HashMap<String, String>) -> Result<()> {
-        for (key, value) in env_info {
-            if let Err(e) = self.cache.update_environment(key, value) {
-                warn!("Failed to update environment info for {key}: {e}");
-            }
-        }
-        Ok(())
-    }
-
-    fn categorize_prompt(&self, prompt: &str) -> String {
+    /// The category of a request, e.g. "Docker" or "Git"
+    pub fn categorize_prompt(&self, prompt: &str) -> String {
         let prompt_lower = prompt.to_lowercase();
 
         // Simple categorization based on keywords
@@ -231,37 +1123,6 @@ impl ContextManager {
         }
     }
 
-    fn update_context_learning(&self, prompt: &str, suggestion: &Suggestion) -> Result<()> {
-        let category = self.categorize_prompt(prompt);
-
-        let learning_content = format!(
-            "User prompt: \"{}\"\n→ Suggested: `{}`\n{}",
-            prompt,
-            suggestion.command,
-            suggestion
-                .explanation
-                .as_ref()
-                .map(|e| format!("Explanation: {e}"))
-                .unwrap_or_default()
-        );
-
-        self.storage
-            .append_to_context(&category, &learning_content)?;
-
-        Ok(())
-    }
-
-    fn update_successful_command_pattern(&self, prompt: &str, command: &str) -> Result<()> {
-        let category = self.categorize_prompt(prompt);
-
-        let success_content = format!("✓ Successful execution:\n\"{prompt}\" → `{command}`");
-
-        self.storage
-            .append_to_context(&category, &success_content)?;
-
-        Ok(())
-    }
-
     fn is_command_relevant(&self, command: &str, prompt: &str) -> bool {
         let prompt_lower = prompt.to_lowercase();
         let command_lower = command.to_lowercase();
@@ -302,3 +1163,30 @@ impl ContextManager {
         false
     }
 }
+
+/// How often each executable of `entries` was run and succeeded, most used first
+fn top_tools(entries: &[HistoryEntry], limit: usize) -> Vec<ToolUsage> {
+    let validator = CommandValidator::new();
+    let mut usage: HashMap<String, ToolUsage> = HashMap::new();
+
+    for entry in entries {
+        let mut tools = validator.extract_executables(&entry.command);
+        tools.sort();
+        tools.dedup();
+
+        for tool in tools {
+            let tool_usage = usage.entry(tool.clone()).or_insert(ToolUsage {
+                tool,
+                uses: 0,
+                successes: 0,
+            });
+            tool_usage.uses += 1;
+            tool_usage.successes += usize::from(entry.success);
+        }
+    }
+
+    let mut tools: Vec<ToolUsage> = usage.into_values().collect();
+    tools.sort_by(|a, b| b.uses.cmp(&a.uses).then_with(|| a.tool.cmp(&b.tool)));
+    tools.truncate(limit);
+    tools
+}
@@ -0,0 +1,209 @@
+use anyhow::{Context, Result};
+use log::{debug, info};
+use rusqlite::{params, Connection, TransactionBehavior};
+
+/// One step of the cache database schema
+struct Migration {
+    version: u32,
+    name: &'static str,
+    sql: &'static str,
+}
+
+/// Ordered schema changes. Append new migrations; never edit one that has shipped.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("../../sql/migrations/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "suggestion_backend",
+        sql: include_str!("../../sql/migrations/0002_suggestion_backend.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "generations",
+        sql: include_str!("../../sql/migrations/0003_generations.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "prompt_embeddings",
+        sql: include_str!("../../sql/migrations/0004_prompt_embeddings.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "full_text_search",
+        sql: include_str!("../../sql/migrations/0005_full_text_search.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "project_scoped_cache",
+        sql: include_str!("../../sql/migrations/0006_project_scoped_cache.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "learned_patterns",
+        sql: include_str!("../../sql/migrations/0007_learned_patterns.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "rejected_suggestions",
+        sql: include_str!("../../sql/migrations/0008_rejected_suggestions.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "corrections",
+        sql: include_str!("../../sql/migrations/0009_corrections.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "host_environment",
+        sql: include_str!("../../sql/migrations/0010_host_environment.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "history_output",
+        sql: include_str!("../../sql/migrations/0011_history_output.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "favorites",
+        sql: include_str!("../../sql/migrations/0012_favorites.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "workflows",
+        sql: include_str!("../../sql/migrations/0013_workflows.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "usage_stats",
+        sql: include_str!("../../sql/migrations/0014_usage_stats.sql"),
+    },
+    Migration {
+        version: 15,
+        name: "executables",
+        sql: include_str!("../../sql/migrations/0015_executables.sql"),
+    },
+];
+
+/// Brings the database schema up to date, each migration in its own transaction.
+/// Transactions take the write lock up front, so concurrent phloem processes
+/// apply every migration exactly once.
+pub fn migrate(connection: &mut Connection) -> Result<()> {
+    let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    transaction.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+             version INTEGER PRIMARY KEY,
+             name TEXT NOT NULL,
+             applied_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+         );",
+    )?;
+
+    let mut current = current_version(&transaction)?;
+    if current == 0 {
+        current = adopt_unversioned_database(&transaction)?;
+    }
+    transaction.commit()?;
+
+    let latest = MIGRATIONS.last().map_or(0, |m| m.version);
+    if current > latest {
+        return Err(anyhow::anyhow!(
+            "The cache database uses schema version {current}, but this phloem only knows up to \
+             {latest}. Upgrade phloem, or move ~/.phloem/cache/suggestions.db aside to start fresh."
+        ));
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        // Another process may have applied it while we waited for the lock
+        if current_version(&transaction)? >= migration.version {
+            continue;
+        }
+
+        info!(
+            "Applying cache migration {} ({})",
+            migration.version, migration.name
+        );
+        transaction.execute_batch(migration.sql).with_context(|| {
+            format!(
+                "Cache migration {} ({}) failed",
+                migration.version, migration.name
+            )
+        })?;
+        transaction.execute(
+            "INSERT INTO schema_migrations (version, name) VALUES (?1, ?2)",
+            params![migration.version, migration.name],
+        )?;
+        transaction.commit()?;
+    }
+
+    Ok(())
+}
+
+fn current_version(connection: &Connection) -> Result<u32> {
+    Ok(connection.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_migrations",
+        [],
+        |row| row.get(0),
+    )?)
+}
+
+/// Databases from before versioned migrations: works out which migrations
+/// their schema already contains and records them as applied
+fn adopt_unversioned_database(connection: &Connection) -> Result<u32> {
+    if !has_table(connection, "suggestions")? {
+        return Ok(0);
+    }
+
+    // The oldest databases predate the success tracking columns
+    if !has_column(connection, "suggestions", "success_count")? {
+        connection.execute(
+            "ALTER TABLE suggestions ADD COLUMN success_count INTEGER DEFAULT 0",
+            [],
+        )?;
+    }
+    if !has_column(connection, "suggestions", "success_rate")? {
+        connection.execute(
+            "ALTER TABLE suggestions ADD COLUMN success_rate REAL DEFAULT 0.5",
+            [],
+        )?;
+    }
+
+    let present = [
+        has_column(connection, "suggestions", "backend")?,
+        has_table(connection, "generations")?,
+        has_table(connection, "prompt_embeddings")?,
+        has_table(connection, "suggestions_fts")?,
+        has_column(connection, "suggestions", "project")?,
+    ];
+    let version = 1 + present.iter().take_while(|&&present| present).count() as u32;
+
+    debug!("Adopting unversioned cache database at schema version {version}");
+    for migration in MIGRATIONS.iter().filter(|m| m.version <= version) {
+        connection.execute(
+            "INSERT INTO schema_migrations (version, name) VALUES (?1, ?2)",
+            params![migration.version, migration.name],
+        )?;
+    }
+
+    Ok(version)
+}
+
+fn has_table(connection: &Connection, table: &str) -> Result<bool> {
+    Ok(connection.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+        [table],
+        |row| row.get(0),
+    )?)
+}
+
+fn has_column(connection: &Connection, table: &str, column: &str) -> Result<bool> {
+    Ok(connection.query_row(
+        "SELECT EXISTS (SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)",
+        [table, column],
+        |row| row.get(0),
+    )?)
+}
@@ -1,7 +1,18 @@
+pub mod audit;
+pub mod backup;
 pub mod cache;
+pub mod learning;
 pub mod manager;
+pub mod migrations;
+pub mod ranking;
 pub mod storage;
 
-pub use cache::CacheManager;
-pub use manager::{ContextData, ContextManager};
+pub use audit::{AuditBreak, AuditEntry, AuditLog, AuditRecord};
+pub use cache::{
+    CacheEntry, CacheExport, CacheManager, CategoryPrompts, DailyPrompts, ExportedEntry, Favorite,
+    HistoryEntry, PromptEmbedding, UsageStats, WeeklySuccess, CACHE_EXPORT_VERSION,
+};
+pub use learning::{LearnedPattern, LearningOutcome};
+pub use manager::{ContextData, ContextManager, Correction, FewShotExample, ToolUsage};
+pub use ranking::SuggestionRanker;
 pub use storage::StorageManager;
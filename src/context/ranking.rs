@@ -0,0 +1,109 @@
+use log::debug;
+use std::path::PathBuf;
+
+use crate::cli::Suggestion;
+use crate::context::CacheManager;
+use crate::utils::{CommandValidator, ExecutableIndex, ShellDetector};
+
+/// Shell builtins that never show up in PATH
+const SHELL_BUILTINS: &[&str] = &[
+    "cd", "echo", "pwd", "export", "source", ".", "alias", "unalias", "set", "unset", "read",
+    "test", "[", "type", "history", "jobs", "fg", "bg", "wait", "eval", "exit", "true", "false",
+    "for", "while", "if", "do", "done", "then", "fi", "ulimit", "umask",
+];
+
+// Weights of the individual signals; missing signals are left out of the average
+const MODEL_WEIGHT: f32 = 0.4;
+const EXECUTABLES_WEIGHT: f32 = 0.3;
+const PATHS_WEIGHT: f32 = 0.1;
+const HISTORY_WEIGHT: f32 = 0.2;
+
+/// Second pass over generated suggestions: scores each one against the
+/// environment and past outcomes, then reorders them best first
+pub struct SuggestionRanker<'a> {
+    cache: &'a CacheManager,
+    validator: CommandValidator,
+}
+
+impl<'a> SuggestionRanker<'a> {
+    pub fn new(cache: &'a CacheManager) -> Self {
+        Self {
+            cache,
+            validator: CommandValidator::new(),
+        }
+    }
+
+    /// Replaces each suggestion's confidence with its computed score and sorts by it
+    pub async fn rank(&self, suggestions: &mut [Suggestion]) {
+        for suggestion in suggestions.iter_mut() {
+            let score = self.score(suggestion).await;
+            debug!(
+                "Ranked '{}': model {:.2} -> {score:.2}",
+                suggestion.command, suggestion.confidence
+            );
+            suggestion.confidence = score;
+        }
+
+        suggestions.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    }
+
+    async fn score(&self, suggestion: &Suggestion) -> f32 {
+        let mut signals = vec![(suggestion.confidence, MODEL_WEIGHT)];
+
+        // Are all referenced executables installed?
+        let executables = self.validator.extract_executables(&suggestion.command);
+        if !executables.is_empty() {
+            let installed = executables.iter().filter(|name| is_available(name)).count();
+            signals.push((
+                installed as f32 / executables.len() as f32,
+                EXECUTABLES_WEIGHT,
+            ));
+        }
+
+        // Do referenced paths exist, or at least their parent directory?
+        let paths = self.validator.extract_path_arguments(&suggestion.command);
+        if !paths.is_empty() {
+            let existing = paths.iter().filter(|path| path_plausible(path)).count();
+            signals.push((existing as f32 / paths.len() as f32, PATHS_WEIGHT));
+        }
+
+        // How did this command do when it was run before?
+        match self
+            .cache
+            .get_command_success_rate(&suggestion.command)
+            .await
+        {
+            Ok(Some(success_rate)) => signals.push((success_rate, HISTORY_WEIGHT)),
+            Ok(None) => {}
+            Err(e) => debug!("Failed to look up command history: {e}"),
+        }
+
+        let total_weight: f32 = signals.iter().map(|(_, weight)| weight).sum();
+        let score: f32 = signals
+            .iter()
+            .map(|(value, weight)| value * weight)
+            .sum::<f32>()
+            / total_weight;
+
+        score.clamp(0.0, 1.0)
+    }
+}
+
+fn is_available(name: &str) -> bool {
+    SHELL_BUILTINS.contains(&name)
+        || ShellDetector::is_user_alias(name)
+        || ExecutableIndex::contains(name)
+}
+
+/// A path is plausible if it exists or could be created in an existing directory
+fn path_plausible(path: &str) -> bool {
+    let path = match path.strip_prefix("~/") {
+        Some(rest) => match dirs::home_dir() {
+            Some(home) => home.join(rest),
+            None => return true,
+        },
+        None => PathBuf::from(path),
+    };
+
+    path.exists() || path.parent().is_some_and(|parent| parent.exists())
+}
@@ -1,24 +1,50 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use chrono::Utc;
-use std::fs;
-use std::path::PathBuf;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::config::DefaultConfig;
+use crate::context::learning::{render_learned_patterns, LearnedPattern};
+
+/// Markers around the section of PHLOEM.md rendered from the learned patterns store
+const LEARNED_START: &str =
+    "<!-- phloem:learned-patterns:start - generated, edits inside are overwritten -->";
+const LEARNED_END: &str = "<!-- phloem:learned-patterns:end -->";
+
+/// Room kept for learned patterns even when the rest of the file fills the budget
+const MIN_LEARNED_SECTION_BYTES: usize = 4 * 1024;
+
+/// Template for a new project-local context file
+const PROJECT_CONTEXT_TEMPLATE: &str = r#"# Project Context
+
+Command patterns for this project. Commit this file to share them with your team;
+phloem adds what it learns while working here.
+
+## Command Patterns
+"#;
 
 pub struct StorageManager {
     phloem_dir: PathBuf,
     context_file: PathBuf,
+    project_context_file: Option<PathBuf>,
 }
 
 impl StorageManager {
     pub fn new() -> Result<Self> {
-        let phloem_dir = dirs::home_dir()
-            .ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?
-            .join(".phloem");
+        let home =
+            dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
+        let phloem_dir = home.join(".phloem");
 
         let context_file = phloem_dir.join("PHLOEM.md");
+        let project_context_file = std::env::current_dir()
+            .ok()
+            .and_then(|dir| find_project_context_file(&dir, &home));
 
         Ok(Self {
             phloem_dir,
             context_file,
+            project_context_file,
         })
     }
 
@@ -34,7 +60,10 @@ impl StorageManager {
 
         // Initialize PHLOEM.md if it doesn't exist
         if !self.context_file.exists() {
-            self.create_initial_context_file()?;
+            let _lock = self.lock_context()?;
+            if !self.context_file.exists() {
+                self.create_initial_context_file()?;
+            }
         }
 
         // Create default config if it doesn't exist
@@ -43,38 +72,84 @@ impl StorageManager {
             self.create_default_config()?;
         }
 
+        // Create default policy if it doesn't exist
+        let policy_file = self.phloem_dir.join("policy.toml");
+        if !policy_file.exists() {
+            fs::write(policy_file, DefaultConfig::create_default_policy_file())?;
+        }
+
         Ok(())
     }
 
+    /// Learned context: the project's file, when there is one, ahead of the global file
     pub fn read_context_file(&self) -> Result<String> {
-        if !self.context_file.exists() {
-            return Ok(String::new());
-        }
+        let global = read_if_exists(&self.context_file)?;
 
-        let content = fs::read_to_string(&self.context_file)?;
-        Ok(content)
+        match &self.project_context_file {
+            Some(project_file) => {
+                let project = read_if_exists(project_file)?;
+                Ok(format!("{project}\n{global}"))
+            }
+            None => Ok(global),
+        }
     }
 
-    pub fn append_to_context(&self, section: &str, content: &str) -> Result<()> {
-        let current_content = self.read_context_file()?;
+    /// Whether learning goes to a project context file rather than the global one
+    pub fn has_project_context(&self) -> bool {
+        self.project_context_file.is_some()
+    }
 
-        // Find the section or create it
-        let updated_content = if current_content.contains(&format!("### {section}")) {
-            self.update_existing_section(&current_content, section, content)
-        } else {
-            self.add_new_section(&current_content, section, content)
-        };
+    /// Replaces the generated learned-patterns section of the project's context
+    /// file, when working inside a project that has one, or else the global file.
+    /// Everything outside the section is left as written, and the section is cut
+    /// down to keep the whole file within `max_bytes`.
+    pub fn write_learned_patterns(
+        &self,
+        patterns: &[LearnedPattern],
+        max_bytes: usize,
+    ) -> Result<()> {
+        // Another phloem process may be updating the file at the same time
+        let _lock = self.lock_context()?;
+
+        let target = self.get_active_context_file_path();
+        let current_content = read_if_exists(target)?;
+
+        let written_by_user = replace_learned_section(&current_content, "").len();
+        let budget = max_bytes
+            .saturating_sub(written_by_user)
+            .max(MIN_LEARNED_SECTION_BYTES);
+        let rendered = render_learned_patterns(patterns, budget);
+
+        let updated_content = replace_learned_section(&current_content, &rendered);
+        if updated_content == current_content {
+            return Ok(());
+        }
 
-        // Backup the current file
-        self.backup_context_file()?;
+        // Backup the global file; project files are expected to live in version control
+        if self.project_context_file.is_none() {
+            self.backup_context_file()?;
+        }
 
-        // Write updated content
-        fs::write(&self.context_file, updated_content)?;
+        write_atomically(target, &updated_content)?;
 
         Ok(())
     }
 
+    /// Creates `.phloem/PHLOEM.md` under a project root, keeping an existing one
+    pub fn create_project_context_file(&self, root: &Path) -> Result<PathBuf> {
+        let project_file = root.join(".phloem").join("PHLOEM.md");
+        if project_file.exists() {
+            return Ok(project_file);
+        }
+
+        let _lock = self.lock_context()?;
+        fs::create_dir_all(root.join(".phloem"))?;
+        write_atomically(&project_file, PROJECT_CONTEXT_TEMPLATE)?;
+        Ok(project_file)
+    }
+
     pub fn clear_context(&self) -> Result<()> {
+        let _lock = self.lock_context()?;
         self.backup_context_file()?;
         self.create_initial_context_file()?;
         Ok(())
@@ -84,6 +159,17 @@ impl StorageManager {
         &self.context_file
     }
 
+    /// The file learning goes to: the project's context file or the global one
+    pub fn get_active_context_file_path(&self) -> &PathBuf {
+        self.project_context_file
+            .as_ref()
+            .unwrap_or(&self.context_file)
+    }
+
+    pub fn get_project_context_file_path(&self) -> Option<&PathBuf> {
+        self.project_context_file.as_ref()
+    }
+
     pub fn get_phloem_dir(&self) -> &PathBuf {
         &self.phloem_dir
     }
@@ -146,34 +232,30 @@ User prefers:
                 .unwrap_or_else(|_| "unknown".to_string()),
         );
 
-        fs::write(&self.context_file, initial_content)?;
+        write_atomically(&self.context_file, &initial_content)?;
         Ok(())
     }
 
+    /// Takes the advisory lock guarding PHLOEM.md; released when the returned file is dropped.
+    /// A separate lock file is used because atomic writes replace the context file itself.
+    fn lock_context(&self) -> Result<File> {
+        fs::create_dir_all(&self.phloem_dir)?;
+
+        let lock_path = self.phloem_dir.join("PHLOEM.md.lock");
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .with_context(|| format!("Failed to open {}", lock_path.display()))?;
+
+        lock.lock()
+            .with_context(|| format!("Failed to lock {}", lock_path.display()))?;
+        Ok(lock)
+    }
+
     fn create_default_config(&self) -> Result<()> {
-        let config_content = r#"[general]
-max_context_size_kb = 50
-recent_commands_limit = 100
-learning_enabled = true
-
-[model]
-model_path = "~/.phloem/models/gemma-3n"
-max_tokens = 100
-temperature = 0.0
-
-[cache]
-max_cache_entries = 1000
-cache_ttl_hours = 24
-
-[output]
-show_explanations = true
-use_colors = true
-max_suggestions = 3
-
-[privacy]
-collect_usage_stats = false
-share_anonymous_data = false
-"#;
+        let config_content = DefaultConfig::create_default_config_file();
 
         let config_path = self.phloem_dir.join("config.toml");
         fs::write(config_path, config_content)?;
@@ -222,61 +304,67 @@ share_anonymous_data = false
 
         Ok(())
     }
+}
 
-    fn update_existing_section(&self, content: &str, section: &str, new_content: &str) -> String {
-        let section_header = format!("### {section}");
-        let lines: Vec<&str> = content.lines().collect();
-        let mut result = Vec::new();
-        let mut in_section = false;
-        let mut section_found = false;
-
-        for line in lines {
-            if line.starts_with("### ") {
-                if in_section {
-                    // End of our section, add new content
-                    let timestamp = format!("Last updated: {}", Utc::now().format("%Y-%m-%d"));
-                    result.push(timestamp);
-                    result.push(new_content.to_string());
-                    result.push(String::new());
-                }
-                in_section = line == section_header;
-                if in_section {
-                    section_found = true;
-                }
-                result.push(line.to_string());
-            } else if in_section && line.starts_with("Last updated:") {
-                // Skip old timestamp, we'll add a new one
-                continue;
-            } else if !in_section {
-                result.push(line.to_string());
-            }
-        }
+/// Swaps the generated section between the markers for `rendered`, appending
+/// it when the file doesn't have one yet
+fn replace_learned_section(content: &str, rendered: &str) -> String {
+    let section = format!("{LEARNED_START}\n{}\n{LEARNED_END}", rendered.trim_end());
 
-        // If we were in the section at the end of file
-        if in_section && section_found {
-            let timestamp = format!("Last updated: {}", Utc::now().format("%Y-%m-%d"));
-            result.push(timestamp);
-            result.push(new_content.to_string());
+    if let Some(start) = content.find(LEARNED_START) {
+        if let Some(end) = content[start..].find(LEARNED_END) {
+            let end = start + end + LEARNED_END.len();
+            return format!("{}{section}{}", &content[..start], &content[end..]);
         }
+    }
 
-        result.join("\n")
+    let content = content.trim_end();
+    if content.is_empty() {
+        format!("{section}\n")
+    } else {
+        format!("{content}\n\n{section}\n")
     }
+}
 
-    fn add_new_section(&self, content: &str, section: &str, new_content: &str) -> String {
-        let section_content = format!(
-            "\n### {}\nLast updated: {}\n{}\n",
-            section,
-            Utc::now().format("%Y-%m-%d"),
-            new_content
-        );
+/// Finds the nearest `.phloem/PHLOEM.md` above `dir`, stopping before the
+/// home directory, whose `.phloem` holds the global file
+fn find_project_context_file(dir: &Path, home: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .take_while(|path| *path != home)
+        .map(|path| path.join(".phloem").join("PHLOEM.md"))
+        .find(|path| path.is_file())
+}
 
-        // Add to the Command Patterns section
-        if let Some(pos) = content.find("## Recent Context") {
-            let mut result = content.to_string();
-            result.insert_str(pos, &section_content);
-            result
-        } else {
-            format!("{content}{section_content}")
-        }
+fn read_if_exists(path: &Path) -> Result<String> {
+    if !path.exists() {
+        return Ok(String::new());
+    }
+
+    fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+/// Writes through a temporary file in the same directory and renames it into
+/// place, so readers never see a partially written file
+fn write_atomically(path: &Path, content: &str) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Invalid path: {}", path.display()))?;
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let result = (|| -> Result<()> {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
     }
+    result.with_context(|| format!("Failed to write {}", path.display()))
 }
@@ -1,17 +1,49 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use clap::Parser;
-use log::error;
+use log::{error, LevelFilter};
+use std::io::{self, IsTerminal, Read};
+use std::time::Instant;
 
-use phloem::{Cli, CommandHandler, Commands};
+use phloem::cli::{daemon, Cancelled, OutputMode, PromptOptions};
+use phloem::config::InvalidConfig;
+use phloem::context::StorageManager;
+use phloem::utils::{InvalidPolicy, Logger, Timing};
+use phloem::{Cli, CommandHandler, Commands, Settings};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize logging - only show errors
-    env_logger::Builder::from_default_env()
-        .filter_level(log::LevelFilter::Error)
-        .init();
+    Timing::start();
+    let started = Instant::now();
+    let mut cli = Cli::parse();
 
-    let cli = Cli::parse();
+    // Errors reach the terminal, `-v` adds debug messages; the log file gets
+    // the configured level. The config is read again below, where it can fail
+    let console = if cli.verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Error
+    };
+    let logging = Settings::load()
+        .map(|settings| settings.logging)
+        .unwrap_or_default();
+    let log_file = StorageManager::new()
+        .ok()
+        .map(|storage| storage.get_phloem_dir().join("logs").join("phloem.log"));
+    if let Err(e) = Logger::init(
+        console,
+        log_file.as_deref().map(|path| (path, &logging)),
+        cli.log_level.map(LevelFilter::from),
+    ) {
+        eprintln!("Warning: {e}");
+    }
+
+    // Everything from project detection to running the picked command happens there
+    if let Some(dir) = &cli.cwd {
+        if let Err(e) = std::env::set_current_dir(dir) {
+            eprintln!("Error: Can't work in {}: {e}", dir.display());
+            std::process::exit(1);
+        }
+    }
 
     // Handle version early
     if matches!(cli.command, Some(Commands::Version)) {
@@ -26,9 +58,70 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
+    // Completions only need the argument definitions
+    if let Some(Commands::Completions { shell }) = cli.command {
+        print!("{}", CommandHandler::completion_script(shell));
+        return Ok(());
+    }
+
+    if let Some(Commands::ShellInit { shell }) = cli.command {
+        print!("{}", phloem::cli::integration::script(shell));
+        return Ok(());
+    }
+
+    // Backups work without a loadable config or cache, so a broken setup can be restored
+    if let Some(Commands::Backup { action }) = cli.command {
+        match CommandHandler::handle_backup(action) {
+            Ok(output) => println!("{output}"),
+            Err(e) => {
+                error!("Backup failed: {e}");
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
     // Initialize command handler
-    let mut handler = match CommandHandler::new() {
+    let handler = Settings::load().and_then(|mut settings| {
+        // Doctor reports invalid settings itself, among its other checks
+        if let Err(e) = settings.validate() {
+            if !matches!(cli.command, Some(Commands::Doctor)) {
+                return Err(e.into());
+            }
+        }
+        settings.override_backend(cli.backend, cli.model.as_deref());
+        cli.apply_output_defaults(&settings.output);
+        CommandHandler::with_settings(settings)
+    });
+    let mut handler = match handler {
         Ok(h) => h,
+        // Still show what's wrong with the config
+        Err(e) if matches!(cli.command, Some(Commands::Doctor)) => {
+            // The policy line of the config checks already says what's wrong with it
+            let mut diagnostics = if e.is::<InvalidPolicy>() {
+                Vec::new()
+            } else {
+                vec![format!("✗ Failed to initialize Phloem: {e}")]
+            };
+            if let Ok(settings) = Settings::load() {
+                diagnostics.extend(CommandHandler::config_diagnostics(&settings));
+            }
+            println!("Phloem Health Check:\n{}", diagnostics.join("\n"));
+            std::process::exit(1);
+        }
+        Err(e) if e.is::<InvalidConfig>() => {
+            eprintln!("Error: {e}");
+            eprintln!(
+                "Fix these values in ~/.phloem/config.toml, or remove them to use the defaults."
+            );
+            std::process::exit(1);
+        }
+        Err(e) if e.is::<InvalidPolicy>() => {
+            eprintln!("Error: {e}");
+            eprintln!("Fix the rule in ~/.phloem/policy.toml, or remove the file to use the default policy.");
+            std::process::exit(1);
+        }
         Err(e) => {
             error!("Failed to initialize Phloem: {e}");
             eprintln!("Error: Failed to initialize Phloem: {e}");
@@ -37,10 +130,29 @@ async fn main() -> Result<()> {
         }
     };
 
+    Timing::record("startup", started.elapsed());
+
+    if let Some(shell) = cli.shell {
+        handler.set_target_shell(shell);
+    }
+    handler.set_dry_run(cli.dry_run);
+    if cli.sandbox {
+        handler.enable_sandbox();
+    }
+
+    // Catch a mistyped model before anything is sent to the backend
+    if let Some(model) = cli.model.as_deref().filter(|_| !cli.offline) {
+        if let Err(e) = handler.check_model_available(model).await {
+            eprintln!("{}", handler.format_error(&e.to_string()));
+            std::process::exit(1);
+        }
+    }
+
     match cli.command {
         Some(command) => {
             // Handle subcommands
             match handler.handle_command(command).await {
+                Ok(output) if output.is_empty() => {}
                 Ok(output) => println!("{output}"),
                 Err(e) => {
                     error!("Command failed: {e}");
@@ -51,43 +163,123 @@ async fn main() -> Result<()> {
             }
         }
         None => {
-            if let Some(ref prompt) = cli.prompt {
+            let prompt = match read_prompt(&cli) {
+                Ok(prompt) => prompt,
+                Err(e) => {
+                    eprintln!("{}", handler.format_error(&e.to_string()));
+                    std::process::exit(1);
+                }
+            };
+
+            if let Some(ref prompt) = prompt {
                 // Handle prompt for command generation
 
-                let options = (&cli).into();
+                let options: PromptOptions = (&cli).into();
+                // Scripts reading the output get no spinner on stderr
+                if cli.output.is_some() || cli.first {
+                    handler.hide_progress();
+                }
+
+                if cli.plan {
+                    let prompt = prompt.as_str();
+                    let result = match handler.handle_plan(prompt, options).await {
+                        Ok(steps) => match cli.output {
+                            Some(mode) => Timing::measure("render", || {
+                                handler.format_suggestions_as(&steps, mode, true, prompt)
+                            }),
+                            None => handler.run_plan(steps, prompt).await,
+                        },
+                        Err(e) => Err(e),
+                    };
+                    report_timing(&cli);
+                    match result {
+                        Ok(output) if output.is_empty() => {}
+                        Ok(output) => println!("{output}"),
+                        Err(e) if e.is::<Cancelled>() => {
+                            eprintln!("{}", handler.format_error("Cancelled"));
+                            std::process::exit(130);
+                        }
+                        Err(e) => {
+                            eprintln!("{}", handler.format_error(&e.to_string()));
+                            std::process::exit(1);
+                        }
+                    }
+                    return Ok(());
+                }
+                // `--first` alone prints the bare command
+                let output = cli.output.or(cli.first.then_some(OutputMode::Plain));
 
-                match handler.handle_prompt(prompt, options).await {
-                    Ok(suggestions) => {
-                        if suggestions.is_empty() {
-                            println!(
-                                "{}",
-                                handler.format_error(
-                                    "No suggestions found. Try rephrasing your prompt."
+                // A running daemon answers faster; without one, or when it has
+                // nothing (e.g. the model asked a question), answer here. It
+                // runs the configured backend, so overrides are answered here too.
+                let overridden = cli.model.is_some() || cli.backend.is_some();
+                let from_daemon = if overridden {
+                    None
+                } else {
+                    let started = Instant::now();
+                    let suggestions = daemon::request(prompt, &options, output.is_none()).await;
+                    Timing::record("daemon", started.elapsed());
+                    suggestions
+                };
+                let suggestions = match from_daemon {
+                    Some(suggestions) if !suggestions.is_empty() => Ok(suggestions),
+                    _ => handler.handle_prompt(prompt, options).await,
+                };
+                if suggestions.is_err() {
+                    report_timing(&cli);
+                }
+                match suggestions {
+                    Ok(suggestions) if suggestions.is_empty() && output.is_none() => {
+                        println!(
+                            "{}",
+                            handler
+                                .format_error("No suggestions found. Try rephrasing your prompt.")
+                        );
+                        report_timing(&cli);
+                    }
+                    Ok(mut suggestions) => {
+                        if cli.first {
+                            suggestions.truncate(1);
+                        }
+                        // Scripts and editors get the suggestions as they are, even none
+                        let formatted = match output {
+                            Some(mode) => Timing::measure("render", || {
+                                handler.format_suggestions_as(
+                                    &suggestions,
+                                    mode,
+                                    cli.explain,
+                                    prompt,
                                 )
-                            );
-                        } else {
-                            match handler
-                                .format_suggestions(suggestions, cli.explain, prompt)
-                                .await
-                            {
-                                Ok(output) => {
-                                    if !output.is_empty() {
-                                        println!("{output}");
-                                    }
-                                }
-                                Err(e) => {
-                                    error!("Failed to format suggestions: {e}");
-                                    eprintln!(
-                                        "{}",
-                                        handler.format_error(&format!(
-                                            "Failed to format suggestions: {e}"
-                                        ))
-                                    );
-                                    std::process::exit(1);
+                            }),
+                            None => {
+                                handler
+                                    .format_suggestions(suggestions, cli.explain, prompt)
+                                    .await
+                            }
+                        };
+                        report_timing(&cli);
+                        match formatted {
+                            Ok(output) => {
+                                if !output.is_empty() {
+                                    println!("{output}");
                                 }
                             }
+                            Err(e) => {
+                                error!("Failed to format suggestions: {e}");
+                                eprintln!(
+                                    "{}",
+                                    handler.format_error(&format!(
+                                        "Failed to format suggestions: {e}"
+                                    ))
+                                );
+                                std::process::exit(1);
+                            }
                         }
                     }
+                    Err(e) if e.is::<Cancelled>() => {
+                        eprintln!("{}", handler.format_error("Cancelled"));
+                        std::process::exit(130);
+                    }
                     Err(e) => {
                         error!("Failed to generate suggestions: {e}");
                         let error_msg = handler.format_error(&format!(
@@ -109,6 +301,8 @@ Examples:
   phloem "list running containers"
   phloem "find large files in current directory"
   phloem --explain "git commit with message"
+  echo "find big files" | phloem -
+  cargo build 2>&1 | phloem --diagnose
 
 Commands:
   init      Initialize phloem setup
@@ -116,13 +310,45 @@ Commands:
   config    Show configuration
   clear     Clear cache and context
   doctor    Run diagnostics
+  warmup    Load the model into memory
+  commit    Suggest a commit message for staged changes
+  bench     Compare models on a built-in prompt suite
+  cache     Inspect and manage the suggestion cache
+  context   Show, add, remove and edit learned patterns
+  history   Browse and search commands run from phloem
+  save      Save a command under a name
+  run       Run a saved command
+  favorites List, delete and rename saved commands
+  stats     Summarize usage (--json for dashboards)
+  audit     Show or verify the audit log of suggestions that ran
+  refresh-env  Detect installed tools and services again
+  backup    Back up or restore config, context and cache
+  daemon    Keep the model warm and answer prompts faster
+  completions  Print a shell completion script (bash, zsh, fish, powershell, elvish)
+  shell-init   Print the Ctrl+G widget that puts the picked command on your prompt
   help      Show this help message
 
 Options:
-  -e, --explain       Show detailed explanations
-  -n, --suggestions   Number of suggestions to show [default: 3]
+  -e, --explain       Show detailed explanations [default: output.show_explanations]
+      --no-explain    Hide explanations, when the config shows them
+  -n, --suggestions   Number of suggestions to show [default: output.max_suggestions]
       --no-cache      Skip cache and force fresh inference
-  -v, --verbose       Verbose output
+      --offline       Only use the cache and shell history
+      --incognito     Don't cache, record or learn from this run
+      --refresh-env   Detect tools and services again before answering
+      --timing        Print how long each step took
+      --output <MODE> Print suggestions as json, plain or table instead of the selector
+      --first         Print only the best command
+      --diagnose      Suggest fixes for error output piped in
+      --plan          Plan a multi-step task and run it step by step
+      --dry-run       Show what the picked command would affect instead of running it
+      --sandbox       Try the picked command in a read-only, offline sandbox first
+      --cwd <PATH>    Work as if started in this directory
+      --shell <SHELL> Write commands for bash, zsh, fish, powershell, nu or cmd instead of your shell
+      --model <NAME>  Use this model instead of the configured one
+      --backend <KIND>  Use ollama or openai instead of the configured backend
+  -v, --verbose       Print debug messages to the terminal
+      --log-level <LEVEL>  Log file level: off, error, warn, info, debug or trace
   -h, --help          Print help
 
 For more information, visit: https://phloem.sh
@@ -134,3 +360,36 @@ For more information, visit: https://phloem.sh
 
     Ok(())
 }
+
+/// Prints where the time went with `--timing`; `-v` logs each step instead
+fn report_timing(cli: &Cli) {
+    if cli.timing {
+        eprintln!("{}", Timing::report());
+    }
+}
+
+/// The prompt to answer: the argument, stdin for `-`, or the request to fix
+/// piped command output with `--diagnose`
+fn read_prompt(cli: &Cli) -> Result<Option<String>> {
+    let from_stdin = cli.prompt.as_deref() == Some("-");
+    if !from_stdin && !cli.diagnose {
+        return Ok(cli.prompt.clone());
+    }
+
+    if io::stdin().is_terminal() {
+        bail!("Nothing piped in; try `echo \"find big files\" | phloem -` or `make 2>&1 | phloem --diagnose`");
+    }
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input)?;
+
+    if cli.diagnose {
+        let request = cli.prompt.as_deref().filter(|_| !from_stdin);
+        return Ok(Some(CommandHandler::diagnosis_prompt(&input, request)));
+    }
+
+    let prompt = input.trim();
+    if prompt.is_empty() {
+        bail!("The prompt piped in is empty");
+    }
+    Ok(Some(prompt.to_string()))
+}
@@ -0,0 +1,108 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Most running containers named in the prompt
+const MAX_CONTAINERS: usize = 30;
+
+/// Label compose tools put on the containers of a project
+const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
+
+/// A running container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainerInfo {
+    pub name: String,
+    pub image: String,
+    /// The compose project that started it, if any
+    pub compose_project: Option<String>,
+}
+
+/// What's running in the detected container runtime
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainerState {
+    /// `Docker` or `Podman`
+    pub runtime: String,
+    pub containers: Vec<ContainerInfo>,
+    /// Compose projects with running containers
+    pub compose_projects: Vec<String>,
+}
+
+impl ContainerState {
+    /// One line for the prompt, e.g. `api (shop-api:latest), db (postgres:16)`
+    pub fn summary(&self) -> String {
+        self.containers
+            .iter()
+            .map(|container| format!("{} ({})", container.name, container.image))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+pub struct ContainerInspector;
+
+impl ContainerInspector {
+    /// Lists the running containers of `runtime` (as detected in the
+    /// environment, `Docker` or `Podman`), giving up after `timeout` so an
+    /// unresponsive daemon can't hold up the prompt
+    pub async fn inspect(runtime: &str, timeout: Duration) -> Option<ContainerState> {
+        let (program, format) = match runtime {
+            "Docker" => (
+                "docker",
+                format!("{{{{.Names}}}}\t{{{{.Image}}}}\t{{{{.Label \"{COMPOSE_PROJECT_LABEL}\"}}}}"),
+            ),
+            "Podman" => (
+                "podman",
+                format!(
+                    "{{{{.Names}}}}\t{{{{.Image}}}}\t{{{{index .Labels \"{COMPOSE_PROJECT_LABEL}\"}}}}"
+                ),
+            ),
+            _ => return None,
+        };
+
+        let probe = Command::new(program)
+            .args(["ps", "--format", &format])
+            .kill_on_drop(true)
+            .output();
+        let output = match tokio::time::timeout(timeout, probe).await {
+            Ok(Ok(output)) if output.status.success() => output,
+            Ok(_) => return None,
+            Err(_) => {
+                debug!("{program} ps did not answer within {timeout:?}, leaving out containers");
+                return None;
+            }
+        };
+
+        let mut state = ContainerState {
+            runtime: runtime.to_string(),
+            ..ContainerState::default()
+        };
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            let mut fields = line.split('\t');
+            let (Some(name), Some(image)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            // Podman prints `<no value>` for a missing label
+            let compose_project = fields
+                .next()
+                .map(str::trim)
+                .filter(|project| !project.is_empty() && *project != "<no value>")
+                .map(str::to_string);
+
+            if let Some(project) = &compose_project {
+                if !state.compose_projects.contains(project) {
+                    state.compose_projects.push(project.clone());
+                }
+            }
+            if state.containers.len() < MAX_CONTAINERS {
+                state.containers.push(ContainerInfo {
+                    name: name.trim().to_string(),
+                    image: image.trim().to_string(),
+                    compose_project,
+                });
+            }
+        }
+
+        (!state.containers.is_empty()).then_some(state)
+    }
+}
@@ -0,0 +1,79 @@
+use regex::Regex;
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// Most lines of a man page kept when it has no SYNOPSIS section
+const MAX_MAN_LINES: usize = 30;
+
+/// Width man pages are formatted for
+const MAN_WIDTH: &str = "80";
+
+pub struct CommandDocs;
+
+impl CommandDocs {
+    /// The tldr page of `executable`, or else the NAME and SYNOPSIS of its
+    /// man page; `None` when neither is installed or has a page
+    pub fn lookup(executable: &str) -> Option<String> {
+        Self::tldr(executable).or_else(|| Self::man_synopsis(executable))
+    }
+
+    fn tldr(executable: &str) -> Option<String> {
+        let output = Command::new("tldr")
+            .arg(executable)
+            .env("NO_COLOR", "1")
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+
+        let page = strip_formatting(&String::from_utf8_lossy(&output.stdout));
+        (!page.trim().is_empty()).then(|| page.trim().to_string())
+    }
+
+    fn man_synopsis(executable: &str) -> Option<String> {
+        let output = Command::new("man")
+            .args(["-P", "cat", executable])
+            .env("MANWIDTH", MAN_WIDTH)
+            .env("MAN_KEEP_FORMATTING", "")
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+
+        let page = strip_formatting(&String::from_utf8_lossy(&output.stdout));
+        let lines: Vec<&str> = page.lines().collect();
+
+        // Section headings are the unindented lines, e.g. `NAME` or `SYNOPSIS`
+        let is_heading = |line: &str| !line.is_empty() && !line.starts_with(char::is_whitespace);
+        let section = |name: &str| -> Vec<&str> {
+            let Some(start) = lines.iter().position(|line| line.trim_end() == name) else {
+                return Vec::new();
+            };
+            let end = lines[start + 1..]
+                .iter()
+                .position(|line| is_heading(line))
+                .map_or(lines.len(), |offset| start + 1 + offset);
+            lines[start..end].to_vec()
+        };
+
+        let mut preview = section("NAME");
+        preview.extend(section("SYNOPSIS"));
+        if preview.is_empty() {
+            preview = lines.into_iter().take(MAX_MAN_LINES).collect();
+        }
+        let preview = preview.join("\n");
+        (!preview.trim().is_empty()).then(|| preview.trim_end().to_string())
+    }
+}
+
+/// Removes terminal colors and the backspace overstriking man uses for bold
+/// and underline
+fn strip_formatting(text: &str) -> String {
+    static FORMATTING: OnceLock<Regex> = OnceLock::new();
+    FORMATTING
+        .get_or_init(|| Regex::new(r"\x1b\[[0-9;]*[A-Za-z]|.\x08").expect("valid regex"))
+        .replace_all(text, "")
+        .into_owned()
+}
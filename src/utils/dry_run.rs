@@ -0,0 +1,342 @@
+use std::process::{Command, Stdio};
+
+use crate::utils::shell_syntax::{Word, WRAPPERS};
+use crate::utils::{Script, ShellKind, SimpleCommand};
+
+/// Most lines of a preview's output kept
+const MAX_PREVIEW_LINES: usize = 200;
+
+/// kubectl flags given before the subcommand whose value is the next word
+const KUBECTL_VALUE_FLAGS: &[&str] = &[
+    "-n",
+    "--namespace",
+    "--context",
+    "--cluster",
+    "--kubeconfig",
+    "--user",
+    "-s",
+    "--server",
+];
+
+/// kubectl subcommands that take `--dry-run`
+const KUBECTL_DRY_RUN: &[&str] = &[
+    "apply",
+    "create",
+    "delete",
+    "replace",
+    "patch",
+    "scale",
+    "expose",
+    "run",
+    "label",
+    "annotate",
+    "set",
+    "autoscale",
+    "drain",
+    "cordon",
+    "uncordon",
+    "taint",
+];
+
+/// kubectl `--dry-run` forms that only simulate
+const KUBECTL_DRY_RUN_MODES: &[&str] = &["--dry-run", "--dry-run=client", "--dry-run=server"];
+
+/// How the effects of a command can be seen without making them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DryRun {
+    /// A command that only reads, listing the files the command would touch
+    Listing(String),
+    /// The command itself in its tool's own dry-run mode
+    Simulated(String),
+    /// No safe way to preview the command
+    Unavailable,
+}
+
+impl DryRun {
+    /// How to preview `command`, written for `shell`. Files given to `rm`,
+    /// `mv` and `cp` are listed, rsync, kubectl, git push and the like get
+    /// their dry-run flag and terraform plans instead of applying. Pipelines,
+    /// lists, redirections and substitutions have effects of their own, so
+    /// they have no preview
+    pub fn plan(command: &str, shell: ShellKind) -> Self {
+        // Previews are written for a POSIX shell
+        if cfg!(windows)
+            || matches!(
+                shell,
+                ShellKind::PowerShell | ShellKind::Nu | ShellKind::Cmd
+            )
+        {
+            return Self::Unavailable;
+        }
+        if command.contains(['|', ';', '&', '>', '<', '`', '\n']) || command.contains("$(") {
+            return Self::Unavailable;
+        }
+
+        let Ok(script) = Script::parse(command) else {
+            return Self::Unavailable;
+        };
+        let ([simple], true) = (script.commands.as_slice(), script.redirects.is_empty()) else {
+            return Self::Unavailable;
+        };
+        let Some(start) = simple.executable() else {
+            return Self::Unavailable;
+        };
+        let prefix = unprivileged_prefix(simple, start);
+        let words = &simple.words[start..];
+        let name = words[0].value.rsplit('/').next().unwrap_or_default();
+        let arguments = &words[1..];
+        let first_operand = || {
+            arguments
+                .iter()
+                .position(|word| !word.value.starts_with('-'))
+                .map(|index| index + 1)
+        };
+
+        match name {
+            "rm" | "rmdir" | "unlink" | "shred" => listing(
+                &operands(arguments),
+                has_flag(arguments, &['r', 'R'], &["--recursive"]),
+            ),
+            "mv" | "cp" => {
+                let recursive = name == "cp"
+                    && has_flag(arguments, &['r', 'R', 'a'], &["--recursive", "--archive"]);
+                listing(&sources(arguments), recursive)
+            }
+            "rsync" => simulated(prefix, words, 1, &["--dry-run", "--itemize-changes"]),
+            "kubectl" => match words
+                .iter()
+                .position(|word| word.value.starts_with("--dry-run"))
+            {
+                // `--dry-run=none` and the like still make the changes
+                Some(index) => {
+                    let mut preview = prefix;
+                    preview.extend(words.iter().enumerate().map(|(at, word)| {
+                        if at == index && !KUBECTL_DRY_RUN_MODES.contains(&word.value.as_str()) {
+                            "--dry-run=client"
+                        } else {
+                            word.raw.as_str()
+                        }
+                    }));
+                    Self::Simulated(preview.join(" "))
+                }
+                None => match kubectl_subcommand(arguments) {
+                    Some(subcommand) if KUBECTL_DRY_RUN.contains(&subcommand) => {
+                        simulated(prefix, words, words.len(), &["--dry-run=client"])
+                    }
+                    _ => Self::Unavailable,
+                },
+            },
+            "git" => match first_operand() {
+                Some(index)
+                    if ["push", "clean", "add", "rm", "mv"]
+                        .contains(&words[index].value.as_str()) =>
+                {
+                    simulated(prefix, words, index + 1, &["--dry-run"])
+                }
+                _ => Self::Unavailable,
+            },
+            "helm" => match first_operand() {
+                Some(index)
+                    if ["install", "upgrade", "uninstall"]
+                        .contains(&words[index].value.as_str()) =>
+                {
+                    simulated(prefix, words, words.len(), &["--dry-run"])
+                }
+                _ => Self::Unavailable,
+            },
+            "ansible-playbook" => simulated(prefix, words, words.len(), &["--check"]),
+            // `plan` is terraform's dry run of `apply` and, with `-destroy`, of `destroy`
+            "terraform" | "tofu" => {
+                let Some(index) = first_operand() else {
+                    return Self::Unavailable;
+                };
+                let plan: &[&str] = match words[index].value.as_str() {
+                    "apply" => &["plan"],
+                    "destroy" => &["plan", "-destroy"],
+                    _ => return Self::Unavailable,
+                };
+                let mut preview = prefix;
+                preview.extend(words[..index].iter().map(|word| word.raw.as_str()));
+                preview.extend(plan);
+                preview.push("-input=false");
+                preview.extend(
+                    words[index + 1..]
+                        .iter()
+                        .filter(|word| {
+                            !word
+                                .value
+                                .trim_start_matches('-')
+                                .starts_with("auto-approve")
+                        })
+                        .map(|word| word.raw.as_str()),
+                );
+                Self::Simulated(preview.join(" "))
+            }
+            _ => Self::Unavailable,
+        }
+    }
+
+    /// The command run to preview, when there is one
+    pub fn command(&self) -> Option<&str> {
+        match self {
+            Self::Listing(command) | Self::Simulated(command) => Some(command),
+            Self::Unavailable => None,
+        }
+    }
+
+    /// Runs the preview and returns what it printed; `None` without a preview
+    pub fn run(&self) -> Option<String> {
+        let command = self.command()?;
+        let output = match Command::new("sh")
+            .args(["-c", command])
+            .stdin(Stdio::null())
+            .output()
+        {
+            Ok(output) => output,
+            Err(e) => return Some(format!("Failed to run the preview: {e}")),
+        };
+
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+        let lines: Vec<&str> = text.trim_end().lines().collect();
+        let mut preview = lines[..lines.len().min(MAX_PREVIEW_LINES)].join("\n");
+        if lines.len() > MAX_PREVIEW_LINES {
+            preview.push_str(&format!(
+                "\n... {} more lines",
+                lines.len() - MAX_PREVIEW_LINES
+            ));
+        }
+        if preview.is_empty() {
+            preview = match self {
+                Self::Listing(_) => "Nothing matches".to_string(),
+                _ => "The preview printed nothing".to_string(),
+            };
+        }
+        Some(preview)
+    }
+}
+
+/// Listing of `paths`, with the contents of directories when `recursive`.
+/// The paths are words as written, so their quoting and globs carry over
+fn listing(paths: &[&str], recursive: bool) -> DryRun {
+    if paths.is_empty() {
+        return DryRun::Unavailable;
+    }
+    let flags = if recursive { "-lR" } else { "-ld" };
+    DryRun::Listing(format!("ls {flags} -- {}", paths.join(" ")))
+}
+
+/// `words` as written with `flags` inserted at `at`, behind `prefix`
+fn simulated(prefix: Vec<&str>, words: &[Word], at: usize, flags: &[&str]) -> DryRun {
+    let mut preview = prefix;
+    preview.extend(words[..at].iter().map(|word| word.raw.as_str()));
+    preview.extend(flags);
+    preview.extend(words[at..].iter().map(|word| word.raw.as_str()));
+    DryRun::Simulated(preview.join(" "))
+}
+
+/// The assignments and wrappers before the word at `start` as written,
+/// leaving out `sudo` and `doas` with their options: previews run unasked,
+/// so never as root
+fn unprivileged_prefix(command: &SimpleCommand, start: usize) -> Vec<&str> {
+    let mut prefix: Vec<&str> = command
+        .assignments
+        .iter()
+        .map(|word| word.raw.as_str())
+        .collect();
+    let mut elevating = false;
+    for word in &command.words[..start] {
+        let value = word.value.as_str();
+        if matches!(value, "sudo" | "doas") {
+            elevating = true;
+        } else if !elevating || WRAPPERS.contains(&value) {
+            elevating = false;
+            prefix.push(&word.raw);
+        }
+    }
+    prefix
+}
+
+/// The arguments that aren't flags; everything after `--` is one
+fn operands(arguments: &[Word]) -> Vec<&str> {
+    let mut after_flags = false;
+    arguments
+        .iter()
+        .filter(|word| {
+            if after_flags {
+                return true;
+            }
+            if word.value == "--" {
+                after_flags = true;
+                return false;
+            }
+            !word.value.starts_with('-')
+        })
+        .map(|word| word.raw.as_str())
+        .collect()
+}
+
+/// What `mv` or `cp` would move or copy: all operands but the destination,
+/// or all of them when it's given with `-t`
+fn sources(arguments: &[Word]) -> Vec<&str> {
+    let mut sources = Vec::new();
+    let mut target_given = false;
+    let mut after_flags = false;
+    let mut skip_next = false;
+    for word in arguments {
+        if skip_next {
+            skip_next = false;
+        } else if after_flags {
+            sources.push(word.raw.as_str());
+        } else {
+            match word.value.as_str() {
+                "--" => after_flags = true,
+                "-t" | "--target-directory" => {
+                    target_given = true;
+                    skip_next = true;
+                }
+                flag if flag.starts_with("--target-directory=")
+                    || (flag.starts_with("-t") && !flag.starts_with("--")) =>
+                {
+                    target_given = true;
+                }
+                flag if flag.starts_with('-') => {}
+                _ => sources.push(word.raw.as_str()),
+            }
+        }
+    }
+
+    if !target_given {
+        sources.pop();
+    }
+    sources
+}
+
+/// The kubectl subcommand, after any global flags such as `-n NAMESPACE`
+fn kubectl_subcommand(arguments: &[Word]) -> Option<&str> {
+    let mut previous: Option<&str> = None;
+    for word in arguments {
+        let word = word.value.as_str();
+        if !word.starts_with('-')
+            && !previous.is_some_and(|flag| KUBECTL_VALUE_FLAGS.contains(&flag))
+        {
+            return Some(word);
+        }
+        previous = Some(word);
+    }
+    None
+}
+
+/// Whether a short flag cluster has one of `short` or a word is one of `long`
+fn has_flag(arguments: &[Word], short: &[char], long: &[&str]) -> bool {
+    arguments
+        .iter()
+        .map(|word| word.value.as_str())
+        .take_while(|word| *word != "--")
+        .any(|word| {
+            long.contains(&word)
+                || (word.starts_with('-')
+                    && !word.starts_with("--")
+                    && word.chars().skip(1).any(|c| short.contains(&c)))
+        })
+}
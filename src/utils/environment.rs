@@ -2,10 +2,37 @@ use anyhow::Result;
 use std::collections::HashMap;
 use std::env;
 use std::process::Command;
-use which::which;
+
+use crate::utils::{ExecutableIndex, ShellDetector};
 
 pub struct EnvironmentDetector;
 
+/// GPU acceleration usable by local models
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Accelerator {
+    Metal,
+    Cuda,
+    Rocm,
+}
+
+impl Accelerator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Accelerator::Metal => "Metal",
+            Accelerator::Cuda => "CUDA",
+            Accelerator::Rocm => "ROCm",
+        }
+    }
+}
+
+/// Hardware relevant to running local models
+#[derive(Debug, Clone)]
+pub struct HardwareInfo {
+    pub accelerator: Option<Accelerator>,
+    /// Physical CPU cores, or logical ones when those can't be determined
+    pub cpu_cores: usize,
+}
+
 impl Default for EnvironmentDetector {
     fn default() -> Self {
         Self::new()
@@ -17,16 +44,20 @@ impl EnvironmentDetector {
         Self
     }
 
-    pub fn detect_environment(&self) -> Result<HashMap<String, String>> {
+    /// Values that are cheap to read and can change between runs, such as the
+    /// working directory; these are never served from the cached snapshot
+    pub fn detect_session(&self) -> HashMap<String, String> {
         let mut env_info = HashMap::new();
 
         // Basic system information
         env_info.insert("os".to_string(), env::consts::OS.to_string());
         env_info.insert("arch".to_string(), env::consts::ARCH.to_string());
 
-        // Shell information
+        // Shell information; Windows has no SHELL outside of Git Bash and MSYS
         if let Ok(shell) = env::var("SHELL") {
             env_info.insert("shell".to_string(), shell);
+        } else if cfg!(windows) {
+            env_info.insert("shell".to_string(), ShellDetector::detect_shell());
         }
 
         // Terminal information
@@ -39,6 +70,43 @@ impl EnvironmentDetector {
             env_info.insert("pwd".to_string(), pwd.display().to_string());
         }
 
+        // The machine phloem runs on, and whether the user reached it over SSH
+        if let Some(host) = Self::hostname() {
+            env_info.insert("host".to_string(), host);
+        }
+        if Self::in_ssh_session() {
+            env_info.insert("ssh_session".to_string(), "true".to_string());
+        }
+
+        env_info
+    }
+
+    /// Name of this machine
+    pub fn hostname() -> Option<String> {
+        let name = std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .ok()
+            .or_else(|| {
+                let output = Command::new("hostname").output().ok()?;
+                output
+                    .status
+                    .success()
+                    .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+            })?;
+        let name = name.trim();
+        (!name.is_empty()).then(|| name.to_string())
+    }
+
+    /// Whether phloem runs in a shell the user reached over SSH
+    pub fn in_ssh_session() -> bool {
+        ["SSH_CONNECTION", "SSH_CLIENT", "SSH_TTY"]
+            .iter()
+            .any(|var| env::var_os(var).is_some_and(|value| !value.is_empty()))
+    }
+
+    /// Everything known about the environment, including the slow tool and service probes
+    pub fn detect_environment(&self) -> Result<HashMap<String, String>> {
+        let mut env_info = self.detect_session();
+
         // Detect available tools
         let available_tools = self.detect_available_tools();
         env_info.insert("available_tools".to_string(), available_tools.join(","));
@@ -53,9 +121,9 @@ impl EnvironmentDetector {
             env_info.insert("cloud_provider".to_string(), cloud_provider);
         }
 
-        // Kubernetes context
-        if let Some(k8s_context) = self.detect_kubernetes_context() {
-            env_info.insert("kubernetes_context".to_string(), k8s_context);
+        // GPU acceleration
+        if let Some(accelerator) = self.detect_accelerator() {
+            env_info.insert("gpu".to_string(), accelerator.as_str().to_string());
         }
 
         Ok(env_info)
@@ -132,7 +200,7 @@ impl EnvironmentDetector {
         ];
 
         for tool in &common_tools {
-            if which(tool).is_ok() {
+            if ExecutableIndex::contains(tool) {
                 available.push(tool.to_string());
             }
         }
@@ -169,7 +237,7 @@ impl EnvironmentDetector {
     }
 
     fn detect_container_runtime(&self) -> Option<String> {
-        if which("docker").is_ok() {
+        if ExecutableIndex::contains("docker") {
             // Check if Docker is running
             if let Ok(output) = Command::new("docker").arg("info").output() {
                 if output.status.success() {
@@ -178,7 +246,7 @@ impl EnvironmentDetector {
             }
         }
 
-        if which("podman").is_ok() {
+        if ExecutableIndex::contains("podman") {
             return Some("Podman".to_string());
         }
 
@@ -221,21 +289,99 @@ impl EnvironmentDetector {
         None
     }
 
-    fn detect_kubernetes_context(&self) -> Option<String> {
-        if which("kubectl").is_ok() {
-            if let Ok(output) = Command::new("kubectl")
-                .args(["config", "current-context"])
-                .output()
-            {
-                if output.status.success() {
-                    let context = String::from_utf8_lossy(&output.stdout).trim().to_string();
-                    if !context.is_empty() {
-                        return Some(context);
-                    }
+    /// Detects GPU acceleration and CPU cores using cheap file and sysctl checks
+    pub fn detect_hardware(&self) -> HardwareInfo {
+        HardwareInfo {
+            accelerator: self.detect_accelerator(),
+            cpu_cores: self.detect_cpu_cores(),
+        }
+    }
+
+    fn detect_accelerator(&self) -> Option<Accelerator> {
+        match env::consts::OS {
+            // Only Apple Silicon Macs get Metal acceleration in Ollama
+            "macos" if env::consts::ARCH == "aarch64" => Some(Accelerator::Metal),
+            "linux" => {
+                if std::path::Path::new("/proc/driver/nvidia/version").exists() {
+                    Some(Accelerator::Cuda)
+                } else if std::path::Path::new("/dev/kfd").exists() {
+                    Some(Accelerator::Rocm)
+                } else {
+                    None
                 }
             }
+            _ => None,
         }
+    }
 
-        None
+    fn detect_cpu_cores(&self) -> usize {
+        let logical = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+
+        let physical = match env::consts::OS {
+            "linux" => std::fs::read_to_string("/proc/cpuinfo")
+                .ok()
+                .map(|cpuinfo| {
+                    // Unique (physical id, core id) pairs are physical cores
+                    let mut cores = std::collections::HashSet::new();
+                    let mut physical_id = "";
+                    for line in cpuinfo.lines() {
+                        if let Some((key, value)) = line.split_once(':') {
+                            match key.trim() {
+                                "physical id" => physical_id = value.trim(),
+                                "core id" => {
+                                    cores.insert((physical_id, value.trim()));
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                    cores.len()
+                }),
+            "macos" => Command::new("sysctl")
+                .args(["-n", "hw.physicalcpu"])
+                .output()
+                .ok()
+                .and_then(|output| String::from_utf8_lossy(&output.stdout).trim().parse().ok()),
+            _ => None,
+        };
+
+        physical.filter(|&n| n > 0).unwrap_or(logical)
+    }
+
+    /// Name of the GPU as reported by the vendor tool, if one is installed
+    pub fn detect_gpu_name(&self, accelerator: Accelerator) -> Option<String> {
+        let output = match accelerator {
+            Accelerator::Cuda => Command::new("nvidia-smi")
+                .args(["--query-gpu=name", "--format=csv,noheader"])
+                .output()
+                .ok()?,
+            Accelerator::Metal => Command::new("sysctl")
+                .args(["-n", "machdep.cpu.brand_string"])
+                .output()
+                .ok()?,
+            Accelerator::Rocm => Command::new("rocm-smi")
+                .arg("--showproductname")
+                .output()
+                .ok()?,
+        };
+
+        if !output.status.success() {
+            return None;
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let name = match accelerator {
+            // e.g. "GPU[0]		: Card series: 		Radeon RX 7900 XTX"
+            Accelerator::Rocm => stdout
+                .lines()
+                .find(|line| line.contains("Card series"))
+                .and_then(|line| line.rsplit(':').next())
+                .map(str::trim)?,
+            _ => stdout.lines().next().map(str::trim)?,
+        };
+
+        Some(name.to_string()).filter(|n| !n.is_empty())
     }
 }
@@ -0,0 +1,128 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+use which::which;
+
+/// The index in use, from `ExecutableIndex::install`, or one scanned on first use
+static INDEX: OnceLock<ExecutableIndex> = OnceLock::new();
+
+/// Names of the executables in PATH, read once instead of searching PATH for
+/// every command checked
+#[derive(Debug, Clone, Default)]
+pub struct ExecutableIndex {
+    names: HashSet<String>,
+}
+
+impl ExecutableIndex {
+    /// Index of what's in PATH now; on Windows names are kept lowercased and
+    /// without their `PATHEXT` extension, as they're typed
+    pub fn scan() -> Self {
+        let Some(path) = env::var_os("PATH") else {
+            return Self::default();
+        };
+        let extensions = path_extensions();
+
+        let mut names = HashSet::new();
+        for dir in env::split_paths(&path) {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                    continue;
+                };
+                if cfg!(windows) {
+                    let name = name.to_lowercase();
+                    if let Some(stem) = extensions
+                        .iter()
+                        .find_map(|extension| name.strip_suffix(extension.as_str()))
+                    {
+                        names.insert(stem.to_string());
+                    }
+                } else if is_executable(&entry.path()) {
+                    names.insert(name);
+                }
+            }
+        }
+        Self { names }
+    }
+
+    /// Index of `names`, e.g. as stored by an earlier run
+    pub fn from_names(names: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            names: names.into_iter().collect(),
+        }
+    }
+
+    /// The indexed names, in no particular order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// What the index was built from: PATH, and on Windows the extensions
+    /// that make a file runnable; a stored index is stale once this changes
+    pub fn fingerprint() -> String {
+        let path = env::var("PATH").unwrap_or_default();
+        if cfg!(windows) {
+            format!("{path}|{}", env::var("PATHEXT").unwrap_or_default())
+        } else {
+            path
+        }
+    }
+
+    /// Makes `index` the one lookups use; only the first call counts
+    pub fn install(index: ExecutableIndex) {
+        let _ = INDEX.set(index);
+    }
+
+    /// The installed index, or one scanned now
+    pub fn current() -> &'static ExecutableIndex {
+        INDEX.get_or_init(Self::scan)
+    }
+
+    /// Whether `name` runs from PATH. Names missing from the index are looked
+    /// up in PATH itself, in case they were installed after it was built
+    pub fn contains(name: &str) -> bool {
+        let index = Self::current();
+        let indexed = if cfg!(windows) {
+            index.names.contains(&name.to_lowercase())
+        } else {
+            index.names.contains(name)
+        };
+        indexed || which(name).is_ok()
+    }
+}
+
+/// Lowercased extensions of runnable files on Windows, like `.exe`
+fn path_extensions() -> Vec<String> {
+    env::var("PATHEXT")
+        .unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string())
+        .split(';')
+        .filter(|extension| !extension.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Whether `path` is a file anyone may run, following symlinks
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
@@ -0,0 +1,183 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Command;
+
+/// Largest share of the diff budget a single file may take
+const MAX_FILE_DIFF_CHARS: usize = 3000;
+
+pub struct GitInspector;
+
+/// State of the git repository around the current directory
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GitState {
+    /// Checked out branch; `None` on a detached HEAD
+    pub branch: Option<String>,
+    /// Upstream the branch tracks, e.g. `origin/main`
+    pub upstream: Option<String>,
+    pub ahead: u32,
+    pub behind: u32,
+    /// Files with staged, unstaged or untracked changes
+    pub changed_files: usize,
+    pub remotes: Vec<String>,
+    /// Rebase, merge or similar operation waiting to be continued
+    pub operation: Option<String>,
+}
+
+impl GitState {
+    /// One-line description for the model, e.g.
+    /// `branch main tracking origin/main (ahead 2); 3 changed files; remotes: origin`
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+
+        let mut branch = match &self.branch {
+            Some(branch) => format!("branch {branch}"),
+            None => "detached HEAD".to_string(),
+        };
+        if let Some(upstream) = &self.upstream {
+            branch.push_str(&format!(" tracking {upstream}"));
+            match (self.ahead, self.behind) {
+                (0, 0) => branch.push_str(" (up to date)"),
+                (ahead, 0) => branch.push_str(&format!(" (ahead {ahead})")),
+                (0, behind) => branch.push_str(&format!(" (behind {behind})")),
+                (ahead, behind) => branch.push_str(&format!(" (ahead {ahead}, behind {behind})")),
+            }
+        } else if self.branch.is_some() {
+            branch.push_str(" (no upstream)");
+        }
+        parts.push(branch);
+
+        parts.push(match self.changed_files {
+            0 => "clean working tree".to_string(),
+            1 => "1 changed file".to_string(),
+            files => format!("{files} changed files"),
+        });
+
+        if !self.remotes.is_empty() {
+            parts.push(format!("remotes: {}", self.remotes.join(", ")));
+        }
+
+        if let Some(operation) = &self.operation {
+            parts.push(format!("{operation} in progress"));
+        }
+
+        parts.join("; ")
+    }
+}
+
+impl GitInspector {
+    /// Branch, working tree state, remotes and any operation in progress of the
+    /// repository around the current directory; `None` outside a repository
+    pub fn repository_state() -> Option<GitState> {
+        let status = Self::git(&["status", "--porcelain=v2", "--branch"]).ok()?;
+
+        let mut state = GitState::default();
+        for line in status.lines() {
+            if let Some(head) = line.strip_prefix("# branch.head ") {
+                state.branch = (head != "(detached)").then(|| head.to_string());
+            } else if let Some(upstream) = line.strip_prefix("# branch.upstream ") {
+                state.upstream = Some(upstream.to_string());
+            } else if let Some(counts) = line.strip_prefix("# branch.ab ") {
+                for count in counts.split_whitespace() {
+                    if let Some(ahead) = count.strip_prefix('+') {
+                        state.ahead = ahead.parse().unwrap_or(0);
+                    } else if let Some(behind) = count.strip_prefix('-') {
+                        state.behind = behind.parse().unwrap_or(0);
+                    }
+                }
+            } else if !line.starts_with('#') && !line.is_empty() {
+                state.changed_files += 1;
+            }
+        }
+
+        state.remotes = Self::git(&["remote"])
+            .map(|remotes| remotes.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+
+        state.operation = Self::git(&["rev-parse", "--absolute-git-dir"])
+            .ok()
+            .and_then(|git_dir| Self::operation_in_progress(Path::new(git_dir.trim())));
+
+        Some(state)
+    }
+
+    /// Operation git stopped in the middle of, judging by the files it leaves behind
+    fn operation_in_progress(git_dir: &Path) -> Option<String> {
+        let markers = [
+            ("rebase-merge", "rebase"),
+            ("rebase-apply", "rebase"),
+            ("MERGE_HEAD", "merge"),
+            ("CHERRY_PICK_HEAD", "cherry-pick"),
+            ("REVERT_HEAD", "revert"),
+            ("BISECT_LOG", "bisect"),
+        ];
+
+        markers
+            .iter()
+            .find(|(marker, _)| git_dir.join(marker).exists())
+            .map(|(_, operation)| operation.to_string())
+    }
+
+    /// Summary of the staged changes for the model: the `--stat` overview
+    /// followed by the patch, truncated per file and overall to `max_chars`.
+    /// Returns `None` when nothing is staged.
+    pub fn staged_diff(max_chars: usize) -> Result<Option<String>> {
+        let stat = Self::git(&["diff", "--staged", "--stat"])?;
+        if stat.trim().is_empty() {
+            return Ok(None);
+        }
+
+        let patch = Self::git(&["diff", "--staged", "--no-color", "--no-ext-diff"])?;
+
+        let mut summary = format!("{}\n", stat.trim_end());
+        let files: Vec<&str> = patch
+            .split("\ndiff --git ")
+            .filter(|file| !file.trim().is_empty())
+            .collect();
+
+        for (index, file) in files.iter().enumerate() {
+            let file = file.strip_prefix("diff --git ").unwrap_or(file);
+            let mut section = format!("\ndiff --git {}", truncate(file, MAX_FILE_DIFF_CHARS));
+
+            if summary.len() + section.len() > max_chars {
+                section = format!("\n... ({} more files omitted)\n", files.len() - index);
+                summary.push_str(&section);
+                break;
+            }
+            summary.push_str(&section);
+        }
+
+        Ok(Some(summary))
+    }
+
+    fn git(args: &[&str]) -> Result<String> {
+        let output = Command::new("git")
+            .args(args)
+            .output()
+            .context("Failed to run git")?;
+
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// Cuts `text` to at most `max_chars` bytes on a line boundary, noting the cut
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.len() <= max_chars {
+        return text.to_string();
+    }
+
+    let mut end = max_chars;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let cut = text[..end].rfind('\n').unwrap_or(end);
+    format!("{}\n... (truncated)\n", &text[..cut])
+}
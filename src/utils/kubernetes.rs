@@ -0,0 +1,154 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::process::Command;
+
+use crate::utils::{ExecutableIndex, ShellDetector};
+
+/// Most other contexts named in the prompt
+const MAX_CONTEXTS: usize = 10;
+
+/// Most recently used resource kinds named in the prompt
+const MAX_KINDS: usize = 5;
+
+/// kubectl verbs whose first argument is a resource kind
+const KIND_VERBS: &[&str] = &[
+    "get", "describe", "delete", "edit", "scale", "top", "label", "annotate", "patch",
+];
+
+/// kubectl flags that take the next word as their value
+const VALUE_FLAGS: &[&str] = &[
+    "-n",
+    "--namespace",
+    "-o",
+    "--output",
+    "-l",
+    "--selector",
+    "--context",
+];
+
+/// Where kubectl commands would go, and what the user works with there
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KubernetesState {
+    /// The current kubeconfig context
+    pub context: String,
+    /// Namespace of the current context
+    pub namespace: String,
+    /// The other contexts in the kubeconfig
+    pub contexts: Vec<String>,
+    /// Resource kinds from recent kubectl commands, most recent first
+    pub recent_kinds: Vec<String>,
+}
+
+impl KubernetesState {
+    /// One line for the prompt, e.g. `context prod, namespace payments; other contexts: dev`
+    pub fn summary(&self) -> String {
+        let mut summary = format!("context {}, namespace {}", self.context, self.namespace);
+        if !self.contexts.is_empty() {
+            summary.push_str(&format!("; other contexts: {}", self.contexts.join(", ")));
+        }
+        summary
+    }
+}
+
+pub struct KubernetesInspector;
+
+impl KubernetesInspector {
+    /// Reads the current context, its namespace and the other contexts from
+    /// kubectl, giving up after `timeout` so a slow kubeconfig can't hold up
+    /// the prompt; `None` without kubectl or a current context
+    pub async fn inspect(timeout: Duration, history: &[String]) -> Option<KubernetesState> {
+        if !ExecutableIndex::contains("kubectl") {
+            return None;
+        }
+
+        let probe = async {
+            tokio::join!(
+                kubectl(&["config", "current-context"]),
+                kubectl(&["config", "view", "--minify", "-o", "jsonpath={..namespace}"]),
+                kubectl(&["config", "get-contexts", "-o", "name"]),
+            )
+        };
+        let (context, namespace, contexts) = match tokio::time::timeout(timeout, probe).await {
+            Ok(results) => results,
+            Err(_) => {
+                debug!("kubectl did not answer within {timeout:?}, leaving out Kubernetes context");
+                return None;
+            }
+        };
+
+        let context = context.filter(|context| !context.is_empty())?;
+        let contexts = contexts
+            .unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            .filter(|name| !name.is_empty() && *name != context)
+            .take(MAX_CONTEXTS)
+            .map(str::to_string)
+            .collect();
+
+        Some(KubernetesState {
+            namespace: namespace
+                .filter(|namespace| !namespace.is_empty())
+                .unwrap_or_else(|| "default".to_string()),
+            context,
+            contexts,
+            recent_kinds: recent_resource_kinds(history),
+        })
+    }
+}
+
+/// Trimmed stdout of a successful kubectl run; the process is killed if the
+/// probe is abandoned
+async fn kubectl(args: &[&str]) -> Option<String> {
+    let output = Command::new("kubectl")
+        .args(args)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Resource kinds the user ran kubectl against, newest first, following
+/// aliases such as `k` for `kubectl`
+fn recent_resource_kinds(history: &[String]) -> Vec<String> {
+    let mut kinds: Vec<String> = Vec::new();
+
+    for command in history {
+        let mut words = command.split_whitespace();
+        let Some(first) = words.next() else {
+            continue;
+        };
+        let is_kubectl = first == "kubectl"
+            || ShellDetector::user_aliases().iter().any(|alias| {
+                alias.name == first && alias.expansion.split_whitespace().next() == Some("kubectl")
+            });
+        if !is_kubectl {
+            continue;
+        }
+
+        // The kind follows the verb, skipping flags such as `-n payments`
+        let mut words = words.skip_while(|word| !KIND_VERBS.contains(word)).skip(1);
+        let mut kind = None;
+        while let Some(word) = words.next() {
+            if VALUE_FLAGS.contains(&word) {
+                words.next();
+            } else if !word.starts_with('-') {
+                kind = word.split(['/', ',']).next().map(str::to_lowercase);
+                break;
+            }
+        }
+        if let Some(kind) = kind.filter(|kind| !kinds.contains(kind)) {
+            kinds.push(kind);
+        }
+        if kinds.len() >= MAX_KINDS {
+            break;
+        }
+    }
+
+    kinds
+}
@@ -0,0 +1,95 @@
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Most entries of a directory named in the prompt
+const MAX_ENTRIES: usize = 40;
+
+/// Most file extensions summarised
+const MAX_EXTENSIONS: usize = 8;
+
+/// What's in a directory, leaving out hidden and git-ignored entries
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryListing {
+    /// Entry names, directories first and ending in `/`
+    pub entries: Vec<String>,
+    /// How many entries were left out of `entries`
+    pub omitted: usize,
+    /// The most common file extensions and how many files have them
+    pub extensions: Vec<(String, usize)>,
+}
+
+impl DirectoryListing {
+    /// Lists `dir`; `None` when it's empty or can't be read
+    pub fn detect(dir: &Path) -> Option<Self> {
+        let mut directories = Vec::new();
+        let mut files = Vec::new();
+        let mut extension_counts: HashMap<String, usize> = HashMap::new();
+
+        for entry in WalkBuilder::new(dir)
+            .max_depth(Some(1))
+            .build()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.depth() == 1)
+        {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type().is_some_and(|kind| kind.is_dir()) {
+                directories.push(format!("{name}/"));
+                continue;
+            }
+
+            if let Some(extension) = Path::new(&name).extension() {
+                *extension_counts
+                    .entry(extension.to_string_lossy().to_lowercase())
+                    .or_default() += 1;
+            }
+            files.push(name);
+        }
+
+        if directories.is_empty() && files.is_empty() {
+            return None;
+        }
+
+        directories.sort();
+        files.sort();
+        let mut entries = directories;
+        entries.extend(files);
+        let omitted = entries.len().saturating_sub(MAX_ENTRIES);
+        entries.truncate(MAX_ENTRIES);
+
+        let mut extensions: Vec<(String, usize)> = extension_counts.into_iter().collect();
+        extensions.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        extensions.truncate(MAX_EXTENSIONS);
+
+        Some(Self {
+            entries,
+            omitted,
+            extensions,
+        })
+    }
+
+    /// Listing of the current working directory
+    pub fn current() -> Option<Self> {
+        let dir = std::env::current_dir().ok()?;
+        Self::detect(&dir)
+    }
+
+    /// One line for the prompt, e.g. `src/, Cargo.toml, README.md (and 3 more)`
+    pub fn summary(&self) -> String {
+        let mut summary = self.entries.join(", ");
+        if self.omitted > 0 {
+            summary.push_str(&format!(" (and {} more)", self.omitted));
+        }
+        summary
+    }
+
+    /// Extension counts, e.g. `12 .log, 3 .py`
+    pub fn extension_summary(&self) -> String {
+        self.extensions
+            .iter()
+            .map(|(extension, count)| format!("{count} .{extension}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
@@ -0,0 +1,170 @@
+use anyhow::{Context, Result};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::config::settings::LoggingConfig;
+
+/// Messages of other crates (reqwest, hyper, ...) below this level are
+/// left out, as they drown phloem's own
+const DEPENDENCY_LEVEL: LevelFilter = LevelFilter::Warn;
+
+/// Writes log messages to the terminal and to a log file that is rotated by
+/// size, each with its own level
+pub struct Logger {
+    console: LevelFilter,
+    file_level: LevelFilter,
+    file: Option<Mutex<LogFile>>,
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl Logger {
+    /// Installs the logger: `console` is the level printed to stderr, `file`
+    /// the log file and its settings, with `level` in place of the
+    /// configured level when given. The log file is left out when it can't
+    /// be opened, so logging never stops phloem from running
+    pub fn init(
+        console: LevelFilter,
+        file: Option<(&Path, &LoggingConfig)>,
+        level: Option<LevelFilter>,
+    ) -> Result<()> {
+        let (file_level, file) = match file {
+            Some((path, config)) => {
+                let file_level = level.unwrap_or_else(|| config.level.into());
+                let file = (file_level != LevelFilter::Off)
+                    .then(|| LogFile::open(path, config))
+                    .transpose()
+                    .unwrap_or_else(|e| {
+                        eprintln!("Warning: {e:#}, logging to the terminal only");
+                        None
+                    });
+                (file_level, file.map(Mutex::new))
+            }
+            None => (LevelFilter::Off, None),
+        };
+
+        let logger = Self {
+            console,
+            file_level,
+            file,
+        };
+        let max_level = logger.console.max(logger.file_level);
+        // The logger lives as long as the process
+        log::set_logger(Box::leak(Box::new(logger)))
+            .map_err(|_| anyhow::anyhow!("A logger is already installed"))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+
+    fn allows(level: LevelFilter, metadata: &Metadata) -> bool {
+        let level = if metadata.target().starts_with("phloem") {
+            level
+        } else {
+            level.min(DEPENDENCY_LEVEL)
+        };
+        metadata.level() <= level
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        Self::allows(self.console, metadata) || Self::allows(self.file_level, metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let metadata = record.metadata();
+        if Self::allows(self.console, metadata) {
+            eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
+        }
+        if Self::allows(self.file_level, metadata) {
+            if let Some(file) = &self.file {
+                if let Ok(mut file) = file.lock() {
+                    let line = format!(
+                        "{} {:<5} {}: {}\n",
+                        chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                        record.level(),
+                        record.target(),
+                        record.args()
+                    );
+                    file.write(line.as_bytes());
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.file.flush();
+            }
+        }
+    }
+}
+
+impl LogFile {
+    fn open(path: &Path, config: &LoggingConfig) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let file = Self::append(path)?;
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_bytes: config.max_file_kb * 1024,
+            max_files: config.max_files,
+        })
+    }
+
+    fn append(path: &Path) -> Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))
+    }
+
+    /// Appends `bytes`, rotating the file first when they'd take it past its size
+    fn write(&mut self, bytes: &[u8]) {
+        if self.size > 0 && self.size + bytes.len() as u64 > self.max_bytes {
+            if let Err(e) = self.rotate() {
+                eprintln!("Warning: Failed to rotate {}: {e:#}", self.path.display());
+            }
+        }
+        if self.file.write_all(bytes).is_ok() {
+            self.size += bytes.len() as u64;
+        }
+    }
+
+    /// Renames phloem.log to phloem.log.1, phloem.log.1 to phloem.log.2 and
+    /// so on, dropping the oldest, and starts a new phloem.log
+    fn rotate(&mut self) -> Result<()> {
+        let rotated = |index: usize| PathBuf::from(format!("{}.{index}", self.path.display()));
+
+        if self.max_files == 0 {
+            let _ = fs::remove_file(&self.path);
+        } else {
+            let _ = fs::remove_file(rotated(self.max_files));
+            for index in (1..self.max_files).rev() {
+                let _ = fs::rename(rotated(index), rotated(index + 1));
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+
+        self.file = Self::append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
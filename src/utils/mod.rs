@@ -1,7 +1,43 @@
+pub mod containers;
+pub mod docs;
+pub mod dry_run;
 pub mod environment;
+pub mod executables;
+pub mod git;
+pub mod kubernetes;
+pub mod listing;
+pub mod logging;
+pub mod packages;
+pub mod policy;
+pub mod project;
+pub mod redaction;
+pub mod release;
+pub mod sandbox;
 pub mod shell;
+pub mod shell_profile;
+pub mod shell_syntax;
+pub mod shellcheck;
+pub mod timing;
 pub mod validation;
 
-pub use environment::EnvironmentDetector;
-pub use shell::ShellDetector;
-pub use validation::CommandValidator;
+pub use containers::{ContainerInspector, ContainerState};
+pub use docs::CommandDocs;
+pub use dry_run::DryRun;
+pub use environment::{Accelerator, EnvironmentDetector, HardwareInfo};
+pub use executables::ExecutableIndex;
+pub use git::{GitInspector, GitState};
+pub use kubernetes::{KubernetesInspector, KubernetesState};
+pub use listing::DirectoryListing;
+pub use logging::Logger;
+pub use packages::PackageManager;
+pub use policy::{Categories, InvalidPolicy, Policy, PolicyAction, Verdict};
+pub use project::{Project, ProjectInfo};
+pub use redaction::SecretRedactor;
+pub use release::Release;
+pub use sandbox::Sandbox;
+pub use shell::{ShellAlias, ShellDetector, ShellKind};
+pub use shell_profile::ShellProfile;
+pub use shell_syntax::{Script, SimpleCommand, SyntaxError};
+pub use shellcheck::{ShellCheck, ShellCheckFinding};
+pub use timing::Timing;
+pub use validation::{CommandValidator, Risk, RiskLevel};
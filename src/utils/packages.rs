@@ -0,0 +1,138 @@
+use std::process::{Command, Stdio};
+
+use crate::utils::ExecutableIndex;
+
+/// winget installs by package id, so only tools listed here are offered there
+const WINGET_IDS: &[(&str, &str)] = &[
+    ("rg", "BurntSushi.ripgrep.MSVC"),
+    ("fd", "sharkdp.fd"),
+    ("bat", "sharkdp.bat"),
+    ("jq", "jqlang.jq"),
+    ("gh", "GitHub.cli"),
+    ("git", "Git.Git"),
+    ("http", "HTTPie.HTTPie"),
+    ("kubectl", "Kubernetes.kubectl"),
+    ("helm", "Helm.Helm"),
+    ("terraform", "Hashicorp.Terraform"),
+    ("node", "OpenJS.NodeJS"),
+    ("npm", "OpenJS.NodeJS"),
+    ("ffmpeg", "Gyan.FFmpeg"),
+    ("ffprobe", "Gyan.FFmpeg"),
+    ("magick", "ImageMagick.ImageMagick"),
+    ("7z", "7zip.7zip"),
+    ("curl", "cURL.cURL"),
+];
+
+/// A system package manager phloem can write install commands for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageManager {
+    Brew,
+    Apt,
+    Dnf,
+    Pacman,
+    Winget,
+}
+
+impl PackageManager {
+    /// The package manager of this machine, the first found in PATH of the
+    /// ones usual for the OS
+    pub fn detect() -> Option<Self> {
+        let candidates: &[Self] = match std::env::consts::OS {
+            "macos" => &[Self::Brew],
+            "windows" => &[Self::Winget],
+            _ => &[Self::Apt, Self::Dnf, Self::Pacman, Self::Brew],
+        };
+        candidates
+            .iter()
+            .copied()
+            .find(|manager| ExecutableIndex::contains(manager.executable()))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Brew => "Homebrew",
+            Self::Apt => "apt",
+            Self::Dnf => "dnf",
+            Self::Pacman => "pacman",
+            Self::Winget => "winget",
+        }
+    }
+
+    fn executable(&self) -> &'static str {
+        match self {
+            Self::Brew => "brew",
+            Self::Apt => "apt-get",
+            Self::Dnf => "dnf",
+            Self::Pacman => "pacman",
+            Self::Winget => "winget",
+        }
+    }
+
+    /// Package that provides `executable`, or `None` when this package
+    /// manager isn't known to carry it
+    pub fn package_for(&self, executable: &str) -> Option<String> {
+        let package = match (self, executable) {
+            (Self::Winget, _) => {
+                return WINGET_IDS
+                    .iter()
+                    .find(|(name, _)| *name == executable)
+                    .map(|(_, id)| id.to_string());
+            }
+            (_, "rg") => "ripgrep",
+            (Self::Apt | Self::Dnf, "fd") => "fd-find",
+            (_, "http") => "httpie",
+            (Self::Dnf, "convert" | "magick") => "ImageMagick",
+            (_, "convert" | "magick") => "imagemagick",
+            (Self::Apt, "dig" | "nslookup") => "dnsutils",
+            (Self::Dnf, "dig" | "nslookup") => "bind-utils",
+            (_, "dig" | "nslookup") => "bind",
+            (_, "ffprobe") => "ffmpeg",
+            (Self::Pacman, "gh") => "github-cli",
+            (Self::Brew, "npm") => "node",
+            (Self::Apt | Self::Dnf | Self::Pacman, "node") => "nodejs",
+            (Self::Brew | Self::Pacman, "python3") => "python",
+            (Self::Pacman, "pip3") => "python-pip",
+            (Self::Brew, "pip3") => "python",
+            (_, "pip3") => "python3-pip",
+            (Self::Apt, "7z") => "p7zip-full",
+            (_, "7z") => "p7zip",
+            (_, "netstat") => "net-tools",
+            // macOS has no ss, and no package for it
+            (Self::Brew, "ss") => return None,
+            (Self::Dnf, "ss") => "iproute",
+            (_, "ss") => "iproute2",
+            _ => executable,
+        };
+        Some(package.to_string())
+    }
+
+    /// Whether the package index knows `package`, so made-up tools aren't
+    /// offered for installation; the winget ids are known to exist
+    pub fn has_package(&self, package: &str) -> bool {
+        let (program, args): (&str, &[&str]) = match self {
+            Self::Brew => ("brew", &["info", "--formula"]),
+            Self::Apt => ("apt-cache", &["show", "--no-all-versions"]),
+            Self::Dnf => ("dnf", &["info", "--quiet", "--cacheonly"]),
+            Self::Pacman => ("pacman", &["-Si"]),
+            Self::Winget => return true,
+        };
+        Command::new(program)
+            .args(args)
+            .arg(package)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+
+    /// Command that installs `package`, without asking questions on the way
+    pub fn install_command(&self, package: &str) -> String {
+        match self {
+            Self::Brew => format!("brew install {package}"),
+            Self::Apt => format!("sudo apt-get install -y {package}"),
+            Self::Dnf => format!("sudo dnf install -y {package}"),
+            Self::Pacman => format!("sudo pacman -S --noconfirm {package}"),
+            Self::Winget => format!("winget install --exact --id {package}"),
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use crate::config::DefaultConfig;
+use crate::utils::ShellKind;
+
+/// The policy in use, from `Policy::install`, or the default one
+static POLICY: OnceLock<Policy> = OnceLock::new();
+
+/// What a policy rule does with the commands it matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyAction {
+    /// Shown and run without the rules below it
+    Allow,
+    /// Never shown, and refused when it's about to run
+    Deny,
+    /// Asks for a typed "yes" before it runs
+    Confirm,
+}
+
+/// What the policy decided for a command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verdict {
+    pub action: PolicyAction,
+    /// Why, as the rule explains it
+    pub reason: Option<String>,
+    /// What the matched command acts on: its arguments, less options and
+    /// those the rule matched, like the paths given to `rm` or the remote
+    /// and branch of a force-push
+    pub targets: Vec<String>,
+}
+
+/// A policy file that can't be read as TOML or has an invalid rule
+#[derive(Debug)]
+pub struct InvalidPolicy {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid policy in {}: {}",
+            self.path.display(),
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for InvalidPolicy {}
+
+/// A rule as written in policy.toml
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    action: PolicyAction,
+    executable: Option<Names>,
+    #[serde(default)]
+    args: Vec<String>,
+    pattern: Option<String>,
+    #[serde(default)]
+    shells: Vec<ShellKind>,
+    reason: Option<String>,
+}
+
+/// `executable = "rm"` or `executable = ["rm", "shred"]`
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Names {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// What the policy does with commands by what they do, whatever rule
+/// matches them, unless an `allow` rule does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Categories {
+    /// Redirects replacing a file that exists, like `> config.yaml`
+    pub overwrites: PolicyAction,
+    /// Changes made over the network, like `curl -X DELETE`, `kubectl
+    /// delete` or `terraform apply`
+    pub network: PolicyAction,
+}
+
+impl Default for Categories {
+    fn default() -> Self {
+        Self {
+            overwrites: PolicyAction::Confirm,
+            network: PolicyAction::Confirm,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PolicyFile {
+    #[serde(default)]
+    categories: Categories,
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleSpec>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    action: PolicyAction,
+    executables: Vec<String>,
+    args: Vec<Regex>,
+    pattern: Option<Regex>,
+    shells: Vec<ShellKind>,
+    reason: Option<String>,
+}
+
+/// Rules deciding which suggested commands are shown, which run only after
+/// a typed "yes" and which never run, read from ~/.phloem/policy.toml
+#[derive(Debug)]
+pub struct Policy {
+    rules: Vec<Rule>,
+    categories: Categories,
+}
+
+impl Policy {
+    /// The policy of ~/.phloem/policy.toml, or the default one when there's
+    /// no such file
+    pub fn load() -> Result<Self> {
+        let path = Self::get_policy_path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&content).map_err(|e| {
+            InvalidPolicy {
+                reason: format!("{e:#}").trim().to_string(),
+                path,
+            }
+            .into()
+        })
+    }
+
+    pub fn get_policy_path() -> Result<PathBuf> {
+        let home_dir =
+            dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
+
+        Ok(home_dir.join(".phloem").join("policy.toml"))
+    }
+
+    /// Parses the TOML of a policy file, failing on the first invalid rule
+    pub fn parse(content: &str) -> Result<Self> {
+        let file: PolicyFile = toml::from_str(content)?;
+        let rules = file
+            .rules
+            .into_iter()
+            .enumerate()
+            .map(|(index, spec)| Rule::compile(spec).with_context(|| format!("rule {}", index + 1)))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            rules,
+            categories: file.categories,
+        })
+    }
+
+    /// Makes `policy` the one every validator uses; only the first call counts
+    pub fn install(policy: Policy) {
+        let _ = POLICY.set(policy);
+    }
+
+    /// The installed policy, or the default one
+    pub fn current() -> &'static Policy {
+        POLICY.get_or_init(Policy::default)
+    }
+
+    /// What happens to overwrites and changes over the network
+    pub fn categories(&self) -> Categories {
+        self.categories
+    }
+
+    /// The first rule matching `command`, written for `shell`. `commands`
+    /// are the words of each command it runs, from its executable on
+    pub fn evaluate(
+        &self,
+        command: &str,
+        commands: &[Vec<String>],
+        shell: ShellKind,
+    ) -> Option<Verdict> {
+        self.rules
+            .iter()
+            .find_map(|rule| rule.verdict(command, commands, shell))
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::parse(&DefaultConfig::create_default_policy_file())
+            .expect("the default policy is valid")
+    }
+}
+
+impl Rule {
+    fn compile(spec: RuleSpec) -> Result<Self> {
+        let regex = |pattern: &str| {
+            Regex::new(pattern).map_err(|e| {
+                let e = e.to_string();
+                let reason = e.lines().last().unwrap_or(&e).trim();
+                anyhow::anyhow!("invalid regex \"{pattern}\" ({reason})")
+            })
+        };
+
+        let executables = match spec.executable {
+            Some(Names::One(name)) => vec![name],
+            Some(Names::Many(names)) => names,
+            None => Vec::new(),
+        };
+        if executables.is_empty() && spec.args.is_empty() && spec.pattern.is_none() {
+            anyhow::bail!("give an executable, args or a pattern to match");
+        }
+
+        Ok(Self {
+            action: spec.action,
+            executables,
+            args: spec
+                .args
+                .iter()
+                .map(|pattern| regex(pattern))
+                .collect::<Result<_>>()?,
+            pattern: spec.pattern.as_deref().map(regex).transpose()?,
+            shells: spec.shells,
+            reason: spec.reason,
+        })
+    }
+
+    fn verdict(&self, line: &str, commands: &[Vec<String>], shell: ShellKind) -> Option<Verdict> {
+        if !self.shells.is_empty() && !self.shells.contains(&shell) {
+            return None;
+        }
+        if self
+            .pattern
+            .as_ref()
+            .is_some_and(|pattern| !pattern.is_match(line))
+        {
+            return None;
+        }
+
+        let targets = if self.executables.is_empty() && self.args.is_empty() {
+            Vec::new()
+        } else {
+            commands
+                .iter()
+                .find_map(|words| self.targets(words, shell))?
+        };
+
+        Some(Verdict {
+            action: self.action,
+            reason: self.reason.clone(),
+            targets,
+        })
+    }
+
+    /// The targets of `words` when the rule's executable and arguments match them
+    fn targets(&self, words: &[String], shell: ShellKind) -> Option<Vec<String>> {
+        let (name, arguments) = words.split_first()?;
+        let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+        // `Remove-Item` is `remove-item` where the shell ignores case
+        let same = |a: &str, b: &str| {
+            if shell.profile().case_insensitive {
+                a.eq_ignore_ascii_case(b)
+            } else {
+                a == b
+            }
+        };
+        // `mkfs.ext4` is `mkfs`
+        let matches_name = |executable: &String| {
+            same(name, executable)
+                || name
+                    .split('.')
+                    .next()
+                    .is_some_and(|stem| same(stem, executable))
+        };
+        if !self.executables.is_empty() && !self.executables.iter().any(matches_name) {
+            return None;
+        }
+        if !self
+            .args
+            .iter()
+            .all(|arg| arguments.iter().any(|word| arg.is_match(word)))
+        {
+            return None;
+        }
+
+        Some(
+            arguments
+                .iter()
+                .filter(|word| !self.args.iter().any(|arg| arg.is_match(word)))
+                .filter_map(|word| match word.split_once('=') {
+                    // dd's `of=/dev/sdb`; its other operands aren't targets
+                    Some(("of", device)) => Some(device),
+                    Some((key, _)) if key.chars().all(|c| c.is_ascii_alphanumeric()) => None,
+                    _ if word.starts_with('-') => None,
+                    _ => Some(word.as_str()),
+                })
+                .map(str::to_string)
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,303 @@
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+/// Most runnable tasks listed for the model
+const MAX_TASKS: usize = 30;
+
+/// Compose files, in the order docker compose looks for them
+const COMPOSE_FILES: &[&str] = &[
+    "compose.yaml",
+    "compose.yml",
+    "docker-compose.yaml",
+    "docker-compose.yml",
+];
+
+/// Files marking the root of a project that isn't a git repository
+const PROJECT_MARKERS: &[&str] = &[
+    "Cargo.toml",
+    "package.json",
+    "go.mod",
+    "pyproject.toml",
+    "setup.py",
+    "pom.xml",
+    "build.gradle",
+    "Gemfile",
+    "composer.json",
+    "CMakeLists.txt",
+];
+
+/// Toolchains used in a directory and the tasks they define
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProjectInfo {
+    /// Project types, e.g. `rust (cargo)` or `node (pnpm)`
+    pub types: Vec<String>,
+    /// Commands running the defined tasks, e.g. `npm run build` or `make test`
+    pub tasks: Vec<String>,
+}
+
+/// The project a directory belongs to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Project {
+    pub root: PathBuf,
+}
+
+impl Project {
+    /// Finds the enclosing project: the git repository root, or else the
+    /// nearest directory with a build manifest. The home directory itself
+    /// never counts, so a dotfiles repo doesn't swallow everything.
+    pub fn detect(dir: &Path) -> Option<Self> {
+        let home = dirs::home_dir();
+        let is_home = |path: &Path| home.as_deref() == Some(path);
+
+        let root = dir
+            .ancestors()
+            .take_while(|path| !is_home(path))
+            .find(|path| path.join(".git").exists())
+            .or_else(|| {
+                dir.ancestors()
+                    .take_while(|path| !is_home(path))
+                    .find(|path| PROJECT_MARKERS.iter().any(|m| path.join(m).is_file()))
+            })?;
+
+        Some(Self {
+            root: root.canonicalize().unwrap_or_else(|_| root.to_path_buf()),
+        })
+    }
+
+    /// Project of the current working directory
+    pub fn current() -> Option<Self> {
+        std::env::current_dir()
+            .ok()
+            .and_then(|dir| Self::detect(&dir))
+    }
+
+    /// Stable identifier of the project, without revealing its path
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.root.hash(&mut hasher);
+        format!("{:x}", hasher.finish())
+    }
+}
+
+impl ProjectInfo {
+    /// Project types and tasks defined by the manifests in `dir`; `None` when there are none
+    pub fn detect(dir: &Path) -> Option<Self> {
+        let mut info = Self::default();
+
+        if dir.join("Cargo.toml").is_file() {
+            info.types.push("rust (cargo)".to_string());
+            info.tasks.extend(cargo_aliases(dir));
+        }
+
+        if let Ok(manifest) = fs::read_to_string(dir.join("package.json")) {
+            let runner = node_package_manager(dir);
+            info.types.push(format!("node ({runner})"));
+            info.tasks.extend(npm_scripts(&manifest, runner));
+        }
+
+        if let Ok(manifest) = fs::read_to_string(dir.join("pyproject.toml")) {
+            let runner = python_runner(dir, &manifest);
+            info.types
+                .push(format!("python ({})", runner.unwrap_or("pip")));
+            info.tasks.extend(python_scripts(&manifest, runner));
+        }
+
+        if dir.join("go.mod").is_file() {
+            info.types.push("go".to_string());
+        }
+
+        if let Some(makefile) = ["GNUmakefile", "makefile", "Makefile"]
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file())
+        {
+            info.types.push("make".to_string());
+            if let Ok(content) = fs::read_to_string(makefile) {
+                info.tasks.extend(make_targets(&content));
+            }
+        }
+
+        if let Some(compose) = COMPOSE_FILES
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file())
+        {
+            info.types.push("docker compose".to_string());
+            if let Ok(content) = fs::read_to_string(compose) {
+                info.tasks.extend(
+                    compose_services(&content)
+                        .into_iter()
+                        .map(|service| format!("docker compose up {service}")),
+                );
+            }
+        }
+
+        info.tasks.dedup();
+        info.tasks.truncate(MAX_TASKS);
+        (!info.types.is_empty()).then_some(info)
+    }
+
+    /// Project info of the current directory, or else of the project it belongs to
+    pub fn current() -> Option<Self> {
+        let dir = std::env::current_dir().ok()?;
+        Self::detect(&dir).or_else(|| {
+            Project::detect(&dir)
+                .filter(|project| project.root != dir)
+                .and_then(|project| Self::detect(&project.root))
+        })
+    }
+}
+
+/// `cargo` aliases from the project's `.cargo/config.toml`
+fn cargo_aliases(dir: &Path) -> Vec<String> {
+    let config = fs::read_to_string(dir.join(".cargo").join("config.toml"))
+        .or_else(|_| fs::read_to_string(dir.join(".cargo").join("config")));
+    let Ok(config) = config else {
+        return Vec::new();
+    };
+
+    config
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|config| config.get("alias")?.as_table().cloned())
+        .map(|aliases| {
+            aliases
+                .keys()
+                .map(|alias| format!("cargo {alias}"))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The package manager a lock file points to
+fn node_package_manager(dir: &Path) -> &'static str {
+    if dir.join("pnpm-lock.yaml").is_file() {
+        "pnpm"
+    } else if dir.join("yarn.lock").is_file() {
+        "yarn"
+    } else if dir.join("bun.lockb").is_file() || dir.join("bun.lock").is_file() {
+        "bun"
+    } else {
+        "npm"
+    }
+}
+
+fn npm_scripts(manifest: &str, runner: &str) -> Vec<String> {
+    let Ok(manifest) = serde_json::from_str::<serde_json::Value>(manifest) else {
+        return Vec::new();
+    };
+
+    manifest
+        .get("scripts")
+        .and_then(|scripts| scripts.as_object())
+        .map(|scripts| {
+            scripts
+                .keys()
+                .map(|script| format!("{runner} run {script}"))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The tool managing a Python project's environment, if any
+fn python_runner(dir: &Path, manifest: &str) -> Option<&'static str> {
+    if dir.join("uv.lock").is_file() {
+        Some("uv")
+    } else if dir.join("poetry.lock").is_file() || manifest.contains("[tool.poetry]") {
+        Some("poetry")
+    } else if dir.join("pdm.lock").is_file() {
+        Some("pdm")
+    } else {
+        None
+    }
+}
+
+/// Console scripts declared in pyproject.toml, run through the project's tool
+fn python_scripts(manifest: &str, runner: Option<&str>) -> Vec<String> {
+    let Ok(manifest) = manifest.parse::<toml::Table>() else {
+        return Vec::new();
+    };
+
+    let project_scripts = manifest
+        .get("project")
+        .and_then(|project| project.get("scripts"));
+    let poetry_scripts = manifest
+        .get("tool")
+        .and_then(|tool| tool.get("poetry"))
+        .and_then(|poetry| poetry.get("scripts"));
+
+    project_scripts
+        .into_iter()
+        .chain(poetry_scripts)
+        .filter_map(|scripts| scripts.as_table())
+        .flat_map(|scripts| scripts.keys())
+        .map(|script| match runner {
+            Some(runner) => format!("{runner} run {script}"),
+            None => script.clone(),
+        })
+        .collect()
+}
+
+/// Explicit targets of a Makefile, leaving out special and pattern targets
+fn make_targets(makefile: &str) -> Vec<String> {
+    let mut targets = Vec::new();
+    for line in makefile.lines() {
+        if line.starts_with(['\t', ' ', '#', '.']) {
+            continue;
+        }
+        let Some((names, rest)) = line.split_once(':') else {
+            continue;
+        };
+        // `VAR := value` and `VAR ::= value` are assignments
+        if rest.starts_with('=') || rest.starts_with(":=") || names.contains('=') {
+            continue;
+        }
+
+        for name in names.split_whitespace() {
+            if !name.contains(['%', '$']) && !targets.iter().any(|target| target == name) {
+                targets.push(name.to_string());
+            }
+        }
+    }
+
+    targets
+        .into_iter()
+        .map(|target| format!("make {target}"))
+        .collect()
+}
+
+/// Service names under the top-level `services:` key of a compose file
+fn compose_services(compose: &str) -> Vec<String> {
+    let mut services = Vec::new();
+    let mut in_services = false;
+    let mut indent = None;
+
+    for line in compose.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let line_indent = line.len() - trimmed.len();
+        if line_indent == 0 {
+            in_services = trimmed.starts_with("services:");
+            continue;
+        }
+        if !in_services {
+            continue;
+        }
+
+        // Services are the keys at the first indentation level below `services:`
+        let service_indent = *indent.get_or_insert(line_indent);
+        if line_indent == service_indent {
+            if let Some(name) = trimmed.strip_suffix(':') {
+                services.push(name.trim_matches(['"', '\'']).to_string());
+            }
+        }
+    }
+
+    services
+}
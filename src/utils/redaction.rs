@@ -0,0 +1,99 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Built-in secret patterns with their replacement; `$name` groups are kept
+const BUILTIN_PATTERNS: &[(&str, &str)] = &[
+    // Private key blocks
+    (
+        r"-----BEGIN [A-Z ]*PRIVATE KEY-----[\s\S]*?-----END [A-Z ]*PRIVATE KEY-----",
+        REDACTED,
+    ),
+    // Bearer and basic credentials, e.g. in curl headers
+    (
+        r"(?i)(?P<scheme>\bbearer\s+)[A-Za-z0-9\-._~+/]{8,}=*",
+        "${scheme}[REDACTED]",
+    ),
+    (
+        r"(?i)(?P<scheme>authorization:\s*basic\s+)[A-Za-z0-9+/]+=*",
+        "${scheme}[REDACTED]",
+    ),
+    // Credentials embedded in URLs
+    (r"(?P<user>://[^/\s:@]+:)[^@\s/]+@", "${user}[REDACTED]@"),
+    // KEY=value assignments whose name suggests a secret (env exports, .env lines)
+    (
+        r#"(?i)(?P<key>\b[A-Z0-9_]*(?:SECRET|TOKEN|PASSWORD|PASSWD|API_?KEY|ACCESS_?KEY|PRIVATE_?KEY)[A-Z0-9_]*\s*[=:]\s*)(?:"[^"]*"|'[^']*'|[^\s"']+)"#,
+        "${key}[REDACTED]",
+    ),
+    // Secret-looking command line flags
+    (
+        r#"(?i)(?P<flag>--(?:password|passwd|pass|token|secret|api-key|apikey|access-key)[=\s]\s*)(?:"[^"]*"|'[^']*'|\S+)"#,
+        "${flag}[REDACTED]",
+    ),
+    // AWS access key IDs
+    (r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b", REDACTED),
+    // GitHub tokens
+    (
+        r"\b(?:gh[pousr]_[A-Za-z0-9]{36,}|github_pat_[A-Za-z0-9_]{20,})\b",
+        REDACTED,
+    ),
+    // OpenAI/Anthropic style and Stripe keys
+    (
+        r"\b(?:sk-[A-Za-z0-9_\-]{20,}|[sr]k_(?:live|test)_[A-Za-z0-9]{16,})\b",
+        REDACTED,
+    ),
+    // Slack tokens
+    (r"\bxox[abprs]-[A-Za-z0-9\-]{10,}\b", REDACTED),
+    // Google API keys
+    (r"\bAIza[0-9A-Za-z\-_]{35}\b", REDACTED),
+    // JSON Web Tokens
+    (
+        r"\beyJ[A-Za-z0-9_\-]{8,}\.[A-Za-z0-9_\-]{8,}\.[A-Za-z0-9_\-]{8,}\b",
+        REDACTED,
+    ),
+];
+
+/// Masks secrets in text before it is sent to a model
+#[derive(Debug, Clone)]
+pub struct SecretRedactor {
+    patterns: Vec<(Regex, String)>,
+}
+
+impl Default for SecretRedactor {
+    fn default() -> Self {
+        Self::new(&[]).expect("built-in redaction patterns are valid")
+    }
+}
+
+impl SecretRedactor {
+    /// Creates a redactor with the built-in patterns plus `custom` regexes,
+    /// whose whole match is masked
+    pub fn new(custom: &[String]) -> Result<Self> {
+        let mut patterns = Vec::new();
+
+        for (pattern, replacement) in BUILTIN_PATTERNS {
+            patterns.push((Regex::new(pattern)?, replacement.to_string()));
+        }
+
+        for pattern in custom {
+            let regex = Regex::new(pattern)
+                .with_context(|| format!("Invalid redaction pattern: {pattern}"))?;
+            patterns.push((regex, REDACTED.to_string()));
+        }
+
+        Ok(Self { patterns })
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        let mut redacted = text.to_string();
+        for (regex, replacement) in &self.patterns {
+            if regex.is_match(&redacted) {
+                redacted = regex
+                    .replace_all(&redacted, replacement.as_str())
+                    .into_owned();
+            }
+        }
+        redacted
+    }
+}
@@ -0,0 +1,217 @@
+use anyhow::{bail, Context, Result};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Repository whose GitHub releases carry the phloem binaries
+const GITHUB_REPO: &str = "phloem-sh/phloem";
+
+/// How long the release lookup may take
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long the binary download may take
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// A published phloem release
+#[derive(Debug, Clone, Deserialize)]
+pub struct Release {
+    /// Git tag of the release, e.g. "v0.2.0"
+    #[serde(rename = "tag_name")]
+    pub tag: String,
+    #[serde(rename = "html_url")]
+    pub url: String,
+    assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ReleaseAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    /// The latest release on GitHub
+    pub async fn latest() -> Result<Self> {
+        let url = format!("https://api.github.com/repos/{GITHUB_REPO}/releases/latest");
+        let response = client()?
+            .get(url)
+            .header("Accept", "application/vnd.github+json")
+            .timeout(LOOKUP_TIMEOUT)
+            .send()
+            .await
+            .context("Failed to reach GitHub")?;
+        if !response.status().is_success() {
+            bail!(
+                "GitHub answered {} looking up the latest release",
+                response.status()
+            );
+        }
+        response
+            .json()
+            .await
+            .context("Failed to read the latest release")
+    }
+
+    /// Version of the release, without the leading "v"
+    pub fn version(&self) -> &str {
+        self.tag.trim_start_matches('v')
+    }
+
+    /// Whether the release is newer than the running binary
+    pub fn is_newer(&self) -> bool {
+        version_parts(self.version()) > version_parts(env!("CARGO_PKG_VERSION"))
+    }
+
+    /// Downloads the binary for this platform, checks it against the SHA-256
+    /// published with it and puts it in place of the running executable;
+    /// returns the path replaced. `on_progress` gets the bytes downloaded and
+    /// the size, when known. Releases aren't signed, so this catches a corrupt
+    /// download but not a tampered release
+    pub async fn install(
+        &self,
+        on_progress: &(dyn Fn(u64, Option<u64>) + Sync),
+    ) -> Result<PathBuf> {
+        let name = asset_name().with_context(|| {
+            format!(
+                "No release binary is built for {}-{}",
+                std::env::consts::ARCH,
+                std::env::consts::OS
+            )
+        })?;
+        let binary = self
+            .asset(&name)
+            .with_context(|| format!("Release {} has no {name}", self.tag))?;
+        // Refuse a binary that can't be verified
+        let checksum = self
+            .asset(&format!("{name}.sha256"))
+            .with_context(|| format!("Release {} publishes no checksum for {name}", self.tag))?;
+
+        let client = client()?;
+        let expected = client
+            .get(&checksum.browser_download_url)
+            .timeout(LOOKUP_TIMEOUT)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .context("Failed to download the checksum")?
+            .text()
+            .await?;
+        // `sha256sum` format: the digest, then the file name
+        let expected = expected
+            .split_whitespace()
+            .next()
+            .map(str::to_lowercase)
+            .with_context(|| format!("The checksum of {name} is empty"))?;
+
+        let response = client
+            .get(&binary.browser_download_url)
+            .timeout(DOWNLOAD_TIMEOUT)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Failed to download {name}"))?;
+        let total = response.content_length();
+        let mut stream = response.bytes_stream();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            bytes.extend_from_slice(&chunk.with_context(|| format!("Failed to download {name}"))?);
+            on_progress(bytes.len() as u64, total);
+        }
+
+        let actual = format!("{:x}", Sha256::digest(&bytes));
+        if actual != expected {
+            bail!("Checksum mismatch for {name}: expected {expected}, got {actual}");
+        }
+
+        let executable = std::env::current_exe()
+            .and_then(fs::canonicalize)
+            .context("Failed to find the running executable")?;
+        replace_executable(&executable, &bytes)?;
+        Ok(executable)
+    }
+
+    fn asset(&self, name: &str) -> Option<&ReleaseAsset> {
+        self.assets.iter().find(|asset| asset.name == name)
+    }
+}
+
+/// GitHub's API refuses requests without a user agent
+fn client() -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .user_agent(concat!("phloem/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .context("Failed to create HTTP client")
+}
+
+/// Name of the release binary built for this platform, as the release
+/// workflow uploads it
+fn asset_name() -> Option<String> {
+    let target = match (std::env::consts::ARCH, std::env::consts::OS) {
+        ("x86_64", "macos") => "x86_64-apple-darwin",
+        ("aarch64", "macos") => "aarch64-apple-darwin",
+        ("x86_64", "linux") => "x86_64-unknown-linux-gnu",
+        ("aarch64", "linux") => "aarch64-unknown-linux-gnu",
+        ("x86_64", "windows") => "x86_64-pc-windows-msvc",
+        _ => return None,
+    };
+    Some(format!("phloem-{target}{}", std::env::consts::EXE_SUFFIX))
+}
+
+/// Numeric parts of a version, e.g. [0, 2, 1] for "0.2.1"; a pre-release
+/// suffix is ignored
+fn version_parts(version: &str) -> Vec<u64> {
+    version
+        .split(['-', '+'])
+        .next()
+        .unwrap_or_default()
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+/// Writes `bytes` next to `executable` and renames it over it, so the
+/// executable is never half written
+fn replace_executable(executable: &Path, bytes: &[u8]) -> Result<()> {
+    let directory = executable
+        .parent()
+        .context("The executable has no parent directory")?;
+    let staged = directory.join(".phloem-update");
+    let write = || -> std::io::Result<()> {
+        let mut file = fs::File::create(&staged)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+        }
+        Ok(())
+    };
+    if let Err(e) = write() {
+        let _ = fs::remove_file(&staged);
+        return Err(e).with_context(|| {
+            format!(
+                "Failed to write to {}; run the update with permission to change it",
+                directory.display()
+            )
+        });
+    }
+
+    // Windows can't replace a running executable, but it can rename it
+    #[cfg(windows)]
+    {
+        let old = executable.with_extension("old");
+        let _ = fs::remove_file(&old);
+        fs::rename(executable, &old)
+            .with_context(|| format!("Failed to move {} aside", executable.display()))?;
+    }
+
+    fs::rename(&staged, executable).with_context(|| {
+        let _ = fs::remove_file(&staged);
+        format!("Failed to replace {}", executable.display())
+    })
+}
@@ -0,0 +1,133 @@
+use anyhow::Result;
+use std::path::Path;
+use std::process::Command;
+
+use crate::config::settings::{SandboxBackend, SandboxConfig};
+use crate::utils::ExecutableIndex;
+
+/// Container runtimes tried for `backend = "container"`, in order
+const CONTAINER_RUNTIMES: &[&str] = &["podman", "docker"];
+
+/// A restricted environment picked commands can be tried in first: the file
+/// system is read-only, /tmp is empty and there's no network
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    backend: SandboxBackend,
+    /// Image commands run in with `backend = "container"`
+    image: String,
+}
+
+impl Sandbox {
+    pub fn new(config: &SandboxConfig) -> Self {
+        Self {
+            backend: config.backend,
+            image: config.image.clone(),
+        }
+    }
+
+    /// `command` as it would run for real, wrapped to run in the sandbox with
+    /// `cwd` as its directory; fails when the backend isn't installed, or
+    /// for `auto` when none is
+    pub fn wrap(&self, command: &Command, cwd: &Path) -> Result<(Command, &'static str)> {
+        let backend = match self.backend {
+            SandboxBackend::Auto => Self::detect().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "no sandbox found; install bubblewrap or firejail (Linux), \
+                     or Docker or Podman with sandbox.backend = \"container\""
+                )
+            })?,
+            backend => backend,
+        };
+
+        let cwd = cwd.to_string_lossy();
+        let (mut sandboxed, name) = match backend {
+            SandboxBackend::Bubblewrap => {
+                let mut sandboxed = Command::new(require("bwrap")?);
+                sandboxed
+                    .args(["--ro-bind", "/", "/", "--dev", "/dev", "--proc", "/proc"])
+                    .args(["--tmpfs", "/tmp", "--unshare-all", "--die-with-parent"])
+                    .args(["--chdir", &cwd, "--"]);
+                (sandboxed, "bubblewrap")
+            }
+            SandboxBackend::Firejail => {
+                let mut sandboxed = Command::new(require("firejail")?);
+                sandboxed.args([
+                    "--quiet",
+                    "--noprofile",
+                    "--net=none",
+                    "--private-tmp",
+                    "--read-only=/",
+                ]);
+                (sandboxed, "firejail")
+            }
+            SandboxBackend::SandboxExec => {
+                let mut sandboxed = Command::new(require("sandbox-exec")?);
+                // Writes go nowhere but the temporary directories
+                sandboxed.args([
+                    "-p",
+                    "(version 1) (allow default) (deny network*) (deny file-write*) \
+                     (allow file-write* (subpath \"/private/tmp\") \
+                     (subpath \"/private/var/folders\") (literal \"/dev/null\"))",
+                ]);
+                (sandboxed, "sandbox-exec")
+            }
+            SandboxBackend::Container => {
+                let runtime = CONTAINER_RUNTIMES
+                    .iter()
+                    .find(|runtime| ExecutableIndex::contains(runtime))
+                    .ok_or_else(|| anyhow::anyhow!("neither podman nor docker is installed"))?;
+                let mut sandboxed = Command::new(runtime);
+                sandboxed.args(["run", "--rm", "--network", "none"]).args([
+                    "-v",
+                    &format!("{cwd}:{cwd}:ro"),
+                    "-w",
+                    &cwd,
+                    &self.image,
+                ]);
+                (sandboxed, "container")
+            }
+            SandboxBackend::Auto => unreachable!("auto is resolved above"),
+        };
+
+        sandboxed
+            .arg(command.get_program())
+            .args(command.get_args())
+            .current_dir(cwd.as_ref());
+        for (key, value) in command.get_envs() {
+            match value {
+                Some(value) => sandboxed.env(key, value),
+                None => sandboxed.env_remove(key),
+            };
+        }
+        Ok((sandboxed, name))
+    }
+
+    /// The first sandbox installed for this platform; containers are only
+    /// used when asked for, as their image lacks the user's tools
+    fn detect() -> Option<SandboxBackend> {
+        let candidates: &[(SandboxBackend, &str)] = if cfg!(target_os = "linux") {
+            &[
+                (SandboxBackend::Bubblewrap, "bwrap"),
+                (SandboxBackend::Firejail, "firejail"),
+            ]
+        } else if cfg!(target_os = "macos") {
+            &[(SandboxBackend::SandboxExec, "sandbox-exec")]
+        } else {
+            &[]
+        };
+
+        candidates
+            .iter()
+            .find(|(_, program)| ExecutableIndex::contains(program))
+            .map(|&(backend, _)| backend)
+    }
+}
+
+/// `program`, when it's in PATH
+fn require(program: &str) -> Result<&str> {
+    if ExecutableIndex::contains(program) {
+        Ok(program)
+    } else {
+        Err(anyhow::anyhow!("{program} is not installed"))
+    }
+}
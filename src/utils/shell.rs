@@ -1,8 +1,75 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// An alias or abbreviation defined in the user's shell startup files
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShellAlias {
+    pub name: String,
+    /// The command it stands for, e.g. `kubectl` for `k`
+    pub expansion: String,
+}
+
+/// Shells phloem can write commands for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ShellKind {
+    Bash,
+    Zsh,
+    Fish,
+    #[value(name = "powershell")]
+    PowerShell,
+    Nu,
+    Cmd,
+}
+
+impl ShellKind {
+    /// The user's shell; POSIX shells without an entry of their own count as bash
+    pub fn detect() -> Self {
+        Self::from_name(&ShellDetector::detect_shell())
+    }
+
+    /// The shell named like `name`, e.g. "zsh" or "pwsh.exe"
+    pub fn from_name(name: &str) -> Self {
+        let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+        match name.trim_end_matches(".exe").to_lowercase().as_str() {
+            "zsh" => Self::Zsh,
+            "fish" => Self::Fish,
+            "pwsh" | "powershell" => Self::PowerShell,
+            "nu" => Self::Nu,
+            "cmd" => Self::Cmd,
+            _ => Self::Bash,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Bash => "bash",
+            Self::Zsh => "zsh",
+            Self::Fish => "fish",
+            Self::PowerShell => "powershell",
+            Self::Nu => "nu",
+            Self::Cmd => "cmd",
+        }
+    }
+}
+
+/// Shell of Windows users outside of Git Bash and MSYS, from
+/// `execution.windows_shell`
+static WINDOWS_SHELL: OnceLock<&'static str> = OnceLock::new();
 
 pub struct ShellDetector;
 
 impl ShellDetector {
+    /// Makes `shell` the one detected on Windows when SHELL isn't set; only
+    /// the first call counts
+    pub fn set_windows_shell(shell: &'static str) {
+        let _ = WINDOWS_SHELL.set(shell);
+    }
+
     pub fn detect_shell() -> String {
         // Try to detect from SHELL environment variable
         if let Ok(shell) = env::var("SHELL") {
@@ -20,6 +87,12 @@ impl ShellDetector {
             return "bash".to_string();
         }
 
+        // Without SHELL (set by Git Bash, MSYS and WSL), Windows users are
+        // in PowerShell or cmd, which is also what commands run with there
+        if cfg!(windows) {
+            return WINDOWS_SHELL.get().unwrap_or(&"powershell").to_string();
+        }
+
         // Default fallback
         "sh".to_string()
     }
@@ -46,123 +119,188 @@ impl ShellDetector {
         }
     }
 
-    pub fn get_completion_script(&self, shell: &str) -> Option<String> {
-        match shell {
-            "bash" => Some(self.get_bash_completion()),
-            "zsh" => Some(self.get_zsh_completion()),
-            "fish" => Some(self.get_fish_completion()),
-            _ => None,
+    /// Aliases and fish abbreviations from the detected shell's startup files,
+    /// read once per run. Functions are left out: commands run through `sh`,
+    /// which can't see them.
+    pub fn user_aliases() -> &'static [ShellAlias] {
+        static ALIASES: OnceLock<Vec<ShellAlias>> = OnceLock::new();
+        ALIASES.get_or_init(|| {
+            let Some(home) = dirs::home_dir() else {
+                return Vec::new();
+            };
+
+            let mut aliases: Vec<ShellAlias> = Vec::new();
+            for file in alias_files(&Self::detect_shell(), &home) {
+                let Ok(content) = fs::read_to_string(&file) else {
+                    continue;
+                };
+                let parse = if file.extension().is_some_and(|ext| ext == "fish") {
+                    parse_fish_alias
+                } else {
+                    parse_posix_alias
+                };
+                // Later definitions override earlier ones, as in the shell
+                for alias in content.lines().filter_map(parse) {
+                    aliases.retain(|existing| existing.name != alias.name);
+                    aliases.push(alias);
+                }
+            }
+            aliases
+        })
+    }
+
+    /// Whether `name` is one of the user's aliases
+    pub fn is_user_alias(name: &str) -> bool {
+        Self::user_aliases().iter().any(|alias| alias.name == name)
+    }
+
+    /// Prefixes `command` with the definitions of the user's aliases it uses, so
+    /// it runs the same under `sh -c` as at the user's prompt
+    pub fn with_alias_definitions(command: &str) -> String {
+        let words: Vec<&str> = command
+            .split(|c: char| c.is_whitespace() || matches!(c, '|' | '&' | ';' | '(' | ')'))
+            .collect();
+        let definitions: Vec<String> = Self::user_aliases()
+            .iter()
+            .filter(|alias| words.contains(&alias.name.as_str()))
+            .map(|alias| {
+                format!(
+                    "alias {}='{}'",
+                    alias.name,
+                    alias.expansion.replace('\'', r"'\''")
+                )
+            })
+            .collect();
+
+        if definitions.is_empty() {
+            command.to_string()
+        } else {
+            // Aliases only apply to lines read after the one defining them
+            format!("{}\n{command}", definitions.join("\n"))
         }
     }
+}
 
-    fn get_bash_completion(&self) -> String {
-        r#"# Phloem bash completion
-_phloem_complete() {
-    local cur prev opts
-    COMPREPLY=()
-    cur="${COMP_WORDS[COMP_CWORD]}"
-    prev="${COMP_WORDS[COMP_CWORD-1]}"
-    opts="init update config clear doctor version --help --explain --suggestions --no-cache --verbose"
-    
-    case ${prev} in
-        phloem)
-            COMPREPLY=( $(compgen -W "${opts}" -- ${cur}) )
-            return 0
-            ;;
-        update)
-            COMPREPLY=( $(compgen -W "--model --binary" -- ${cur}) )
-            return 0
-            ;;
-        clear)
-            COMPREPLY=( $(compgen -W "--cache --context" -- ${cur}) )
-            return 0
-            ;;
-        *)
-            ;;
-    esac
-    
-    COMPREPLY=( $(compgen -W "${opts}" -- ${cur}) )
-    return 0
+/// Startup files where `shell` users define aliases, in the order they're read
+fn alias_files(shell: &str, home: &Path) -> Vec<PathBuf> {
+    match shell {
+        "bash" => [
+            ".bash_profile",
+            ".profile",
+            ".bashrc",
+            ".bash_aliases",
+            ".aliases",
+        ]
+        .iter()
+        .map(|name| home.join(name))
+        .collect(),
+        "zsh" => {
+            let zdotdir = env::var_os("ZDOTDIR")
+                .filter(|dir| !dir.is_empty())
+                .map_or_else(|| home.to_path_buf(), PathBuf::from);
+            [".zprofile", ".zshrc", ".zsh_aliases", ".aliases"]
+                .iter()
+                .map(|name| zdotdir.join(name))
+                .collect()
+        }
+        "fish" => {
+            let config = env::var_os("XDG_CONFIG_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map_or_else(|| home.join(".config"), PathBuf::from)
+                .join("fish");
+            let mut files: Vec<PathBuf> = fs::read_dir(config.join("conf.d"))
+                .map(|entries| {
+                    entries
+                        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                        .filter(|path| path.extension().is_some_and(|ext| ext == "fish"))
+                        .collect()
+                })
+                .unwrap_or_default();
+            files.sort();
+            files.push(config.join("config.fish"));
+            files
+        }
+        _ => Vec::new(),
+    }
 }
 
-complete -F _phloem_complete phloem
-"#.to_string()
+/// `alias name='value'` as written in bash and zsh startup files
+fn parse_posix_alias(line: &str) -> Option<ShellAlias> {
+    let rest = line.trim().strip_prefix("alias ")?;
+    // Skip flags such as zsh's `-g` (global) and `-s` (suffix) aliases
+    let mut rest = rest.trim_start();
+    while rest.starts_with('-') {
+        if rest.starts_with("-g") || rest.starts_with("-s") {
+            return None;
+        }
+        rest = rest.split_once(char::is_whitespace)?.1.trim_start();
     }
 
-    fn get_zsh_completion(&self) -> String {
-        r#"# Phloem zsh completion
-_phloem() {
-    local context state state_descr line
-    typeset -A opt_args
-    
-    _arguments \
-        '1: :->commands' \
-        '--explain[Show detailed explanations]' \
-        '--suggestions[Number of suggestions to show]:number:' \
-        '--no-cache[Skip cache and force fresh inference]' \
-        '--verbose[Verbose output]' \
-        '--help[Show help]' \
-        '*: :->args'
-    
-    case $state in
-        commands)
-            local commands
-            commands=(
-                'init:Initialize phloem setup'
-                'update:Update model or binary'
-                'config:Show configuration'
-                'clear:Clear cache and context'
-                'doctor:Run diagnostics'
-                'version:Show version information'
-            )
-            _describe 'commands' commands
-            ;;
-        args)
-            case $words[2] in
-                update)
-                    _arguments \
-                        '--model[Update the ML model]' \
-                        '--binary[Update the binary]'
-                    ;;
-                clear)
-                    _arguments \
-                        '--cache[Clear command cache]' \
-                        '--context[Clear learning context]'
-                    ;;
-            esac
-            ;;
-    esac
+    let (name, value) = rest.split_once('=')?;
+    alias(name, &unquote(value))
 }
 
-compdef _phloem phloem
-"#
-        .to_string()
+/// `alias name 'value'`, `alias name=value` or `abbr -a name value` in fish
+fn parse_fish_alias(line: &str) -> Option<ShellAlias> {
+    let line = line.trim();
+    if let Some(rest) = line.strip_prefix("alias ") {
+        let rest = rest.trim_start();
+        let (name, value) = match rest.split_once(char::is_whitespace) {
+            Some((name, value)) if !name.contains('=') => (name, value),
+            _ => rest.split_once('=')?,
+        };
+        return alias(name, &unquote(value));
     }
 
-    fn get_fish_completion(&self) -> String {
-        r#"# Phloem fish completion
-complete -c phloem -f
-
-# Main commands
-complete -c phloem -n "not __fish_seen_subcommand_from init update config clear doctor version" -a "init" -d "Initialize phloem setup"
-complete -c phloem -n "not __fish_seen_subcommand_from init update config clear doctor version" -a "update" -d "Update model or binary"
-complete -c phloem -n "not __fish_seen_subcommand_from init update config clear doctor version" -a "config" -d "Show configuration"
-complete -c phloem -n "not __fish_seen_subcommand_from init update config clear doctor version" -a "clear" -d "Clear cache and context"
-complete -c phloem -n "not __fish_seen_subcommand_from init update config clear doctor version" -a "doctor" -d "Run diagnostics"
-complete -c phloem -n "not __fish_seen_subcommand_from init update config clear doctor version" -a "version" -d "Show version information"
-
-# Global options
-complete -c phloem -l explain -d "Show detailed explanations"
-complete -c phloem -l suggestions -d "Number of suggestions to show"
-complete -c phloem -l no-cache -d "Skip cache and force fresh inference"
-complete -c phloem -l verbose -d "Verbose output"
-complete -c phloem -l help -d "Show help"
-
-# Subcommand options
-complete -c phloem -n "__fish_seen_subcommand_from update" -l model -d "Update the ML model"
-complete -c phloem -n "__fish_seen_subcommand_from update" -l binary -d "Update the binary"
-complete -c phloem -n "__fish_seen_subcommand_from clear" -l cache -d "Clear command cache"
-complete -c phloem -n "__fish_seen_subcommand_from clear" -l context -d "Clear learning context"
-"#.to_string()
+    // Options such as `--position anywhere` change where an abbreviation
+    // expands; only plain command-position ones behave like aliases
+    let mut words = line.strip_prefix("abbr ")?.split_whitespace();
+    let mut name = words.next()?;
+    while name.starts_with('-') {
+        if !matches!(
+            name,
+            "-a" | "--add" | "-g" | "--global" | "-U" | "--universal"
+        ) {
+            return None;
+        }
+        name = words.next()?;
+    }
+    let value = words.collect::<Vec<_>>().join(" ");
+    alias(name, &unquote(&value))
+}
+
+fn alias(name: &str, expansion: &str) -> Option<ShellAlias> {
+    let name = name.trim();
+    let expansion = expansion.trim();
+    let valid_name = !name.is_empty()
+        && !name.starts_with(['_', '-'])
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    (valid_name && !expansion.is_empty()).then(|| ShellAlias {
+        name: name.to_string(),
+        expansion: expansion.to_string(),
+    })
+}
+
+/// Strips one level of shell quoting from an alias value, dropping any
+/// trailing comment after an unquoted value
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    if let Some(inner) = value.strip_prefix('\'') {
+        // `'\''` is how a single quote is written inside single quotes
+        let inner = inner.replace(r"'\''", "\u{0}");
+        let end = inner.find('\'').unwrap_or(inner.len());
+        inner[..end].replace('\u{0}', "'")
+    } else if let Some(inner) = value.strip_prefix('"') {
+        let end = inner.rfind('"').unwrap_or(inner.len());
+        inner[..end].replace("\\\"", "\"")
+    } else {
+        value
+            .split(" #")
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string()
     }
 }
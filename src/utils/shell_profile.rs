@@ -0,0 +1,99 @@
+use crate::utils::ShellKind;
+
+/// How commands are written and checked in one shell: what the model is told
+/// about its syntax and how it escapes and quotes
+#[derive(Debug)]
+pub struct ShellProfile {
+    /// Whether commands are POSIX shell, which `Script` parses; the others
+    /// are checked by their quotes and brackets
+    pub posix: bool,
+    /// Character that escapes the next one
+    pub escape: char,
+    /// Characters that start and end a quoted string
+    pub quotes: &'static [char],
+    /// Characters other than letters, digits and `/` a command may start
+    /// with, like PowerShell's `$` and `&`
+    pub line_starts: &'static str,
+    /// Whether command names match regardless of case
+    pub case_insensitive: bool,
+    /// Whether the shell runs Windows commands, like cmdlets and cmd built-ins
+    /// that aren't files in PATH
+    pub windows: bool,
+    /// Syntax rules the model is given, so answers are idiomatic for the
+    /// shell rather than bash with its words swapped
+    pub guidance: &'static [&'static str],
+}
+
+const POSIX: ShellProfile = ShellProfile {
+    posix: true,
+    escape: '\\',
+    quotes: &['\'', '"'],
+    line_starts: "",
+    case_insensitive: false,
+    windows: false,
+    guidance: &[],
+};
+
+const FISH: ShellProfile = ShellProfile {
+    posix: false,
+    guidance: &[
+        "Substitute commands with (command), not $(command) or backticks",
+        "Set variables with `set NAME value` and export them with `set -x`",
+        "Chain with `and`/`or` or `;`; there are no `[[ ]]` tests, use `test`",
+    ],
+    ..POSIX
+};
+
+const NU: ShellProfile = ShellProfile {
+    posix: false,
+    line_starts: "$^",
+    guidance: &[
+        "Pipelines carry tables: filter with `where`, pick columns with `select`, sort with `sort-by`",
+        "Prefix an external program with ^ when a built-in has the same name, e.g. ^ls",
+        "Environment variables are $env.NAME; chain commands with `;`",
+    ],
+    ..POSIX
+};
+
+const POWERSHELL: ShellProfile = ShellProfile {
+    posix: false,
+    escape: '`',
+    quotes: &['\'', '"'],
+    line_starts: "$&.[",
+    case_insensitive: true,
+    windows: true,
+    guidance: &[
+        "Use cmdlets by their full Verb-Noun names (Get-ChildItem, Select-String, Get-Process), not aliases or Unix tools like grep, awk or sed",
+        "Pipelines pass objects: filter with Where-Object, pick properties with Select-Object, sort with Sort-Object",
+        "Escape with a backtick (`), never a backslash; backslashes are path separators",
+        "Variables start with $, environment variables are $env:NAME and parameters are -Name value",
+    ],
+};
+
+const CMD: ShellProfile = ShellProfile {
+    posix: false,
+    escape: '^',
+    quotes: &['"'],
+    line_starts: "@%",
+    case_insensitive: true,
+    windows: true,
+    guidance: &[
+        "Write cmd.exe commands: dir, copy, move, del, type, findstr, where and set, not Unix tools",
+        "Escape with a caret (^); only double quotes quote, single quotes are literal",
+        "Environment variables are %NAME%; chain commands with &, && or ||",
+        "Options start with a slash, e.g. dir /s /b",
+    ],
+};
+
+impl ShellKind {
+    /// How commands for this shell are written and checked
+    pub fn profile(&self) -> &'static ShellProfile {
+        match self {
+            Self::Bash | Self::Zsh => &POSIX,
+            Self::Fish => &FISH,
+            Self::Nu => &NU,
+            Self::PowerShell => &POWERSHELL,
+            Self::Cmd => &CMD,
+        }
+    }
+}
@@ -0,0 +1,872 @@
+use std::fmt;
+
+/// Commands that run the command given after them
+pub const WRAPPERS: &[&str] = &[
+    "sudo", "doas", "env", "time", "nohup", "exec", "command", "builtin",
+];
+
+/// Operators, longest first so `&&` isn't read as two `&`
+const OPERATORS: &[&str] = &[
+    "&>>", "<<-", "<<<", ";;&", "&&", "||", ";;", ";&", "|&", ">>", "<<", "<&", ">&", "<>", ">|",
+    "&>", "|", "&", ";", "(", ")", "<", ">",
+];
+
+/// A word of a command line as written, and what it stands for once quotes
+/// and escapes are removed; expansions like `$HOME` are kept as written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Word {
+    pub raw: String,
+    pub value: String,
+    /// Any part of it was quoted or escaped, so it's never a reserved word
+    quoted: bool,
+}
+
+impl Word {
+    /// Whether the shell would change the word before running it: it has a
+    /// `$` or backtick expansion, an unquoted glob or brace, or starts with
+    /// an unquoted `~`
+    pub fn expands(&self) -> bool {
+        let mut quote = None;
+        let mut escaped = false;
+        for (index, c) in self.raw.chars().enumerate() {
+            match (quote, c) {
+                _ if escaped => escaped = false,
+                (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+                (Some('\''), _) => {}
+                (_, '\\') => escaped = true,
+                (_, '$' | '`') => return true,
+                (Some(_), _) => {}
+                (None, '\'' | '"') => quote = Some(c),
+                (None, '*' | '?' | '[' | '{') => return true,
+                (None, '~') if index == 0 => return true,
+                (None, _) => {}
+            }
+        }
+        false
+    }
+}
+
+/// A redirection like `> out.txt` or `2>&1`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    /// The descriptor before the operator, like the 2 of `2>`
+    pub fd: Option<u32>,
+    pub op: &'static str,
+    pub target: Word,
+}
+
+/// One command with its arguments, like each side of `ls | wc -l`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimpleCommand {
+    /// `NAME=value` words before the command
+    pub assignments: Vec<Word>,
+    /// The command's name and arguments
+    pub words: Vec<Word>,
+}
+
+impl SimpleCommand {
+    /// Index of the word naming what runs, past wrappers like `sudo -u admin`
+    /// or `env FOO=1` and their options
+    pub fn executable(&self) -> Option<usize> {
+        let mut index = 0;
+        while let Some(word) = self.words.get(index) {
+            if !WRAPPERS.contains(&word.value.as_str()) {
+                return Some(index);
+            }
+            let wrapper = word.value.as_str();
+            index += 1;
+            while let Some(word) = self.words.get(index) {
+                let option = word.value.as_str();
+                if option.starts_with('-') {
+                    index += 1;
+                    // Options of the wrapper that take a value
+                    if matches!(
+                        (wrapper, option),
+                        ("sudo", "-u" | "-g" | "-C" | "-D" | "-p" | "-h")
+                            | ("doas", "-u" | "-C")
+                            | ("env", "-u" | "-C")
+                    ) {
+                        index += 1;
+                    }
+                } else if wrapper == "env" && is_assignment(option) {
+                    index += 1;
+                } else {
+                    break;
+                }
+            }
+        }
+        None
+    }
+
+    /// Name of what runs, e.g. `rm` for `sudo rm -rf build`
+    pub fn name(&self) -> Option<&str> {
+        self.executable()
+            .map(|index| self.words[index].value.as_str())
+    }
+}
+
+/// Why a command line isn't valid POSIX shell
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyntaxError {
+    /// A quote or backtick that is never closed
+    UnterminatedQuote(char),
+    /// `$(`, `${` or `$((` that is never closed
+    Unclosed(&'static str),
+    /// A token where the grammar doesn't allow it, like the `)` of `ls )`
+    Unexpected(String),
+    /// The line ends inside a command, like `ls |`
+    UnexpectedEnd,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnterminatedQuote(quote) => write!(f, "unterminated {quote}"),
+            Self::Unclosed(opening) => write!(f, "unclosed {opening}"),
+            Self::Unexpected(token) => write!(f, "unexpected {token}"),
+            Self::UnexpectedEnd => write!(f, "unexpected end of command"),
+        }
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+/// A command line parsed as POSIX shell (with the bash additions models
+/// tend to use), flattened to what it runs: every simple command, including
+/// those in pipelines, lists, subshells, loops and command substitutions,
+/// and every redirection
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    pub commands: Vec<SimpleCommand>,
+    pub redirects: Vec<Redirect>,
+    /// Names of the functions it defines, which its commands may call
+    pub functions: Vec<String>,
+}
+
+impl Script {
+    pub fn parse(command: &str) -> Result<Self, SyntaxError> {
+        let mut parser = Parser::new(command);
+        parser.list(&[])?;
+        match parser.next()? {
+            Token::End => Ok(parser.script),
+            token => Err(unexpected(&token)),
+        }
+    }
+
+    /// Names of what each command runs, in order, leaving out calls of the
+    /// functions it defines
+    pub fn executables(&self) -> impl Iterator<Item = &str> {
+        self.commands
+            .iter()
+            .filter_map(SimpleCommand::name)
+            .filter(|name| !self.functions.iter().any(|function| function == name))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(Word),
+    /// `|`, `&&`, `;`, `(` and the like
+    Operator(&'static str),
+    /// A redirection operator and the descriptor written before it
+    Redirect(Option<u32>, &'static str),
+    Newline,
+    End,
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    peeked: Option<Token>,
+    /// Delimiters of here-documents whose bodies start on the next line, and
+    /// whether leading tabs are stripped (`<<-`)
+    heredocs: Vec<(String, bool)>,
+    script: Script,
+}
+
+impl Parser {
+    fn new(command: &str) -> Self {
+        Self {
+            chars: command.chars().collect(),
+            pos: 0,
+            peeked: None,
+            heredocs: Vec::new(),
+            script: Script::default(),
+        }
+    }
+
+    // Grammar
+
+    /// Commands separated by `;`, `&` and newlines, up to the end or one of
+    /// `ends`, an operator like `)` or a reserved word like `fi`
+    fn list(&mut self, ends: &[&str]) -> Result<(), SyntaxError> {
+        loop {
+            self.skip_newlines()?;
+            match self.peek()? {
+                Token::End => return Ok(()),
+                Token::Operator(op) if ends.contains(&op) => return Ok(()),
+                Token::Word(word) if is_reserved(&word, ends) => return Ok(()),
+                _ => {}
+            }
+            self.and_or()?;
+            match self.peek()? {
+                Token::Operator(";" | "&") | Token::Newline => {
+                    self.next()?;
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn and_or(&mut self) -> Result<(), SyntaxError> {
+        self.pipeline()?;
+        while let Token::Operator("&&" | "||") = self.peek()? {
+            self.next()?;
+            self.skip_newlines()?;
+            self.pipeline()?;
+        }
+        Ok(())
+    }
+
+    fn pipeline(&mut self) -> Result<(), SyntaxError> {
+        if let Token::Word(word) = self.peek()? {
+            if is_reserved(&word, &["!"]) {
+                self.next()?;
+            }
+        }
+        self.command()?;
+        while let Token::Operator("|" | "|&") = self.peek()? {
+            self.next()?;
+            self.skip_newlines()?;
+            self.command()?;
+        }
+        Ok(())
+    }
+
+    fn command(&mut self) -> Result<(), SyntaxError> {
+        let word = match self.peek()? {
+            Token::Operator("(") => {
+                self.next()?;
+                self.list(&[")"])?;
+                self.expect_operator(")")?;
+                return self.redirects();
+            }
+            Token::Word(word) if !word.quoted => word,
+            Token::Word(_) | Token::Redirect(..) => return self.simple_command(),
+            Token::End | Token::Newline => return Err(SyntaxError::UnexpectedEnd),
+            token => return Err(unexpected(&token)),
+        };
+
+        match word.raw.as_str() {
+            "{" => {
+                self.next()?;
+                self.list(&["}"])?;
+                self.expect_word("}")?;
+            }
+            "if" => {
+                self.next()?;
+                self.list(&["then"])?;
+                self.expect_word("then")?;
+                self.list(&["elif", "else", "fi"])?;
+                loop {
+                    match self.peek()? {
+                        Token::Word(word) if is_reserved(&word, &["elif"]) => {
+                            self.next()?;
+                            self.list(&["then"])?;
+                            self.expect_word("then")?;
+                            self.list(&["elif", "else", "fi"])?;
+                        }
+                        Token::Word(word) if is_reserved(&word, &["else"]) => {
+                            self.next()?;
+                            self.list(&["fi"])?;
+                        }
+                        _ => break,
+                    }
+                }
+                self.expect_word("fi")?;
+            }
+            "while" | "until" => {
+                self.next()?;
+                self.list(&["do"])?;
+                self.do_group()?;
+            }
+            "for" | "select" => self.for_clause()?,
+            "case" => self.case_clause()?,
+            "function" => {
+                self.next()?;
+                match self.next()? {
+                    Token::Word(name) => self.script.functions.push(name.value),
+                    Token::End => return Err(SyntaxError::UnexpectedEnd),
+                    token => return Err(unexpected(&token)),
+                }
+                if self.peek()? == Token::Operator("(") {
+                    self.next()?;
+                    self.expect_operator(")")?;
+                }
+                self.skip_newlines()?;
+                return self.command();
+            }
+            // Reserved words that only continue a compound command
+            "then" | "else" | "elif" | "fi" | "do" | "done" | "esac" | "}" => {
+                return Err(unexpected(&Token::Word(word)));
+            }
+            "[[" => {
+                // bash's conditional: its `<`, `&&` and `(` aren't operators
+                self.next()?;
+                loop {
+                    match self.next()? {
+                        Token::Word(word) if is_reserved(&word, &["]]"]) => break,
+                        Token::End => return Err(SyntaxError::UnexpectedEnd),
+                        _ => {}
+                    }
+                }
+            }
+            _ => return self.simple_command(),
+        }
+        self.redirects()
+    }
+
+    fn for_clause(&mut self) -> Result<(), SyntaxError> {
+        self.next()?;
+        if self.peek()? == Token::Operator("(") {
+            // bash's `for ((i = 0; i < 3; i++))`
+            let mut depth = 0;
+            loop {
+                match self.next()? {
+                    Token::Operator("(") => depth += 1,
+                    Token::Operator(")") => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    Token::End => return Err(SyntaxError::UnexpectedEnd),
+                    _ => {}
+                }
+            }
+        } else {
+            self.expect_any_word()?;
+            self.skip_newlines()?;
+            if let Token::Word(word) = self.peek()? {
+                if is_reserved(&word, &["in"]) {
+                    self.next()?;
+                    while let Token::Word(_) = self.peek()? {
+                        self.next()?;
+                    }
+                }
+            }
+        }
+        if self.peek()? == Token::Operator(";") {
+            self.next()?;
+        }
+        self.skip_newlines()?;
+        self.do_group()
+    }
+
+    fn do_group(&mut self) -> Result<(), SyntaxError> {
+        self.expect_word("do")?;
+        self.list(&["done"])?;
+        self.expect_word("done")
+    }
+
+    fn case_clause(&mut self) -> Result<(), SyntaxError> {
+        self.next()?;
+        self.expect_any_word()?;
+        self.skip_newlines()?;
+        self.expect_word("in")?;
+        loop {
+            self.skip_newlines()?;
+            if let Token::Word(word) = self.peek()? {
+                if is_reserved(&word, &["esac"]) {
+                    self.next()?;
+                    return Ok(());
+                }
+            }
+            if self.peek()? == Token::Operator("(") {
+                self.next()?;
+            }
+            // Patterns like `*.tar.gz | *.tgz )`
+            loop {
+                self.expect_any_word()?;
+                match self.next()? {
+                    Token::Operator("|") => {}
+                    Token::Operator(")") => break,
+                    token => return Err(unexpected(&token)),
+                }
+            }
+            self.list(&[";;", ";&", ";;&", "esac"])?;
+            match self.peek()? {
+                Token::Operator(";;" | ";&" | ";;&") => {
+                    self.next()?;
+                }
+                Token::Word(word) if is_reserved(&word, &["esac"]) => {}
+                Token::End => return Err(SyntaxError::UnexpectedEnd),
+                token => return Err(unexpected(&token)),
+            }
+        }
+    }
+
+    fn simple_command(&mut self) -> Result<(), SyntaxError> {
+        let mut command = SimpleCommand::default();
+        loop {
+            match self.peek()? {
+                Token::Word(word) => {
+                    self.next()?;
+                    if command.words.is_empty() && is_assignment(&word.raw) {
+                        command.assignments.push(word);
+                        continue;
+                    }
+                    command.words.push(word);
+                    // `name() { ...; }` defines a function rather than running `name`
+                    if command.words.len() == 1
+                        && command.assignments.is_empty()
+                        && self.peek()? == Token::Operator("(")
+                    {
+                        self.next()?;
+                        self.expect_operator(")")?;
+                        self.skip_newlines()?;
+                        self.script
+                            .functions
+                            .extend(command.words.pop().map(|name| name.value));
+                        return self.command();
+                    }
+                }
+                Token::Redirect(..) => self.redirect()?,
+                token => {
+                    if command.words.is_empty() && command.assignments.is_empty() {
+                        return Err(match token {
+                            Token::End | Token::Newline => SyntaxError::UnexpectedEnd,
+                            token => unexpected(&token),
+                        });
+                    }
+                    break;
+                }
+            }
+        }
+        self.script.commands.push(command);
+        Ok(())
+    }
+
+    /// Redirections after a compound command, like `{ ...; } > log`
+    fn redirects(&mut self) -> Result<(), SyntaxError> {
+        while let Token::Redirect(..) = self.peek()? {
+            self.redirect()?;
+        }
+        Ok(())
+    }
+
+    fn redirect(&mut self) -> Result<(), SyntaxError> {
+        let Token::Redirect(fd, op) = self.next()? else {
+            return Ok(());
+        };
+        let target = match self.next()? {
+            Token::Word(word) => word,
+            Token::End | Token::Newline => return Err(SyntaxError::UnexpectedEnd),
+            token => return Err(unexpected(&token)),
+        };
+        if matches!(op, "<<" | "<<-") {
+            self.heredocs.push((target.value.clone(), op == "<<-"));
+        }
+        self.script.redirects.push(Redirect { fd, op, target });
+        Ok(())
+    }
+
+    fn skip_newlines(&mut self) -> Result<(), SyntaxError> {
+        while self.peek()? == Token::Newline {
+            self.next()?;
+        }
+        Ok(())
+    }
+
+    fn expect_operator(&mut self, expected: &str) -> Result<(), SyntaxError> {
+        match self.next()? {
+            Token::Operator(op) if op == expected => Ok(()),
+            Token::End => Err(SyntaxError::UnexpectedEnd),
+            token => Err(unexpected(&token)),
+        }
+    }
+
+    fn expect_word(&mut self, expected: &str) -> Result<(), SyntaxError> {
+        match self.next()? {
+            Token::Word(word) if is_reserved(&word, &[expected]) => Ok(()),
+            Token::End => Err(SyntaxError::UnexpectedEnd),
+            token => Err(unexpected(&token)),
+        }
+    }
+
+    fn expect_any_word(&mut self) -> Result<(), SyntaxError> {
+        match self.next()? {
+            Token::Word(_) => Ok(()),
+            Token::End => Err(SyntaxError::UnexpectedEnd),
+            token => Err(unexpected(&token)),
+        }
+    }
+
+    fn peek(&mut self) -> Result<Token, SyntaxError> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.lex()?);
+        }
+        Ok(self.peeked.clone().unwrap_or(Token::End))
+    }
+
+    fn next(&mut self) -> Result<Token, SyntaxError> {
+        match self.peeked.take() {
+            Some(token) => Ok(token),
+            None => self.lex(),
+        }
+    }
+
+    // Tokens
+
+    fn lex(&mut self) -> Result<Token, SyntaxError> {
+        // Blanks, line continuations and comments
+        loop {
+            match self.char_at(0) {
+                Some(' ' | '\t' | '\r') => self.pos += 1,
+                Some('\\') if self.char_at(1) == Some('\n') => self.pos += 2,
+                Some('#') => {
+                    while !matches!(self.char_at(0), None | Some('\n')) {
+                        self.pos += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        match self.char_at(0) {
+            None => return Ok(Token::End),
+            Some('\n') => {
+                self.pos += 1;
+                self.heredoc_bodies();
+                return Ok(Token::Newline);
+            }
+            // Process substitution, `diff <(sort a) <(sort b)`
+            Some('<' | '>') if self.char_at(1) == Some('(') => {}
+            Some(_) => {
+                if let Some(token) = self.operator() {
+                    return Ok(token);
+                }
+            }
+        }
+
+        let word = self.word()?;
+        // The 2 of `2>&1`
+        if !word.quoted
+            && word.raw.chars().all(|c| c.is_ascii_digit())
+            && matches!(self.char_at(0), Some('<' | '>'))
+        {
+            if let Some(Token::Redirect(None, op)) = self.operator() {
+                return Ok(Token::Redirect(word.raw.parse().ok(), op));
+            }
+        }
+        Ok(Token::Word(word))
+    }
+
+    fn operator(&mut self) -> Option<Token> {
+        let op = OPERATORS.iter().find(|op| {
+            op.chars()
+                .enumerate()
+                .all(|(offset, c)| self.char_at(offset) == Some(c))
+        })?;
+        self.pos += op.len();
+        Some(if op.starts_with(['<', '>']) || op.starts_with("&>") {
+            Token::Redirect(None, op)
+        } else {
+            Token::Operator(op)
+        })
+    }
+
+    fn word(&mut self) -> Result<Word, SyntaxError> {
+        let start = self.pos;
+        let mut value = String::new();
+        let mut quoted = false;
+
+        if matches!(self.char_at(0), Some('<' | '>')) && self.char_at(1) == Some('(') {
+            self.pos += 2;
+            self.substitution()?;
+            let raw = self.text_from(start);
+            return Ok(Word {
+                value: raw.clone(),
+                raw,
+                quoted,
+            });
+        }
+
+        while let Some(c) = self.char_at(0) {
+            match c {
+                ' ' | '\t' | '\r' | '\n' | '|' | '&' | ';' | '<' | '>' | ')' => break,
+                // bash arrays, `files=(a b c)`
+                '(' if value.ends_with('=') && is_assignment(&value) => {
+                    self.pos += 1;
+                    let array = self.until(')')?;
+                    value.push('(');
+                    value.push_str(&array);
+                    value.push(')');
+                }
+                '(' => break,
+                '\\' => {
+                    quoted = true;
+                    self.pos += 1;
+                    match self.char_at(0) {
+                        Some('\n') => self.pos += 1,
+                        Some(c) => {
+                            value.push(c);
+                            self.pos += 1;
+                        }
+                        None => {}
+                    }
+                }
+                '\'' => {
+                    quoted = true;
+                    self.pos += 1;
+                    value.push_str(&self.until('\'')?);
+                }
+                '"' => {
+                    quoted = true;
+                    self.pos += 1;
+                    self.double_quoted(&mut value)?;
+                }
+                // bash's `$'\n'`
+                '$' if self.char_at(1) == Some('\'') => {
+                    quoted = true;
+                    self.pos += 2;
+                    value.push_str(&self.ansi_quoted()?);
+                }
+                '$' => self.dollar(&mut value)?,
+                '`' => {
+                    let start = self.pos;
+                    self.pos += 1;
+                    self.backticks()?;
+                    value.push_str(&self.text_from(start));
+                }
+                c => {
+                    value.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+
+        Ok(Word {
+            raw: self.text_from(start),
+            value,
+            quoted,
+        })
+    }
+
+    /// Text up to the closing `quote`, which is skipped
+    fn until(&mut self, quote: char) -> Result<String, SyntaxError> {
+        let mut text = String::new();
+        loop {
+            match self.char_at(0) {
+                Some(c) if c == quote => {
+                    self.pos += 1;
+                    return Ok(text);
+                }
+                Some(c) => {
+                    text.push(c);
+                    self.pos += 1;
+                }
+                None => return Err(SyntaxError::UnterminatedQuote(quote)),
+            }
+        }
+    }
+
+    fn ansi_quoted(&mut self) -> Result<String, SyntaxError> {
+        let mut text = String::new();
+        loop {
+            match self.char_at(0) {
+                Some('\'') => {
+                    self.pos += 1;
+                    return Ok(text);
+                }
+                Some('\\') if self.char_at(1).is_some() => {
+                    text.extend(self.chars[self.pos..self.pos + 2].iter());
+                    self.pos += 2;
+                }
+                Some(c) => {
+                    text.push(c);
+                    self.pos += 1;
+                }
+                None => return Err(SyntaxError::UnterminatedQuote('\'')),
+            }
+        }
+    }
+
+    fn double_quoted(&mut self, value: &mut String) -> Result<(), SyntaxError> {
+        loop {
+            match self.char_at(0) {
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some('\\') => {
+                    self.pos += 1;
+                    match self.char_at(0) {
+                        Some('\n') => {}
+                        Some(c @ ('$' | '`' | '"' | '\\')) => value.push(c),
+                        Some(c) => {
+                            value.push('\\');
+                            value.push(c);
+                        }
+                        None => return Err(SyntaxError::UnterminatedQuote('"')),
+                    }
+                    self.pos += 1;
+                }
+                Some('$') => self.dollar(value)?,
+                Some('`') => {
+                    let start = self.pos;
+                    self.pos += 1;
+                    self.backticks()?;
+                    value.push_str(&self.text_from(start));
+                }
+                Some(c) => {
+                    value.push(c);
+                    self.pos += 1;
+                }
+                None => return Err(SyntaxError::UnterminatedQuote('"')),
+            }
+        }
+    }
+
+    /// `$(...)`, `$((...))`, `${...}` or a plain `$`, kept as written in
+    /// `value`; the commands of a substitution are parsed with the others
+    fn dollar(&mut self, value: &mut String) -> Result<(), SyntaxError> {
+        let start = self.pos;
+        match (self.char_at(1), self.char_at(2)) {
+            (Some('('), Some('(')) => {
+                self.pos += 3;
+                self.skip_balanced('(', ')', 2, "$((")?;
+            }
+            (Some('('), _) => {
+                self.pos += 2;
+                self.substitution()?;
+            }
+            (Some('{'), _) => {
+                self.pos += 2;
+                self.skip_balanced('{', '}', 1, "${")?;
+            }
+            _ => self.pos += 1,
+        }
+        value.push_str(&self.text_from(start));
+        Ok(())
+    }
+
+    /// The commands of `$(...)` or `<(...)`, up to and including the `)`
+    fn substitution(&mut self) -> Result<(), SyntaxError> {
+        let peeked = self.peeked.take();
+        self.list(&[")"])?;
+        match self.next()? {
+            Token::Operator(")") => {}
+            Token::End => return Err(SyntaxError::Unclosed("$(")),
+            token => return Err(unexpected(&token)),
+        }
+        self.peeked = peeked;
+        Ok(())
+    }
+
+    /// Skips to the `close` that brings `depth` open brackets to zero
+    fn skip_balanced(
+        &mut self,
+        open: char,
+        close: char,
+        mut depth: usize,
+        opening: &'static str,
+    ) -> Result<(), SyntaxError> {
+        while depth > 0 {
+            match self.char_at(0) {
+                Some(c) if c == open => depth += 1,
+                Some(c) if c == close => depth -= 1,
+                Some('\\') => self.pos += 1,
+                Some(_) => {}
+                None => return Err(SyntaxError::Unclosed(opening)),
+            }
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    /// The commands of an old-style `` `...` `` substitution, after its
+    /// opening backtick
+    fn backticks(&mut self) -> Result<(), SyntaxError> {
+        let mut inner = String::new();
+        loop {
+            match self.char_at(0) {
+                Some('`') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some('\\') if matches!(self.char_at(1), Some('`' | '\\' | '$')) => {
+                    inner.extend(self.char_at(1));
+                    self.pos += 2;
+                }
+                Some(c) => {
+                    inner.push(c);
+                    self.pos += 1;
+                }
+                None => return Err(SyntaxError::UnterminatedQuote('`')),
+            }
+        }
+        let nested = Script::parse(&inner)?;
+        self.script.commands.extend(nested.commands);
+        self.script.redirects.extend(nested.redirects);
+        self.script.functions.extend(nested.functions);
+        Ok(())
+    }
+
+    /// Skips the bodies of the here-documents started on the line just ended
+    fn heredoc_bodies(&mut self) {
+        for (delimiter, strip_tabs) in std::mem::take(&mut self.heredocs) {
+            while self.pos < self.chars.len() {
+                let end = self.chars[self.pos..]
+                    .iter()
+                    .position(|&c| c == '\n')
+                    .map_or(self.chars.len(), |offset| self.pos + offset);
+                let line: String = self.chars[self.pos..end].iter().collect();
+                self.pos = (end + 1).min(self.chars.len());
+                let line = if strip_tabs {
+                    line.trim_start_matches('\t')
+                } else {
+                    &line
+                };
+                if line == delimiter {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn char_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn text_from(&self, start: usize) -> String {
+        self.chars[start..self.pos].iter().collect()
+    }
+}
+
+/// Whether `word` is one of the reserved words in `words`, which only counts
+/// when it's unquoted
+fn is_reserved(word: &Word, words: &[&str]) -> bool {
+    !word.quoted && words.contains(&word.raw.as_str())
+}
+
+/// `NAME=value`, as before a command or after `env`
+pub fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| {
+        let name = name.strip_suffix('+').unwrap_or(name);
+        name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+fn unexpected(token: &Token) -> SyntaxError {
+    match token {
+        Token::Word(word) => SyntaxError::Unexpected(format!("\"{}\"", word.raw)),
+        Token::Operator(op) | Token::Redirect(None, op) => {
+            SyntaxError::Unexpected(format!("\"{op}\""))
+        }
+        Token::Redirect(Some(fd), op) => SyntaxError::Unexpected(format!("\"{fd}{op}\"")),
+        Token::Newline => SyntaxError::Unexpected("newline".to_string()),
+        Token::End => SyntaxError::UnexpectedEnd,
+    }
+}
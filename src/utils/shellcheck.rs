@@ -0,0 +1,94 @@
+use log::debug;
+use serde::Deserialize;
+use std::fmt;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::utils::{ExecutableIndex, ShellKind};
+
+/// One problem ShellCheck found in a command
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ShellCheckFinding {
+    /// The SC number, e.g. 2086 for an unquoted variable
+    pub code: u32,
+    /// error, warning or info
+    pub level: String,
+    pub message: String,
+}
+
+impl fmt::Display for ShellCheckFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SC{} ({}): {}", self.code, self.level, self.message)
+    }
+}
+
+/// `--format=json1` output
+#[derive(Debug, Deserialize)]
+struct Report {
+    comments: Vec<ShellCheckFinding>,
+}
+
+pub struct ShellCheck;
+
+impl ShellCheck {
+    /// The `--shell` ShellCheck reads `shell`'s commands as; it knows no
+    /// zsh, fish, nu, PowerShell or cmd
+    pub fn dialect(shell: ShellKind) -> Option<&'static str> {
+        (shell == ShellKind::Bash).then_some("bash")
+    }
+
+    /// What ShellCheck finds in `command`, written for `shell`, giving up
+    /// after `timeout`; `None` without shellcheck, for shells it can't read,
+    /// or when it fails or is too slow
+    pub async fn check(
+        command: &str,
+        shell: ShellKind,
+        timeout: Duration,
+    ) -> Option<Vec<ShellCheckFinding>> {
+        let dialect = Self::dialect(shell)?;
+        if !ExecutableIndex::contains("shellcheck") {
+            return None;
+        }
+
+        match tokio::time::timeout(timeout, run(command, dialect)).await {
+            Ok(findings) => findings,
+            Err(_) => {
+                debug!("shellcheck did not answer within {timeout:?} for \"{command}\"");
+                None
+            }
+        }
+    }
+}
+
+/// Findings of shellcheck for `command` passed on stdin; the process is
+/// killed if the check is abandoned
+async fn run(command: &str, dialect: &str) -> Option<Vec<ShellCheckFinding>> {
+    let mut child = Command::new("shellcheck")
+        .args([
+            &format!("--shell={dialect}"),
+            "--severity=info",
+            "--format=json1",
+            "-",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| debug!("Failed to start shellcheck: {e}"))
+        .ok()?;
+
+    let mut stdin = child.stdin.take()?;
+    stdin.write_all(command.as_bytes()).await.ok()?;
+    stdin.write_all(b"\n").await.ok()?;
+    drop(stdin);
+
+    // It exits with 1 when it finds something, so only the report counts
+    let output = child.wait_with_output().await.ok()?;
+    serde_json::from_slice::<Report>(&output.stdout)
+        .map_err(|e| debug!("Unreadable shellcheck report: {e}"))
+        .ok()
+        .map(|report| report.comments)
+}
@@ -0,0 +1,107 @@
+use log::debug;
+use std::future::Future;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Time phloem may take before the model is asked, e.g. reading settings,
+/// the cache and the context
+pub const OVERHEAD_BUDGET: Duration = Duration::from_millis(100);
+
+/// When the run started, from `Timing::start`
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+/// When the first request went to the model, counted from `STARTED`
+static MODEL_REQUESTED: OnceLock<Duration> = OnceLock::new();
+
+/// Time spent in each phase so far, in the order they first ran
+static PHASES: Mutex<Vec<(&'static str, Duration)>> = Mutex::new(Vec::new());
+
+/// Where the time of a run goes, for `--timing` and `-v`
+pub struct Timing;
+
+impl Timing {
+    /// Starts the clock of the run; only the first call counts
+    pub fn start() {
+        STARTED.get_or_init(Instant::now);
+    }
+
+    /// Adds `duration` to `phase`, e.g. a second model round-trip to the first
+    pub fn record(phase: &'static str, duration: Duration) {
+        debug!("{phase} took {} ms", duration.as_millis());
+        let Ok(mut phases) = PHASES.lock() else {
+            return;
+        };
+        match phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += duration,
+            None => phases.push((phase, duration)),
+        }
+    }
+
+    /// Runs `f`, adding the time it takes to `phase`
+    pub fn measure<T>(phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = f();
+        Self::record(phase, started.elapsed());
+        result
+    }
+
+    /// Awaits `future`, adding the time it takes to `phase`
+    pub async fn measure_async<T>(phase: &'static str, future: impl Future<Output = T>) -> T {
+        let started = Instant::now();
+        let result = future.await;
+        Self::record(phase, started.elapsed());
+        result
+    }
+
+    /// Notes that the model is being asked; only the first call counts
+    pub fn model_requested() {
+        if let Some(started) = STARTED.get() {
+            MODEL_REQUESTED.get_or_init(|| started.elapsed());
+        }
+    }
+
+    /// Forgets what was timed, e.g. before the daemon answers the next prompt
+    pub fn reset() {
+        if let Ok(mut phases) = PHASES.lock() {
+            phases.clear();
+        }
+    }
+
+    /// Each phase and its time, then how long it took to ask the model and
+    /// the whole run; phases running alongside others are counted in full
+    pub fn report() -> String {
+        let phases = PHASES
+            .lock()
+            .map(|phases| phases.clone())
+            .unwrap_or_default();
+        let width = phases
+            .iter()
+            .map(|(name, _)| name.len())
+            .chain(["before the model".len()])
+            .max()
+            .unwrap_or_default();
+        let line = |name: &str, duration: Duration| {
+            format!(
+                "  {name:<width$}  {:>6.1} ms",
+                duration.as_secs_f64() * 1000.0
+            )
+        };
+
+        let mut lines = vec!["Timing:".to_string()];
+        lines.extend(phases.iter().map(|(name, duration)| line(name, *duration)));
+        if let Some(&overhead) = MODEL_REQUESTED.get() {
+            let mut before = line("before the model", overhead);
+            if overhead > OVERHEAD_BUDGET {
+                before.push_str(&format!(
+                    " (over the {} ms budget)",
+                    OVERHEAD_BUDGET.as_millis()
+                ));
+            }
+            lines.push(before);
+        }
+        if let Some(started) = STARTED.get() {
+            lines.push(line("total", started.elapsed()));
+        }
+        lines.join("\n")
+    }
+}
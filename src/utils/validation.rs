@@ -1,23 +1,268 @@
-use regex::Regex;
-use std::collections::HashSet;
+use std::path::PathBuf;
 
-pub struct CommandValidator;
+use crate::utils::shell_syntax::Word;
+use crate::utils::{Policy, PolicyAction, Script, ShellKind, ShellProfile, Verdict};
+
+/// Commands that run what follows them as another user, usually root
+const ELEVATORS: &[&str] = &["sudo", "doas", "su", "pkexec", "runas", "gsudo"];
+
+/// Commands that write the files given as arguments; the first argument of
+/// those in `SKIP_FIRST` is a mode, owner or script rather than a file
+const FILE_WRITERS: &[&str] = &[
+    "touch",
+    "mkdir",
+    "rm",
+    "rmdir",
+    "unlink",
+    "shred",
+    "truncate",
+    "tee",
+    "chmod",
+    "chown",
+    "chgrp",
+    "chattr",
+    "setfacl",
+    "set-content",
+    "add-content",
+    "out-file",
+    "new-item",
+    "remove-item",
+    "del",
+    "erase",
+    "rd",
+    "md",
+];
+const SKIP_FIRST: &[&str] = &["chmod", "chown", "chgrp", "chattr", "setfacl", "truncate"];
+
+/// Commands that write their last argument, a destination
+const COPIERS: &[&str] = &[
+    "cp",
+    "mv",
+    "ln",
+    "install",
+    "copy-item",
+    "move-item",
+    "copy",
+    "move",
+    "xcopy",
+    "robocopy",
+];
+
+/// Commands that reach the network whatever their arguments
+const NETWORK_CLIENTS: &[&str] = &[
+    "curl",
+    "wget",
+    "ssh",
+    "scp",
+    "sftp",
+    "ftp",
+    "telnet",
+    "nc",
+    "ncat",
+    "netcat",
+    "http",
+    "https",
+    "xh",
+    "invoke-webrequest",
+    "invoke-restmethod",
+    "iwr",
+    "irm",
+];
+
+/// Subcommands that reach the network of tools that mostly work locally
+const NETWORK_SUBCOMMANDS: &[(&str, &[&str])] = &[
+    ("git", &["push", "pull", "fetch", "clone", "ls-remote"]),
+    ("docker", &["pull", "push", "login", "build"]),
+    ("podman", &["pull", "push", "login", "build"]),
+    (
+        "kubectl",
+        &[
+            "apply", "create", "delete", "patch", "replace", "scale", "rollout", "edit", "set",
+            "label", "annotate", "drain", "cordon", "exec",
+        ],
+    ),
+    ("helm", &["install", "upgrade", "uninstall", "rollback"]),
+];
+
+/// Tools changing what they manage over the network, the subcommands that do
+/// and what they change
+const NETWORK_CHANGES: &[(&str, &[&str], &str)] = &[
+    (
+        "kubectl",
+        &[
+            "apply",
+            "create",
+            "delete",
+            "patch",
+            "replace",
+            "scale",
+            "edit",
+            "set",
+            "label",
+            "annotate",
+            "drain",
+            "cordon",
+            "uncordon",
+            "taint",
+            "expose",
+            "autoscale",
+        ],
+        "changes the cluster",
+    ),
+    (
+        "oc",
+        &[
+            "apply", "create", "delete", "patch", "replace", "scale", "edit", "set", "label",
+            "annotate", "expose",
+        ],
+        "changes the cluster",
+    ),
+    (
+        "helm",
+        &["install", "upgrade", "uninstall", "delete", "rollback"],
+        "changes the cluster",
+    ),
+    (
+        "terraform",
+        &["apply", "destroy", "import", "taint", "untaint"],
+        "changes infrastructure",
+    ),
+    (
+        "tofu",
+        &["apply", "destroy", "import", "taint", "untaint"],
+        "changes infrastructure",
+    ),
+    (
+        "pulumi",
+        &["up", "update", "destroy", "import"],
+        "changes infrastructure",
+    ),
+    ("git", &["push"], "changes a remote repository"),
+    ("docker", &["push"], "changes a registry"),
+    ("podman", &["push"], "changes a registry"),
+];
+
+/// curl options sending a body, which makes the request a POST
+const CURL_DATA_OPTIONS: &[&str] = &[
+    "-d",
+    "--data",
+    "--data-raw",
+    "--data-binary",
+    "--data-urlencode",
+    "--json",
+    "-F",
+    "--form",
+];
+
+/// Package managers and the subcommands that install or remove packages
+const PACKAGE_MANAGERS: &[(&str, &[&str])] = &[
+    (
+        "apt",
+        &[
+            "install",
+            "remove",
+            "purge",
+            "upgrade",
+            "full-upgrade",
+            "autoremove",
+        ],
+    ),
+    (
+        "apt-get",
+        &[
+            "install",
+            "remove",
+            "purge",
+            "upgrade",
+            "dist-upgrade",
+            "autoremove",
+        ],
+    ),
+    ("dnf", &["install", "remove", "upgrade", "update", "erase"]),
+    ("yum", &["install", "remove", "upgrade", "update", "erase"]),
+    (
+        "zypper",
+        &["install", "in", "remove", "rm", "update", "up", "dup"],
+    ),
+    ("apk", &["add", "del", "upgrade"]),
+    ("brew", &["install", "uninstall", "upgrade", "reinstall"]),
+    ("port", &["install", "uninstall", "upgrade"]),
+    ("snap", &["install", "remove", "refresh"]),
+    ("pip", &["install", "uninstall"]),
+    ("pip3", &["install", "uninstall"]),
+    ("npm", &["install", "i", "uninstall", "update"]),
+    ("pnpm", &["add", "install", "remove", "update"]),
+    ("yarn", &["add", "remove", "upgrade"]),
+    ("cargo", &["install", "uninstall"]),
+    ("gem", &["install", "uninstall", "update"]),
+    ("winget", &["install", "uninstall", "upgrade"]),
+    ("choco", &["install", "uninstall", "upgrade"]),
+    ("scoop", &["install", "uninstall", "update"]),
+];
+
+/// Package manager subcommands that only remove
+const REMOVALS: &[&str] = &[
+    "remove",
+    "purge",
+    "autoremove",
+    "erase",
+    "rm",
+    "del",
+    "uninstall",
+];
+
+/// How much running a command could affect, from its own output to the
+/// whole system
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RiskLevel {
+    /// Reads and prints, as far as can be told
+    Safe,
+    /// Writes files, reaches the network or runs as root
+    Caution,
+    /// Matches a deny or confirm rule of the policy, or changes the system as root
+    Dangerous,
+}
+
+impl RiskLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Safe => "safe",
+            Self::Caution => "caution",
+            Self::Dangerous => "dangerous",
+        }
+    }
+}
+
+/// The risk of a command and what it's based on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Risk {
+    pub level: RiskLevel,
+    /// What the command does that makes it risky, like "runs as root" or
+    /// "writes out.txt"; empty when it's safe
+    pub reasons: Vec<String>,
+}
+
+pub struct CommandValidator {
+    /// Shell the commands are written for
+    shell: ShellKind,
+}
 
 impl CommandValidator {
+    /// Validator for commands in the user's own shell
     pub fn new() -> Self {
-        Self
+        Self::for_shell(ShellKind::detect())
     }
 
-    pub fn is_safe_command(&self, command: &str) -> bool {
-        let dangerous_patterns = self.get_dangerous_patterns();
-
-        for pattern in dangerous_patterns {
-            if pattern.is_match(command) {
-                return false;
-            }
-        }
+    /// Validator for commands written for `shell`, e.g. one picked with `--shell`
+    pub fn for_shell(shell: ShellKind) -> Self {
+        Self { shell }
+    }
 
-        true
+    /// Whether the policy lets `command` be shown and run
+    pub fn is_safe_command(&self, command: &str) -> bool {
+        !self
+            .verdict(command)
+            .is_some_and(|verdict| verdict.action == PolicyAction::Deny)
     }
 
     pub fn is_valid_syntax(&self, command: &str) -> bool {
@@ -28,6 +273,12 @@ impl CommandValidator {
             return false;
         }
 
+        // POSIX shells are parsed: a valid line runs at least one command
+        if self.profile().posix {
+            return Script::parse(trimmed)
+                .is_ok_and(|script| script.commands.iter().any(|c| !c.words.is_empty()));
+        }
+
         // Check for balanced quotes
         if !self.has_balanced_quotes(trimmed) {
             return false;
@@ -38,8 +289,10 @@ impl CommandValidator {
             return false;
         }
 
-        // Check if it looks like a command (starts with alphanumeric or slash)
-        if !trimmed.chars().next().unwrap_or(' ').is_alphanumeric() && !trimmed.starts_with('/') {
+        // Check if it looks like a command (starts with alphanumeric or slash);
+        // PowerShell and nu lines may also start with a variable or call operator
+        let first = trimmed.chars().next().unwrap_or(' ');
+        if !first.is_alphanumeric() && first != '/' && !self.profile().line_starts.contains(first) {
             return false;
         }
 
@@ -64,7 +317,12 @@ impl CommandValidator {
         sanitized
     }
 
+    /// Name of the first command that runs, past wrappers like `sudo`
     pub fn extract_command_name(&self, command: &str) -> Option<String> {
+        if let Some(script) = self.script(command) {
+            return script.executables().next().map(str::to_string);
+        }
+
         let parts: Vec<&str> = command.split_whitespace().collect();
         if let Some(first_part) = parts.first() {
             // Handle cases like "sudo command" or pipes
@@ -78,46 +336,409 @@ impl CommandValidator {
         }
     }
 
+    /// Executables invoked by each command of a pipeline or list, including
+    /// those in subshells and substitutions, skipping variable assignments
+    /// and wrappers like `sudo`
+    pub fn extract_executables(&self, command: &str) -> Vec<String> {
+        let names: Vec<String> = match self.script(command) {
+            Some(script) => script.executables().map(str::to_string).collect(),
+            None => Self::split_executables(command),
+        };
+
+        names
+            .into_iter()
+            // Drop names only known when it runs, like `$EDITOR`, and leftovers
+            // of redirections like `2>&1` and substitutions like `$(...)`
+            .filter(|name| {
+                name.starts_with(|c: char| c.is_alphanumeric() || "/._[".contains(c))
+                    && !name.chars().all(|c| c.is_ascii_digit())
+            })
+            .collect()
+    }
+
+    /// First words of the segments of `command` split at operators, for
+    /// shells the parser doesn't read
+    fn split_executables(command: &str) -> Vec<String> {
+        let wrappers = [
+            "sudo", "doas", "env", "time", "nohup", "exec", "command", "builtin",
+        ];
+
+        command
+            .split(['|', ';', '&', '(', ')', '`'])
+            .filter_map(|segment| {
+                segment
+                    .split_whitespace()
+                    .find(|word| !word.contains('=') && !wrappers.contains(word))
+                    .map(|word| {
+                        // nu runs `^ls` as the external `ls`
+                        word.trim_start_matches('^')
+                            .trim_matches(|c| c == '"' || c == '\'')
+                            .to_string()
+                    })
+            })
+            .collect()
+    }
+
+    /// `command` as the program to run and its arguments, when that's all it
+    /// is: one command of a POSIX shell without assignments, redirections,
+    /// expansions, globs or anything around it, so it can run without a shell
+    pub fn argv(&self, command: &str) -> Option<Vec<String>> {
+        let script = self.script(command)?;
+        let [simple] = script.commands.as_slice() else {
+            return None;
+        };
+        let (first, last) = (simple.words.first()?, simple.words.last()?);
+        let trimmed = command.trim();
+        // Loops, subshells, `!` and `&` put words around the command
+        let bare = trimmed.starts_with(&first.raw) && trimmed.ends_with(&last.raw);
+        if !bare
+            || !script.redirects.is_empty()
+            || !script.functions.is_empty()
+            || !simple.assignments.is_empty()
+            || simple.words.iter().any(Word::expands)
+        {
+            return None;
+        }
+        Some(simple.words.iter().map(|word| word.value.clone()).collect())
+    }
+
+    /// Arguments that look like filesystem paths (`/x`, `./x`, `../x`, `~/x`)
+    pub fn extract_path_arguments(&self, command: &str) -> Vec<String> {
+        let is_path = |raw: &str| {
+            ["/", "./", "../", "~/"]
+                .iter()
+                .any(|prefix| raw.starts_with(prefix))
+                && !raw.contains(['*', '?', '$', '{'])
+        };
+
+        if let Some(script) = self.script(command) {
+            return script
+                .commands
+                .iter()
+                .filter_map(|command| Some(&command.words[command.executable()? + 1..]))
+                .flatten()
+                .filter(|word| is_path(word.raw.trim_matches(|c| c == '"' || c == '\'')))
+                .map(|word| word.value.clone())
+                .collect();
+        }
+
+        command
+            .split_whitespace()
+            .skip(1)
+            .map(|word| word.trim_matches(|c| c == '"' || c == '\''))
+            .filter(|word| is_path(word))
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Whether the policy asks before `command` runs, as it does for commands
+    /// that delete data, rewrite shared history or stop the machine
     pub fn is_destructive_command(&self, command: &str) -> bool {
-        let destructive_commands = self.get_destructive_commands();
+        self.verdict(command)
+            .is_some_and(|verdict| verdict.action == PolicyAction::Confirm)
+    }
 
-        if let Some(cmd_name) = self.extract_command_name(command) {
-            destructive_commands.contains(&cmd_name.as_str())
-        } else {
-            false
-        }
-    }
-
-    fn get_dangerous_patterns(&self) -> Vec<Regex> {
-        let patterns = vec![
-            r"rm\s+-rf\s+/",        // rm -rf /
-            r"rm\s+-rf\s+\*",       // rm -rf *
-            r">\s*/dev/sd[a-z]",    // Write to raw disk
-            r"dd.*of=/dev/sd[a-z]", // DD to disk
-            r"mkfs\.",              // Format filesystem
-            r"fdisk\s+/dev/",       // Disk partitioning
-            r"parted\s+/dev/",      // Disk partitioning
-            r":\(\)\{.*\}\;",       // Fork bomb pattern
-            r"curl.*\|\s*bash",     // Dangerous curl | bash
-            r"wget.*\|\s*bash",     // Dangerous wget | bash
-            r"chmod\s+777\s+/",     // Dangerous chmod on root
-            r"chown.*:.*\s+/",      // Dangerous chown on root
+    /// What a destructive command would act on, e.g. the paths given to `rm`,
+    /// the `of=` device of `dd` or the remote and branch of a force-push
+    pub fn destructive_targets(&self, command: &str) -> Vec<String> {
+        self.verdict(command)
+            .filter(|verdict| verdict.action == PolicyAction::Confirm)
+            .map(|verdict| verdict.targets)
+            .unwrap_or_default()
+    }
+
+    /// What the policy of ~/.phloem/policy.toml decides for `command`, if
+    /// any of its rules match it
+    pub fn verdict(&self, command: &str) -> Option<Verdict> {
+        let commands: Vec<Vec<String>> = self
+            .command_words(command)
+            .into_iter()
+            .map(|(_, words)| words)
+            .filter(|words| !words.is_empty())
+            .collect();
+
+        Policy::current().evaluate(command, &commands, self.shell)
+    }
+
+    /// How much running `command` could affect, judged by the policy, running
+    /// as root, the files it writes and what it reaches over the network
+    pub fn assess_risk(&self, command: &str) -> Risk {
+        let mut reasons = Vec::new();
+        let mut level = RiskLevel::Safe;
+
+        if let Some(verdict) = self.verdict(command) {
+            match verdict.action {
+                PolicyAction::Deny => {
+                    level = RiskLevel::Dangerous;
+                    reasons.push(format!(
+                        "denied by the policy, {}",
+                        verdict.reason.as_deref().unwrap_or("can wreck the system")
+                    ));
+                }
+                PolicyAction::Confirm => {
+                    level = RiskLevel::Dangerous;
+                    reasons.push(
+                        verdict
+                            .reason
+                            .unwrap_or_else(|| "deletes data or stops the machine".to_string()),
+                    );
+                }
+                PolicyAction::Allow => {}
+            }
+        }
+
+        let mut elevated = false;
+        let mut effects = Effects::new(self.profile().windows);
+        for (wrappers, words) in self.command_words(command) {
+            elevated |= self.elevation(&wrappers, &words).is_some();
+            if let Some(name) = words.first() {
+                effects.add(
+                    &executable_name(name, self.profile().case_insensitive),
+                    &words[1..],
+                );
+            }
+        }
+        effects.writes.extend(self.redirect_targets(command));
+
+        if elevated {
+            reasons.push(if self.profile().windows {
+                "runs as administrator".to_string()
+            } else {
+                "runs as root".to_string()
+            });
+        }
+        reasons.extend(effects.reasons());
+
+        if elevated && reasons.len() > 1 {
+            level = RiskLevel::Dangerous;
+        } else if level == RiskLevel::Safe && !reasons.is_empty() {
+            level = RiskLevel::Caution;
+        }
+        Risk { level, reasons }
+    }
+
+    /// Files that exist and that `command` replaces by redirecting output
+    /// into them with `>`, rather than appending with `>>`
+    pub fn overwritten_files(&self, command: &str) -> Vec<String> {
+        let mut files: Vec<String> = self
+            .redirections(command)
+            .into_iter()
+            .filter(|(appends, _)| !appends)
+            .map(|(_, target)| target)
+            .filter(|target| expand_home(target).is_file())
+            .collect();
+        files.dedup();
+        files
+    }
+
+    /// What the commands of `command` change over the network, like "curl
+    /// sends a DELETE request to api.example.com" or "terraform apply
+    /// changes infrastructure"
+    pub fn network_changes(&self, command: &str) -> Vec<String> {
+        let mut changes: Vec<String> = self
+            .command_words(command)
+            .into_iter()
+            .filter_map(|(_, words)| {
+                let (name, args) = words.split_first()?;
+                network_change(
+                    &executable_name(name, self.profile().case_insensitive),
+                    args,
+                    self.profile().windows,
+                )
+            })
+            .collect();
+        changes.dedup();
+        changes
+    }
+
+    /// What each command of `command` that runs as root or another user
+    /// does, e.g. "`apt install ripgrep` as root: changes installed packages"
+    pub fn elevated_actions(&self, command: &str) -> Vec<String> {
+        self.command_words(command)
+            .into_iter()
+            .filter_map(|(wrappers, words)| {
+                let (user, words) = self.elevation(&wrappers, &words)?;
+                let mut effects = Effects::new(self.profile().windows);
+                let action = match words.split_first() {
+                    Some((name, args)) => {
+                        effects.add(
+                            &executable_name(name, self.profile().case_insensitive),
+                            args,
+                        );
+                        format!("`{}` as {user}", words.join(" "))
+                    }
+                    None => format!("a shell as {user}"),
+                };
+                let reasons = effects.reasons();
+                Some(if reasons.is_empty() {
+                    action
+                } else {
+                    format!("{action}: {}", reasons.join(", "))
+                })
+            })
+            .collect()
+    }
+
+    /// Who the command of `wrappers` and `words` runs as and what it runs
+    /// then, when it runs as another user through sudo, doas, su or RunAs
+    fn elevation(&self, wrappers: &[String], words: &[String]) -> Option<(String, Vec<String>)> {
+        let administrator = || {
+            if self.profile().windows {
+                "administrator".to_string()
+            } else {
+                "root".to_string()
+            }
+        };
+
+        if let Some(position) = wrappers
+            .iter()
+            .position(|word| ELEVATORS.contains(&word.as_str()))
+        {
+            let user = wrappers[position..]
+                .windows(2)
+                .find(|pair| pair[0] == "-u")
+                .map_or_else(administrator, |pair| pair[1].clone());
+            return Some((user, words.to_vec()));
+        }
+
+        let (name, args) = words.split_first()?;
+        let name = executable_name(name, self.profile().case_insensitive);
+        if name == "su" {
+            // su [options] [user], running -c's command or a shell
+            let mut command = Vec::new();
+            let mut user = None;
+            let mut args = args.iter();
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "-c" | "--command" => command = args.next().into_iter().cloned().collect(),
+                    "-" => {}
+                    arg if arg.starts_with('-') => {}
+                    arg => user = user.or_else(|| Some(arg.to_string())),
+                }
+            }
+            return Some((user.unwrap_or_else(administrator), command));
+        }
+        if ELEVATORS.contains(&name.as_str()) {
+            // Bare `sudo -i` or `doas -s`, or runas and gsudo on Windows
+            let rest: Vec<String> = args
+                .iter()
+                .skip_while(|arg| arg.starts_with('-') || arg.starts_with('/'))
+                .cloned()
+                .collect();
+            return Some((administrator(), rest));
+        }
+        (name == "start-process" && args.iter().any(|arg| arg.eq_ignore_ascii_case("runas")))
+            .then(|| (administrator(), words.to_vec()))
+    }
+
+    /// Wrappers like `sudo -u admin` and the words from the executable on,
+    /// of each command in `command`
+    fn command_words(&self, command: &str) -> Vec<(Vec<String>, Vec<String>)> {
+        let wrappers = [
+            "sudo", "doas", "env", "time", "nohup", "exec", "command", "builtin",
         ];
 
-        patterns
+        match self.script(command) {
+            Some(script) => script
+                .commands
+                .iter()
+                .map(|command| {
+                    // `sudo -i` runs no command, so it's all wrappers
+                    let executable = command.executable().unwrap_or(command.words.len());
+                    let (before, words) = command.words.split_at(executable);
+                    let values = |words: &[Word]| words.iter().map(|w| w.value.clone()).collect();
+                    (values(before), values(words))
+                })
+                .collect(),
+            None => command
+                .split(['|', ';', '&'])
+                .map(|segment| {
+                    let words: Vec<String> = segment
+                        .split_whitespace()
+                        .map(|word| word.trim_matches(|c| c == '"' || c == '\'').to_string())
+                        .collect();
+                    let start = words
+                        .iter()
+                        .position(|word| !word.contains('=') && !wrappers.contains(&word.as_str()))
+                        .unwrap_or(words.len());
+                    let (before, words) = words.split_at(start);
+                    (before.to_vec(), words.to_vec())
+                })
+                .collect(),
+        }
+    }
+
+    /// Files `command` redirects output into, leaving out /dev/null and
+    /// descriptors like `2>&1`
+    fn redirect_targets(&self, command: &str) -> Vec<String> {
+        self.redirections(command)
             .into_iter()
-            .filter_map(|p| Regex::new(p).ok())
+            .map(|(_, target)| target)
             .collect()
     }
 
-    fn get_destructive_commands(&self) -> HashSet<&'static str> {
-        [
-            "rm", "rmdir", "dd", "mkfs", "fdisk", "parted", "format", "del", "erase", "shred",
-            "wipe", "halt", "shutdown", "reboot", "poweroff",
-        ]
-        .iter()
-        .cloned()
-        .collect()
+    /// Each file `command` redirects output into, and whether it's appended
+    /// to or opened without truncating rather than replaced
+    fn redirections(&self, command: &str) -> Vec<(bool, String)> {
+        let kept = |target: &str| !target.is_empty() && target != "/dev/null" && target != "$null";
+
+        if let Some(script) = self.script(command) {
+            return script
+                .redirects
+                .iter()
+                .filter_map(|redirect| match redirect.op {
+                    ">" | ">|" | "&>" => Some((false, redirect.target.value.clone())),
+                    ">>" | "&>>" | "<>" => Some((true, redirect.target.value.clone())),
+                    _ => None,
+                })
+                .filter(|(_, target)| kept(target))
+                .collect();
+        }
+
+        // Other shells write with `>` and `>>` too, as in `dir > files.txt`
+        let words: Vec<&str> = command.split_whitespace().collect();
+        words
+            .iter()
+            .enumerate()
+            .filter_map(|(index, word)| {
+                let rest = word.trim_start_matches(|c: char| c.is_ascii_digit());
+                let (appends, target) = match rest.strip_prefix(">>") {
+                    Some(target) => (true, target),
+                    None => (false, rest.strip_prefix('>')?),
+                };
+                match target {
+                    "" => words.get(index + 1).map(|target| (appends, *target)),
+                    target if target.starts_with('&') => None,
+                    target => Some((appends, target)),
+                }
+            })
+            .map(|(appends, target)| {
+                let target = target.trim_matches(|c| c == '"' || c == '\'');
+                (appends, target.to_string())
+            })
+            .filter(|(_, target)| kept(target) && !target.eq_ignore_ascii_case("nul"))
+            .collect()
+    }
+
+    /// `command` parsed, when it's written for a POSIX shell and parses
+    fn script(&self, command: &str) -> Option<Script> {
+        if !self.profile().posix {
+            return None;
+        }
+        Script::parse(command)
+            .map_err(|e| log::debug!("Could not parse \"{command}\": {e}"))
+            .ok()
+    }
+
+    /// How commands of the validator's shell are written
+    fn profile(&self) -> &'static ShellProfile {
+        self.shell.profile()
+    }
+
+    /// Character that escapes the next one: a backtick in PowerShell, a
+    /// caret in cmd
+    fn escape_char(&self) -> char {
+        self.profile().escape
     }
 
     fn has_balanced_quotes(&self, text: &str) -> bool {
@@ -125,10 +746,13 @@ impl CommandValidator {
         let mut double_quotes = 0;
         let mut escaped = false;
 
+        let escape = self.escape_char();
+        let quotes = self.profile().quotes;
+
         for ch in text.chars() {
             match ch {
-                '\\' if !escaped => escaped = true,
-                '\'' if !escaped => single_quotes += 1,
+                c if c == escape && !escaped => escaped = true,
+                '\'' if !escaped && quotes.contains(&'\'') => single_quotes += 1,
                 '"' if !escaped => double_quotes += 1,
                 _ => escaped = false,
             }
@@ -142,11 +766,13 @@ impl CommandValidator {
         let mut escaped = false;
         let mut in_quotes = false;
         let mut quote_char = ' ';
+        let escape = self.escape_char();
+        let quotes = self.profile().quotes;
 
         for ch in text.chars() {
             match ch {
-                '\\' if !escaped => escaped = true,
-                '\'' | '"' if !escaped && !in_quotes => {
+                c if c == escape && !escaped => escaped = true,
+                c if !escaped && !in_quotes && quotes.contains(&c) => {
                     in_quotes = true;
                     quote_char = ch;
                 }
@@ -167,6 +793,234 @@ impl CommandValidator {
     }
 }
 
+/// What commands change outside their output
+#[derive(Default)]
+struct Effects {
+    /// Files written
+    writes: Vec<String>,
+    /// What's reached over the network
+    network: Vec<String>,
+    /// Whether packages are installed or removed
+    packages: bool,
+    /// Whether two-character words like `/s` are cmd switches, not paths
+    windows: bool,
+}
+
+impl Effects {
+    fn new(windows: bool) -> Self {
+        Self {
+            windows,
+            ..Self::default()
+        }
+    }
+
+    /// Adds what `name` does when run with `args`
+    fn add(&mut self, name: &str, args: &[String]) {
+        self.writes.extend(written_files(name, args, self.windows));
+        if let Some(reason) = network_use(name, args, self.windows) {
+            self.network.push(reason);
+        }
+        if let Some(downloads) = package_change(name, args, self.windows) {
+            self.packages = true;
+            if downloads {
+                self.network.push("downloads packages".to_string());
+            }
+        }
+    }
+
+    /// The effects as risk reasons, like "writes out.txt"
+    fn reasons(mut self) -> Vec<String> {
+        self.writes.dedup();
+        self.network.dedup();
+        let mut reasons = Vec::new();
+        if !self.writes.is_empty() {
+            reasons.push(format!("writes {}", self.writes.join(", ")));
+        }
+        if self.packages {
+            reasons.push("changes installed packages".to_string());
+        }
+        reasons.extend(self.network);
+        reasons
+    }
+}
+
+/// `name` without its directory and, for Windows, `.exe`, lowercased in
+/// case-insensitive shells
+fn executable_name(name: &str, case_insensitive: bool) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    if case_insensitive {
+        let name = name.to_lowercase();
+        name.strip_suffix(".exe").unwrap_or(&name).to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Arguments that aren't options, like `-f`, `--force` or, in Windows
+/// shells, cmd's `/s`
+fn operands(args: &[String], windows: bool) -> impl Iterator<Item = &str> {
+    args.iter().map(String::as_str).filter(move |arg| {
+        !(arg.starts_with('-') || (windows && arg.starts_with('/') && arg.len() == 2))
+    })
+}
+
+/// Files `name` writes when run with `args`
+fn written_files(name: &str, args: &[String], windows: bool) -> Vec<String> {
+    let files: Vec<&str> = if FILE_WRITERS.contains(&name) {
+        operands(args, windows)
+            .skip(usize::from(SKIP_FIRST.contains(&name)))
+            .collect()
+    } else if COPIERS.contains(&name) {
+        operands(args, windows).last().into_iter().collect()
+    } else if name == "sed"
+        && args
+            .iter()
+            .any(|arg| arg.starts_with("-i") || arg.starts_with("--in-place"))
+    {
+        // The first operand is the script unless it's given with -e
+        let scripted = args.iter().any(|arg| arg == "-e" || arg == "-f");
+        operands(args, windows)
+            .skip(usize::from(!scripted))
+            .collect()
+    } else if name == "dd" {
+        args.iter()
+            .filter_map(|arg| arg.strip_prefix("of="))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    files.into_iter().map(str::to_string).collect()
+}
+
+/// What `name` reaches over the network when run with `args`, if anything
+fn network_use(name: &str, args: &[String], windows: bool) -> Option<String> {
+    if NETWORK_CLIENTS.contains(&name) {
+        return Some(match url_host(args) {
+            Some(host) => format!("reaches {host}"),
+            None => format!("{name} uses the network"),
+        });
+    }
+    // rsync only leaves the machine for `host:path` arguments
+    if name == "rsync"
+        && operands(args, windows).any(|arg| arg.contains(':') || arg.contains("://"))
+    {
+        return Some("rsync copies to or from another host".to_string());
+    }
+    let subcommand = operands(args, windows).next()?;
+    NETWORK_SUBCOMMANDS
+        .iter()
+        .any(|(tool, subcommands)| *tool == name && subcommands.contains(&subcommand))
+        .then(|| format!("{name} {subcommand} uses the network"))
+}
+
+/// What `name` changes over the network when run with `args`, if anything:
+/// HTTP requests other than GET, HEAD and OPTIONS, and subcommands like
+/// `kubectl delete` or `terraform apply`
+fn network_change(name: &str, args: &[String], windows: bool) -> Option<String> {
+    if let Some(method) = http_method(name, args, windows) {
+        let method = method.to_uppercase();
+        if matches!(method.as_str(), "GET" | "HEAD" | "OPTIONS") {
+            return None;
+        }
+        return Some(match url_host(args) {
+            Some(host) => format!("{name} sends a {method} request to {host}"),
+            None => format!("{name} sends a {method} request"),
+        });
+    }
+    let subcommand = operands(args, windows).next()?;
+    NETWORK_CHANGES
+        .iter()
+        .find(|(tool, subcommands, _)| *tool == name && subcommands.contains(&subcommand))
+        .map(|(_, _, what)| format!("{name} {subcommand} {what}"))
+}
+
+/// The HTTP method `name` sends when run with `args`, if it's an HTTP client
+fn http_method(name: &str, args: &[String], windows: bool) -> Option<String> {
+    let method = match name {
+        "curl" => option_value(args, &["-X", "--request"]).or_else(|| {
+            if option_value(args, CURL_DATA_OPTIONS).is_some() {
+                Some("POST")
+            } else if option_value(args, &["-T", "--upload-file"]).is_some() {
+                Some("PUT")
+            } else {
+                None
+            }
+        }),
+        "wget" => option_value(args, &["--method"])
+            .or_else(|| option_value(args, &["--post-data", "--post-file"]).map(|_| "POST")),
+        // HTTPie takes the method as its first argument
+        "http" | "https" | "xh" => operands(args, windows)
+            .next()
+            .filter(|word| word.chars().all(|c| c.is_ascii_uppercase())),
+        "invoke-webrequest" | "invoke-restmethod" | "iwr" | "irm" => {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_lowercase()).collect();
+            return Some(
+                option_value(&args, &["-method"])
+                    .unwrap_or("GET")
+                    .to_string(),
+            );
+        }
+        _ => return None,
+    };
+    Some(method.unwrap_or("GET").to_string())
+}
+
+/// The value `args` give the first of `options` found, as in `-X POST`,
+/// `-XPOST` or `--request=POST`
+fn option_value<'a>(args: &'a [String], options: &[&str]) -> Option<&'a str> {
+    args.iter().enumerate().find_map(|(index, arg)| {
+        options.iter().find_map(|option| {
+            let rest = arg.strip_prefix(option)?;
+            if rest.is_empty() {
+                args.get(index + 1).map(String::as_str)
+            } else if option.len() == 2 {
+                // `-dfoo`, but not `--digest` for `-d`
+                (!arg.starts_with("--")).then_some(rest)
+            } else {
+                rest.strip_prefix(['=', ':'])
+            }
+        })
+    })
+}
+
+/// The host of the first URL in `args`
+fn url_host(args: &[String]) -> Option<&str> {
+    let url = args.iter().find(|arg| arg.contains("://"))?;
+    let host = url.split("://").nth(1).unwrap_or(url);
+    host.split('/').next()
+}
+
+/// `path` with a leading `~` standing for the home directory
+fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => dirs::home_dir()
+            .map(|home| home.join(rest.trim_start_matches('/')))
+            .unwrap_or_else(|| PathBuf::from(path)),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Whether `name` installs or removes packages when run with `args`, and
+/// if so whether it downloads them
+fn package_change(name: &str, args: &[String], windows: bool) -> Option<bool> {
+    // pacman's operations are options: -S installs (but -Ss, -Si, -Sl and -Sg
+    // only look), -U installs a file and -R removes
+    if name == "pacman" {
+        return args.iter().find_map(|arg| {
+            if let Some(flags) = arg.strip_prefix("-S") {
+                (!flags.contains(['s', 'i', 'l', 'g'])).then_some(true)
+            } else {
+                (arg.starts_with("-U") || arg.starts_with("-R")).then_some(false)
+            }
+        });
+    }
+    let subcommand = operands(args, windows).next()?;
+    PACKAGE_MANAGERS
+        .iter()
+        .any(|(tool, subcommands)| *tool == name && subcommands.contains(&subcommand))
+        .then(|| !REMOVALS.contains(&subcommand))
+}
+
 impl Default for CommandValidator {
     fn default() -> Self {
         Self::new()